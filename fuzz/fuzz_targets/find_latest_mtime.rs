@@ -0,0 +1,67 @@
+// fuzz/fuzz_targets/find_latest_mtime.rs
+// Build a one-level tree from fuzzer input and check the child-priority rule:
+// newest file if any file exists, else newest subdir, else None.
+#![no_main]
+
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use arbitrary::Arbitrary;
+use filetime::{set_file_mtime, FileTime};
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/scan.rs"]
+mod scan;
+
+#[derive(Arbitrary, Debug)]
+struct Entry {
+    name: String,
+    is_file: bool,
+    mtime_secs: u32,
+}
+
+/// Names the filesystem will take as a single, distinct child.
+fn usable(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 200
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\', '\0'])
+}
+
+fuzz_target!(|entries: Vec<Entry>| {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let mut seen = HashSet::new();
+    let mut newest_file: Option<SystemTime> = None;
+    let mut newest_dir: Option<SystemTime> = None;
+
+    for e in entries.iter().take(64) {
+        if !usable(&e.name) || !seen.insert(e.name.as_str()) {
+            continue;
+        }
+        let path = dir.path().join(&e.name);
+        let created = if e.is_file {
+            fs::File::create(&path).map(drop)
+        } else {
+            fs::create_dir(&path)
+        };
+        if created.is_err() {
+            continue;
+        }
+        let t = UNIX_EPOCH + Duration::from_secs(e.mtime_secs.into());
+        set_file_mtime(&path, FileTime::from_system_time(t)).expect("set mtime");
+
+        let slot = if e.is_file {
+            &mut newest_file
+        } else {
+            &mut newest_dir
+        };
+        *slot = Some(slot.map_or(t, |cur| cur.max(t)));
+    }
+
+    let got = scan::find_target_mtime(dir.path(), scan::Strategy::Newest)
+        .expect("scan temp dir")
+        .map(|l| l.mtime);
+    assert_eq!(got, newest_file.or(newest_dir));
+});
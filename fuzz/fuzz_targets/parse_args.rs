@@ -0,0 +1,21 @@
+// fuzz/fuzz_targets/parse_args.rs
+// Arbitrary argument vectors must never panic the parser: every input either
+// parses or yields a usage-error message.
+#![no_main]
+
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/cli.rs"]
+mod cli;
+
+fuzz_target!(|data: &[u8]| {
+    // NUL can't appear inside a real argument, so use it as the separator.
+    let args = data
+        .split(|&b| b == 0)
+        .map(|a| OsString::from_vec(a.to_vec()));
+    let _ = cli::parse_args(args);
+});
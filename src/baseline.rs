@@ -0,0 +1,183 @@
+// src/baseline.rs
+// --baseline: the drift a previous run computed (root-relative folder, its
+// mtime then, the target then), so the report can show only what is new or
+// different since. JSON lines like the snapshot format.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::paths;
+use crate::report::Change;
+use crate::snapshot::{join_time, split_time};
+
+/// Baseline files this build writes and accepts.
+pub const BASELINE_VERSION: u32 = 1;
+
+/// Drift within this of the recorded drift counts as the same.
+const SAME_WITHIN: Duration = Duration::from_secs(1);
+
+/// First line of a baseline.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    baseline_version: u32,
+    dirstamp_version: String,
+    /// Informational: entries are relative, so the tree may have moved.
+    #[serde(with = "paths::json")]
+    root: PathBuf,
+    #[serde(with = "time::serde::rfc3339")]
+    created: OffsetDateTime,
+}
+
+/// `["media/photos", FROM_SECS, FROM_NANOS, TO_SECS, TO_NANOS]`
+#[derive(Serialize, Deserialize)]
+struct Entry(#[serde(with = "paths::json")] PathBuf, i64, u32, i64, u32);
+
+/// One folder's drift, relative to the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Drift {
+    pub path: PathBuf,
+    pub from: SystemTime,
+    pub to: SystemTime,
+}
+
+impl Drift {
+    pub fn new(change: &Change, root: &Path) -> Self {
+        Drift {
+            path: change
+                .path
+                .strip_prefix(root)
+                .unwrap_or(&change.path)
+                .to_path_buf(),
+            from: change.from,
+            to: change.to,
+        }
+    }
+}
+
+pub struct Baseline {
+    drift: HashMap<PathBuf, (SystemTime, SystemTime)>,
+}
+
+impl Baseline {
+    /// `Ok(None)` if there is no baseline yet.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).lines();
+        let header: Header = serde_json::from_str(&lines.next().transpose()?.unwrap_or_default())?;
+        if header.baseline_version != BASELINE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported baseline version {} (this dirstamp reads version {BASELINE_VERSION})",
+                    header.baseline_version
+                ),
+            ));
+        }
+        let mut drift = HashMap::new();
+        for line in lines {
+            let Entry(rel, from_secs, from_nanos, to_secs, to_nanos) =
+                serde_json::from_str(&line?)?;
+            drift.insert(
+                rel,
+                (
+                    join_time(from_secs, from_nanos),
+                    join_time(to_secs, to_nanos),
+                ),
+            );
+        }
+        Ok(Some(Baseline { drift }))
+    }
+
+    /// Whether the baseline already recorded this same drift.
+    pub fn knows(&self, drift: &Drift) -> bool {
+        let close = |a: SystemTime, b: SystemTime| {
+            a.duration_since(b).unwrap_or_else(|e| e.duration()) <= SAME_WITHIN
+        };
+        self.drift
+            .get(&drift.path)
+            .is_some_and(|&(from, to)| close(from, drift.from) && close(to, drift.to))
+    }
+}
+
+/// Write `drift` as the new baseline for `root`.
+pub fn save(path: &Path, root: &Path, created: SystemTime, drift: &[Drift]) -> io::Result<()> {
+    // Through a temporary file, so a failed write leaves the old baseline.
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(tmp_name);
+    let written = File::create(&tmp).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        serde_json::to_writer(
+            &mut out,
+            &Header {
+                baseline_version: BASELINE_VERSION,
+                dirstamp_version: env!("CARGO_PKG_VERSION").into(),
+                root: root.to_path_buf(),
+                created: created.into(),
+            },
+        )?;
+        out.write_all(b"\n")?;
+        for d in drift {
+            let (from_secs, from_nanos) = split_time(d.from);
+            let (to_secs, to_nanos) = split_time(d.to);
+            serde_json::to_writer(
+                &mut out,
+                &Entry(d.path.clone(), from_secs, from_nanos, to_secs, to_nanos),
+            )?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    });
+    match written {
+        Ok(()) => fs::rename(&tmp, path),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    fn drift(path: &str, from: u64, to: u64) -> Drift {
+        Drift {
+            path: path.into(),
+            from: UNIX_EPOCH + Duration::from_secs(from),
+            to: UNIX_EPOCH + Duration::from_secs(to),
+        }
+    }
+
+    #[test]
+    fn knows_only_the_same_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("baseline.jsonl");
+        assert!(Baseline::load(&file).unwrap().is_none());
+
+        save(
+            &file,
+            Path::new("/photos"),
+            UNIX_EPOCH,
+            &[drift("2014/Rome", 500, 100), drift("", 900, 800)],
+        )
+        .unwrap();
+        let baseline = Baseline::load(&file).unwrap().unwrap();
+        assert!(baseline.knows(&drift("2014/Rome", 500, 100)));
+        assert!(baseline.knows(&drift("", 900, 801)));
+        assert!(!baseline.knows(&drift("2014/Rome", 500, 300)));
+        assert!(!baseline.knows(&drift("2014/Venice", 500, 100)));
+    }
+}
@@ -0,0 +1,230 @@
+// src/cache.rs
+// --cache: the names in each folder as the last run listed them, with the
+// folder's identity then (device, inode, mtime and ctime). Adding, removing
+// or renaming an entry changes a folder's ctime, and so does setting its
+// mtime, which nothing can set back; a folder whose identity is unchanged
+// therefore still holds exactly those names, and the walk stats them by name
+// instead of listing it. The entries themselves are always read afresh, so
+// the cache only ever saves listings: it holds no mtimes of children and no
+// planned changes, and no option makes an entry wrong. One that doesn't
+// match is listed as usual. Unix only, for the ctime.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+/// Cache files this build writes and accepts.
+const CACHE_VERSION: u32 = 1;
+
+/// A folder changed this close to being listed may change again within the
+/// same ctime tick, unseen; it isn't recorded.
+const RACY: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    cache_version: u32,
+    /// The root as the walk spells it; the paths are relative to it.
+    #[serde(with = "paths::json")]
+    root: PathBuf,
+}
+
+/// Device, inode, mtime and ctime (seconds and nanoseconds).
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Identity(u64, u64, i64, i64, i64, i64);
+
+#[derive(Serialize, Deserialize)]
+struct Name(#[serde(with = "paths::json")] PathBuf);
+
+/// `["media/photos", IDENTITY, NAMES]`.
+#[derive(Serialize, Deserialize)]
+struct Entry(#[serde(with = "paths::json")] PathBuf, Identity, Vec<Name>);
+
+pub struct DirCache {
+    root: PathBuf,
+    /// From the last run, by root-relative path.
+    known: HashMap<PathBuf, (Identity, Vec<OsString>)>,
+    /// This run's listings so far.
+    listed: HashMap<PathBuf, (Identity, Vec<OsString>)>,
+}
+
+impl DirCache {
+    /// The cache in `file` for a walk of `root`. A missing file is a first
+    /// run; an unreadable one, or one for another root, is reported and
+    /// ignored.
+    pub fn load(file: &Path, root: &Path) -> Self {
+        let mut cache = DirCache {
+            root: root.to_path_buf(),
+            known: HashMap::new(),
+            listed: HashMap::new(),
+        };
+        match read(file, root) {
+            Ok(known) => cache.known = known,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!(
+                "warning: ignoring --cache {} ({e}); listing every folder",
+                file.display()
+            ),
+        }
+        cache
+    }
+
+    fn rel(&self, dir: &Path) -> PathBuf {
+        dir.strip_prefix(&self.root).unwrap_or(dir).to_path_buf()
+    }
+
+    /// The names `dir` held last run, if `meta` (its metadata now) says it
+    /// is the same folder, unchanged since.
+    pub fn names(&self, dir: &Path, meta: &Metadata) -> Option<&[OsString]> {
+        let now = identity(meta)?;
+        let (then, names) = self.known.get(&self.rel(dir))?;
+        (*then == now).then_some(names.as_slice())
+    }
+
+    /// Remember this run's listing of `dir`, whose metadata before it was
+    /// listed was `meta`.
+    pub fn record(&mut self, dir: &Path, meta: &Metadata, names: Vec<OsString>) {
+        let Some(id) = identity(meta) else {
+            return;
+        };
+        if racy(id) {
+            return;
+        }
+        self.listed.insert(self.rel(dir), (id, names));
+    }
+
+    /// This run's listings become the ones the next run is checked against.
+    pub fn finish_run(&mut self) {
+        self.known = std::mem::take(&mut self.listed);
+    }
+
+    /// Write the last finished run's listings to `file`, through a temporary
+    /// file so an interrupted save leaves the old cache whole.
+    pub fn save(&self, file: &Path) -> io::Result<()> {
+        let mut tmp = file.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        let header = Header {
+            cache_version: CACHE_VERSION,
+            root: self.root.clone(),
+        };
+        serde_json::to_writer(&mut out, &header)?;
+        out.write_all(b"\n")?;
+        let mut dirs: Vec<_> = self.known.iter().collect();
+        dirs.sort_by(|a, b| a.0.cmp(b.0));
+        for (rel, (id, names)) in dirs {
+            let names = names.iter().map(|n| Name(n.into())).collect();
+            serde_json::to_writer(&mut out, &Entry(rel.clone(), *id, names))?;
+            out.write_all(b"\n")?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, file)
+    }
+}
+
+fn read(file: &Path, root: &Path) -> io::Result<HashMap<PathBuf, (Identity, Vec<OsString>)>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut lines = BufReader::new(File::open(file)?).lines();
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).map_err(|e| invalid(e.to_string()))?,
+        None => return Err(invalid("empty file".into())),
+    };
+    if header.cache_version != CACHE_VERSION {
+        return Err(invalid(format!(
+            "cache version {} (this build reads {CACHE_VERSION})",
+            header.cache_version
+        )));
+    }
+    if header.root != root {
+        return Err(invalid(format!("made for {}", header.root.display())));
+    }
+    let mut known = HashMap::new();
+    for line in lines {
+        let Entry(rel, id, names) =
+            serde_json::from_str(&line?).map_err(|e| invalid(e.to_string()))?;
+        let names = names.into_iter().map(|n| n.0.into_os_string()).collect();
+        known.insert(rel, (id, names));
+    }
+    Ok(known)
+}
+
+/// Whether the folder's ctime is within `RACY` of now (or in the future).
+fn racy(id: Identity) -> bool {
+    let ctime = Duration::from_secs(id.4.max(0) as u64) + Duration::from_nanos(id.5.max(0) as u64);
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    now.saturating_sub(ctime) < RACY
+}
+
+#[cfg(unix)]
+fn identity(meta: &Metadata) -> Option<Identity> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(Identity(
+        meta.dev(),
+        meta.ino(),
+        meta.mtime(),
+        meta.mtime_nsec(),
+        meta.ctime(),
+        meta.ctime_nsec(),
+    ))
+}
+
+/// No ctime to trust: nothing is recorded, so every folder is listed.
+#[cfg(not(unix))]
+fn identity(_meta: &Metadata) -> Option<Identity> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filetime::{set_file_mtime, FileTime};
+
+    #[test]
+    fn a_folder_changed_since_no_longer_matches() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        let old = FileTime::from_unix_time(1_577_836_800, 0);
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("a"), "").unwrap();
+        set_file_mtime(&root, old).unwrap();
+        let file = tmp.path().join("cache");
+
+        let mut cache = DirCache::load(&file, &root);
+        let meta = fs::metadata(&root).unwrap();
+        // Just changed (its ctime is now), so not trusted yet.
+        cache.record(&root, &meta, vec!["a".into()]);
+        assert!(cache.listed.is_empty());
+
+        // As if it had been listed long ago.
+        let mut id = identity(&meta).unwrap();
+        id.4 -= 60;
+        cache.listed.insert(PathBuf::new(), (id, vec!["a".into()]));
+        cache.finish_run();
+        cache.save(&file).unwrap();
+        let reloaded = DirCache::load(&file, &root);
+        assert_eq!(reloaded.known[Path::new("")].1, ["a"]);
+        // The ctime on record isn't the folder's.
+        assert!(reloaded.names(&root, &meta).is_none());
+        let mut same = DirCache::load(&file, &root);
+        let now = identity(&meta).unwrap();
+        same.known.insert(PathBuf::new(), (now, vec!["a".into()]));
+        assert_eq!(same.names(&root, &meta).unwrap(), ["a"]);
+        assert!(DirCache::load(&file, &tmp.path().join("other"))
+            .known
+            .is_empty());
+
+        fs::write(&file, "not json\n").unwrap();
+        assert!(DirCache::load(&file, &root).known.is_empty());
+    }
+}
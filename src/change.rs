@@ -0,0 +1,135 @@
+// src/change.rs
+// What a run does, or would do, to one folder, and why it leaves others
+// alone. Shared by the library and every report the binary writes.
+
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+/// A directory whose mtime is out of step with its newest child.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub path: PathBuf,
+    pub from: SystemTime,
+    pub to: SystemTime,
+    /// Name of the child whose mtime was chosen: `None` if no single one
+    /// decided it, `..` when it is the parent's (`Clamped`).
+    pub source_child: Option<PathBuf>,
+    /// `--copy-from`: the directory whose mtime is being copied.
+    pub copied_from: Option<PathBuf>,
+    /// Where `to` came from.
+    #[serde(default)]
+    pub reason: ChangeReason,
+}
+
+/// Why a folder is getting the mtime it is. Serialized in kebab-case.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeReason {
+    /// The child the selection policy picked (`source_child`, if one did).
+    #[default]
+    Child,
+    /// `--clamp-to-parent`: the child's time, held back to the parent's.
+    Clamped,
+    /// `--copy-from` / `--compare-to`: another folder's (`copied_from`).
+    Copied,
+    /// `--apply-plan`, `--undo` or `--restore-snapshot`: a time saved earlier.
+    Saved,
+}
+
+impl Change {
+    /// Signed drift in seconds; positive means the folder moves forward in time.
+    pub fn delta_secs(&self) -> f64 {
+        match self.to.duration_since(self.from) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        }
+    }
+}
+
+/// What happened to a planned change.
+/// Serialized as its `word`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Dry run: the change would be made.
+    Planned,
+    /// The new mtime was written.
+    #[serde(rename = "updated")]
+    Applied,
+    /// Writing the new mtime failed (reported on stderr unless silenced).
+    Failed,
+}
+
+impl Status {
+    /// Single-character status used by `--porcelain`.
+    pub fn code(self) -> char {
+        match self {
+            Status::Planned => 'P',
+            Status::Applied => 'U',
+            Status::Failed => 'F',
+        }
+    }
+
+    /// Word used by the log and `--output-json`.
+    pub fn word(self) -> &'static str {
+        match self {
+            Status::Planned => "planned",
+            Status::Applied => "updated",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// Why a directory was left alone (`--show-skipped`). Serialized as its
+/// `keyword`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// No children to take a time from.
+    Empty,
+    /// Already within tolerance of its newest child.
+    InSync,
+    /// `--apply-plan`: the mtime moved since the plan was made.
+    ChangedSincePlan,
+    /// `--verify-before-apply`: it changed between the scan and the stamp.
+    ChangedSinceScan,
+    /// `--compare-to`: the reference tree has no such directory.
+    NotInReference,
+    /// `--newer-ref`: no child is newer than the reference.
+    NotNewer,
+    /// `--no-update-root` / `--no-update-top`: one of the top folders.
+    TopLevel,
+    /// `--directory-filter` didn't select it.
+    Filtered,
+    /// `--include-device` / `--exclude-device` filtered it (and everything below) out.
+    OtherDevice,
+    /// `--interactive`: answered no (or skipped, or never asked after
+    /// quitting); or the `--before-stamp` command failed.
+    Declined,
+    /// `--dir-timeout` expired while reading it.
+    TimedOut,
+    /// Reading, scanning or stamping it failed.
+    Error,
+}
+
+impl SkipReason {
+    /// Stable keyword printed by `--show-skipped`.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SkipReason::Empty => "empty",
+            SkipReason::InSync => "in-sync",
+            SkipReason::ChangedSincePlan => "changed-since-plan",
+            SkipReason::ChangedSinceScan => "changed-since-scan",
+            SkipReason::NotInReference => "not-in-reference",
+            SkipReason::NotNewer => "not-newer",
+            SkipReason::TopLevel => "top-level",
+            SkipReason::OtherDevice => "other-device",
+            SkipReason::Filtered => "filtered",
+            SkipReason::Declined => "declined",
+            SkipReason::TimedOut => "timed-out",
+            SkipReason::Error => "error",
+        }
+    }
+}
@@ -0,0 +1,1704 @@
+// src/cli.rs
+// Command-line parsing. No I/O and no process::exit here, so the parser can be
+// exercised directly (see fuzz/fuzz_targets/parse_args.rs).
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+pub const USAGE: &str = "\
+dirstamp {VERSION}
+
+Usage:
+  dirstamp [stamp] [PATH] [OPTIONS]
+  dirstamp check [PATH] [OPTIONS]
+  dirstamp find-newest DIR [OPTIONS]
+  dirstamp undo JOURNAL [OPTIONS]
+
+Commands:
+  stamp             Stamp the tree at PATH (the default; a dry run unless -C)
+  check             Exit 1 if any folder would change, 0 if all are in sync,
+                    2 if any couldn't be read (even if others would change)
+  find-newest       Print the time and name of the child DIR would be stamped
+                    from (as -D shows dates); exit 1 if it has none
+  undo              Put back the mtimes a -C run recorded in JOURNAL (a dry
+                    run unless -C is given)
+
+Options:
+  -C, --confirm     Apply changes (default is dry run)
+      --verify-before-apply
+                    With -C, look at each folder again right before setting it
+                    and skip it if it changed since the scan
+      --recompute   Like --verify-before-apply, but re-plan changed folders
+                    from what is on disk instead of skipping them
+      --verify      With -C, re-read every folder afterwards and exit 1 if any
+                    mtime isn't within 2 seconds of the one set
+      --watch       After the first pass, keep running and restamp the folders
+                    that filesystem events point at, until Ctrl-C
+      --debounce DURATION
+                    With --watch, wait until events have been quiet this long
+                    before acting on them (default 2s)
+      --interval DURATION
+                    Run again every DURATION (e.g. 15m) until Ctrl-C or SIGTERM,
+                    printing one summary line per run instead of the report
+      --newer-ref FILE
+                    Only process folders with a child newer than FILE, as in
+                    find -newer; the others are skipped as not-newer
+      --no-update-root
+                    Leave PATH's own mtime alone (skipped as top-level)
+      --no-update-top N
+                    Leave the folders less than N levels below PATH alone;
+                    1 is --no-update-root
+      --skip-if-stamped-file FILE
+                    Do nothing if FILE exists and nothing under PATH is newer
+                    than it (another tool already stamped the tree)
+      --git-changed[=RANGE]
+                    Only visit the folders holding files git reports changed in
+                    RANGE (default HEAD~1..HEAD), and their parents; RANGE
+                    worktree means uncommitted and untracked changes
+      --limit N     With -C, make at most N changes, then stop (exit code 4)
+                    and report how many remain; 0 applies nothing
+      --atomic      With -C, plan every change first, then make them all; if
+                    one fails, set the folders already changed back
+      --sparse N    Judge N folders picked at random and estimate how many in
+                    the whole tree need updating; stamps nothing
+      --interactive Ask before each change (y/n/a/q/s) and apply the ones
+                    approved straight away; implies -C
+      --after-stamp COMMAND
+                    With -C, run COMMAND with sh -c after each folder is
+                    stamped; {path}, {from_mtime} and {to_mtime} are replaced,
+                    already quoted. A failure is only warned about
+      --before-stamp COMMAND
+                    Run COMMAND likewise before each folder is stamped, in a
+                    dry run too; if it fails (or sh can't start), the folder
+                    is skipped as declined
+      --fail-fast   Stop the run (exit code 3) at the first --after-stamp
+                    command that fails; --before-stamp isn't covered
+  -n, --dry-run, --no-act
+                    Only report what would change (the default; for explicit scripts)
+      --dry-run-exit-code N
+                    In a dry run, exit with code N if any folder would change
+                    (check's own codes win)
+      --check       The same as dirstamp check (deprecated)
+      --strict      Exit 3 if any folder was skipped because of an error
+      --ignore-permission-errors
+                    Silently skip folders that fail with permission denied
+      --quiet-errors
+                    Don't print each error as it happens; only the grouped summary
+      --retries N   Try a metadata read, child scan or mtime write up to N more
+                    times when it times out or would block (default 0)
+      --buffered    Collect every folder before starting, and process them
+                    deepest first across the whole tree, instead of each one
+                    as soon as the walk has left it
+      --jobs N      List, read and set up to N folders at once (default 1; 0 means
+                    one per CPU); for high-latency network shares
+      --lockfile FILE
+                    Hold an exclusive lock on FILE for the run; if another run
+                    has it, exit 3 (Another dirstamp is running)
+      --wait-for-lock
+                    With --lockfile, wait for the other run to finish instead
+      --max-open N  List at most N folders at once, across the walk, the scans
+                    and --jobs (default 256, or half the open-file limit)
+      --rate-limit N
+                    With -C, set at most N folder mtimes per second (default 0,
+                    no limit), to spare a busy share
+      --shard       Process PATH one top-level folder at a time, walking,
+                    planning, stamping and reporting each before the next, so
+                    memory is bounded by the largest subtree; --sort applies
+                    within each folder
+      --max-memory SIZE
+                    With --shard, split any subtree whose buffered folders or
+                    report would take more than about SIZE (e.g. 2G) into its
+                    subfolders; implies --shard
+      --cache FILE  Remember each folder's entries in FILE, and next time stat
+                    them by name in folders unchanged since instead of listing
+                    them (Unix; ignored elsewhere)
+      --dir-timeout DURATION
+                    Give up on a folder that takes longer than DURATION (e.g.
+                    30s) to list or stat, and skip it as timed-out
+      --retry-delay DURATION
+                    Wait before the first retry, doubling each time, e.g. 500ms
+                    or 2s (default 200ms)
+  -D, --show-dates  Show from → to timestamps and ±days for each change
+      --utc         Show dates in UTC (default)
+      --local       Show dates in the system's local timezone
+      --epoch       Show dates as Unix epoch seconds (implies -D)
+      --epoch-ns    Show dates as Unix epoch nanoseconds (implies -D)
+      --include-device DEVNUM
+                    Only visit folders on filesystem device DEVNUM (st_dev);
+                    may be repeated (Unix only)
+      --exclude-device DEVNUM
+                    Don't visit folders on device DEVNUM; may be repeated
+      --directory-filter EXPR
+                    Only change folders matching EXPR, e.g.
+                    'depth >= 2 AND children > 3 AND delta_days > 7'
+                    (attributes depth, children, delta_days, name; see README)
+      --clamp-to-parent
+                    Never give a folder a newer mtime than its parent's current one
+      --time-style STYLE
+                    Date style for -D (implies -D): full-iso, iso, rfc3339,
+                    epoch, epoch.N (N fractional digits) or +FORMAT (strftime)
+      --human-delta With -D, describe the drift in words (back 2 weeks)
+      --sort KEY    Report changes ordered by KEY: path or delta (largest drift first)
+      --top N       Only report the N largest drifts; the rest are counted
+      --group-depth N
+                    Summarise changes per folder N levels below PATH instead of
+                    listing each one (count, min/max/mean drift, errors)
+  -v, --verbose     With --group-depth, also list the individual changes
+      --tree        Draw the folders as a tree, each marked [OK], [UPDATE +3.2d],
+                    [EMPTY] or why it was skipped (a dry run)
+      --porcelain[=v1]
+                    Stable tab-separated output for scripts (see README)
+      --show-skipped[=errors]
+                    List folders left alone, with a reason keyword (empty,
+                    in-sync, changed-since-plan, changed-since-scan,
+                    not-in-reference, not-newer, top-level, other-device,
+                    filtered, declined, timed-out, error); =errors lists the
+                    last two only
+      --columns     Aligned table of delta, old and new date, and path, fitted
+                    to the terminal (implies -D; plain -D output when piped)
+      --escape STYLE
+                    How change lines write paths: quoted (default), never
+                    (raw bytes), octal (like ls -b) or shell
+      --itemize     One compact rsync-style line per change, e.g. >t+9y PATH (see README)
+      --output-json One JSON object per line for each change, then a summary
+                    object as the last line (see README)
+      --format-version N
+                    Keep stdout in the output format of schema version N
+                    (default: the newest this build supports)
+      --emit-script[=FILE]
+                    Write a script that makes the planned changes, instead of
+                    applying them (to stdout unless FILE is given)
+      --script-format FMT
+                    Script flavour for --emit-script: sh (default) or powershell
+      --propagate   After --copy-from or --apply-plan, re-check the stamped
+                    folders' ancestors and stamp those that now need it, up to
+                    PATH or the plan's root
+      --report-html FILE
+                    Also write a self-contained HTML report of the run to FILE
+      --report-rows N
+                    Cap the --report-html change table at N rows (default 5000)
+      --baseline FILE
+                    Only list changes that are new or different since the
+                    drift recorded in FILE
+      --update-baseline
+                    Record this run's changes in the --baseline FILE
+      --compare-to REFERENCE
+                    Expect each folder to have the mtime of the same folder
+                    under REFERENCE, instead of its newest child's
+      --metrics-file PATH
+                    Write Prometheus textfile-collector metrics for the run to PATH
+      --copy-from SRC DST
+                    Give folder DST the mtime of folder SRC instead of scanning
+                    PATH; may be repeated. A PATH is only given with
+                    --propagate, and every DST must be inside it
+      --plan FILE   Save the planned changes as JSON for review (dry run only)
+      --apply-plan FILE
+                    Apply the changes saved by --plan instead of scanning PATH
+                    (a dry run unless -C is given)
+      --plan-force  With --apply-plan, also apply changes to folders whose
+                    mtime changed since the plan was made
+      --log-file FILE
+                    Append a timestamped record of each change and error to FILE
+      --log-rotate SIZE
+                    Before the log would grow past SIZE (bytes, or with K, M
+                    or G), move it to FILE.1 and start a new one
+      --log-rotate-keep N
+                    Rotated logs to keep, FILE.1 to FILE.N (default 5)
+      --save-snapshot FILE
+                    Record the mtime of every folder under PATH in FILE
+                    (changes nothing)
+      --restore-snapshot FILE
+                    Set every folder back to its mtime in FILE (a dry run
+                    unless -C is given)
+      --journal FILE
+                    With -C, write the undo journal to FILE instead of the
+                    default location (see README)
+      --no-journal  With -C, don't write an undo journal
+      --undo JOURNAL
+                    The same as dirstamp undo JOURNAL (deprecated)
+      --undo-force  With undo, also restore folders whose mtime changed
+                    since that run
+      --otel        Export OpenTelemetry traces over OTLP/HTTP (also on when
+                    OTEL_EXPORTER_OTLP_ENDPOINT is set)
+      --pretend-time TIME
+                    Use TIME (@EPOCH_SECONDS or YYYY-MM-DD[THH:MM:SS], UTC)
+                    wherever the current time would be, for reproducible output
+      --count-dirs  At the end, print how many folders were scanned, changed
+                    or not (Scanned N directories)
+      --timing      At the end, print to stderr how long the run and each
+                    phase took, how many folder listings, metadata reads and
+                    mtime sets it made and their average time, and the slowest
+                    listing (also in the --output-json summary)
+      --extension-stats
+                    After the summary, list how many files of each extension
+                    were considered and the newest mtime among them
+      --include-type TYPE
+                    Only count children of TYPE when finding the newest: f, d,
+                    l, s, p, b or c as in find -type; may be repeated
+      --exclude-type TYPE
+                    Leave children of TYPE out when finding the newest; may be
+                    repeated
+      --touch-symlinks
+                    With --include-type l and -C, give a symlink a folder took
+                    its time from that time too, without following it
+      --oldest      Give each folder the mtime of its oldest child instead
+      --median      Give each folder the median mtime of its children (the
+                    lower middle one for an even count), so a single new file
+                    doesn't move it
+      --percentile P
+                    Give each folder the mtime at the Pth percentile (0-100) of
+                    its children's; 50 is --median, 0 --oldest, 100 the default
+      --touch-created
+                    Rank children by creation (birth) time instead of mtime,
+                    where the filesystem records it; mtime otherwise
+      --hardlink-aware
+                    Count a file with several hard links in one folder once
+                    (Unix; accepted and ignored elsewhere)
+      --ignore-dot-underscore
+                    Leave out files named ._* (macOS AppleDouble sidecars)
+                    when finding the newest
+  -q, --quiet       Don't print the end-of-run summary (changes and errors still are)
+      --summary-only
+                    Print only the end-of-run summary, with how many folders
+                    needed updating, and exit 1 if any did (for CI)
+  -V, --version     Show version information
+      --version-check
+                    Check online whether a newer release is available
+      --doctor PATH Try out timestamp handling on PATH's filesystem in a
+                    scratch folder and recommend a tolerance (--json for JSON)
+      --find-newest DIR
+                    The same as dirstamp find-newest DIR (deprecated)
+  -h, --help        Show this help message
+";
+
+/// Ordering for the buffered report (`--sort` / `--top`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Path,
+    Delta,
+}
+
+/// `--time-style`: how `-D` timestamps are written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimeStyle {
+    /// `2020-01-01 00:00:00.000000000 +0000`
+    FullIso,
+    /// `2020-01-01 00:00`
+    Iso,
+    /// `2020-01-01T00:00:00+00:00`
+    Rfc3339,
+    /// Seconds since the Unix epoch, with `digits` fractional digits.
+    Epoch { digits: u8 },
+    /// `+FORMAT`: a strftime-style format (without the `+`).
+    Custom(String),
+}
+
+/// `--escape`: how human-readable lines write paths (see src/paths.rs).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathEscape {
+    /// Double-quoted, with backslash escapes.
+    #[default]
+    Quoted,
+    /// The raw bytes, unquoted.
+    Never,
+    /// Unquoted, with `\\` and `\ooo` escapes (like `ls -b`).
+    Octal,
+    /// Quoted so a POSIX shell reads back the same path.
+    Shell,
+}
+
+/// Which skipped directories `--show-skipped` lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShowSkipped {
+    All,
+    /// Only those skipped because of an error.
+    Errors,
+}
+
+/// What `--git-changed` asks git about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GitChanged {
+    /// A commit range for `git diff`, e.g. `HEAD~1..HEAD`.
+    Range(String),
+    /// Changes not yet committed, untracked files included.
+    Worktree,
+}
+
+/// A `find -type` letter for `--exclude-type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Symlink,
+    Socket,
+    Fifo,
+    BlockDevice,
+    CharDevice,
+}
+
+impl EntryType {
+    fn from_letter(letter: &str) -> Option<Self> {
+        Some(match letter {
+            "f" => EntryType::File,
+            "d" => EntryType::Dir,
+            "l" => EntryType::Symlink,
+            "s" => EntryType::Socket,
+            "p" => EntryType::Fifo,
+            "b" => EntryType::BlockDevice,
+            "c" => EntryType::CharDevice,
+            _ => return None,
+        })
+    }
+}
+
+/// Which child's mtime a folder takes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    #[default]
+    Newest,
+    Oldest,
+    Median,
+    /// `--percentile P`, 0-100.
+    Percentile(u8),
+}
+
+/// Target shell for `--emit-script`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScriptFormat {
+    /// POSIX sh using `touch -m -d`.
+    #[default]
+    Sh,
+    /// PowerShell using `LastWriteTimeUtc`.
+    PowerShell,
+}
+
+/// Options for a normal run, as given on the command line.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub root: PathBuf,
+    pub confirm: bool,
+    pub check: bool,
+    pub strict: bool,
+    pub ignore_permission: bool,
+    pub quiet_errors: bool,
+    pub show_dates: bool,
+    pub local: bool,
+    pub epoch: bool,
+    pub epoch_ns: bool,
+    pub human_delta: bool,
+    pub time_style: Option<TimeStyle>,
+    pub sort: Option<SortKey>,
+    pub top: Option<usize>,
+    pub porcelain: bool,
+    pub itemize: bool,
+    pub columns: bool,
+    pub output_json: bool,
+    /// `--directory-filter`, unparsed (see src/filter.rs).
+    pub directory_filter: Option<String>,
+    /// `--include-device`: only walk folders on these `st_dev`s (any, if empty).
+    pub include_devices: Vec<u64>,
+    /// `--exclude-device`: never walk folders on these.
+    pub exclude_devices: Vec<u64>,
+    pub escape: PathEscape,
+    pub log_file: Option<PathBuf>,
+    /// `--log-rotate`, in bytes.
+    pub log_rotate: Option<u64>,
+    pub log_rotate_keep: Option<usize>,
+    pub save_snapshot: Option<PathBuf>,
+    pub restore_snapshot: Option<PathBuf>,
+    /// `--journal`: where `-C` records its undo journal (else the default place).
+    pub journal: Option<PathBuf>,
+    /// `--before-stamp`: a shell command that decides whether a folder is stamped.
+    pub before_stamp: Option<String>,
+    /// `--after-stamp`: a shell command run for each folder stamped.
+    pub after_stamp: Option<String>,
+    /// `--fail-fast`: a failing `--after-stamp` stops the run.
+    pub fail_fast: bool,
+    pub no_journal: bool,
+    pub extension_stats: bool,
+    pub count_dirs: bool,
+    pub timing: bool,
+    pub hardlink_aware: bool,
+    /// `--ignore-dot-underscore`: `._*` files don't count as children.
+    pub ignore_dot_underscore: bool,
+    /// `--touch-created`: children's birth times stand in for their mtimes.
+    pub touch_created: bool,
+    /// `--touch-symlinks`: a link a folder took its time from gets it too.
+    pub touch_symlinks: bool,
+    /// `--oldest` / `--median` / `--percentile` (default: the newest child).
+    pub strategy: Strategy,
+    /// `--include-type`: only children of these types count (default: files and folders).
+    pub include_types: Vec<EntryType>,
+    /// `--exclude-type`: children of these types don't count.
+    pub exclude_types: Vec<EntryType>,
+    pub verify: bool,
+    pub verify_before_apply: bool,
+    pub recompute: bool,
+    /// `--dry-run-exit-code`: exit status of a dry run with changes to make.
+    pub dry_run_exit_code: Option<u8>,
+    /// `--retries`: extra attempts after a transient I/O error.
+    pub retries: u32,
+    pub retry_delay: Option<Duration>,
+    /// `--buffered`: walk the whole tree before visiting any folder.
+    pub buffered: bool,
+    /// `--jobs`: folders worked on at once (`None`: one).
+    pub jobs: Option<usize>,
+    /// `--lockfile`: locked for the whole run.
+    pub lockfile: Option<PathBuf>,
+    pub wait_for_lock: bool,
+    /// `--max-open`: folder listings open at once.
+    pub max_open: Option<usize>,
+    /// `--rate-limit`: mtime sets per second; 0 is no limit.
+    pub rate_limit: usize,
+    /// `--find-newest`: print the child this folder would be stamped from.
+    pub find_newest: Option<PathBuf>,
+    /// Warnings for mode flags given in place of their command word, for
+    /// the caller to print.
+    pub deprecations: Vec<String>,
+    /// `--shard`: one top-level subtree at a time.
+    pub shard: bool,
+    /// `--max-memory`: rough bytes the buffered folders and report may take.
+    pub max_memory: Option<u64>,
+    /// `--cache`: folder listings kept between runs.
+    pub cache: Option<PathBuf>,
+    /// `--dir-timeout`: longest wait on one folder's reads.
+    pub dir_timeout: Option<Duration>,
+    /// `--watch`: keep restamping as filesystem events arrive.
+    pub watch: bool,
+    /// `--debounce`: how long events must settle before a watch acts.
+    pub debounce: Option<Duration>,
+    /// `--interval`: repeat the whole run this often.
+    pub interval: Option<Duration>,
+    /// `--newer-ref`: a file whose mtime folders' children must beat.
+    pub newer_ref: Option<PathBuf>,
+    /// `--no-update-top` (`--no-update-root` is 1): depths left alone.
+    pub no_update_top: usize,
+    /// `--skip-if-stamped-file`: a sentinel whose mtime marks the last stamping.
+    pub stamped_file: Option<PathBuf>,
+    /// `--git-changed`: visit only the folders git says were touched.
+    pub git_changed: Option<GitChanged>,
+    /// `--limit`: most changes a `-C` run may make.
+    pub limit: Option<usize>,
+    /// `--atomic`: all of a `-C` run's changes, or (best effort) none.
+    pub atomic: bool,
+    /// `--sparse`: judge this many folders picked at random, and stamp none.
+    pub sparse: Option<usize>,
+    /// `--interactive`: ask before each change (implies `confirm`).
+    pub interactive: bool,
+    pub undo: Option<PathBuf>,
+    pub undo_force: bool,
+    /// `--otel`: export traces even without `OTEL_EXPORTER_OTLP_ENDPOINT`.
+    pub otel: bool,
+    /// `--pretend-time`: stands in for the current time everywhere.
+    pub pretend_time: Option<SystemTime>,
+    /// `--format-version`: checked against the supported list by the report.
+    pub format_version: Option<u8>,
+    pub emit_script: bool,
+    pub script_file: Option<PathBuf>,
+    pub script_format: ScriptFormat,
+    pub plan: Option<PathBuf>,
+    pub apply_plan: Option<PathBuf>,
+    pub plan_force: bool,
+    pub quiet: bool,
+    /// `--summary-only`: no per-folder lines; exit 1 if anything changed.
+    pub summary_only: bool,
+    pub show_skipped: Option<ShowSkipped>,
+    pub clamp_to_parent: bool,
+    /// `--tree`: draw every folder with its status instead of listing changes.
+    pub tree: bool,
+    /// `--group-depth`: roll changes up to their ancestor this deep.
+    pub group_depth: Option<usize>,
+    pub verbose: bool,
+    pub report_html: Option<PathBuf>,
+    pub metrics_file: Option<PathBuf>,
+    /// Row cap for `--report-html`; `None` means the default.
+    pub report_rows: Option<usize>,
+    /// `--baseline`: hide changes this earlier run already recorded.
+    pub baseline: Option<PathBuf>,
+    pub update_baseline: bool,
+    /// `--compare-to`: take each folder's expected mtime from this tree.
+    pub compare_to: Option<PathBuf>,
+    /// `--propagate`: re-check ancestors of individually stamped folders.
+    pub propagate: bool,
+    /// `--copy-from SRC DST` pairs, in command-line order.
+    pub copy_from: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Rough cost of one buffered folder or change: its path and bookkeeping.
+pub const ENTRY_BYTES: u64 = 256;
+
+impl Config {
+    /// `--max-memory` as a count of buffered folders or changes.
+    pub fn entry_budget(&self) -> Option<usize> {
+        self.max_memory.map(|bytes| {
+            usize::try_from(bytes / ENTRY_BYTES)
+                .unwrap_or(usize::MAX)
+                .max(1)
+        })
+    }
+}
+
+/// What the command line asked for.
+#[derive(Debug)]
+pub enum Command {
+    Run(Box<Config>),
+    Help,
+    Version,
+    /// `--version-check`; `quiet` only reports when an update exists.
+    VersionCheck {
+        quiet: bool,
+    },
+    /// `--doctor PATH`; `json` prints the findings as one JSON object.
+    Doctor {
+        path: PathBuf,
+        json: bool,
+    },
+    /// `dirstamp gen-tree DIR ...` (not in `--help`): build a synthetic tree.
+    GenTree(GenTree),
+}
+
+/// `gen-tree`: the shape of the tree to build. The same options and seed
+/// always build the same tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenTree {
+    pub dir: PathBuf,
+    /// Folders below `dir`.
+    pub dirs: usize,
+    pub files_per_dir: usize,
+    /// Deepest level below `dir`; 1 puts every folder straight in it.
+    pub depth: usize,
+    pub seed: u64,
+    /// The mtimes fall in this span before 2020-01-01.
+    pub time_spread: Duration,
+    /// Replace `dir` if it exists and isn't empty.
+    pub force: bool,
+    pub quiet: bool,
+}
+
+impl Default for GenTree {
+    fn default() -> Self {
+        GenTree {
+            dir: PathBuf::new(),
+            dirs: 1_000,
+            files_per_dir: 4,
+            depth: 8,
+            seed: 0,
+            time_spread: Duration::from_secs(10 * YEAR_SECS),
+            force: false,
+            quiet: false,
+        }
+    }
+}
+
+const YEAR_SECS: u64 = 365 * 86_400;
+
+/// `gen-tree`'s arguments, after the word itself.
+fn parse_gen_tree(args: impl IntoIterator<Item = OsString>) -> Result<Command, String> {
+    let mut spec = GenTree::default();
+    let mut dir = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(text) = arg.to_str().filter(|t| t.starts_with('-')) else {
+            if dir.replace(PathBuf::from(arg)).is_some() {
+                return Err("gen-tree takes one DIR".into());
+            }
+            continue;
+        };
+        let (name, inline) = match text.split_once('=') {
+            Some((n, v)) if n.starts_with("--") => (n, Some(v)),
+            _ => (text, None),
+        };
+        let mut count = |what: &str| -> Result<usize, String> {
+            let v = take_str(name, inline, &mut args)?;
+            v.parse().map_err(|_| format!("Invalid {what}: {v}"))
+        };
+        match name {
+            "--dirs" => spec.dirs = count("--dirs")?,
+            "--files-per-dir" => spec.files_per_dir = count("--files-per-dir")?,
+            "--depth" => match count("--depth")? {
+                0 => return Err("Invalid --depth: 0 (the folders go at depth 1 or below)".into()),
+                n => spec.depth = n,
+            },
+            "--seed" => {
+                let v = take_str(name, inline, &mut args)?;
+                spec.seed = v.parse().map_err(|_| format!("Invalid --seed: {v}"))?;
+            }
+            "--time-spread" => {
+                let v = take_str(name, inline, &mut args)?;
+                spec.time_spread =
+                    parse_spread(&v).ok_or_else(|| format!("Invalid --time-spread: {v}"))?;
+            }
+            "--force" => spec.force = true,
+            "-q" | "--quiet" => spec.quiet = true,
+            _ => return Err(format!("Unknown gen-tree option: {text}")),
+        }
+    }
+    spec.dir = dir.ok_or("gen-tree needs a DIR to create")?;
+    Ok(Command::GenTree(spec))
+}
+
+/// `10y`, `30d`, `12h`, `90m` or plain seconds (`3600s`).
+fn parse_spread(v: &str) -> Option<Duration> {
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let (digits, unit) = v.split_at(split);
+    let n: u64 = digits.parse().ok()?;
+    let unit = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        "y" => YEAR_SECS,
+        _ => return None,
+    };
+    Some(Duration::from_secs(n.checked_mul(unit)?))
+}
+
+/// The command words that may come first, with what follows them; `stamp`
+/// is what no word means.
+const COMMANDS: [(&str, &str); 4] = [
+    ("stamp", "[PATH]"),
+    ("check", "[PATH]"),
+    ("find-newest", "DIR"),
+    ("undo", "JOURNAL"),
+];
+
+/// Parse the arguments (without the program name). `Err` carries a message
+/// suitable for printing before the `--help` hint.
+pub fn parse_args(args: impl IntoIterator<Item = OsString>) -> Result<Command, String> {
+    let mut cfg = Config::default();
+    let mut dry_run = false;
+    let mut utc = false;
+    let mut path_arg: Option<PathBuf> = None;
+    let mut version_check = false;
+    let mut doctor: Option<PathBuf> = None;
+    let mut json = false;
+
+    let mut end_of_opts = false;
+    let mut args = args.into_iter().peekable();
+    if args.peek().is_some_and(|a| a == "gen-tree") {
+        args.next();
+        return parse_gen_tree(args);
+    }
+    // `dirstamp check` and the rest: the same as `--check` and the rest,
+    // which are kept, with a warning.
+    let word = args
+        .next_if(|a| COMMANDS.iter().any(|(w, _)| a == w))
+        .map(|a| a.to_string_lossy().into_owned());
+    let mut mode_flags = Vec::new();
+    while let Some(arg) = args.next() {
+        if !end_of_opts && arg.to_string_lossy().starts_with('-') {
+            let Some(text) = arg.to_str() else {
+                return Err(format!("Unknown option: {}", arg.to_string_lossy()));
+            };
+            // Long options may carry their value inline: --sort=delta
+            let (name, inline) = match text.split_once('=') {
+                Some((n, v)) if n.starts_with("--") => (n, Some(v)),
+                _ => (text, None),
+            };
+            match name {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-V" | "--version" => return Ok(Command::Version),
+                "--version-check" => version_check = true,
+                "--doctor" => doctor = Some(take_value(name, inline, &mut args)?.into()),
+                "--find-newest" => {
+                    cfg.find_newest = Some(take_value(name, inline, &mut args)?.into());
+                    mode_flags.push("find-newest");
+                }
+                "--json" => json = true,
+                "-q" | "--quiet" => cfg.quiet = true,
+                "--summary-only" => cfg.summary_only = true,
+                "-C" | "--confirm" => cfg.confirm = true,
+                "--interactive" => cfg.interactive = true,
+                "--extension-stats" => cfg.extension_stats = true,
+                "--count-dirs" => cfg.count_dirs = true,
+                "--timing" => cfg.timing = true,
+                "--hardlink-aware" => cfg.hardlink_aware = true,
+                "--ignore-dot-underscore" => cfg.ignore_dot_underscore = true,
+                "--touch-created" => cfg.touch_created = true,
+                "--touch-symlinks" => cfg.touch_symlinks = true,
+                "--oldest" | "--median" | "--percentile" => {
+                    let strategy = match name {
+                        "--oldest" => Strategy::Oldest,
+                        "--median" => Strategy::Median,
+                        _ => {
+                            let v = take_str(name, inline, &mut args)?;
+                            let p = v.parse().ok().filter(|&p| p <= 100);
+                            Strategy::Percentile(
+                                p.ok_or_else(|| format!("Invalid --percentile: {v} (0-100)"))?,
+                            )
+                        }
+                    };
+                    // Repeating a flag just changes its value: the last one wins.
+                    let repeated =
+                        std::mem::discriminant(&cfg.strategy) == std::mem::discriminant(&strategy);
+                    if cfg.strategy != Strategy::Newest && !repeated {
+                        return Err(
+                            "--oldest, --median and --percentile are mutually exclusive".into()
+                        );
+                    }
+                    cfg.strategy = strategy;
+                }
+                "--include-type" | "--exclude-type" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    let kind = EntryType::from_letter(&v).ok_or_else(|| {
+                        format!("Invalid {name}: {v} (expected f, d, l, s, p, b or c)")
+                    })?;
+                    if name == "--include-type" {
+                        cfg.include_types.push(kind);
+                    } else {
+                        cfg.exclude_types.push(kind);
+                    }
+                }
+                "--verify" => cfg.verify = true,
+                "--verify-before-apply" => cfg.verify_before_apply = true,
+                "--recompute" => cfg.recompute = true,
+                "--atomic" => cfg.atomic = true,
+                "--dry-run-exit-code" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.dry_run_exit_code = Some(
+                        v.parse()
+                            .map_err(|_| format!("Invalid --dry-run-exit-code (0-255): {v}"))?,
+                    );
+                }
+                "--newer-ref" => cfg.newer_ref = Some(take_value(name, inline, &mut args)?.into()),
+                "--no-update-root" => cfg.no_update_top = cfg.no_update_top.max(1),
+                "--no-update-top" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    let n: usize = v
+                        .parse()
+                        .map_err(|_| format!("Invalid --no-update-top: {v}"))?;
+                    cfg.no_update_top = cfg.no_update_top.max(n);
+                }
+                "--skip-if-stamped-file" => {
+                    cfg.stamped_file = Some(take_value(name, inline, &mut args)?.into())
+                }
+                "--git-changed" => {
+                    cfg.git_changed = Some(match inline {
+                        None => GitChanged::Range("HEAD~1..HEAD".into()),
+                        Some("worktree") => GitChanged::Worktree,
+                        // It goes on git's command line, where it mustn't pass for an option.
+                        Some(v) if v.is_empty() || v.starts_with('-') => {
+                            return Err(format!("Invalid --git-changed range: {v}"))
+                        }
+                        Some(v) => GitChanged::Range(v.into()),
+                    })
+                }
+                "--limit" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.limit = Some(v.parse().map_err(|_| format!("Invalid --limit: {v}"))?);
+                }
+                "--sparse" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    let n = v.parse().ok().filter(|n| *n > 0);
+                    cfg.sparse = Some(n.ok_or_else(|| format!("Invalid --sparse: {v}"))?);
+                }
+                "-n" | "--dry-run" | "--no-act" => dry_run = true,
+                "--check" => {
+                    cfg.check = true;
+                    mode_flags.push("check");
+                }
+                "--ignore-permission-errors" => cfg.ignore_permission = true,
+                "--quiet-errors" => cfg.quiet_errors = true,
+                "--buffered" => cfg.buffered = true,
+                "--jobs" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    let n: usize = v.parse().map_err(|_| format!("Invalid --jobs: {v}"))?;
+                    cfg.jobs = Some(match n {
+                        0 => std::thread::available_parallelism().map_or(1, usize::from),
+                        n => n,
+                    });
+                }
+                "--lockfile" => cfg.lockfile = Some(take_value(name, inline, &mut args)?.into()),
+                "--wait-for-lock" => cfg.wait_for_lock = true,
+                "--max-open" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    match v.parse() {
+                        Ok(n) if n > 0 => cfg.max_open = Some(n),
+                        _ => return Err(format!("Invalid --max-open: {v}")),
+                    }
+                }
+                "--rate-limit" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.rate_limit = v
+                        .parse()
+                        .map_err(|_| format!("Invalid --rate-limit: {v}"))?;
+                }
+                "--shard" => cfg.shard = true,
+                "--max-memory" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.max_memory = match parse_size(&v) {
+                        Some(n) if n > 0 => Some(n),
+                        _ => return Err(format!("Invalid --max-memory size: {v}")),
+                    };
+                    cfg.shard = true;
+                }
+                "--cache" => cfg.cache = Some(take_value(name, inline, &mut args)?.into()),
+                "--retries" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.retries = v.parse().map_err(|_| format!("Invalid --retries: {v}"))?;
+                }
+                "--watch" => cfg.watch = true,
+                "--debounce" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.debounce =
+                        Some(parse_duration(&v).ok_or_else(|| format!("Invalid --debounce: {v}"))?);
+                }
+                "--interval" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.interval = Some(
+                        parse_duration(&v)
+                            .filter(|d| !d.is_zero())
+                            .ok_or_else(|| format!("Invalid --interval: {v}"))?,
+                    );
+                }
+                "--dir-timeout" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.dir_timeout = Some(
+                        parse_duration(&v)
+                            .filter(|d| !d.is_zero())
+                            .ok_or_else(|| format!("Invalid --dir-timeout: {v}"))?,
+                    );
+                }
+                "--retry-delay" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.retry_delay = Some(
+                        parse_duration(&v).ok_or_else(|| format!("Invalid --retry-delay: {v}"))?,
+                    );
+                }
+                "--strict" => cfg.strict = true,
+                "-D" | "--show-dates" => cfg.show_dates = true,
+                "--utc" => utc = true,
+                "--local" => cfg.local = true,
+                "--epoch" => cfg.epoch = true,
+                "--epoch-ns" => cfg.epoch_ns = true,
+                "--human-delta" => cfg.human_delta = true,
+                "--time-style" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.time_style = Some(match v.as_str() {
+                        "full-iso" => TimeStyle::FullIso,
+                        "iso" => TimeStyle::Iso,
+                        "rfc3339" => TimeStyle::Rfc3339,
+                        "epoch" => TimeStyle::Epoch { digits: 0 },
+                        _ if v.starts_with('+') => {
+                            strftime_description(&v[1..])?;
+                            TimeStyle::Custom(v[1..].to_string())
+                        }
+                        _ => match v.strip_prefix("epoch.").map(str::parse::<u8>) {
+                            Some(Ok(digits @ 1..=9)) => TimeStyle::Epoch { digits },
+                            _ => {
+                                return Err(format!(
+                                    "Invalid --time-style: {v} (expected full-iso, iso, \
+                                     rfc3339, epoch, epoch.N or +FORMAT)"
+                                ))
+                            }
+                        },
+                    });
+                }
+                "--sort" => {
+                    cfg.sort = match take_str(name, inline, &mut args)?.as_str() {
+                        "path" => Some(SortKey::Path),
+                        "delta" => Some(SortKey::Delta),
+                        other => {
+                            return Err(format!(
+                                "Invalid --sort key: {other} (expected path or delta)"
+                            ))
+                        }
+                    };
+                }
+                "--top" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.top = Some(v.parse().map_err(|_| format!("Invalid --top count: {v}"))?);
+                }
+                "--porcelain" => match inline {
+                    None | Some("v1") => cfg.porcelain = true,
+                    Some(v) => {
+                        return Err(format!(
+                            "Unsupported porcelain version: {v} (supported: v1)"
+                        ))
+                    }
+                },
+                "--show-skipped" => {
+                    cfg.show_skipped = match inline {
+                        None | Some("all") => Some(ShowSkipped::All),
+                        Some("errors") => Some(ShowSkipped::Errors),
+                        Some(v) => {
+                            return Err(format!(
+                                "Invalid --show-skipped filter: {v} (expected all or errors)"
+                            ))
+                        }
+                    };
+                }
+                "--itemize" => cfg.itemize = true,
+                "--escape" => {
+                    cfg.escape = match take_str(name, inline, &mut args)?.as_str() {
+                        "quoted" => PathEscape::Quoted,
+                        "never" => PathEscape::Never,
+                        "octal" => PathEscape::Octal,
+                        "shell" => PathEscape::Shell,
+                        other => {
+                            return Err(format!(
+                                "Invalid --escape style: {other} \
+                                 (expected quoted, never, octal or shell)"
+                            ))
+                        }
+                    };
+                }
+                "--columns" => cfg.columns = true,
+                "--output-json" => cfg.output_json = true,
+                "--format-version" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.format_version = Some(
+                        v.parse()
+                            .map_err(|_| format!("Invalid --format-version: {v}"))?,
+                    );
+                }
+                "--emit-script" => {
+                    cfg.emit_script = true;
+                    cfg.script_file = inline.map(PathBuf::from);
+                }
+                "--script-format" => {
+                    cfg.script_format = match take_str(name, inline, &mut args)?.as_str() {
+                        "sh" => ScriptFormat::Sh,
+                        "powershell" | "ps" => ScriptFormat::PowerShell,
+                        other => {
+                            return Err(format!(
+                                "Invalid --script-format: {other} (expected sh or powershell)"
+                            ))
+                        }
+                    };
+                }
+                "--plan" => cfg.plan = Some(take_value(name, inline, &mut args)?.into()),
+                "--apply-plan" => {
+                    cfg.apply_plan = Some(take_value(name, inline, &mut args)?.into())
+                }
+                "--plan-force" => cfg.plan_force = true,
+                "--pretend-time" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.pretend_time = Some(parse_time(&v).ok_or_else(|| {
+                        format!(
+                            "Invalid --pretend-time: {v} (expected @EPOCH_SECONDS or \
+                             YYYY-MM-DD[THH:MM:SS])"
+                        )
+                    })?);
+                }
+                "--save-snapshot" => {
+                    cfg.save_snapshot = Some(take_value(name, inline, &mut args)?.into())
+                }
+                "--restore-snapshot" => {
+                    cfg.restore_snapshot = Some(take_value(name, inline, &mut args)?.into())
+                }
+                "--journal" => cfg.journal = Some(take_value(name, inline, &mut args)?.into()),
+                "--no-journal" => cfg.no_journal = true,
+                "--before-stamp" => cfg.before_stamp = Some(take_str(name, inline, &mut args)?),
+                "--after-stamp" => cfg.after_stamp = Some(take_str(name, inline, &mut args)?),
+                "--fail-fast" => cfg.fail_fast = true,
+                "--undo" => {
+                    cfg.undo = Some(take_value(name, inline, &mut args)?.into());
+                    mode_flags.push("undo");
+                }
+                "--undo-force" => cfg.undo_force = true,
+                "--otel" => cfg.otel = true,
+                "--propagate" => cfg.propagate = true,
+                "--clamp-to-parent" => cfg.clamp_to_parent = true,
+                "--directory-filter" => {
+                    cfg.directory_filter = Some(take_str(name, inline, &mut args)?)
+                }
+                "--include-device" | "--exclude-device" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    let dev = v
+                        .parse()
+                        .map_err(|_| format!("Invalid device number for {name}: {v}"))?;
+                    if name == "--include-device" {
+                        cfg.include_devices.push(dev);
+                    } else {
+                        cfg.exclude_devices.push(dev);
+                    }
+                }
+                "--tree" => cfg.tree = true,
+                "--group-depth" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.group_depth = Some(
+                        v.parse()
+                            .map_err(|_| format!("Invalid --group-depth: {v}"))?,
+                    );
+                }
+                "-v" | "--verbose" => cfg.verbose = true,
+                "--report-html" => {
+                    cfg.report_html = Some(take_value(name, inline, &mut args)?.into())
+                }
+                "--baseline" => cfg.baseline = Some(take_value(name, inline, &mut args)?.into()),
+                "--update-baseline" => cfg.update_baseline = true,
+                "--metrics-file" => {
+                    cfg.metrics_file = Some(take_value(name, inline, &mut args)?.into())
+                }
+                "--report-rows" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.report_rows = Some(
+                        v.parse()
+                            .map_err(|_| format!("Invalid --report-rows count: {v}"))?,
+                    );
+                }
+                "--log-file" => cfg.log_file = Some(take_value(name, inline, &mut args)?.into()),
+                "--log-rotate" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.log_rotate = Some(
+                        parse_size(&v).ok_or_else(|| format!("Invalid --log-rotate size: {v}"))?,
+                    );
+                }
+                "--log-rotate-keep" => {
+                    let v = take_str(name, inline, &mut args)?;
+                    cfg.log_rotate_keep = match v.parse() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("Invalid --log-rotate-keep count: {v}")),
+                    };
+                }
+                "--compare-to" => {
+                    cfg.compare_to = Some(take_value(name, inline, &mut args)?.into())
+                }
+                "--copy-from" => {
+                    let src = take_value(name, inline, &mut args)?;
+                    let dst = args
+                        .next()
+                        .ok_or_else(|| format!("{name} requires SRC and DST"))?;
+                    cfg.copy_from.push((src.into(), dst.into()));
+                }
+                "--" => end_of_opts = true, // everything after is positional
+                _ => return Err(format!("Unknown option: {text}")),
+            }
+            continue;
+        }
+
+        // Positional PATH handling (first one wins)
+        if path_arg.is_none() {
+            path_arg = Some(PathBuf::from(arg));
+        } else {
+            return Err(format!(
+                "Unexpected extra argument: {}",
+                arg.to_string_lossy()
+            ));
+        }
+    }
+
+    for flag in mode_flags {
+        match word.as_deref() {
+            Some(w) if w == flag => {}
+            Some(w) => return Err(format!("dirstamp {w} can't be combined with --{flag}")),
+            None => {
+                let (_, rest) = COMMANDS
+                    .iter()
+                    .find(|(w, _)| *w == flag)
+                    .expect("a command");
+                cfg.deprecations.push(format!(
+                    "--{flag} is deprecated; use dirstamp {flag} {rest}"
+                ));
+            }
+        }
+    }
+    match word.as_deref() {
+        Some("check") => cfg.check = true,
+        Some("find-newest") if cfg.find_newest.is_none() => {
+            cfg.find_newest = Some(path_arg.take().ok_or("find-newest needs a DIR")?);
+        }
+        Some("undo") if cfg.undo.is_none() => {
+            let journal = path_arg
+                .take()
+                .ok_or("undo needs the JOURNAL of the run to revert")?;
+            cfg.undo = Some(journal);
+        }
+        _ => {}
+    }
+    if version_check {
+        return Ok(Command::VersionCheck { quiet: cfg.quiet });
+    }
+    if let Some(path) = doctor {
+        return Ok(Command::Doctor { path, json });
+    }
+    if json {
+        return Err("--json only applies to --doctor; a run's JSON is --output-json".into());
+    }
+    if cfg.find_newest.is_some() {
+        if path_arg.is_some() {
+            return Err("--find-newest reads just its DIR; don't give a PATH".into());
+        }
+        if cfg.confirm || cfg.interactive || cfg.check {
+            return Err(
+                "--find-newest only reads; it can't be combined with -C, --interactive or --check"
+                    .into(),
+            );
+        }
+    }
+    if cfg.interactive {
+        if dry_run
+            || cfg.check
+            || cfg.emit_script
+            || cfg.plan.is_some()
+            || cfg.save_snapshot.is_some()
+        {
+            return Err(
+                "--interactive can't be combined with --dry-run, --check, --emit-script, \
+                        --plan or --save-snapshot"
+                    .into(),
+            );
+        }
+        cfg.confirm = true;
+    }
+    if dry_run && cfg.confirm {
+        return Err("--dry-run and --confirm are mutually exclusive".into());
+    }
+    if cfg.check && cfg.confirm {
+        return Err("--check and --confirm are mutually exclusive".into());
+    }
+    if cfg.emit_script && (cfg.confirm || cfg.check) {
+        return Err("--emit-script can't be combined with --confirm or --check".into());
+    }
+    if cfg.plan.is_some() && (cfg.confirm || cfg.apply_plan.is_some()) {
+        return Err("--plan can't be combined with --confirm or --apply-plan".into());
+    }
+    if cfg.undo.is_some() {
+        if path_arg.is_some() {
+            return Err("--undo takes the folders from the journal; don't give a PATH".into());
+        }
+        if cfg.check
+            || cfg.plan.is_some()
+            || cfg.apply_plan.is_some()
+            || !cfg.copy_from.is_empty()
+            || cfg.compare_to.is_some()
+        {
+            return Err(
+                "--undo can't be combined with --check, --plan, --apply-plan, \
+                        --copy-from or --compare-to"
+                    .into(),
+            );
+        }
+    }
+    let sources = [
+        cfg.save_snapshot.is_some(),
+        cfg.restore_snapshot.is_some(),
+        cfg.undo.is_some(),
+        cfg.apply_plan.is_some(),
+        !cfg.copy_from.is_empty(),
+        cfg.compare_to.is_some(),
+    ];
+    if (cfg.save_snapshot.is_some() || cfg.restore_snapshot.is_some())
+        && (sources.iter().filter(|s| **s).count() > 1 || cfg.plan.is_some() || cfg.check)
+    {
+        return Err(
+            "--save-snapshot and --restore-snapshot can't be combined with each \
+                    other, --check, --plan, --apply-plan, --undo, --copy-from or --compare-to"
+                .into(),
+        );
+    }
+    if cfg.save_snapshot.is_some() && (cfg.confirm || cfg.emit_script) {
+        return Err(
+            "--save-snapshot changes nothing; don't give --confirm or --emit-script".into(),
+        );
+    }
+    if cfg.undo_force && cfg.undo.is_none() {
+        return Err("--undo-force only makes sense with --undo".into());
+    }
+    if cfg.journal.is_some() && cfg.no_journal {
+        return Err("--journal and --no-journal are mutually exclusive".into());
+    }
+    if cfg.summary_only {
+        let lines = [
+            (cfg.check, "--check"),
+            (cfg.quiet, "--quiet"),
+            (cfg.dry_run_exit_code.is_some(), "--dry-run-exit-code"),
+            (cfg.show_skipped.is_some(), "--show-skipped"),
+            (cfg.porcelain, "--porcelain"),
+            (cfg.itemize, "--itemize"),
+            (cfg.output_json, "--output-json"),
+            (
+                cfg.emit_script && cfg.script_file.is_none(),
+                "--emit-script",
+            ),
+            (cfg.tree, "--tree"),
+            (cfg.interactive, "--interactive"),
+            (cfg.watch, "--watch"),
+            (cfg.interval.is_some(), "--interval"),
+        ];
+        if let Some((_, flag)) = lines.iter().find(|(given, _)| *given) {
+            return Err(format!("--summary-only can't be combined with {flag}"));
+        }
+    }
+    if (cfg.verify_before_apply || cfg.recompute) && !cfg.confirm {
+        return Err("--verify-before-apply and --recompute only make sense with --confirm".into());
+    }
+    if cfg.update_baseline && cfg.baseline.is_none() {
+        return Err("--update-baseline only makes sense with --baseline".into());
+    }
+    if cfg.verify && !cfg.confirm {
+        return Err("--verify only makes sense with --confirm".into());
+    }
+    if cfg.atomic && !cfg.confirm {
+        return Err("--atomic only makes sense with --confirm".into());
+    }
+    if cfg.atomic
+        && (cfg.apply_plan.is_some()
+            || cfg.undo.is_some()
+            || !cfg.copy_from.is_empty()
+            || cfg.restore_snapshot.is_some()
+            || cfg.compare_to.is_some()
+            || cfg.watch)
+    {
+        return Err(
+            "--atomic only applies to a single walk of PATH, not to --apply-plan, --undo, \
+             --copy-from, --restore-snapshot, --compare-to or --watch"
+                .into(),
+        );
+    }
+    if cfg.atomic && cfg.jobs.is_some() {
+        return Err("--atomic sets folders one at a time; don't give --jobs".into());
+    }
+    if cfg.atomic && cfg.shard {
+        return Err(
+            "--atomic plans the whole tree at once; it can't be combined with --shard".into(),
+        );
+    }
+    if cfg.retry_delay.is_some() && cfg.retries == 0 {
+        return Err("--retry-delay only makes sense with --retries".into());
+    }
+    if (cfg.stamped_file.is_some() || cfg.newer_ref.is_some() || cfg.no_update_top > 0)
+        && (cfg.apply_plan.is_some()
+            || cfg.undo.is_some()
+            || !cfg.copy_from.is_empty()
+            || cfg.compare_to.is_some()
+            || cfg.save_snapshot.is_some()
+            || cfg.restore_snapshot.is_some())
+    {
+        let name = if cfg.stamped_file.is_some() {
+            "--skip-if-stamped-file"
+        } else if cfg.newer_ref.is_some() {
+            "--newer-ref"
+        } else {
+            "--no-update-top"
+        };
+        return Err(format!("{name} only applies to a scan of PATH"));
+    }
+    if cfg.jobs.is_some_and(|n| n > 1) && cfg.dir_timeout.is_some() {
+        return Err("--jobs can't be combined with --dir-timeout".into());
+    }
+    if cfg.debounce.is_some() && !cfg.watch {
+        return Err("--debounce only makes sense with --watch".into());
+    }
+    let one_pass = [
+        (cfg.check, "--check"),
+        (cfg.plan.is_some(), "--plan"),
+        (cfg.emit_script, "--emit-script"),
+        (cfg.interactive, "--interactive"),
+        (cfg.tree, "--tree"),
+        (cfg.sort.is_some() || cfg.top.is_some(), "--sort or --top"),
+        (cfg.limit.is_some(), "--limit"),
+        (
+            cfg.apply_plan.is_some() || cfg.undo.is_some(),
+            "--apply-plan or --undo",
+        ),
+        (
+            !cfg.copy_from.is_empty() || cfg.compare_to.is_some(),
+            "--copy-from or --compare-to",
+        ),
+        (
+            cfg.save_snapshot.is_some() || cfg.restore_snapshot.is_some(),
+            "--save-snapshot or --restore-snapshot",
+        ),
+        (cfg.git_changed.is_some(), "--git-changed"),
+        (cfg.stamped_file.is_some(), "--skip-if-stamped-file"),
+    ];
+    if cfg.fail_fast && cfg.after_stamp.is_none() {
+        return Err(
+            "--fail-fast needs --after-stamp: it stops at the first hook that fails".into(),
+        );
+    }
+    let links_count = cfg.include_types.contains(&EntryType::Symlink)
+        && !cfg.exclude_types.contains(&EntryType::Symlink);
+    if cfg.touch_symlinks && !links_count {
+        return Err(
+            "--touch-symlinks needs --include-type l: otherwise a symlink is never the child a folder takes its time from"
+                .into(),
+        );
+    }
+    if cfg.sparse.is_some() {
+        let whole = [
+            (cfg.confirm, "-C"),
+            (cfg.watch || cfg.interval.is_some(), "--watch or --interval"),
+            (cfg.summary_only, "--summary-only"),
+            (cfg.shard || cfg.cache.is_some(), "--shard or --cache"),
+            (cfg.jobs.is_some_and(|n| n > 1), "--jobs"),
+            (
+                cfg.porcelain || cfg.output_json || cfg.itemize,
+                "--porcelain, --output-json or --itemize",
+            ),
+        ];
+        if let Some((_, flag)) = one_pass.iter().chain(&whole).find(|(given, _)| *given) {
+            return Err(format!(
+                "--sparse samples the tree without stamping it; it can't be combined with {flag}"
+            ));
+        }
+    }
+    let repeating = if cfg.watch {
+        Some("--watch")
+    } else if cfg.interval.is_some() {
+        Some("--interval")
+    } else {
+        None
+    };
+    if let Some(repeating) = repeating {
+        if let Some((_, flag)) = one_pass.iter().find(|(given, _)| *given) {
+            return Err(format!("{repeating} can't be combined with {flag}"));
+        }
+    }
+    if cfg.interval.is_some() && cfg.watch {
+        return Err("--interval and --watch are mutually exclusive".into());
+    }
+    if cfg.interval.is_some() && (cfg.porcelain || cfg.output_json) {
+        return Err("--interval prints plain summary lines; it can't be combined with --porcelain or --output-json".into());
+    }
+    if cfg.count_dirs && (cfg.porcelain || cfg.output_json) {
+        return Err("--count-dirs prints a plain line; it can't be combined with --porcelain or --output-json".into());
+    }
+    if cfg.git_changed.is_some()
+        && (cfg.apply_plan.is_some()
+            || cfg.undo.is_some()
+            || !cfg.copy_from.is_empty()
+            || cfg.save_snapshot.is_some()
+            || cfg.restore_snapshot.is_some())
+    {
+        return Err("--git-changed only applies to a scan of PATH".into());
+    }
+    if cfg.limit.is_some() && cfg.check {
+        return Err("--limit and --check are mutually exclusive".into());
+    }
+    if cfg.apply_plan.is_some() && cfg.check {
+        return Err("--apply-plan and --check are mutually exclusive".into());
+    }
+    if cfg.apply_plan.is_some() && path_arg.is_some() {
+        return Err("--apply-plan takes the folders from the plan; don't give a PATH".into());
+    }
+    if !cfg.copy_from.is_empty() {
+        // The PATH only bounds --propagate's climb; nothing is scanned.
+        if path_arg.is_some() && !cfg.propagate {
+            return Err("--copy-from takes a PATH only with --propagate".into());
+        }
+        if path_arg.is_none() && cfg.propagate {
+            return Err(
+                "--propagate with --copy-from needs a PATH: the tree to climb no higher than"
+                    .into(),
+            );
+        }
+        if cfg.plan.is_some() || cfg.apply_plan.is_some() {
+            return Err("--copy-from can't be combined with --plan or --apply-plan".into());
+        }
+    }
+    if cfg.compare_to.is_some() && (cfg.apply_plan.is_some() || !cfg.copy_from.is_empty()) {
+        return Err("--compare-to can't be combined with --apply-plan or --copy-from".into());
+    }
+    if cfg.itemize && cfg.porcelain {
+        return Err("--itemize and --porcelain are mutually exclusive".into());
+    }
+    if cfg.columns && (cfg.itemize || cfg.porcelain) {
+        return Err("--columns can't be combined with --itemize or --porcelain".into());
+    }
+    if cfg.group_depth.is_some() && cfg.porcelain {
+        return Err("--group-depth and --porcelain are mutually exclusive".into());
+    }
+    if cfg.output_json && (cfg.porcelain || cfg.itemize || cfg.columns || cfg.group_depth.is_some())
+    {
+        return Err(
+            "--output-json can't be combined with --porcelain, --itemize, --columns or --group-depth"
+                .into(),
+        );
+    }
+    if cfg.output_json
+        && (cfg.interactive
+            || cfg.extension_stats
+            || (cfg.emit_script && cfg.script_file.is_none()))
+    {
+        return Err(
+            "--output-json can't share stdout with --interactive, --extension-stats or --emit-script"
+                .into(),
+        );
+    }
+    if cfg.tree && cfg.confirm {
+        return Err("--tree is a dry run; it can't be combined with -C or --interactive".into());
+    }
+    if cfg.tree
+        && (cfg.porcelain
+            || cfg.output_json
+            || cfg.itemize
+            || cfg.columns
+            || cfg.group_depth.is_some())
+    {
+        return Err(
+            "--tree can't be combined with --porcelain, --output-json, --itemize, --columns \
+             or --group-depth"
+                .into(),
+        );
+    }
+    if cfg.propagate && cfg.copy_from.is_empty() && cfg.apply_plan.is_none() {
+        return Err("--propagate only applies to --copy-from and --apply-plan".into());
+    }
+    if cfg.shard {
+        let whole = [
+            (cfg.git_changed.is_some(), "--git-changed"),
+            (cfg.cache.is_some(), "--cache"),
+            (cfg.apply_plan.is_some(), "--apply-plan"),
+            (cfg.undo.is_some(), "--undo"),
+            (!cfg.copy_from.is_empty(), "--copy-from"),
+            (cfg.compare_to.is_some(), "--compare-to"),
+            (cfg.save_snapshot.is_some(), "--save-snapshot"),
+            (cfg.restore_snapshot.is_some(), "--restore-snapshot"),
+        ];
+        if let Some((_, flag)) = whole.iter().find(|(given, _)| *given) {
+            return Err(format!(
+                "--shard only applies to a walk of PATH; it can't be combined with {flag}"
+            ));
+        }
+    }
+    if cfg.cache.is_some() {
+        let walked = [
+            (cfg.buffered, "--buffered"),
+            (cfg.jobs.is_some_and(|n| n > 1), "--jobs"),
+            (cfg.limit.is_some(), "--limit"),
+            (cfg.git_changed.is_some(), "--git-changed"),
+            (cfg.dir_timeout.is_some(), "--dir-timeout"),
+            (cfg.apply_plan.is_some(), "--apply-plan"),
+            (cfg.undo.is_some(), "--undo"),
+            (!cfg.copy_from.is_empty(), "--copy-from"),
+            (cfg.compare_to.is_some(), "--compare-to"),
+            (cfg.save_snapshot.is_some(), "--save-snapshot"),
+            (cfg.restore_snapshot.is_some(), "--restore-snapshot"),
+        ];
+        if let Some((_, flag)) = walked.iter().find(|(given, _)| *given) {
+            return Err(format!(
+                "--cache only applies to a streamed scan of PATH; it can't be combined with {flag}"
+            ));
+        }
+    }
+    if cfg.wait_for_lock && cfg.lockfile.is_none() {
+        return Err("--wait-for-lock only makes sense with --lockfile".into());
+    }
+    if cfg.log_rotate.is_some() && cfg.log_file.is_none() {
+        return Err("--log-rotate only makes sense with --log-file".into());
+    }
+    if cfg.log_rotate_keep.is_some() && cfg.log_rotate.is_none() {
+        return Err("--log-rotate-keep only makes sense with --log-rotate".into());
+    }
+    if cfg.plan_force && cfg.apply_plan.is_none() {
+        return Err("--plan-force only makes sense with --apply-plan".into());
+    }
+    if utc && cfg.local {
+        return Err("--utc and --local are mutually exclusive".into());
+    }
+    if cfg.time_style.is_some() && (cfg.epoch || cfg.epoch_ns) {
+        return Err("--time-style can't be combined with --epoch or --epoch-ns".into());
+    }
+    if cfg.epoch && cfg.epoch_ns {
+        return Err("--epoch and --epoch-ns are mutually exclusive".into());
+    }
+
+    cfg.root = path_arg.unwrap_or_else(|| PathBuf::from("."));
+    Ok(Command::Run(Box::new(cfg)))
+}
+
+/// `10MB`, `512K`, `1G` or plain bytes; the suffixes are powers of 1024 and
+/// may be followed by `B` or `iB`.
+fn parse_size(v: &str) -> Option<u64> {
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let (digits, unit) = v.split_at(split);
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// `500ms`, `2s`, `1m` or plain seconds.
+fn parse_duration(v: &str) -> Option<Duration> {
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let (digits, unit) = v.split_at(split);
+    let n: u64 = digits.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(n)),
+        "" | "s" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n.checked_mul(60)?)),
+        _ => None,
+    }
+}
+
+/// `@1577836800`, `2020-01-01` or `2020-01-01T12:30:00` (optionally with a
+/// trailing `Z` or a space for the `T`), always UTC.
+fn parse_time(v: &str) -> Option<SystemTime> {
+    if let Some(secs) = v.strip_prefix('@') {
+        return SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs.parse().ok()?));
+    }
+    let v = v.strip_suffix('Z').unwrap_or(v);
+    let (date, clock) = match v.split_once(['T', ' ']) {
+        Some((date, clock)) => (date, Some(clock)),
+        None => (v, None),
+    };
+    let num = |s: &str, max: u64| s.parse::<u64>().ok().filter(|n| *n <= max);
+    let mut ymd = date.splitn(3, '-');
+    let year = ymd
+        .next()
+        .filter(|y| y.len() == 4)
+        .and_then(|y| num(y, 9999))?;
+    let month = ymd.next().and_then(|m| num(m, 12)).filter(|m| *m > 0)?;
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let month_len = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    let day = ymd
+        .next()
+        .and_then(|d| num(d, month_len))
+        .filter(|d| *d > 0)?;
+    let secs_of_day = match clock {
+        None => 0,
+        Some(clock) => {
+            let parts: Vec<&str> = clock.split(':').collect();
+            let (h, m, s) = match parts[..] {
+                [h, m] => (h, m, "0"),
+                [h, m, s] => (h, m, s),
+                _ => return None,
+            };
+            num(h, 23)? * 3_600 + num(m, 59)? * 60 + num(s, 59)?
+        }
+    };
+    // Days since 1970-01-01 in the proleptic Gregorian calendar.
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era_days = 365 * y + y / 4 - y / 100 + y / 400 + (153 * m + 2) / 5 + day - 1;
+    let days = era_days.checked_sub(719_468)?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(days * 86_400 + secs_of_day))
+}
+
+/// Fetch the value of a `--flag VALUE` / `--flag=VALUE` option.
+fn take_value(
+    flag: &str,
+    inline: Option<&str>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<OsString, String> {
+    match inline {
+        Some(v) => Ok(v.into()),
+        None => args
+            .next()
+            .ok_or_else(|| format!("{flag} requires a value")),
+    }
+}
+
+/// Like `take_value`, for options whose value must be text.
+fn take_str(
+    flag: &str,
+    inline: Option<&str>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<String, String> {
+    take_value(flag, inline, args)?
+        .into_string()
+        .map_err(|v| format!("Invalid value for {flag}: {}", v.to_string_lossy()))
+}
+
+/// Translate a strftime-style format (GNU `date +FORMAT`) into a `time`
+/// format description. `[` and `]` are literal but must pair up, and a
+/// bracketed `time` component such as `[year]` is refused, so a mistyped
+/// format fails here rather than printing brackets in place of every date.
+pub fn strftime_description(f: &str) -> Result<String, String> {
+    const COMPONENTS: &[&str] = &[
+        "year",
+        "month",
+        "day",
+        "hour",
+        "minute",
+        "second",
+        "subsecond",
+        "weekday",
+        "ordinal",
+        "period",
+        "offset_hour",
+        "offset_minute",
+        "offset_second",
+        "unix_timestamp",
+        "week_number",
+        "ignore",
+        "end",
+    ];
+    let mut out = String::new();
+    let mut open = 0usize;
+    let mut chars = f.chars();
+    while let Some(c) = chars.next() {
+        if c == '[' {
+            let inner = chars.as_str().split(']').next().unwrap_or_default();
+            let word = inner.split_whitespace().next().unwrap_or_default();
+            if COMPONENTS.contains(&word) {
+                return Err(format!(
+                    "Invalid --time-style format: [{inner}] is not strftime (use % codes such as %Y)"
+                ));
+            }
+            open += 1;
+            out.push_str("[[");
+            continue;
+        }
+        if c == ']' {
+            if open == 0 {
+                return Err("Invalid --time-style format: ] without an opening [".into());
+            }
+            open -= 1;
+            out.push(c);
+            continue;
+        }
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let colon = chars.clone().next() == Some(':');
+        if colon {
+            chars.next();
+        }
+        let spec = chars.next();
+        let item = match (spec, colon) {
+            (Some('z'), true) => "[offset_hour sign:mandatory]:[offset_minute]",
+            (_, true) => return Err("Invalid --time-style format: only %:z takes a colon".into()),
+            (Some('Y'), _) => "[year]",
+            (Some('y'), _) => "[year repr:last_two]",
+            (Some('m'), _) => "[month]",
+            (Some('b' | 'h'), _) => "[month repr:short]",
+            (Some('B'), _) => "[month repr:long]",
+            (Some('d'), _) => "[day]",
+            (Some('e'), _) => "[day padding:space]",
+            (Some('j'), _) => "[ordinal]",
+            (Some('a'), _) => "[weekday repr:short]",
+            (Some('A'), _) => "[weekday]",
+            (Some('H'), _) => "[hour]",
+            (Some('I'), _) => "[hour repr:12]",
+            (Some('p'), _) => "[period]",
+            (Some('M'), _) => "[minute]",
+            (Some('S'), _) => "[second]",
+            (Some('N'), _) => "[subsecond digits:9]",
+            (Some('z'), _) => "[offset_hour sign:mandatory][offset_minute]",
+            (Some('s'), _) => "[unix_timestamp]",
+            (Some('F'), _) => "[year]-[month]-[day]",
+            (Some('T'), _) => "[hour]:[minute]:[second]",
+            (Some('R'), _) => "[hour]:[minute]",
+            (Some('n'), _) => "\n",
+            (Some('t'), _) => "\t",
+            (Some('%'), _) => "%",
+            (Some(other), _) => {
+                return Err(format!("Invalid --time-style format: unsupported %{other}"))
+            }
+            (None, _) => return Err("Invalid --time-style format: trailing %".into()),
+        };
+        out.push_str(item);
+    }
+    if open > 0 {
+        return Err("Invalid --time-style format: [ without a closing ]".into());
+    }
+    Ok(out)
+}
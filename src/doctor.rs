@@ -0,0 +1,255 @@
+// src/doctor.rs
+// --doctor PATH: find out by experiment how a filesystem keeps timestamps
+// before trusting dirstamp with it. Everything happens on a scratch folder
+// inside PATH, which is removed again however the probes end.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use filetime::{set_file_atime, set_file_mtime, FileTime};
+use serde::Serialize;
+
+/// Candidate resolutions, finest first: POSIX, NTFS, exFAT's 10ms, whole
+/// seconds, FAT's two.
+const STEPS: [Duration; 7] = [
+    Duration::from_nanos(1),
+    Duration::from_nanos(100),
+    Duration::from_micros(1),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+];
+
+/// Tolerance to recommend when the granularity couldn't be worked out.
+const FALLBACK_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// What the probes found. Serialized as-is for `--json`.
+#[derive(Serialize)]
+pub struct Findings {
+    pub path: PathBuf,
+    /// An mtime on a whole, even second read back exactly as set.
+    pub round_trip: bool,
+    /// Finest step the read-back mtimes stay on; `None` if none of `STEPS` fit.
+    pub granularity_ns: Option<u64>,
+    pub creation_time_readable: bool,
+    pub creation_time_settable: bool,
+    pub pre_1970_kept: bool,
+    pub post_2038_kept: bool,
+    /// Whether listing a folder moved its atime; `None` if atimes can't be read.
+    pub read_updates_atime: Option<bool>,
+    /// How far a timestamp read back may be off what was set.
+    pub recommended_tolerance_ns: u64,
+}
+
+/// Removes the scratch folder when dropped, so a failed probe leaves nothing behind.
+struct Scratch(PathBuf);
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run every probe in a scratch folder under `path`. `Err` means the
+/// probes couldn't be run at all (no scratch folder, no mtime written).
+pub fn examine(path: &Path) -> io::Result<Findings> {
+    let scratch = Scratch(path.join(format!(".dirstamp-doctor-{}", std::process::id())));
+    fs::create_dir(&scratch.0)?;
+    // A folder, since folders' mtimes are what dirstamp sets.
+    let probe = scratch.0.join("probe");
+    fs::create_dir(&probe)?;
+
+    let whole = at(1_577_880_000, 0); // 2020-01-01T12:00:00Z
+    set_mtime(&probe, whole)?;
+    let round_trip = read_mtime(&probe)? == whole;
+
+    let granularity = granularity(&probe)?;
+    let tolerance = granularity.unwrap_or(FALLBACK_TOLERANCE);
+    let kept = |t: SystemTime| {
+        set_mtime(&probe, t).is_ok() && read_mtime(&probe).is_ok_and(|got| off(t, got) <= tolerance)
+    };
+    let pre_1970_kept = kept(at(-14_182_940, 0)); // 1969-07-20
+    let post_2038_kept = kept(at(2_208_988_800, 0)); // 2040-01-01
+
+    let creation_time_readable = fs::metadata(&probe).and_then(|m| m.created()).is_ok();
+    let creation_time_settable = creation_time_readable
+        && set_created(&probe, whole).is_ok()
+        && fs::metadata(&probe)
+            .and_then(|m| m.created())
+            .is_ok_and(|got| off(whole, got) <= tolerance);
+
+    Ok(Findings {
+        path: path.to_path_buf(),
+        round_trip,
+        granularity_ns: granularity.map(|g| g.as_nanos() as u64),
+        creation_time_readable,
+        creation_time_settable,
+        pre_1970_kept,
+        post_2038_kept,
+        read_updates_atime: read_updates_atime(&scratch.0),
+        recommended_tolerance_ns: tolerance.as_nanos() as u64,
+    })
+}
+
+/// `secs` (negative before 1970) plus `nanos` after the epoch.
+fn at(secs: i64, nanos: u32) -> SystemTime {
+    let whole = Duration::from_secs(secs.unsigned_abs());
+    let base = if secs < 0 {
+        UNIX_EPOCH - whole
+    } else {
+        UNIX_EPOCH + whole
+    };
+    base + Duration::from_nanos(nanos.into())
+}
+
+fn set_mtime(path: &Path, t: SystemTime) -> io::Result<()> {
+    set_file_mtime(path, FileTime::from_system_time(t))
+}
+
+fn read_mtime(path: &Path) -> io::Result<SystemTime> {
+    fs::metadata(path)?.modified()
+}
+
+fn off(a: SystemTime, b: SystemTime) -> Duration {
+    a.duration_since(b).unwrap_or_else(|e| e.duration())
+}
+
+/// Set mtimes that need every digit kept, one rounding down and one up,
+/// and find the finest step that explains what comes back.
+fn granularity(dir: &Path) -> io::Result<Option<Duration>> {
+    let mut readings = Vec::new();
+    for probe in [
+        at(1_600_000_001, 123_456_789),
+        at(1_600_000_003, 987_654_321),
+    ] {
+        set_mtime(dir, probe)?;
+        readings.push((probe, read_mtime(dir)?));
+    }
+    Ok(STEPS
+        .into_iter()
+        .find(|&step| readings.iter().all(|&(set, got)| fits(set, got, step))))
+}
+
+/// Whether `got` lies on a multiple of `step` less than a step from `set`.
+fn fits(set: SystemTime, got: SystemTime, step: Duration) -> bool {
+    got.duration_since(UNIX_EPOCH)
+        .is_ok_and(|since| since.as_nanos() % step.as_nanos() == 0)
+        && off(set, got) < step
+}
+
+/// Windows and macOS can set a folder's creation time; elsewhere it's read-only.
+#[cfg(any(windows, target_vendor = "apple"))]
+fn set_created(path: &Path, t: SystemTime) -> io::Result<()> {
+    #[cfg(target_vendor = "apple")]
+    use std::os::darwin::fs::FileTimesExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::{FileTimesExt, OpenOptionsExt};
+
+    let mut open = fs::OpenOptions::new();
+    // A folder opens read-only on macOS; Windows needs it opened for
+    // writing, with FILE_FLAG_BACKUP_SEMANTICS.
+    #[cfg(target_vendor = "apple")]
+    open.read(true);
+    #[cfg(windows)]
+    open.write(true).custom_flags(0x0200_0000);
+    open.open(path)?
+        .set_times(fs::FileTimes::new().set_created(t))
+}
+
+#[cfg(not(any(windows, target_vendor = "apple")))]
+fn set_created(_path: &Path, _t: SystemTime) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Age the folder's atime below its mtime (so relatime would refresh it),
+/// list the folder, and see whether the atime moved.
+fn read_updates_atime(dir: &Path) -> Option<bool> {
+    let old = at(1_577_836_800, 0); // 2020-01-01
+    set_file_atime(dir, FileTime::from_system_time(old)).ok()?;
+    let before = fs::metadata(dir).and_then(|m| m.accessed()).ok()?;
+    fs::read_dir(dir).ok()?.for_each(drop);
+    let after = fs::metadata(dir).and_then(|m| m.accessed()).ok()?;
+    Some(after != before)
+}
+
+fn yes_no(ok: bool, yes: &str, no: &str) -> String {
+    if ok { yes } else { no }.to_string()
+}
+
+impl fmt::Display for Findings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let granularity = match self.granularity_ns {
+            Some(ns) => format!("{:?}", Duration::from_nanos(ns)),
+            None => "unknown (read-back mtimes fit no common resolution)".into(),
+        };
+        let creation = match (self.creation_time_readable, self.creation_time_settable) {
+            (false, _) => "not available".into(),
+            (true, settable) => yes_no(settable, "readable and settable", "readable, not settable"),
+        };
+        let atime = match self.read_updates_atime {
+            Some(true) => "updates its atime",
+            Some(false) => "leaves its atime alone",
+            None => "atime couldn't be checked",
+        };
+        let rows = [
+            (
+                "mtime round-trip",
+                yes_no(self.round_trip, "ok", "FAILED (read back differently)"),
+            ),
+            ("mtime granularity", granularity),
+            ("creation time", creation),
+            (
+                "mtime before 1970",
+                yes_no(self.pre_1970_kept, "kept", "not kept"),
+            ),
+            (
+                "mtime after 2038",
+                yes_no(self.post_2038_kept, "kept", "not kept"),
+            ),
+            ("listing a folder", atime.into()),
+        ];
+        writeln!(f, "Timestamp check of {}:", self.path.display())?;
+        for (what, result) in rows {
+            writeln!(f, "  {what:<19} {result}")?;
+        }
+        write!(
+            f,
+            "Recommended tolerance: {:?}",
+            Duration::from_nanos(self.recommended_tolerance_ns)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_explain_coarse_read_backs() {
+        let set = at(1_600_000_001, 123_456_789);
+        assert!(fits(set, set, Duration::from_nanos(1)));
+        // FAT: truncated to the even second below.
+        let fat = at(1_600_000_000, 0);
+        let step = STEPS.into_iter().find(|&s| fits(set, fat, s));
+        assert_eq!(step, Some(Duration::from_secs(2)));
+        assert!(!fits(set, at(1_599_999_998, 0), Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn probes_a_temp_dir_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let findings = examine(dir.path()).unwrap();
+        assert!(findings.round_trip);
+        assert!(findings.granularity_ns.is_some());
+        assert_eq!(
+            Some(findings.recommended_tolerance_ns),
+            findings.granularity_ns
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert!(findings.to_string().contains("Recommended tolerance: "));
+    }
+}
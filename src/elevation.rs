@@ -0,0 +1,12 @@
+// src/elevation.rs
+// Windows: some folders (under Program Files, other users' profiles) only
+// take a new mtime from an elevated process. A -C run that isn't elevated
+// says so once, before it starts, so the set failures that may follow
+// don't need explaining one by one.
+
+/// Whether the process has administrator rights (is elevated, under UAC).
+pub fn is_admin() -> bool {
+    // SAFETY: IsUserAnAdmin takes no arguments and only looks at the
+    // process token.
+    unsafe { windows_sys::Win32::UI::Shell::IsUserAnAdmin() != 0 }
+}
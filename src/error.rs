@@ -0,0 +1,149 @@
+// src/error.rs
+// What can go wrong in a library run. A root that can't be stamped at all
+// ends the run with one of these; a single folder that can't be read or set
+// doesn't, and is one of the report's `errors` instead. Options that don't
+// make sense together are turned down before any run starts.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Serialized with each `io::Error` as its message, which is read back as
+/// an `ErrorKind::Other` with that message.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirstampError {
+    /// A root that doesn't exist.
+    RootNotFound(PathBuf),
+    /// A root that is something other than a directory.
+    NotADirectory(PathBuf),
+    /// The walk couldn't get into `path` (or couldn't look at a root).
+    Walk {
+        path: PathBuf,
+        #[serde(with = "message")]
+        source: io::Error,
+    },
+    /// A folder's own mtime couldn't be read.
+    ReadMtime {
+        path: PathBuf,
+        #[serde(with = "message")]
+        source: io::Error,
+    },
+    /// A folder's children couldn't be listed or looked at.
+    ReadDir {
+        path: PathBuf,
+        #[serde(with = "message")]
+        source: io::Error,
+    },
+    /// A folder couldn't be given its new mtime.
+    SetMtime {
+        path: PathBuf,
+        #[serde(with = "message")]
+        source: io::Error,
+    },
+}
+
+impl DirstampError {
+    /// The root or folder it is about.
+    pub fn path(&self) -> &Path {
+        match self {
+            DirstampError::RootNotFound(path) | DirstampError::NotADirectory(path) => path,
+            DirstampError::Walk { path, .. }
+            | DirstampError::ReadMtime { path, .. }
+            | DirstampError::ReadDir { path, .. }
+            | DirstampError::SetMtime { path, .. } => path,
+        }
+    }
+
+    /// The root's own error for a root that can't be looked at: missing,
+    /// not a folder, or unreadable.
+    pub(crate) fn root(path: &Path, source: io::Error) -> Self {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => DirstampError::RootNotFound(path),
+            io::ErrorKind::NotADirectory => DirstampError::NotADirectory(path),
+            _ => DirstampError::Walk { path, source },
+        }
+    }
+}
+
+impl fmt::Display for DirstampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DirstampError::RootNotFound(path) => write!(f, "{} does not exist", path.display()),
+            DirstampError::NotADirectory(path) => {
+                write!(f, "{} is not a directory", path.display())
+            }
+            DirstampError::Walk { path, source } => {
+                write!(f, "cannot walk {}: {source}", path.display())
+            }
+            DirstampError::ReadMtime { path, source } => {
+                write!(f, "cannot read the mtime of {}: {source}", path.display())
+            }
+            DirstampError::ReadDir { path, source } => {
+                write!(f, "cannot list {}: {source}", path.display())
+            }
+            DirstampError::SetMtime { path, source } => {
+                write!(f, "cannot set the mtime of {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for DirstampError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DirstampError::RootNotFound(_) | DirstampError::NotADirectory(_) => None,
+            DirstampError::Walk { source, .. }
+            | DirstampError::ReadMtime { source, .. }
+            | DirstampError::ReadDir { source, .. }
+            | DirstampError::SetMtime { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Why `OptionsBuilder::build` turned the options down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionsError {
+    /// No root to stamp.
+    NoRoots,
+    /// `inner` is `outer` or lies inside it.
+    NestedRoots { outer: PathBuf, inner: PathBuf },
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::NoRoots => f.write_str("no root to stamp"),
+            OptionsError::NestedRoots { outer, inner } if outer == inner => {
+                write!(f, "{} is given twice", outer.display())
+            }
+            OptionsError::NestedRoots { outer, inner } => write!(
+                f,
+                "{} is inside {}, so its folders would be stamped twice",
+                inner.display(),
+                outer.display()
+            ),
+        }
+    }
+}
+
+impl Error for OptionsError {}
+
+/// An `io::Error` as its message.
+mod message {
+    use std::io;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(error: &io::Error, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(error)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<io::Error, D::Error> {
+        String::deserialize(d).map(io::Error::other)
+    }
+}
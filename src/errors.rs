@@ -0,0 +1,144 @@
+// src/errors.rs
+// Error categories and the grouped end-of-run error summary.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many example paths each category keeps for the summary.
+const MAX_EXAMPLES: usize = 3;
+/// How many top-level folders each category lists before "and N more".
+const MAX_TOP_LEVEL: usize = 5;
+
+/// Broad error classes used to group the end-of-run summary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorCategory {
+    PermissionDenied,
+    NotFound,
+    SetMtime,
+    /// `--dir-timeout` expired.
+    TimedOut,
+    Other,
+}
+
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 5] = [
+        ErrorCategory::PermissionDenied,
+        ErrorCategory::NotFound,
+        ErrorCategory::SetMtime,
+        ErrorCategory::TimedOut,
+        ErrorCategory::Other,
+    ];
+
+    /// Category for a walk / read failure of the given kind.
+    pub fn classify(kind: Option<io::ErrorKind>) -> Self {
+        match kind {
+            Some(io::ErrorKind::PermissionDenied) => ErrorCategory::PermissionDenied,
+            Some(io::ErrorKind::NotFound) => ErrorCategory::NotFound,
+            _ => ErrorCategory::Other,
+        }
+    }
+
+    /// Stable machine-readable name (metrics labels).
+    pub fn key(self) -> &'static str {
+        match self {
+            ErrorCategory::PermissionDenied => "permission_denied",
+            ErrorCategory::NotFound => "not_found",
+            ErrorCategory::SetMtime => "set_mtime",
+            ErrorCategory::TimedOut => "timed_out",
+            ErrorCategory::Other => "other",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ErrorCategory::PermissionDenied => "permission denied",
+            ErrorCategory::NotFound => "not found (probably deleted mid-run)",
+            ErrorCategory::SetMtime => "failed to set mtime",
+            ErrorCategory::TimedOut => "timed out (--dir-timeout)",
+            ErrorCategory::Other => "other errors",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Group {
+    count: usize,
+    examples: Vec<PathBuf>,
+    /// Errors per top-level folder below the root.
+    top_level: BTreeMap<PathBuf, usize>,
+}
+
+/// Errors collected during a run, grouped by category.
+pub struct ErrorLog {
+    root: PathBuf,
+    groups: BTreeMap<ErrorCategory, Group>,
+}
+
+impl ErrorLog {
+    pub fn new(root: &Path) -> Self {
+        ErrorLog {
+            root: root.to_path_buf(),
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, category: ErrorCategory, path: Option<&Path>) {
+        let group = self.groups.entry(category).or_default();
+        group.count += 1;
+        let Some(path) = path else { return };
+        if group.examples.len() < MAX_EXAMPLES {
+            group.examples.push(path.to_path_buf());
+        }
+        let top = match path.strip_prefix(&self.root) {
+            Ok(rel) => match rel.components().next() {
+                Some(first) => self.root.join(first),
+                None => self.root.clone(),
+            },
+            Err(_) => path.to_path_buf(),
+        };
+        *group.top_level.entry(top).or_default() += 1;
+    }
+
+    pub fn count(&self, category: ErrorCategory) -> usize {
+        self.groups.get(&category).map_or(0, |g| g.count)
+    }
+
+    pub fn total(&self) -> usize {
+        self.groups.values().map(|g| g.count).sum()
+    }
+
+    /// `(label, count, example paths)` per category, in category order.
+    pub fn groups(&self) -> impl Iterator<Item = (&'static str, usize, &[PathBuf])> {
+        self.groups
+            .iter()
+            .map(|(c, g)| (c.label(), g.count, g.examples.as_slice()))
+    }
+
+    /// Print the grouped breakdown to stderr.
+    pub fn print_summary(&self) {
+        for (category, group) in &self.groups {
+            eprintln!("  {}: {}", category.label(), group.count);
+            if !group.top_level.is_empty() {
+                let mut under: Vec<String> = group
+                    .top_level
+                    .iter()
+                    .take(MAX_TOP_LEVEL)
+                    .map(|(p, n)| format!("{:?} ({n})", p))
+                    .collect();
+                if group.top_level.len() > MAX_TOP_LEVEL {
+                    under.push(format!(
+                        "and {} more",
+                        group.top_level.len() - MAX_TOP_LEVEL
+                    ));
+                }
+                eprintln!("    under {}", under.join(", "));
+            }
+            if !group.examples.is_empty() {
+                let examples: Vec<String> =
+                    group.examples.iter().map(|p| format!("{:?}", p)).collect();
+                eprintln!("    e.g. {}", examples.join(", "));
+            }
+        }
+    }
+}
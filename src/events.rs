@@ -0,0 +1,33 @@
+// src/events.rs
+// Progress for embedders: what `run`, `plan` and `apply` find and do, as it
+// happens, instead of only in the report at the end. The binary's own
+// printer is fed the same way (`report::ReportEvents`).
+
+use std::path::Path;
+
+use crate::change::{Change, SkipReason, Status};
+use crate::error::DirstampError;
+
+/// Told about each step of a run, synchronously, on the thread that called
+/// `run_with`, `plan_with` or `apply_with`, in the order things happen: no
+/// two calls overlap, so an implementation needs no locking and doesn't
+/// have to be `Sync`. A slow one slows the run down. Every method does
+/// nothing unless overridden.
+///
+/// A folder is scanned once, then either changed or skipped. In a real run
+/// a change is seen twice: `Planned` while the tree is planned, then
+/// `Applied` or `Failed` once `apply` gets to it, children first.
+pub trait Events {
+    /// `path` has been looked at; `on_change` or `on_skipped` follows.
+    fn on_scanned(&self, _path: &Path) {}
+    fn on_change(&self, _change: &Change, _status: Status) {}
+    fn on_skipped(&self, _path: &Path, _reason: SkipReason) {}
+    /// Comes before the `on_skipped` (or `Failed` change) it explains. A
+    /// place the walk couldn't get into has only this.
+    fn on_error(&self, _error: &DirstampError) {}
+}
+
+/// Wants to hear about nothing: what `run`, `plan` and `apply` use.
+pub struct NoEvents;
+
+impl Events for NoEvents {}
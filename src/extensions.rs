@@ -0,0 +1,112 @@
+// src/extensions.rs
+// --extension-stats: how many files of each extension the child scans looked
+// at, and the newest mtime among them, to show which file types drive the
+// folder timestamps.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::report::DateFormat;
+
+/// Shown for files without an extension (including dotfiles like `.bashrc`).
+const NO_EXTENSION: &str = "(none)";
+
+#[derive(Default)]
+pub struct ExtensionStats {
+    /// `.ext` → (files considered, newest mtime).
+    by_extension: HashMap<String, (usize, SystemTime)>,
+}
+
+impl ExtensionStats {
+    /// Count one file child seen by a scan.
+    pub fn add(&mut self, name: &OsStr, mtime: SystemTime) {
+        let key = match Path::new(name).extension() {
+            Some(ext) => format!(".{}", ext.to_string_lossy()),
+            None => NO_EXTENSION.to_string(),
+        };
+        let slot = self.by_extension.entry(key).or_insert((0, mtime));
+        slot.0 += 1;
+        slot.1 = slot.1.max(mtime);
+    }
+
+    /// Most files first, then by extension.
+    fn rows(&self) -> Vec<(&str, usize, SystemTime)> {
+        let mut rows: Vec<_> = self
+            .by_extension
+            .iter()
+            .map(|(ext, &(files, newest))| (ext.as_str(), files, newest))
+            .collect();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        rows
+    }
+
+    /// The table, header included, with `dates` rendering the mtimes.
+    pub fn render(&self, dates: &DateFormat) -> String {
+        const HEADER: [&str; 3] = ["Extension", "Files considered", "Newest mtime"];
+        let rows: Vec<(&str, String, String)> = self
+            .rows()
+            .into_iter()
+            .map(|(ext, files, newest)| (ext, files.to_string(), dates.render(newest)))
+            .collect();
+        let ext_width = rows
+            .iter()
+            .map(|r| r.0.chars().count())
+            .chain([HEADER[0].len()])
+            .max()
+            .unwrap_or(0);
+        let files_width = HEADER[1].len();
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<ext_width$} | {} | {}",
+            HEADER[0], HEADER[1], HEADER[2]
+        );
+        for (ext, files, newest) in &rows {
+            let _ = writeln!(out, "{ext:<ext_width$} | {files:>files_width$} | {newest}");
+        }
+        out
+    }
+
+    pub fn print(&self, dates: &DateFormat) {
+        if self.by_extension.is_empty() {
+            println!("No files were considered.");
+        } else {
+            print!("{}", self.render(dates));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn counts_and_newest_per_extension() {
+        let mut stats = ExtensionStats::default();
+        stats.add(OsStr::new("main.rs"), at(100));
+        stats.add(OsStr::new("lib.rs"), at(1_710_504_000));
+        stats.add(OsStr::new("README"), at(50));
+        stats.add(OsStr::new(".bashrc"), at(60));
+        stats.add(OsStr::new("photo.JPG"), at(70));
+
+        assert_eq!(
+            stats.render(&DateFormat::utc()),
+            "\
+Extension | Files considered | Newest mtime
+(none)    |                2 | 1970-01-01 00:01:00 UTC
+.rs       |                2 | 2024-03-15 12:00:00 UTC
+.JPG      |                1 | 1970-01-01 00:01:10 UTC
+"
+        );
+    }
+}
@@ -0,0 +1,367 @@
+// src/filter.rs
+// --directory-filter: a small boolean expression language over a directory's
+// attributes, e.g. `depth >= 2 AND children > 3 AND delta_days > 7`.
+// Parsed once at startup; self-contained so it can be tested on its own.
+
+/// What an expression can look at for one directory.
+pub struct DirAttrs<'a> {
+    /// Levels below the root (the root itself is 0).
+    pub depth: usize,
+    /// Number of immediate children (files, folders and anything else).
+    pub children: usize,
+    /// |mtime change| in days.
+    pub delta_days: f64,
+    /// Base name as raw bytes, so names that aren't UTF-8 still compare exactly.
+    pub name: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Attr {
+    Depth,
+    Children,
+    DeltaDays,
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Ge,
+    Le,
+    Gt,
+    Lt,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Number(Attr, Op, f64),
+    /// Compared byte for byte.
+    Name(Op, Vec<u8>),
+}
+
+/// A parsed `--directory-filter` expression.
+#[derive(Debug, PartialEq)]
+pub struct DirFilter {
+    expr: Expr,
+}
+
+impl DirFilter {
+    /// `Err` describes the first problem found.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let tokens = tokenize(src)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(DirFilter { expr }),
+            Some(t) => Err(format!("unexpected {t} after the end of the expression")),
+        }
+    }
+
+    /// Whether evaluating needs `children` (which costs a directory read).
+    pub fn uses_children(&self) -> bool {
+        fn walk(e: &Expr) -> bool {
+            match e {
+                Expr::And(a, b) | Expr::Or(a, b) => walk(a) || walk(b),
+                Expr::Not(a) => walk(a),
+                Expr::Number(attr, ..) => *attr == Attr::Children,
+                Expr::Name(..) => false,
+            }
+        }
+        walk(&self.expr)
+    }
+
+    pub fn matches(&self, dir: &DirAttrs) -> bool {
+        fn eval(e: &Expr, dir: &DirAttrs) -> bool {
+            match e {
+                Expr::And(a, b) => eval(a, dir) && eval(b, dir),
+                Expr::Or(a, b) => eval(a, dir) || eval(b, dir),
+                Expr::Not(a) => !eval(a, dir),
+                Expr::Number(attr, op, want) => {
+                    let have = match attr {
+                        Attr::Depth => dir.depth as f64,
+                        Attr::Children => dir.children as f64,
+                        Attr::DeltaDays => dir.delta_days,
+                        Attr::Name => unreachable!("names compare as text"),
+                    };
+                    match op {
+                        Op::Ge => have >= *want,
+                        Op::Le => have <= *want,
+                        Op::Gt => have > *want,
+                        Op::Lt => have < *want,
+                        Op::Eq => have == *want,
+                        Op::Ne => have != *want,
+                    }
+                }
+                Expr::Name(op, want) => (dir.name == want.as_slice()) == (*op == Op::Eq),
+            }
+        }
+        eval(&self.expr, dir)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Text(Vec<u8>),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "'{w}'"),
+            Token::Number(n) => write!(f, "'{n}'"),
+            Token::Text(s) => write!(f, "string {:?}", String::from_utf8_lossy(s)),
+            Token::Op(op) => write!(f, "'{}'", op_text(*op)),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+        }
+    }
+}
+
+fn op_text(op: Op) -> &'static str {
+    match op {
+        Op::Ge => ">=",
+        Op::Le => "<=",
+        Op::Gt => ">",
+        Op::Lt => "<",
+        Op::Eq => "==",
+        Op::Ne => "!=",
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let next_is = |chars: &mut std::iter::Peekable<std::str::CharIndices>, want| {
+            chars.next_if(|&(_, c)| c == want).is_some()
+        };
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '>' if next_is(&mut chars, '=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '<' if next_is(&mut chars, '=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '=' if next_is(&mut chars, '=') => Token::Op(Op::Eq),
+            '!' if next_is(&mut chars, '=') => Token::Op(Op::Ne),
+            // `\xHH` writes a raw byte, for names that aren't UTF-8; `\` before
+            // anything else takes that character literally.
+            '"' | '\'' => {
+                let mut text = Vec::new();
+                loop {
+                    match chars.next() {
+                        Some((_, q)) if q == c => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'x')) => {
+                                let hex: String = (0..2)
+                                    .filter_map(|_| chars.next())
+                                    .map(|(_, h)| h)
+                                    .collect();
+                                let byte = u8::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .filter(|_| hex.len() == 2)
+                                    .ok_or_else(|| format!("invalid escape '\\x{hex}'"))?;
+                                text.push(byte);
+                            }
+                            Some((_, e)) => {
+                                text.extend_from_slice(e.encode_utf8(&mut [0; 4]).as_bytes())
+                            }
+                            None => return Err("unterminated string".into()),
+                        },
+                        Some((_, ch)) => {
+                            text.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes())
+                        }
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+                Token::Text(text)
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, _)) = chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.') {
+                    end = i + 1;
+                }
+                let text = &src[start..end];
+                Token::Number(
+                    text.parse()
+                        .map_err(|_| format!("invalid number '{text}'"))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                    end = i + c.len_utf8();
+                }
+                Token::Word(src[start..end].to_string())
+            }
+            other => return Err(format!("unexpected character '{other}' at offset {start}")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Precedence, loosest first: OR, AND, NOT, then comparisons and parentheses.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek_keyword("OR") {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.not()?;
+        while self.peek_keyword("AND") {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.peek_keyword("NOT") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let inner = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(inner),
+                    Some(t) => Err(format!("expected ')' but found {t}")),
+                    None => Err("missing ')'".into()),
+                }
+            }
+            Some(Token::Word(w)) => {
+                let attr = match w.to_ascii_lowercase().as_str() {
+                    "depth" => Attr::Depth,
+                    "children" => Attr::Children,
+                    "delta_days" => Attr::DeltaDays,
+                    "name" => Attr::Name,
+                    _ => {
+                        return Err(format!(
+                            "unknown attribute '{w}' (expected depth, children, delta_days or name)"
+                        ))
+                    }
+                };
+                let op = match self.next() {
+                    Some(Token::Op(op)) => op,
+                    Some(t) => return Err(format!("expected a comparison after '{w}', found {t}")),
+                    None => return Err(format!("expected a comparison after '{w}'")),
+                };
+                match (attr, self.next()) {
+                    (Attr::Name, Some(Token::Text(s))) if matches!(op, Op::Eq | Op::Ne) => {
+                        Ok(Expr::Name(op, s))
+                    }
+                    (Attr::Name, Some(Token::Text(_))) => {
+                        Err(format!("name only supports == and !=, not {}", op_text(op)))
+                    }
+                    (Attr::Name, _) => Err("name must be compared with a quoted string".into()),
+                    (_, Some(Token::Number(n))) => Ok(Expr::Number(attr, op, n)),
+                    (_, Some(t)) => Err(format!("expected a number after '{w}', found {t}")),
+                    (_, None) => Err(format!("expected a number after '{w}'")),
+                }
+            }
+            Some(t) => Err(format!("expected an attribute or '(' but found {t}")),
+            None => Err("expression ends too early".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(depth: usize, children: usize, delta_days: f64, name: &str) -> DirAttrs<'_> {
+        DirAttrs {
+            depth,
+            children,
+            delta_days,
+            name: name.as_bytes(),
+        }
+    }
+
+    fn matches(src: &str, d: &DirAttrs) -> bool {
+        DirFilter::parse(src).unwrap().matches(d)
+    }
+
+    #[test]
+    fn evaluates_with_precedence() {
+        let d = dir(2, 4, 10.0, "Rome");
+        assert!(matches(
+            "depth >= 2 AND children > 3 AND delta_days > 7",
+            &d
+        ));
+        assert!(!matches("depth >= 3 AND children > 3", &d));
+        // AND binds tighter than OR; NOT tighter than AND.
+        assert!(matches("depth == 9 OR depth == 2 AND children == 4", &d));
+        assert!(!matches(
+            "(depth == 9 OR depth == 2) AND NOT children == 4",
+            &d
+        ));
+        assert!(matches("name == 'Rome' and not name != \"Rome\"", &d));
+        assert!(matches("delta_days < 10.5", &d));
+        let latin1 = DirAttrs {
+            name: b"caf\xe9",
+            ..dir(1, 0, 0.0, "")
+        };
+        assert!(matches(r"name == 'caf\xE9'", &latin1));
+    }
+
+    #[test]
+    fn only_reads_children_when_asked() {
+        assert!(DirFilter::parse("NOT (children > 3)")
+            .unwrap()
+            .uses_children());
+        assert!(!DirFilter::parse("depth > 3").unwrap().uses_children());
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        for (src, want) in [
+            ("depth >", "expected a number"),
+            ("size > 3", "unknown attribute"),
+            ("name > 'a'", "only supports == and !="),
+            ("depth > 1 AND", "ends too early"),
+            ("(depth > 1", "missing ')'"),
+            ("depth > 1 depth", "unexpected 'depth'"),
+            ("depth = 1", "unexpected character '='"),
+            ("name == 'x", "unterminated"),
+        ] {
+            let err = DirFilter::parse(src).unwrap_err();
+            assert!(err.contains(want), "{src}: {err}");
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// src/gentree.rs
+// `dirstamp gen-tree DIR`: build a synthetic tree to benchmark and test
+// against. Everything comes from the seed: which folder each new one goes
+// in, and every file's and folder's mtime, so a seed and the options
+// reproduce a tree exactly, on any machine. The mtimes are spread back from
+// a fixed date rather than from now for the same reason.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use filetime::{set_file_mtime, FileTime};
+
+use crate::cli::GenTree;
+use crate::rng::Rng;
+
+/// 2020-01-01 00:00:00 UTC: the newest mtime a tree can have.
+const END: u64 = 1_577_836_800;
+
+/// Left next to DIR, as `.DIR.dirstamp-gen-tree`, so that `--force` only
+/// ever replaces a tree made here. Outside the tree, so it can't be a
+/// folder's child and skew the stamps measured on it.
+pub const MARKER: &str = ".dirstamp-gen-tree";
+
+/// How often `generate` reports progress, in folders.
+pub const PROGRESS_EVERY: usize = 10_000;
+
+/// What `generate` made.
+pub struct Generated {
+    pub dirs: usize,
+    pub files: usize,
+}
+
+/// One generated folder: its parent's index (the root is 0) and its depth.
+struct Node {
+    parent: usize,
+    depth: usize,
+}
+
+/// Build the tree `spec` describes, calling `progress` with the folders
+/// made so far every `PROGRESS_EVERY`.
+pub fn generate(spec: &GenTree, mut progress: impl FnMut(usize)) -> io::Result<Generated> {
+    prepare(&spec.dir, spec.force)?;
+    let mut rng = Rng::new(spec.seed);
+
+    // The shape first: each folder goes in one picked at random from those
+    // with room below them.
+    let mut nodes = vec![Node {
+        parent: 0,
+        depth: 0,
+    }];
+    let mut open = vec![0];
+    for i in 1..=spec.dirs {
+        let parent = open[rng.below(open.len() as u64) as usize];
+        let depth = nodes[parent].depth + 1;
+        nodes.push(Node { parent, depth });
+        if depth < spec.depth {
+            open.push(i);
+        }
+    }
+
+    let spread = spec.time_spread.as_secs();
+    let mut time = || {
+        let secs = END - rng.below(spread.min(END) + 1);
+        FileTime::from_system_time(UNIX_EPOCH + Duration::from_secs(secs))
+    };
+    let mut files = 0;
+    for i in 0..nodes.len() {
+        let dir = path_of(&spec.dir, &nodes, i);
+        if i > 0 {
+            fs::create_dir(&dir)?;
+        }
+        for j in 0..spec.files_per_dir {
+            let file = dir.join(format!("f{j}"));
+            fs::write(&file, b"")?;
+            set_file_mtime(&file, time())?;
+            files += 1;
+        }
+        if i > 0 && i % PROGRESS_EVERY == 0 {
+            progress(i);
+        }
+    }
+    // Folders last, since filling them set their mtimes to now.
+    for i in 0..nodes.len() {
+        set_file_mtime(path_of(&spec.dir, &nodes, i), time())?;
+    }
+    Ok(Generated {
+        dirs: spec.dirs,
+        files,
+    })
+}
+
+/// Make `dir` ready: created if missing, left if empty, and with `force`
+/// replaced if it holds an earlier generated tree.
+fn prepare(dir: &Path, force: bool) -> io::Result<()> {
+    let empty = match fs::read_dir(dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e),
+    };
+    if !empty {
+        if !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "not empty; use --force to replace an earlier generated tree",
+            ));
+        }
+        if !marker_of(dir)?.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("not a generated tree (no {MARKER} beside it); --force won't remove it"),
+            ));
+        }
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+    fs::write(marker_of(dir)?, b"")
+}
+
+/// `.NAME.dirstamp-gen-tree` in the folder holding `dir`.
+fn marker_of(dir: &Path) -> io::Result<PathBuf> {
+    let dir = std::path::absolute(dir)?;
+    let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "needs a folder with a parent to leave its marker in",
+        ));
+    };
+    let mut marker = std::ffi::OsString::from(".");
+    marker.push(name);
+    marker.push(MARKER);
+    Ok(parent.join(marker))
+}
+
+fn path_of(root: &Path, nodes: &[Node], mut i: usize) -> PathBuf {
+    let mut names = Vec::new();
+    while i > 0 {
+        names.push(format!("d{i}"));
+        i = nodes[i].parent;
+    }
+    let mut path = root.to_path_buf();
+    path.extend(names.iter().rev());
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::SystemTime;
+
+    use walkdir::WalkDir;
+
+    fn listing(dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+        WalkDir::new(dir)
+            .sort_by_file_name()
+            .into_iter()
+            .map(|e| {
+                let e = e.unwrap();
+                let rel = e.path().strip_prefix(dir).unwrap().to_path_buf();
+                (rel, e.metadata().unwrap().modified().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_seed_builds_the_same_tree_every_time() {
+        let tmp = tempfile::tempdir().unwrap();
+        let spec = |dir: &str, seed| GenTree {
+            dir: tmp.path().join(dir),
+            dirs: 50,
+            files_per_dir: 2,
+            depth: 3,
+            seed,
+            ..GenTree::default()
+        };
+        let made = generate(&spec("a", 7), |_| {}).unwrap();
+        assert_eq!((made.dirs, made.files), (50, 102));
+        generate(&spec("b", 7), |_| {}).unwrap();
+        generate(&spec("c", 8), |_| {}).unwrap();
+        let a = listing(&tmp.path().join("a"));
+        assert_eq!(a, listing(&tmp.path().join("b")));
+        assert_ne!(a, listing(&tmp.path().join("c")));
+        // 50 folders, 102 files and the root itself.
+        assert_eq!(a.len(), 153);
+        assert!(tmp.path().join(".a.dirstamp-gen-tree").exists());
+        assert!(a.iter().all(|(rel, _)| rel.components().count() <= 4));
+
+        // Only a generated tree is replaced, and only with --force.
+        assert!(generate(&spec("a", 7), |_| {}).is_err());
+        let forced = GenTree {
+            force: true,
+            ..spec("a", 8)
+        };
+        generate(&forced, |_| {}).unwrap();
+        assert_eq!(
+            listing(&tmp.path().join("a")),
+            listing(&tmp.path().join("c"))
+        );
+        fs::write(tmp.path().join("d"), b"").unwrap();
+        let other = GenTree {
+            dir: tmp.path().to_path_buf(),
+            force: true,
+            ..GenTree::default()
+        };
+        assert!(generate(&other, |_| {}).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+// src/git.rs
+// --git-changed: ask git which files a commit range (or the work tree)
+// touched, and turn them into the folders to visit instead of walking the
+// whole tree.
+
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::cli::GitChanged;
+
+/// The folders under `root` holding a file `changed` names, and their
+/// ancestors, spelled as the walk would (`root` joined with the rest).
+/// Only existing folders are returned, in no particular order. `Err` is a
+/// message for the user.
+pub fn changed_dirs(root: &Path, changed: &GitChanged) -> Result<Vec<PathBuf>, String> {
+    let out = run(root, &["rev-parse", "--show-toplevel"])?;
+    if !out.status.success() {
+        return Err(format!("{} is not in a git work tree", root.display()));
+    }
+    let top = PathBuf::from(String::from_utf8_lossy(&out.stdout).trim_end());
+
+    let mut files = Vec::new();
+    match changed {
+        GitChanged::Range(range) => files.extend(git(
+            &top,
+            &["diff", "--name-only", "--no-renames", "-z", range],
+        )?),
+        GitChanged::Worktree => {
+            files.extend(git(
+                &top,
+                &["diff", "--name-only", "--no-renames", "-z", "HEAD"],
+            )?);
+            files.push(0);
+            files.extend(git(
+                &top,
+                &["ls-files", "--others", "--exclude-standard", "-z"],
+            )?);
+        }
+    }
+
+    let canonical = |p: &Path| fs::canonicalize(p).map_err(|e| format!("{}: {e}", p.display()));
+    let (top, canonical_root) = (canonical(&top)?, canonical(root)?);
+    let mut dirs = BTreeSet::new();
+    for name in files.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let file = top.join(path_from_bytes(name));
+        for dir in file.ancestors().skip(1) {
+            let Ok(rel) = dir.strip_prefix(&canonical_root) else {
+                break;
+            };
+            if !dirs.insert(rel.to_path_buf()) {
+                break; // The rest of the way up is in already.
+            }
+        }
+    }
+    Ok(dirs
+        .into_iter()
+        .map(|rel| {
+            if rel.as_os_str().is_empty() {
+                root.to_path_buf()
+            } else {
+                root.join(rel)
+            }
+        })
+        .filter(|dir| dir.is_dir())
+        .collect())
+}
+
+fn run(dir: &Path, args: &[&str]) -> Result<Output, String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("can't run git ({e})"))
+}
+
+/// Run git in `dir`, returning its stdout. `Err` carries git's complaint.
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    let out = run(dir, args)?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(format!("git {}: {}", args.join(" "), stderr.trim()));
+    }
+    Ok(out.stdout)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(name: &[u8]) -> &Path {
+    use std::os::unix::ffi::OsStrExt;
+    Path::new(OsStr::from_bytes(name))
+}
+
+/// git prints UTF-8 paths outside Unix.
+#[cfg(not(unix))]
+fn path_from_bytes(name: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).into_owned())
+}
@@ -0,0 +1,124 @@
+// src/handles.rs
+// --max-open: how many folder listings may hold a directory handle at once,
+// across the walk, the scans and the --jobs workers. A listing past the
+// bound waits for one to finish instead of failing with "Too many open
+// files". The descriptor limit is also raised as far as the system lets an
+// unprivileged process, at startup.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// `--max-open` unless given, or half the descriptor limit if that's lower.
+pub const DEFAULT_MAX_OPEN: usize = 256;
+
+static LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_OPEN);
+static OPEN: Mutex<usize> = Mutex::new(0);
+static CLOSED: Condvar = Condvar::new();
+
+pub fn set_limit(max_open: usize) {
+    LIMIT.store(max_open.max(1), Ordering::Relaxed);
+}
+
+pub fn limit() -> usize {
+    LIMIT.load(Ordering::Relaxed)
+}
+
+/// The bound for a run that didn't give `--max-open`, with the process
+/// allowed `nofile` descriptors (if known). The rest are left for the
+/// journal, the log, walkdir's own handles and the standard streams.
+pub fn default_limit(nofile: Option<u64>) -> usize {
+    let half = nofile.map_or(DEFAULT_MAX_OPEN, |n| {
+        usize::try_from(n / 2).unwrap_or(usize::MAX)
+    });
+    half.clamp(1, DEFAULT_MAX_OPEN)
+}
+
+/// A listing's claim on a handle; let go when dropped.
+pub struct Open(());
+
+/// Wait until one more listing fits under the bound.
+pub fn open() -> Open {
+    let mut open = OPEN.lock().unwrap_or_else(|e| e.into_inner());
+    while *open >= limit() {
+        open = CLOSED.wait(open).unwrap_or_else(|e| e.into_inner());
+    }
+    *open += 1;
+    Open(())
+}
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        let mut open = OPEN.lock().unwrap_or_else(|e| e.into_inner());
+        *open -= 1;
+        CLOSED.notify_one();
+    }
+}
+
+/// Raise the soft descriptor limit to the hard one where permitted, and
+/// return the soft limit in force afterwards.
+#[cfg(unix)]
+pub fn raise_nofile_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit for getrlimit to fill in.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    if limit.rlim_cur < limit.rlim_max {
+        let raised = libc::rlimit {
+            rlim_cur: limit.rlim_max,
+            rlim_max: limit.rlim_max,
+        };
+        // SAFETY: as above; a refusal (macOS caps it below an infinite hard
+        // limit) leaves the old limit in force.
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            limit.rlim_cur = limit.rlim_max;
+        }
+    }
+    // rlim_t is u64 here, but signed on some BSDs.
+    #[allow(clippy::useless_conversion)]
+    let soft = u64::try_from(limit.rlim_cur).unwrap_or(u64::MAX);
+    Some(soft)
+}
+
+/// Windows has no per-process descriptor limit to speak of.
+#[cfg(not(unix))]
+pub fn raise_nofile_limit() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn listings_past_the_bound_wait_their_turn() {
+        assert_eq!(default_limit(Some(64)), 32);
+        assert_eq!(default_limit(Some(1 << 20)), DEFAULT_MAX_OPEN);
+        assert_eq!(default_limit(None), DEFAULT_MAX_OPEN);
+
+        set_limit(2);
+        let most = Mutex::new((0, 0)); // (now, highest)
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _open = open();
+                    {
+                        let mut most = most.lock().unwrap();
+                        most.0 += 1;
+                        most.1 = most.1.max(most.0);
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                    most.lock().unwrap().0 -= 1;
+                });
+            }
+        });
+        assert_eq!(most.into_inner().unwrap().1, 2);
+        set_limit(DEFAULT_MAX_OPEN);
+    }
+}
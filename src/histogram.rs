@@ -0,0 +1,103 @@
+// src/histogram.rs
+// Drift histogram for the end-of-run summary: how far the changed folders'
+// mtimes move, in a few fixed buckets.
+
+use std::io::{self, Write};
+
+const DAY: f64 = 86_400.0;
+
+/// Upper bound (exclusive, in seconds) and label for each bucket but the last.
+const BUCKETS: [(f64, &str); 4] = [
+    (DAY, "< 1 day"),
+    (7.0 * DAY, "1-7 days"),
+    (30.0 * DAY, "1-4 weeks"),
+    (365.0 * DAY, "1-12 months"),
+];
+const LAST_LABEL: &str = "> 1 year";
+
+/// Widest bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+#[derive(Debug, Default)]
+pub struct DriftHistogram {
+    counts: [usize; BUCKETS.len() + 1],
+}
+
+impl DriftHistogram {
+    /// Count one change of `delta_secs` (either direction).
+    pub fn add(&mut self, delta_secs: f64) {
+        let drift = delta_secs.abs();
+        let slot = BUCKETS
+            .iter()
+            .position(|(limit, _)| drift < *limit)
+            .unwrap_or(BUCKETS.len());
+        self.counts[slot] += 1;
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// `(label, count)` for every bucket, smallest drift first.
+    pub fn buckets(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        BUCKETS
+            .iter()
+            .map(|(_, label)| *label)
+            .chain([LAST_LABEL])
+            .zip(self.counts)
+    }
+
+    pub fn print(&self) {
+        let total = self.total();
+        if total == 0 {
+            return;
+        }
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        let mut out = io::stdout().lock();
+        // Unlike println!, a closed stdout (`| head`) just ends the histogram.
+        if writeln!(out, "\nDrift (how far each folder's mtime moves):").is_err() {
+            return;
+        }
+        for (label, count) in self.buckets() {
+            let pct = 100.0 * count as f64 / total as f64;
+            // Round up so any non-empty bucket gets at least one mark.
+            let bar = (count * BAR_WIDTH).div_ceil(max);
+            let line = format!("  {label:<12} {count:>7}  {pct:>5.1}%  {}", "#".repeat(bar));
+            if writeln!(out, "{}", line.trim_end()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_by_absolute_drift() {
+        let mut h = DriftHistogram::default();
+        for d in [
+            0.0,
+            -3_600.0,
+            2.0 * DAY,
+            -10.0 * DAY,
+            90.0 * DAY,
+            400.0 * DAY,
+        ] {
+            h.add(d);
+        }
+        let counts: Vec<usize> = h.buckets().map(|(_, n)| n).collect();
+        assert_eq!(counts, [2, 1, 1, 1, 1]);
+        assert_eq!(h.total(), 6);
+    }
+
+    #[test]
+    fn boundaries_belong_to_the_larger_bucket() {
+        let mut h = DriftHistogram::default();
+        h.add(DAY);
+        h.add(365.0 * DAY);
+        let counts: Vec<usize> = h.buckets().map(|(_, n)| n).collect();
+        assert_eq!(counts, [0, 1, 0, 0, 1]);
+    }
+}
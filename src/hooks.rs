@@ -0,0 +1,80 @@
+// src/hooks.rs
+// --after-stamp: a shell command run for each folder stamped, with the
+// folder and its old and new times put in. Each value is substituted
+// already quoted for sh, so a name with spaces or quotes in it stays one
+// word and can't run anything.
+
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use crate::report::{Change, DateFormat};
+use crate::script::sh_quote;
+
+pub struct Hook {
+    command: String,
+    /// How `{from_mtime}` and `{to_mtime}` are written.
+    dates: DateFormat,
+    /// stdout is machine-readable: the command's goes to stderr instead.
+    stdout_to_stderr: bool,
+}
+
+impl Hook {
+    pub fn new(command: String, dates: DateFormat, stdout_to_stderr: bool) -> Self {
+        Hook {
+            command,
+            dates,
+            stdout_to_stderr,
+        }
+    }
+
+    /// Run the command for `change` through `sh -c` and wait for it. Its
+    /// output goes straight to ours.
+    pub fn run(&self, change: &Change) -> io::Result<ExitStatus> {
+        // Whatever the report wrote comes first.
+        io::stdout().flush()?;
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(self.expand(change));
+        if self.stdout_to_stderr {
+            command.stdout(Stdio::from(io::stderr()));
+        }
+        command.status()
+    }
+
+    /// The command with `{path}`, `{from_mtime}` and `{to_mtime}` replaced;
+    /// any other braces are left alone.
+    fn expand(&self, change: &Change) -> OsString {
+        let quoted = |s: String| sh_quote(Path::new(&s));
+        let mut out = Vec::with_capacity(self.command.len());
+        let mut rest = self.command.as_str();
+        while let Some(at) = rest.find('{') {
+            out.extend_from_slice(&rest.as_bytes()[..at]);
+            rest = &rest[at..];
+            let (value, len) = if rest.starts_with("{path}") {
+                (sh_quote(&change.path), "{path}".len())
+            } else if rest.starts_with("{from_mtime}") {
+                (quoted(self.dates.render(change.from)), "{from_mtime}".len())
+            } else if rest.starts_with("{to_mtime}") {
+                (quoted(self.dates.render(change.to)), "{to_mtime}".len())
+            } else {
+                (b"{".to_vec(), 1)
+            };
+            out.extend(value);
+            rest = &rest[len..];
+        }
+        out.extend_from_slice(rest.as_bytes());
+        os_string(out)
+    }
+}
+
+#[cfg(unix)]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
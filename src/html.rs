@@ -0,0 +1,210 @@
+// src/html.rs
+// --report-html: a single self-contained HTML page (inline CSS and JS, no
+// external assets) summarising the run, for people who won't read a terminal.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::errors::ErrorLog;
+use crate::report::{Change, DateFormat, Status};
+
+/// Default cap on table rows (`--report-rows`).
+pub const DEFAULT_MAX_ROWS: usize = 5_000;
+
+const STYLE: &str = "\
+body{font-family:system-ui,sans-serif;margin:2em;color:#222}
+h1{font-size:1.4em}h2{font-size:1.1em;margin-top:2em}
+table{border-collapse:collapse;font-size:.9em}
+th,td{border:1px solid #ccc;padding:.25em .6em;text-align:left}
+th{background:#f0f0f0;cursor:pointer;user-select:none}
+td.num{text-align:right;font-variant-numeric:tabular-nums}
+tr.F td{background:#fde8e8}
+.note{color:#666}
+dl{display:grid;grid-template-columns:max-content auto;gap:.2em 1em}
+dt{font-weight:bold}dd{margin:0}
+";
+
+// Click a header to sort by that column; click again to reverse.
+const SCRIPT: &str = "\
+document.querySelectorAll('table.sortable th').forEach(function(th,i){
+  th.addEventListener('click',function(){
+    var body=th.closest('table').tBodies[0];
+    var asc=th.dataset.dir!=='asc';th.dataset.dir=asc?'asc':'desc';
+    var rows=Array.prototype.slice.call(body.rows);
+    rows.sort(function(a,b){
+      var x=a.cells[i].dataset.key||a.cells[i].textContent;
+      var y=b.cells[i].dataset.key||b.cells[i].textContent;
+      var nx=parseFloat(x),ny=parseFloat(y);
+      var c=(!isNaN(nx)&&!isNaN(ny))?nx-ny:x.localeCompare(y);
+      return asc?c:-c;
+    });
+    rows.forEach(function(r){body.appendChild(r)});
+  });
+});
+";
+
+/// Changes collected for the HTML report.
+pub struct HtmlReport {
+    rows: Vec<(Change, Status)>,
+    max_rows: usize,
+    /// All changes seen, including those past the row cap.
+    total: usize,
+    failed: usize,
+    /// Whether this was a -C run (anything applied or attempted).
+    confirmed: bool,
+    /// Sum of |delta| over successful changes, in seconds.
+    total_drift: f64,
+}
+
+impl HtmlReport {
+    pub fn new(max_rows: usize) -> Self {
+        HtmlReport {
+            rows: Vec::new(),
+            max_rows,
+            total: 0,
+            failed: 0,
+            confirmed: false,
+            total_drift: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, change: &Change, status: Status) {
+        self.total += 1;
+        self.confirmed |= status != Status::Planned;
+        if status == Status::Failed {
+            self.failed += 1;
+        } else {
+            self.total_drift += change.delta_secs().abs();
+        }
+        if self.rows.len() < self.max_rows {
+            self.rows.push((change.clone(), status));
+        }
+    }
+
+    pub fn write(
+        &self,
+        path: &Path,
+        root: &Path,
+        options: &str,
+        errors: &ErrorLog,
+        generated: SystemTime,
+    ) -> io::Result<()> {
+        let dates = DateFormat::utc();
+        let mut h = String::new();
+        let _ = write!(
+            h,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>dirstamp report: {root}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n\
+             <h1>dirstamp report: {root}</h1>\n",
+            root = escape(&root.display().to_string()),
+        );
+
+        let _ = write!(
+            h,
+            "<dl>\n<dt>Generated</dt><dd>{}</dd>\n<dt>dirstamp</dt><dd>{}</dd>\n\
+             <dt>Options</dt><dd><code>{}</code></dd>\n<dt>Mode</dt><dd>{}</dd>\n\
+             <dt>Changes</dt><dd>{}</dd>\n<dt>Failed</dt><dd>{}</dd>\n\
+             <dt>Errors</dt><dd>{}</dd>\n<dt>Total drift</dt><dd>{:.1} days</dd>\n</dl>\n",
+            dates.render(generated),
+            env!("CARGO_PKG_VERSION"),
+            escape(options),
+            if self.confirmed {
+                "applied (-C)"
+            } else {
+                "dry run"
+            },
+            self.total - self.failed,
+            self.failed,
+            errors.total(),
+            self.total_drift / 86_400.0,
+        );
+
+        h.push_str("<h2>Changes</h2>\n");
+        if self.rows.is_empty() {
+            h.push_str("<p class=\"note\">No folder timestamps needed updating.</p>\n");
+        } else {
+            h.push_str(
+                "<table class=\"sortable\">\n<thead><tr><th>Status</th><th>Path</th>\
+                 <th>Old mtime</th><th>New mtime</th><th>Delta (days)</th><th>Driven by</th>\
+                 </tr></thead>\n<tbody>\n",
+            );
+            for (change, status) in &self.rows {
+                let status_text = match status {
+                    Status::Planned => "would update",
+                    Status::Applied => "updated",
+                    Status::Failed => "failed",
+                };
+                let driver = change
+                    .source_child
+                    .as_deref()
+                    .or(change.copied_from.as_deref())
+                    .map(|p| escape(&p.display().to_string()))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    h,
+                    "<tr class=\"{}\"><td>{}</td><td>{}</td><td data-key=\"{}\">{}</td>\
+                     <td data-key=\"{}\">{}</td><td class=\"num\">{:+.1}</td><td>{}</td></tr>",
+                    status.code(),
+                    status_text,
+                    escape(&change.path.display().to_string()),
+                    DateFormat::EpochNanos.render(change.from),
+                    dates.render(change.from),
+                    DateFormat::EpochNanos.render(change.to),
+                    dates.render(change.to),
+                    change.delta_secs() / 86_400.0,
+                    driver,
+                );
+            }
+            h.push_str("</tbody>\n</table>\n");
+            if self.total > self.rows.len() {
+                let _ = writeln!(
+                    h,
+                    "<p class=\"note\">Showing the first {} of {} changes (see --report-rows).</p>",
+                    self.rows.len(),
+                    self.total
+                );
+            }
+        }
+
+        h.push_str("<h2>Errors</h2>\n");
+        if errors.total() == 0 {
+            h.push_str("<p class=\"note\">None.</p>\n");
+        } else {
+            h.push_str("<ul>\n");
+            for (label, count, examples) in errors.groups() {
+                let examples: Vec<String> = examples
+                    .iter()
+                    .map(|p| format!("<code>{}</code>", escape(&p.display().to_string())))
+                    .collect();
+                let _ = write!(h, "<li>{} ({count})", escape(label));
+                if !examples.is_empty() {
+                    let _ = write!(h, ": e.g. {}", examples.join(", "));
+                }
+                h.push_str("</li>\n");
+            }
+            h.push_str("</ul>\n");
+        }
+
+        let _ = write!(h, "<script>\n{SCRIPT}</script>\n</body>\n</html>\n");
+        fs::write(path, h)
+    }
+}
+
+/// Escape text for HTML element content and double-quoted attributes.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,236 @@
+// src/interactive.rs
+// --interactive: show each change with its -D details and ask before making
+// it. Approved changes are applied on the spot, so later folders (parents
+// included) are planned against what is really on disk.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Config, PathEscape};
+use crate::report::{self, Change, DateFormat};
+
+const HELP: &str = "\
+y - apply this change
+n - leave this folder alone
+a - apply this change and every one after it without asking
+q - stop here; nothing else is changed
+s - leave alone this folder and everything else in (and including) its parent
+";
+
+/// One reply to the prompt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+    SkipRest,
+}
+
+impl Answer {
+    fn parse(reply: &str) -> Option<Answer> {
+        match reply.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => Some(Answer::Yes),
+            "n" | "no" => Some(Answer::No),
+            "a" | "all" => Some(Answer::All),
+            "q" | "quit" => Some(Answer::Quit),
+            "s" | "skip" => Some(Answer::SkipRest),
+            _ => None,
+        }
+    }
+}
+
+pub struct Prompter {
+    root: PathBuf,
+    dates: DateFormat,
+    human_delta: bool,
+    escape: PathEscape,
+    /// `a` was answered: apply the rest without asking.
+    all: bool,
+    /// `q` was answered (or stdin closed): decline the rest without asking.
+    quit: bool,
+    /// `s` answers: folders whose remaining changes are declined unasked.
+    skipped: Vec<PathBuf>,
+    pub declined: usize,
+    /// Changes that came up after quitting.
+    pub unasked: usize,
+}
+
+impl Prompter {
+    /// `Err` is a usage message: the prompts need a terminal to answer them.
+    pub fn new(cfg: &Config, root: &Path) -> Result<Self, String> {
+        if !io::stdin().is_terminal() {
+            return Err("--interactive needs a terminal on stdin to ask its questions".into());
+        }
+        Ok(Prompter {
+            root: root.to_path_buf(),
+            dates: DateFormat::from_config(cfg)?.unwrap_or_else(DateFormat::utc),
+            human_delta: cfg.human_delta,
+            escape: cfg.escape,
+            all: false,
+            quit: false,
+            skipped: Vec::new(),
+            declined: 0,
+            unasked: 0,
+        })
+    }
+
+    /// Whether `change` should be made, asking on the terminal if need be.
+    pub fn approve(&mut self, change: &Change) -> bool {
+        if let Some(verdict) = self.without_asking(change) {
+            return verdict;
+        }
+        let answer = self.ask(change);
+        self.answer(change, answer)
+    }
+
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// The verdict an earlier `a`, `q` or `s` already settled, if any.
+    fn without_asking(&mut self, change: &Change) -> Option<bool> {
+        if self.quit {
+            self.unasked += 1;
+            Some(false)
+        } else if self.skipped.iter().any(|dir| change.path.starts_with(dir)) {
+            self.declined += 1;
+            Some(false)
+        } else if self.all {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn answer(&mut self, change: &Change, answer: Answer) -> bool {
+        match answer {
+            Answer::Yes => return true,
+            Answer::All => {
+                self.all = true;
+                return true;
+            }
+            Answer::No => {}
+            Answer::Quit => self.quit = true,
+            Answer::SkipRest => {
+                let scope = change
+                    .path
+                    .parent()
+                    .filter(|p| p.starts_with(&self.root))
+                    .unwrap_or(&change.path);
+                self.skipped.push(scope.to_path_buf());
+            }
+        }
+        self.declined += 1;
+        false
+    }
+
+    /// Prompt on stderr until a valid reply comes; end of input means quit.
+    fn ask(&self, change: &Change) -> Answer {
+        let mut question = b"update ".to_vec();
+        question.extend(report::describe_change(
+            change,
+            &self.dates,
+            self.human_delta,
+            self.escape,
+        ));
+        question.extend_from_slice(b"? [y,n,a,q,s,?] ");
+        let mut stdin = io::stdin().lock();
+        loop {
+            let mut err = io::stderr().lock();
+            let _ = err.write_all(&question);
+            let _ = err.flush();
+            drop(err);
+            let mut reply = String::new();
+            match stdin.read_line(&mut reply) {
+                Ok(0) | Err(_) => {
+                    eprintln!();
+                    return Answer::Quit;
+                }
+                Ok(_) if crate::interrupt::requested() => return Answer::Quit,
+                Ok(_) => {}
+            }
+            match Answer::parse(&reply) {
+                Some(answer) => return answer,
+                None => eprint!("{HELP}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::report::ChangeReason;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn change(path: &str) -> Change {
+        Change {
+            path: path.into(),
+            from: UNIX_EPOCH + Duration::from_secs(200),
+            to: UNIX_EPOCH + Duration::from_secs(100),
+            source_child: None,
+            copied_from: None,
+            reason: ChangeReason::Child,
+        }
+    }
+
+    fn prompter() -> Prompter {
+        Prompter {
+            root: "/photos".into(),
+            dates: DateFormat::utc(),
+            human_delta: false,
+            escape: PathEscape::Quoted,
+            all: false,
+            quit: false,
+            skipped: Vec::new(),
+            declined: 0,
+            unasked: 0,
+        }
+    }
+
+    #[test]
+    fn parses_replies() {
+        assert_eq!(Answer::parse("y\n"), Some(Answer::Yes));
+        assert_eq!(Answer::parse(" No "), Some(Answer::No));
+        assert_eq!(Answer::parse("s"), Some(Answer::SkipRest));
+        assert_eq!(Answer::parse("?"), None);
+        assert_eq!(Answer::parse(""), None);
+    }
+
+    #[test]
+    fn skip_declines_the_rest_of_the_parent() {
+        let mut p = prompter();
+        let rome = change("/photos/2014/Rome");
+        assert_eq!(p.without_asking(&rome), None);
+        assert!(!p.answer(&rome, Answer::SkipRest));
+
+        assert_eq!(
+            p.without_asking(&change("/photos/2014/Venice")),
+            Some(false)
+        );
+        assert_eq!(p.without_asking(&change("/photos/2014")), Some(false));
+        assert_eq!(p.without_asking(&change("/photos/2015")), None);
+        assert_eq!(p.declined, 3);
+
+        // At the root, `s` only covers the root itself.
+        let root = change("/photos");
+        assert!(!p.answer(&root, Answer::SkipRest));
+        assert_eq!(p.skipped.last().unwrap(), Path::new("/photos"));
+    }
+
+    #[test]
+    fn all_and_quit_stop_the_questions() {
+        let mut p = prompter();
+        assert!(p.answer(&change("/photos/a"), Answer::All));
+        assert_eq!(p.without_asking(&change("/photos/b")), Some(true));
+
+        let mut p = prompter();
+        assert!(!p.answer(&change("/photos/a"), Answer::Quit));
+        assert_eq!(p.without_asking(&change("/photos/b")), Some(false));
+        assert_eq!((p.declined, p.unasked), (1, 1));
+        assert!(p.quit());
+    }
+}
@@ -0,0 +1,61 @@
+// src/interrupt.rs
+// Ctrl-C / SIGTERM: the first one asks the run to stop after the folder in
+// hand, so the journal, log and summary are all finished properly; a second
+// one exits at once. `--fail-fast`, or an undo journal that can't be
+// written, stops a run the same way.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// Exit code for an interrupted run (128 + SIGINT, as shells report it).
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// `--interval`: while set, a stop waits for the end of the run in hand.
+static HELD: AtomicBool = AtomicBool::new(false);
+/// A stop for a failure, which no hold delays: what went wrong.
+static FAILED: OnceLock<&'static str> = OnceLock::new();
+
+/// Install the handler. Failing to is not worth stopping the run for; the
+/// signals then just kill the process as before.
+pub fn install() {
+    let _ = ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        let what = if HELD.load(Ordering::SeqCst) {
+            "run"
+        } else {
+            "folder"
+        };
+        eprintln!("\nInterrupted: finishing the current {what} (press Ctrl-C again to quit now)");
+    });
+}
+
+/// Whether the run should stop taking on new folders.
+pub fn requested() -> bool {
+    (REQUESTED.load(Ordering::SeqCst) && !HELD.load(Ordering::SeqCst)) || failed()
+}
+
+/// Stop the run as Ctrl-C would, for a failure: it is then finished
+/// properly and exits with an error rather than as interrupted. `cause` is
+/// what `--atomic` says it undid the run over; the first one is kept.
+pub fn fail(cause: &'static str) {
+    let _ = FAILED.set(cause);
+}
+
+/// Whether `fail` stopped the run.
+pub fn failed() -> bool {
+    FAILED.get().is_some()
+}
+
+/// Why `fail` stopped the run, if it did.
+pub fn cause() -> Option<&'static str> {
+    FAILED.get().copied()
+}
+
+/// Keep `requested` false until `hold(false)`, so a whole run completes
+/// before a stop is noticed.
+pub fn hold(on: bool) {
+    HELD.store(on, Ordering::SeqCst);
+}
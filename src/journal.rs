@@ -0,0 +1,226 @@
+// src/journal.rs
+// The undo journal: every -C change is recorded here *before* it is made,
+// one JSON line at a time, so `--undo` can put the old mtimes back even after
+// an interrupted run.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::paths;
+use crate::plan::{ChangeRecord, Plan};
+use crate::report::Change;
+
+/// Journal files this build writes and accepts.
+pub const JOURNAL_VERSION: u32 = 1;
+
+/// First line of a journal.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    journal_version: u32,
+    dirstamp_version: String,
+    #[serde(with = "paths::json")]
+    root: PathBuf,
+    #[serde(with = "time::serde::rfc3339")]
+    created: OffsetDateTime,
+}
+
+/// One directory about to be stamped.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    #[serde(with = "paths::json")]
+    path: PathBuf,
+    #[serde(with = "time::serde::rfc3339")]
+    previous_mtime: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    new_mtime: OffsetDateTime,
+    /// When the entry was written.
+    #[serde(with = "time::serde::rfc3339")]
+    timestamp: OffsetDateTime,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    /// Absolute, as are the entries' paths, so `--undo` works from any
+    /// directory.
+    root: PathBuf,
+    /// Created with the first entry, so runs that change nothing leave no file.
+    file: Option<File>,
+    entries: usize,
+    /// `--pretend-time`, used for the timestamps instead of the clock.
+    pretend_time: Option<SystemTime>,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf, root: &Path, pretend_time: Option<SystemTime>) -> Self {
+        Journal {
+            path,
+            root: absolute(root),
+            file: None,
+            entries: 0,
+            pretend_time,
+        }
+    }
+
+    fn now(&self) -> SystemTime {
+        self.pretend_time.unwrap_or_else(SystemTime::now)
+    }
+
+    /// `$XDG_STATE_HOME/dirstamp` (falling back to `~/.local/state/dirstamp`),
+    /// or `%LOCALAPPDATA%\dirstamp` on Windows, with a file per run.
+    pub fn default_path(now: SystemTime) -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            PathBuf::from(env::var_os("LOCALAPPDATA")?)
+        } else {
+            match env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+                Some(dir) => PathBuf::from(dir),
+                None => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+            }
+        };
+        let stamp = OffsetDateTime::from(now)
+            .format(format_description!(
+                "[year][month][day]T[hour][minute][second]Z"
+            ))
+            .ok()?;
+        let name = format!("journal-{stamp}-{}.jsonl", std::process::id());
+        Some(dir.join("dirstamp").join(name))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Entries written so far.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Record `change` before it is applied. Each line goes straight to the
+    /// file (no buffering), so a crash loses at most the change in flight.
+    pub fn record(&mut self, change: &Change) -> io::Result<()> {
+        let mut line = serde_json::to_string(&Entry {
+            path: absolute(&change.path),
+            previous_mtime: change.from.into(),
+            new_mtime: change.to.into(),
+            timestamp: self.now().into(),
+        })?;
+        line.push('\n');
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(self.create()?),
+        };
+        file.write_all(line.as_bytes())?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn create(&self) -> io::Result<File> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.path)?;
+        let mut header = serde_json::to_string(&Header {
+            journal_version: JOURNAL_VERSION,
+            dirstamp_version: env!("CARGO_PKG_VERSION").into(),
+            root: self.root.clone(),
+            created: self.now().into(),
+        })?;
+        header.push('\n');
+        file.write_all(header.as_bytes())?;
+        Ok(file)
+    }
+
+    /// Read a journal as the plan that reverses it: each folder goes from the
+    /// mtime dirstamp gave it back to the one it had, newest change first. A
+    /// torn last line (the run was killed mid-write) is ignored.
+    pub fn load_undo_plan(path: &Path) -> io::Result<Plan> {
+        let text = fs::read_to_string(path)?;
+        let complete = text.ends_with('\n');
+        let mut lines = text.lines();
+        let header: Header = serde_json::from_str(lines.next().unwrap_or_default())?;
+        if header.journal_version != JOURNAL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported journal version {} (this dirstamp reads version {JOURNAL_VERSION})",
+                    header.journal_version
+                ),
+            ));
+        }
+        let lines: Vec<&str> = lines.collect();
+        let mut changes = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            let entry: Entry = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(_) if i + 1 == lines.len() && !complete => break,
+                Err(e) => return Err(e.into()),
+            };
+            changes.push(ChangeRecord {
+                path: entry.path,
+                current_mtime: entry.new_mtime,
+                target_mtime: entry.previous_mtime,
+                source_child: None,
+            });
+        }
+        changes.reverse();
+        Ok(Plan::new(&header.root, changes, header.created.into()))
+    }
+}
+
+/// `path` made absolute against the current directory, as given when that
+/// can't be done.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::report::ChangeReason;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn change(path: &str, from: u64, to: u64) -> Change {
+        Change {
+            path: path.into(),
+            from: UNIX_EPOCH + Duration::from_secs(from),
+            to: UNIX_EPOCH + Duration::from_secs(to),
+            source_child: None,
+            copied_from: None,
+            reason: ChangeReason::Child,
+        }
+    }
+
+    #[test]
+    fn undo_plan_reverses_the_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/journal.jsonl");
+        let mut journal = Journal::new(path.clone(), Path::new("/photos"), None);
+        journal.record(&change("/photos/a", 100, 200)).unwrap();
+        journal.record(&change("/photos", 300, 400)).unwrap();
+
+        // Simulate a crash in the middle of the next entry.
+        let mut text = fs::read_to_string(&path).unwrap();
+        text.push_str(r#"{"path":"/photos/b","previous"#);
+        fs::write(&path, text).unwrap();
+
+        let plan = Journal::load_undo_plan(&path).unwrap();
+        assert_eq!(plan.root, Path::new("/photos"));
+        let undo: Vec<Change> = plan.changes.iter().map(Change::from).collect();
+        assert_eq!(undo.len(), 2);
+        assert_eq!(undo[0].path, Path::new("/photos"));
+        assert_eq!(undo[0].from, change("", 300, 400).to);
+        assert_eq!(undo[0].to, change("", 300, 400).from);
+        assert_eq!(undo[1].path, Path::new("/photos/a"));
+    }
+}
@@ -0,0 +1,69 @@
+// src/lib.rs
+//! dirstamp as a library: set each directory's mtime to match its newest
+//! immediate child, without shelling out to the `dirstamp` binary.
+//!
+//! A folder takes its time from its newest file, or from its newest
+//! subfolder when it holds no files; an empty folder is left alone.
+//! Folders are stamped deepest first, so a parent sees the time its
+//! subfolders are getting. [`run`] does a whole tree and returns a
+//! [`Report`]; it never prints. As on the command line, it is a dry run
+//! until [`Options::dry_run`] is turned off. [`plan`] and [`apply`] are its
+//! two halves, for callers that show the changes and let the user pick
+//! before any is made. The `_with` forms of the three report each step to
+//! an [`Events`] as it happens, for a progress display. [`collect_dirs`],
+//! [`plan_dirs`] and [`apply_steps`] are the walk and the same two halves
+//! with the caller's own [`Steps`] for reading, picking and setting each
+//! folder, as the `dirstamp` binary uses them.
+//!
+//! ```
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! use dirstamp::{Options, Status};
+//!
+//! # let tmp = tempfile::tempdir()?;
+//! # let library = tmp.path();
+//! let album = library.join("album");
+//! std::fs::create_dir(&album)?;
+//! std::fs::write(album.join("track.flac"), "")?;
+//! let recorded = UNIX_EPOCH + Duration::from_secs(1_577_836_800);
+//! dirstamp::set_mtime(&album.join("track.flac"), recorded)?;
+//!
+//! let options = Options::builder().root(library).dry_run(false).build()?;
+//! let report = dirstamp::run(&options)?;
+//! for (change, status) in &report.changes {
+//!     assert_eq!(*status, Status::Applied);
+//!     println!("{}: now {:?}", change.path.display(), change.to);
+//! }
+//! assert_eq!(std::fs::metadata(&album)?.modified()?, recorded);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! # Features
+//!
+//! | Feature | Default | Enables |
+//! |---------|---------|---------|
+//! | `cli`   | yes     | The `dirstamp` binary, and the crates only it uses (time formatting, JSON, the update check, terminal and signal handling, watch mode) |
+//! | `otel`  | no      | `--otel` trace export in the binary; implies `cli` |
+//!
+//! The library itself needs neither: with `default-features = false` it
+//! depends only on `walkdir`, `filetime` and `serde`.
+//!
+//! ```toml
+//! [dependencies]
+//! dirstamp = { version = "0.1", default-features = false }
+//! ```
+
+mod change;
+mod error;
+mod events;
+mod run;
+pub mod scan;
+
+pub use change::{Change, ChangeReason, SkipReason, Status};
+pub use error::{DirstampError, OptionsError};
+pub use events::{Events, NoEvents};
+pub use run::{
+    apply, apply_steps, apply_with, collect_dirs, differs, plan, plan_dirs, plan_with, run,
+    run_with, set_mtime, ApplyOptions, ApplyReport, Options, OptionsBuilder, Plan, Report, Steps,
+    DEFAULT_TOLERANCE,
+};
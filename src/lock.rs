@@ -0,0 +1,53 @@
+// src/lock.rs
+// --lockfile: an exclusive lock on a file, so two runs over the same tree
+// don't interleave their changes. The lock goes when the process does,
+// however it ends.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::Path;
+
+/// What stopped the lock from being taken.
+pub enum LockError {
+    /// Another process has it, and waiting wasn't asked for.
+    Held,
+    Io(io::Error),
+}
+
+/// Lock `path`, creating it if need be; with `wait`, until the holder lets
+/// go. Keep the file open for as long as the lock is needed.
+pub fn acquire(path: &Path, wait: bool) -> Result<File, LockError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(LockError::Io)?;
+    match file.try_lock() {
+        Ok(()) => return Ok(file),
+        Err(TryLockError::WouldBlock) if !wait => return Err(LockError::Held),
+        Err(TryLockError::WouldBlock) => {}
+        Err(TryLockError::Error(e)) => return Err(LockError::Io(e)),
+    }
+    eprintln!(
+        "Waiting for another dirstamp to finish (lockfile: {})",
+        path.display()
+    );
+    file.lock().map_err(LockError::Io)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_second_lock_is_refused_until_the_first_is_dropped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("dirstamp.lock");
+        let first = acquire(&path, false).ok().unwrap();
+        assert!(matches!(acquire(&path, false), Err(LockError::Held)));
+        drop(first);
+        assert!(acquire(&path, false).is_ok());
+    }
+}
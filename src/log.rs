@@ -0,0 +1,138 @@
+// src/log.rs
+// --log-file: an append-only, timestamped record of what each run did, with
+// optional size-based rotation (--log-rotate, --log-rotate-keep).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+/// Rotated files kept when `--log-rotate-keep` isn't given.
+pub const DEFAULT_KEEP: usize = 5;
+
+pub struct RunLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// Rotate before a write would take the file past this many bytes.
+    rotate_at: Option<u64>,
+    /// How many rotated files (`NAME.1` … `NAME.keep`) to keep.
+    keep: usize,
+    /// Set after a failed write; the run carries on without a log.
+    failed: bool,
+    /// `--pretend-time`, stamped on every line instead of the clock.
+    pretend_time: Option<SystemTime>,
+}
+
+impl RunLog {
+    pub fn open(
+        path: &Path,
+        rotate_at: Option<u64>,
+        keep: usize,
+        pretend_time: Option<SystemTime>,
+    ) -> io::Result<Self> {
+        let file = append(path)?;
+        let size = file.metadata()?.len();
+        Ok(RunLog {
+            path: path.to_path_buf(),
+            file,
+            size,
+            rotate_at,
+            keep,
+            failed: false,
+            pretend_time,
+        })
+    }
+
+    /// Append one timestamped line. A write error is reported once on stderr
+    /// and turns the log off rather than failing the run.
+    pub fn line(&mut self, text: &str) {
+        if self.failed {
+            return;
+        }
+        let now = timestamp(self.pretend_time.unwrap_or_else(SystemTime::now));
+        if let Err(e) = self.write(&format!("{now} {text}\n")) {
+            eprintln!(
+                "warning: cannot write log file {:?} ({e}); logging stopped",
+                self.path
+            );
+            self.failed = true;
+        }
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64;
+        if let Some(limit) = self.rotate_at {
+            // An empty file takes the line whatever its size.
+            if self.size > 0 && self.size + len > limit {
+                self.rotate()?;
+            }
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// `NAME.(keep-1)` → `NAME.keep`, …, `NAME` → `NAME.1`, then start afresh.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        // `fs::rename` replaces an existing target, so `NAME.keep` just
+        // gets overwritten.
+        for n in (1..self.keep).rev() {
+            let from = rotated(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, rotated(&self.path, n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(&self.path, 1))?;
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// UTC, to the second: `2020-01-01T00:00:00Z`.
+pub fn timestamp(t: SystemTime) -> String {
+    OffsetDateTime::from(t)
+        .format(format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+        ))
+        .unwrap_or_else(|_| "<bad time>".into())
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `NAME.n`
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{n}"));
+    name.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_and_keeps_the_newest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dirstamp.log");
+        // Each line is a 20-byte timestamp, a space and the text.
+        let mut log = RunLog::open(&path, Some(60), 2, None).unwrap();
+        for i in 0..10 {
+            log.line(&format!("line {i}"));
+        }
+
+        let read = |p: &Path| fs::read_to_string(p).unwrap();
+        assert!(read(&path).ends_with("line 9\n"));
+        assert!(read(&rotated(&path, 1)).ends_with("line 7\n"));
+        assert!(read(&rotated(&path, 2)).ends_with("line 5\n"));
+        assert!(!rotated(&path, 3).exists());
+        assert!(fs::metadata(&path).unwrap().len() <= 60);
+    }
+}
@@ -1,19 +1,97 @@
 // src/main.rs
 // dirstamp — set each directory's mtime to match its newest immediate child
 // Priority: newest file; if no files, newest immediate subdir. Empty dirs unchanged.
+// (--oldest, --median and --percentile pick a different child of the same tier.)
+
+mod baseline;
+mod cache;
+mod cli;
+mod doctor;
+#[cfg(target_os = "windows")]
+mod elevation;
+mod errors;
+mod extensions;
+mod filter;
+mod gentree;
+mod git;
+mod handles;
+mod histogram;
+mod hooks;
+mod html;
+mod interactive;
+mod interrupt;
+mod jobs;
+mod journal;
+mod lock;
+mod log;
+mod metrics;
+mod otel;
+mod paths;
+mod plan;
+mod readonly;
+mod report;
+mod retry;
+mod rng;
+mod rollup;
+mod script;
+mod snapshot;
+mod throttle;
+mod timing;
+mod tree;
+mod update;
+mod walk;
+mod watch;
+mod watchdog;
 
 use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::io;
+use std::io::{self, IsTerminal};
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use dirstamp::scan::{self, Child};
+use dirstamp::DirstampError;
+use filetime::FileTime;
 
-use filetime::{set_file_mtime, FileTime};
-use walkdir::{DirEntry, WalkDir};
+use baseline::Baseline;
+use cache::DirCache;
+use cli::{Command, Config, EntryType, USAGE};
+use errors::{ErrorCategory, ErrorLog};
+use extensions::ExtensionStats;
+use filter::{DirAttrs, DirFilter};
+use hooks::Hook;
+use interactive::Prompter;
+use interrupt::EXIT_INTERRUPTED;
+use journal::Journal;
+use lock::LockError;
+use log::RunLog;
+use otel::RunTrace;
+use plan::Plan;
+use readonly::ReadOnlyMounts;
+use report::{
+    Change, ChangeReason, Counts, DateFormat, ReportEvents, Reporter, SkipReason, Status,
+};
+use retry::Retry;
+use rng::Rng;
+use script::ScriptWriter;
+use snapshot::{SnapshotReader, SnapshotWriter};
+use throttle::Throttle;
+use timing::{Call, Phase};
+use walk::{DeviceFilter, Walked};
+use watch::Watcher;
+use watchdog::Watchdog;
 
-// For human-readable UTC timestamps when -D/--show-dates is used.
-use time::{format_description::parse as parse_format, OffsetDateTime};
+/// `println!` that lets a closed stdout (`| head`) go instead of panicking,
+/// as the report's own lines do.
+macro_rules! outln {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        let _ = writeln!(io::stdout().lock(), $($arg)*);
+    }};
+}
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -21,220 +99,2700 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 const GIT_HASH_OPT: Option<&'static str> = option_env!("GIT_HASH");
 const BUILD_DATE_OPT: Option<&'static str> = option_env!("BUILD_DATE");
 
-const USAGE: &str = "\
-dirstamp {VERSION}
+// Exit codes (documented in the README).
+const EXIT_CHANGES: i32 = 1; // --check found directories that would change
+const EXIT_USAGE: i32 = 2; // bad arguments, a root that is missing or no folder, or --check couldn't finish cleanly
+const EXIT_ERRORS: i32 = 3; // folders were skipped due to errors (--strict), or -C failed to set one
+const EXIT_LIMIT: i32 = 4; // -C stopped at --limit with changes still to make
+const EXIT_READ_ONLY: i32 = 5; // -C was pointed at a read-only filesystem
 
-Usage:
-  dirstamp [PATH] [OPTIONS]
+fn print_help_and_exit() -> ! {
+    outln!("{}", USAGE.replace("{VERSION}", VERSION));
+    std::process::exit(0)
+}
 
-Options:
-  -C, --confirm     Apply changes (default is dry run)
-  -D, --show-dates  Show from → to timestamps and ±days for each change
-  -V, --version     Show version information
-  -h, --help        Show this help message
-";
+/// Report a command-line mistake and exit with `EXIT_USAGE`.
+fn usage_error(msg: impl std::fmt::Display) -> ! {
+    eprintln!("{msg}");
+    eprintln!("Try 'dirstamp --help' for more information.");
+    std::process::exit(EXIT_USAGE)
+}
 
-fn print_help_and_exit() -> ! {
-    println!("{}", USAGE.replace("{VERSION}", VERSION));
+/// `--version-check`. Network trouble is only a warning: exit 0 either way.
+fn version_check_and_exit(quiet: bool) -> ! {
+    match update::latest_release() {
+        Ok(release) if update::is_newer(&release.version, VERSION) => {
+            eprintln!(
+                "A newer version ({}) is available at {}",
+                release.version, release.url
+            );
+        }
+        Ok(_) => {
+            if !quiet {
+                outln!("dirstamp {VERSION} is up to date.");
+            }
+        }
+        Err(e) => eprintln!("warning: could not check for a newer version ({e})"),
+    }
+    std::process::exit(0)
+}
+
+/// `--doctor`: exits 1 if mtimes don't survive a round trip, 3 if the probes
+/// couldn't run.
+fn doctor_and_exit(path: &Path, json: bool) -> ! {
+    let findings = doctor::examine(path).unwrap_or_else(|e| {
+        eprintln!("Cannot test timestamps in {} ({e})", path.display());
+        std::process::exit(EXIT_ERRORS);
+    });
+    if json {
+        outln!(
+            "{}",
+            serde_json::to_string(&findings).expect("findings serialize")
+        );
+    } else {
+        outln!("{findings}");
+    }
+    std::process::exit(if findings.round_trip { 0 } else { EXIT_CHANGES })
+}
+
+/// `--find-newest DIR`: what a stamp of DIR would take its mtime from, with
+/// the same child rules, printed and nothing else.
+fn find_newest_and_exit(dir: &Path, cfg: &Config) -> ! {
+    let dates = DateFormat::from_config(cfg)
+        .unwrap_or_else(|msg| usage_error(msg))
+        .unwrap_or_else(DateFormat::utc);
+    let rules = ChildRules::new(cfg);
+    let mut children = read_children(dir).unwrap_or_else(|e| {
+        eprintln!("Cannot read {} ({e})", dir.display());
+        std::process::exit(EXIT_USAGE);
+    });
+    rules.apply(&mut children);
+    let newest = scan::target_child(dir, children, &rules.strategy, |_| None, |_, _| {});
+    match newest {
+        Some(latest) => {
+            // The built-in strategies always name the child.
+            let name = latest.name.unwrap_or_default();
+            let name = report::porcelain_escape(Path::new(&name));
+            outln!("{}\t{name}", dates.render(latest.mtime));
+            std::process::exit(0)
+        }
+        None => {
+            if !cfg.quiet {
+                eprintln!("{} has no children to take a time from", dir.display());
+            }
+            std::process::exit(EXIT_CHANGES)
+        }
+    }
+}
+
+fn gen_tree_and_exit(spec: &cli::GenTree) -> ! {
+    let show_progress = !spec.quiet && io::stderr().is_terminal();
+    let made = gentree::generate(spec, |n| {
+        if show_progress {
+            eprint!("\rgen-tree: {n}/{} folders", spec.dirs);
+        }
+    });
+    if show_progress && spec.dirs >= gentree::PROGRESS_EVERY {
+        eprintln!();
+    }
+    let made = made.unwrap_or_else(|e| {
+        eprintln!("Cannot generate a tree in {} ({e})", spec.dir.display());
+        std::process::exit(EXIT_ERRORS);
+    });
+    if !spec.quiet {
+        outln!(
+            "Created {} folder(s) and {} file(s) in {} (seed {}).",
+            made.dirs,
+            made.files,
+            spec.dir.display(),
+            spec.seed
+        );
+    }
     std::process::exit(0)
 }
 
 fn print_version_and_exit() -> ! {
     match (GIT_HASH_OPT, BUILD_DATE_OPT) {
         (Some(hash), Some(date)) if !hash.is_empty() => {
-            println!("dirstamp {} ({} {})", VERSION, hash, date);
+            outln!("dirstamp {} ({} {})", VERSION, hash, date);
         }
         (Some(hash), None) if !hash.is_empty() => {
-            println!("dirstamp {} ({})", VERSION, hash);
+            outln!("dirstamp {} ({})", VERSION, hash);
         }
         _ => {
-            println!("dirstamp {}", VERSION);
+            outln!("dirstamp {}", VERSION);
         }
     }
     std::process::exit(0)
 }
 
-fn is_dir(entry: &DirEntry) -> bool {
-    entry.file_type().is_dir()
+/// What `--verify-before-apply` does with a folder that changed since its scan.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Recheck {
+    Skip,
+    /// `--recompute`: plan it again from what is on disk now.
+    Recompute,
 }
 
-fn depth_of(path: &Path) -> usize {
-    path.components().count()
+/// `--include-type`, `--exclude-type`, `--hardlink-aware` and
+/// `--touch-created`: which children of a folder count, and with what time.
+#[derive(Default)]
+struct ChildRules {
+    hardlink_aware: bool,
+    /// `--ignore-dot-underscore`: `._*` files are macOS metadata, not content.
+    ignore_dot_underscore: bool,
+    /// `--touch-created`: children rank by birth time, where they have one.
+    touch_created: bool,
+    strategy: scan::Strategy,
+    include_types: Vec<EntryType>,
+    exclude_types: Vec<EntryType>,
 }
 
-/// Find newest mtime among *immediate* children of `path`.
-/// Priority: newest file; if none, newest immediate subdir; None if no children.
-fn find_latest_mtime(path: &Path) -> io::Result<Option<SystemTime>> {
-    let mut newest_file: Option<SystemTime> = None;
-    let mut newest_dir: Option<SystemTime> = None;
+impl ChildRules {
+    fn new(cfg: &Config) -> Self {
+        ChildRules {
+            hardlink_aware: cfg.hardlink_aware,
+            ignore_dot_underscore: cfg.ignore_dot_underscore,
+            touch_created: cfg.touch_created,
+            strategy: match cfg.strategy {
+                cli::Strategy::Newest => scan::Strategy::Newest,
+                cli::Strategy::Oldest => scan::Strategy::Oldest,
+                cli::Strategy::Median => scan::Strategy::Median,
+                cli::Strategy::Percentile(p) => scan::Strategy::Percentile(p),
+            },
+            include_types: cfg.include_types.clone(),
+            exclude_types: cfg.exclude_types.clone(),
+        }
+    }
 
-    for item in fs::read_dir(path)? {
-        let entry = item?;
-        let meta = entry.metadata()?;
-        let modified = match meta.modified() {
-            Ok(m) => m,
-            Err(_) => continue,
+    fn counts(&self, kind: scan::Kind) -> bool {
+        let kind = match kind {
+            scan::Kind::File => EntryType::File,
+            scan::Kind::Dir => EntryType::Dir,
+            scan::Kind::Symlink => EntryType::Symlink,
+            scan::Kind::Socket => EntryType::Socket,
+            scan::Kind::Fifo => EntryType::Fifo,
+            scan::Kind::BlockDevice => EntryType::BlockDevice,
+            scan::Kind::CharDevice => EntryType::CharDevice,
+            scan::Kind::Other => return false,
         };
+        let included = if self.include_types.is_empty() {
+            matches!(kind, EntryType::File | EntryType::Dir)
+        } else {
+            self.include_types.contains(&kind)
+        };
+        included && !self.exclude_types.contains(&kind)
+    }
 
-        if meta.is_file() {
-            newest_file = Some(match newest_file {
-                Some(curr) => curr.max(modified),
-                None => modified,
-            });
-        } else if meta.is_dir() {
-            newest_dir = Some(match newest_dir {
-                Some(curr) => curr.max(modified),
-                None => modified,
+    fn apply(&self, children: &mut Vec<Child>) {
+        children.retain(|child| self.counts(child.kind));
+        if self.ignore_dot_underscore {
+            children.retain(|child| {
+                child.kind != scan::Kind::File || !child.name.as_encoded_bytes().starts_with(b"._")
             });
         }
+        if self.touch_created {
+            for child in children.iter_mut() {
+                child.mtime = child.created.unwrap_or(child.mtime);
+            }
+        }
+        if self.hardlink_aware {
+            scan::drop_hardlink_duplicates(children);
+        }
+    }
+}
+
+/// Run counters shared by the walk, scan and apply phases.
+struct Stats {
+    /// What went wrong, each skipping a folder (or leaving it as it was).
+    errors: Vec<DirstampError>,
+    /// The same errors, grouped for the end-of-run summary.
+    error_log: ErrorLog,
+    /// Permission-denied skips silenced by `--ignore-permission-errors`.
+    skipped_permission: usize,
+    ignore_permission: bool,
+    quiet_errors: bool,
+    /// Undo journal for `-C`, written ahead of each change.
+    journal: Option<Journal>,
+    /// `--interactive`: asks before each change.
+    prompter: Option<Prompter>,
+    /// `--verify-before-apply` / `--recompute`.
+    recheck: Option<Recheck>,
+    /// Folders the recheck found changed since they were scanned.
+    changed_since_scan: usize,
+    /// `--limit`: changes `-C` may still make; the rest are only planned.
+    limit_left: Option<usize>,
+    /// `--verify`: every folder `-C` set, with the mtime it was last given.
+    applied: Option<BTreeMap<PathBuf, SystemTime>>,
+    /// `--extension-stats`: every file child the scans looked at.
+    extensions: Option<ExtensionStats>,
+    read_only_mounts: ReadOnlyMounts,
+    retry: Retry,
+    /// `--dir-timeout`.
+    watchdog: Option<Watchdog>,
+    /// `--git-changed`: the only folders to visit; there's no walk.
+    scope: Option<Vec<PathBuf>>,
+    child_rules: ChildRules,
+    /// `--newer-ref`: folders with no child newer than this are left alone.
+    newer_ref: Option<SystemTime>,
+    /// `--rate-limit`.
+    throttle: Throttle,
+    /// `--cache`.
+    cache: Option<DirCache>,
+    /// `--touch-symlinks`.
+    touch_symlinks: bool,
+    /// Set once the platform has refused to set a link's own times.
+    symlink_times_unsupported: bool,
+    /// `--before-stamp` and `--after-stamp`.
+    before_stamp: Option<Hook>,
+    after_stamp: Option<Hook>,
+    fail_fast: bool,
+}
+
+impl Stats {
+    /// Count a skip-causing error; returns whether it should be printed now.
+    fn note_error(&mut self, category: ErrorCategory, error: DirstampError) -> bool {
+        let kind = io_source(&error).map(io::Error::kind);
+        if self.ignore_permission && kind == Some(io::ErrorKind::PermissionDenied) {
+            self.skipped_permission += 1;
+            return false;
+        }
+        let path = Some(error.path()).filter(|p| !p.as_os_str().is_empty());
+        self.error_log.record(category, path);
+        self.errors.push(error);
+        !self.quiet_errors
+    }
+}
+
+/// The system's error behind `error`, if it has one.
+fn io_source(error: &DirstampError) -> Option<&io::Error> {
+    std::error::Error::source(error)?.downcast_ref()
+}
+
+/// `e` again, to keep in a `DirstampError` while `e` is still printed.
+fn copied(e: &io::Error) -> io::Error {
+    io::Error::new(e.kind(), e.to_string())
+}
+
+/// The exit code a run ends with over `error`: a root that is missing or
+/// isn't a folder is a usage mistake, anything else an error.
+fn exit_code(error: &DirstampError) -> i32 {
+    match error {
+        DirstampError::RootNotFound(_) | DirstampError::NotADirectory(_) => EXIT_USAGE,
+        DirstampError::Walk { .. }
+        | DirstampError::ReadMtime { .. }
+        | DirstampError::ReadDir { .. }
+        | DirstampError::SetMtime { .. } => EXIT_ERRORS,
+    }
+}
+
+/// Stop the run over `error`, in the binary's words.
+fn error_exit(error: &DirstampError) -> ! {
+    match error {
+        DirstampError::RootNotFound(path) => eprintln!("Path does not exist: {}", path.display()),
+        DirstampError::NotADirectory(path) => eprintln!("Not a directory: {}", path.display()),
+        other => eprintln!("{other}"),
+    }
+    std::process::exit(exit_code(error))
+}
+
+/// Say where an interrupted run stopped and exit with `EXIT_INTERRUPTED`.
+fn interrupted_exit(report: &dirstamp::Report) -> ! {
+    let held = report.changes_planned - report.changes_applied - report.changes_failed;
+    eprintln!(
+        "Interrupted: stopped after {} folder(s) ({} updated, {held} planned, {} failed); \
+         the rest were not examined.",
+        report.dirs_scanned, report.changes_applied, report.changes_failed
+    );
+    std::process::exit(EXIT_INTERRUPTED)
+}
+
+/// The run's totals as the library's `Report`: what the summary, the
+/// `--output-json` summary and the exit code go by. Only counts: the
+/// changes were reported as they came and aren't kept, so a streamed run
+/// doesn't grow with the tree. The errors move over from `stats`.
+fn run_report(
+    counts: &Counts,
+    skipped: HashMap<SkipReason, usize>,
+    stats: &mut Stats,
+    elapsed: Duration,
+) -> dirstamp::Report {
+    let errors = std::mem::take(&mut stats.errors);
+    let walk_errors = errors
+        .iter()
+        .filter(|e| matches!(e, DirstampError::Walk { .. }))
+        .count();
+    dirstamp::Report {
+        dirs_scanned: counts.scanned,
+        changes_planned: counts.planned + counts.applied + counts.failed,
+        changes_applied: counts.applied,
+        changes_failed: counts.failed,
+        walk_errors,
+        skipped,
+        errors,
+        elapsed,
+        max_drift: Duration::from_secs_f64(counts.max_drift_secs),
+        changes: Vec::new(),
     }
+}
+
+/// The current time, or `--pretend-time` when given. Use this rather than
+/// `SystemTime::now()` so pretend runs are reproducible.
+fn now(cfg: &Config) -> SystemTime {
+    cfg.pretend_time.unwrap_or_else(SystemTime::now)
+}
+
+/// The command-line options as one printable line, for report headers.
+fn options_summary() -> String {
+    let args: Vec<String> = env::args_os()
+        .skip(1)
+        .map(|a| a.to_string_lossy().replace(char::is_control, "?"))
+        .collect();
+    args.join(" ")
+}
 
-    Ok(newest_file.or(newest_dir))
+fn depth_of(path: &Path) -> usize {
+    path.components().count()
 }
 
 fn set_folder_mtime(path: &Path, mtime: SystemTime) -> io::Result<()> {
-    let ft = FileTime::from_system_time(mtime);
-    set_file_mtime(path, ft)
+    timing::count(Call::Set, 1);
+    timing::timed(Call::Set, path, || dirstamp::set_mtime(path, mtime))
 }
 
-fn main() -> io::Result<()> {
-// ---- parse CLI -------------------------------------------------------------
-let mut confirm = false;
-let mut show_dates = false;
-let mut path_arg: Option<PathBuf> = None;
-
-let mut end_of_opts = false;
-for arg in env::args().skip(1) {
-    if !end_of_opts {
-        match arg.as_str() {
-            "-h" | "--help" => print_help_and_exit(),
-            "-V" | "--version" => print_version_and_exit(),
-            "-C" | "--confirm" => { confirm = true; continue; }
-            "-D" | "--show-dates" => { show_dates = true; continue; }
-            "--" => { end_of_opts = true; continue; } // everything after is positional
-            s if s.starts_with('-') => {
-                eprintln!("Unknown option: {s}");
-                print_help_and_exit();
-            }
-            _ => { /* fall through to treat as positional PATH below */ }
-        }
-    }
-
-    // Positional PATH handling (first one wins)
-    if path_arg.is_none() {
-        path_arg = Some(PathBuf::from(&arg));
-    } else {
-        eprintln!("Unexpected extra argument: {}", arg);
-        print_help_and_exit();
+/// Only act if the delta exceeds the tolerance, to avoid noisy rewrites on coarse filesystems.
+fn differs(a: SystemTime, b: SystemTime) -> bool {
+    dirstamp::differs(a, b, dirstamp::DEFAULT_TOLERANCE)
+}
+
+/// `--verify` allows this much difference, so FAT's 2-second resolution passes.
+const VERIFY_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// `--verify`: re-read each applied folder's mtime and report those that
+/// aren't within `VERIFY_TOLERANCE` of what was set. Returns how many failed.
+fn verify_applied(applied: &BTreeMap<PathBuf, SystemTime>) -> usize {
+    let mut failed = 0;
+    for (path, target) in applied {
+        let actual = fs::metadata(path).and_then(|m| m.modified());
+        let off = match &actual {
+            Ok(actual) => actual
+                .duration_since(*target)
+                .unwrap_or_else(|e| e.duration()),
+            Err(_) => Duration::MAX,
+        };
+        if off <= VERIFY_TOLERANCE {
+            continue;
+        }
+        failed += 1;
+        match actual {
+            Ok(actual) => eprintln!(
+                "verify failed: {:?} has mtime {}, expected {}",
+                path,
+                DateFormat::utc().render(actual),
+                DateFormat::utc().render(*target)
+            ),
+            Err(e) => eprintln!("verify failed: {:?} ({e})", path),
+        }
+    }
+    failed
+}
+
+/// Read a directory's current mtime, counting (and maybe printing) a failure.
+fn read_mtime(path: &Path, stats: &mut Stats) -> Option<SystemTime> {
+    match stats.retry.run(|| own_mtime(path)) {
+        Ok(t) => Some(t),
+        Err(e) => {
+            let category = ErrorCategory::classify(Some(e.kind()));
+            if stats.note_error(category, DirRead::Mtime.error(path, &e)) {
+                eprintln!("skipped (mtime read failed): {:?} ({e})", path);
+            }
+            None
+        }
+    }
+}
+
+/// Whether to set `change`'s mtime: `false` leaves it planned, as in a dry
+/// run, and once `--limit` is used up the rest of a `-C` run is. `Err` says
+/// why it was left alone after all: declined at the `--interactive` prompt,
+/// or changed since it was scanned (`--verify-before-apply`).
+fn prepare_stamp(
+    change: &mut Change,
+    confirm: bool,
+    stats: &mut Stats,
+) -> Result<bool, SkipReason> {
+    if confirm && stats.limit_left == Some(0) {
+        return Ok(false);
+    }
+    if let Some(prompter) = stats.prompter.as_mut().filter(|_| confirm) {
+        if !prompter.approve(change) {
+            return Err(SkipReason::Declined);
+        }
     }
+    if let Some(recheck) = stats.recheck.filter(|_| confirm) {
+        recheck_before_apply(change, recheck, stats)?;
+    }
+    // In a dry run too, so it shows what the hook would let through.
+    if let Some(hook) = &stats.before_stamp {
+        before_stamp(hook, change)?;
+    }
+    if !confirm {
+        return Ok(false);
+    }
+    if let Some(journal) = &mut stats.journal {
+        // Never make a change that couldn't be undone. The run stops as
+        // --fail-fast stops it, so the log and summary are still finished.
+        if let Err(e) = journal.record(change) {
+            eprintln!(
+                "Cannot write undo journal {:?} ({e}); stopping before any further changes",
+                journal.path()
+            );
+            interrupt::fail("the undo journal couldn't be written");
+            return Err(SkipReason::Error);
+        }
+    }
+    if let Some(left) = &mut stats.limit_left {
+        *left -= 1;
+    }
+    Ok(true)
 }
 
-let root: PathBuf = path_arg.unwrap_or_else(|| PathBuf::from("."));
-if !root.exists() {
-    eprintln!("Path does not exist: {}", root.display());
-    std::process::exit(2);
+/// After a folder's mtime is set: count a failed set, or note a good one.
+fn stamped(change: &Change, set: &io::Result<()>, stats: &mut Stats) -> Status {
+    if let Err(e) = set {
+        let error = DirstampError::SetMtime {
+            path: change.path.clone(),
+            source: copied(e),
+        };
+        let show = stats.note_error(ErrorCategory::SetMtime, error);
+        if e.kind() != io::ErrorKind::ReadOnlyFilesystem {
+            if show {
+                eprintln!("skipped (set mtime failed): {:?} ({e})", change.path);
+            }
+        } else if show && stats.read_only_mounts.first_failure(&change.path) {
+            eprintln!(
+                "skipped (set mtime failed): {:?} ({e}); \
+                 other folders on this read-only mount are skipped without a message",
+                change.path
+            );
+        }
+        Status::Failed
+    } else {
+        if let Some(applied) = &mut stats.applied {
+            applied.insert(change.path.clone(), change.to);
+        }
+        if stats.touch_symlinks {
+            touch_source_link(change, stats);
+        }
+        // Once --fail-fast has stopped the run, no other command runs.
+        if let Some(hook) = stats.after_stamp.as_ref().filter(|_| !interrupt::failed()) {
+            after_stamp(hook, change, stats.fail_fast);
+        }
+        Status::Applied
+    }
 }
 
+/// A `--before-stamp` or `--after-stamp` hook, writing times as `-D` would.
+fn hook(command: String, cfg: &Config) -> Hook {
+    // Already validated by the reporter.
+    let dates = DateFormat::from_config(cfg).ok().flatten();
+    let to_stderr =
+        cfg.porcelain || cfg.output_json || (cfg.emit_script && cfg.script_file.is_none());
+    Hook::new(command, dates.unwrap_or_else(DateFormat::utc), to_stderr)
+}
 
-    // ---- collect directories and process child-before-parent ----
-    let mut dirs: Vec<DirEntry> = Vec::new();
-    for entry in WalkDir::new(&root).follow_links(true) {
-        match entry {
-            Ok(e) if is_dir(&e) => dirs.push(e),
-            Ok(_) => {}
-            Err(err) => eprintln!("skipped (walk error): {err}"),
+/// Run `hook` for a folder about to be stamped: anything but success leaves
+/// the folder alone, as `declined`.
+fn before_stamp(hook: &Hook, change: &Change) -> Result<(), SkipReason> {
+    match hook.run(change) {
+        Ok(status) if status.success() => Ok(()),
+        Ok(_) => Err(SkipReason::Declined),
+        Err(e) => {
+            eprintln!(
+                "warning: --before-stamp could not run sh for {:?} ({e}); leaving it alone",
+                change.path
+            );
+            Err(SkipReason::Declined)
         }
     }
-    // Deeper paths first ⇒ children stamped before parents.
-    dirs.sort_by_key(|e| Reverse(depth_of(&e.path())));
+}
+
+/// Run `hook` for a folder just stamped. A failure is warned about, or with
+/// `--fail-fast` stops the run, which then finishes as an interrupted one
+/// would and exits 3; the folder keeps its new mtime either way.
+fn after_stamp(hook: &Hook, change: &Change, fail_fast: bool) {
+    let problem = match hook.run(change) {
+        Ok(status) if status.success() => return,
+        Ok(status) => status.to_string(),
+        Err(e) => format!("could not run sh: {e}"),
+    };
+    if fail_fast {
+        eprintln!(
+            "--after-stamp failed for {:?} ({problem}); stopping (--fail-fast)",
+            change.path
+        );
+        interrupt::fail("an --after-stamp command failed");
+        return;
+    }
+    eprintln!(
+        "warning: --after-stamp failed for {:?} ({problem})",
+        change.path
+    );
+}
 
-    let one_sec = Duration::from_secs(1);
-    let mut updated_count = 0usize;
+/// `--touch-symlinks`: if `change` took its time from a symlink, give the
+/// link that time too (not following it), so it orders with its folder. A
+/// link that can't be set is only warned about; the folder is stamped.
+fn touch_source_link(change: &Change, stats: &mut Stats) {
+    let Some(name) = &change.source_child else {
+        return;
+    };
+    let link = change.path.join(name);
+    let Ok(meta) = fs::symlink_metadata(&link) else {
+        return;
+    };
+    if !meta.file_type().is_symlink() || meta.modified().ok() == Some(change.to) {
+        return;
+    }
+    let atime = FileTime::from_last_access_time(&meta);
+    let mtime = FileTime::from_system_time(change.to);
+    match filetime::set_symlink_file_times(&link, atime, mtime) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            if !stats.symlink_times_unsupported {
+                stats.symlink_times_unsupported = true;
+                eprintln!(
+                    "warning: this platform can't set a symlink's own mtime; --touch-symlinks leaves links as they are"
+                );
+            }
+        }
+        Err(e) => eprintln!("warning: cannot set the mtime of symlink {:?} ({e})", link),
+    }
+}
 
-    // Prepare formatter for dates if requested.
-    let fmt = if show_dates {
-        Some(parse_format("[year]-[month]-[day] [hour]:[minute]:[second] UTC").expect("valid time format"))
+/// `--verify-before-apply`: look at the folder once more right before it is
+/// set. If its mtime or (for child-driven changes) its newest child moved
+/// since the scan, skip it, or with `--recompute` take the fresh values.
+fn recheck_before_apply(
+    change: &mut Change,
+    recheck: Recheck,
+    stats: &mut Stats,
+) -> Result<(), SkipReason> {
+    let current = read_mtime(&change.path, stats).ok_or(SkipReason::Error)?;
+    // Copied and clamped times don't come from the children.
+    let child_driven = change.copied_from.is_none()
+        && change
+            .source_child
+            .as_deref()
+            .is_some_and(|c| c != Path::new(".."));
+    let latest = if child_driven {
+        match stats.retry.run(|| read_children(&change.path)) {
+            Ok(mut children) => {
+                stats.child_rules.apply(&mut children);
+                let strategy = &stats.child_rules.strategy;
+                scan::target_child(&change.path, children, strategy, |_| None, |_, _| {})
+            }
+            Err(e) => {
+                let category = ErrorCategory::classify(Some(e.kind()));
+                if stats.note_error(category, DirRead::Children.error(&change.path, &e)) {
+                    eprintln!("skipped (child scan failed): {:?} ({e})", change.path);
+                }
+                return Err(SkipReason::Error);
+            }
+        }
     } else {
         None
     };
+    let child_moved = child_driven && latest.as_ref().is_none_or(|l| differs(l.mtime, change.to));
+    if !differs(current, change.from) && !child_moved {
+        return Ok(());
+    }
+    stats.changed_since_scan += 1;
+    if recheck == Recheck::Skip {
+        eprintln!("skipped (changed since scan): {:?}", change.path);
+        return Err(SkipReason::ChangedSinceScan);
+    }
+    change.from = current;
+    if let Some(latest) = latest {
+        change.to = latest.mtime;
+        change.source_child = latest.name.map(PathBuf::from);
+    } else if child_driven {
+        // The folder has emptied since the scan.
+        return Err(SkipReason::Empty);
+    }
+    if differs(change.to, change.from) {
+        Ok(())
+    } else {
+        Err(SkipReason::InSync)
+    }
+}
+
+/// Every directory under `root` (inclusive) that `steps` walk to, deepest
+/// first so children are stamped before their parents. What the walk
+/// skipped is counted and reported once it is done, or `None` if it found
+/// more than the `--shard` budget, whose parts are walked (and report it)
+/// again.
+fn collect_dirs(
+    root: &Path,
+    steps: &mut CliSteps,
+    reporter: &mut Reporter,
+) -> Option<Vec<PathBuf>> {
+    let dirs = {
+        let _discovery = timing::phase(Phase::Discovery);
+        dirstamp::collect_dirs(
+            root,
+            &mut dirstamp::Plan::default(),
+            steps,
+            &dirstamp::NoEvents,
+        )
+    };
+    if mem::take(&mut steps.over) {
+        steps.walked.clear();
+        return None;
+    }
+    for step in mem::take(&mut steps.walked) {
+        skip_walked(step, steps.stats, reporter);
+    }
+    Some(dirs)
+}
+
+/// Count and report a walk step that isn't a folder to visit.
+fn skip_walked(step: Walked, stats: &mut Stats, reporter: &mut Reporter) {
+    match step {
+        Walked::Dir(..) => {}
+        Walked::OtherDevice(dir) => reporter.skip(&dir, SkipReason::OtherDevice),
+        Walked::Error(err) => {
+            note_walk_error(&err, stats);
+            if let Some(path) = &err.path {
+                reporter.unwalked(path, SkipReason::Error);
+            }
+        }
+        Walked::TimedOut(dir, e) => {
+            let reason = scan_failed(&dir, &e, DirRead::Walk, stats);
+            reporter.unwalked(&dir, reason);
+        }
+    }
+}
 
-    for entry in dirs {
-        let path = entry.path();
+fn note_walk_error(err: &walk::WalkError, stats: &mut Stats) {
+    let error = DirstampError::Walk {
+        path: err.path.clone().unwrap_or_default(),
+        source: io::Error::new(err.kind.unwrap_or(io::ErrorKind::Other), err.to_string()),
+    };
+    if stats.note_error(ErrorCategory::classify(err.kind), error) {
+        eprintln!("skipped (walk error): {err}");
+    }
+}
 
-        // Current dir mtime
-        let dir_mtime = match fs::metadata(&path).and_then(|m| m.modified()) {
-            Ok(t) => t,
-            Err(e) => {
-                eprintln!("skipped (mtime read failed): {:?} ({e})", path);
-                continue;
+/// Walk `root` and stamp every directory, children before parents.
+/// Returns the folders whose mtime was set.
+fn stamp_tree(
+    root: &Path,
+    cfg: &Config,
+    filter: Option<&DirFilter>,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<Vec<PathBuf>> {
+    // Each stamp made or planned is remembered, so parents see the value
+    // their subfolders have after -C, in a dry run too.
+    let mut planned: HashMap<PathBuf, SystemTime> = HashMap::new();
+    if cfg.shard {
+        let mut set = Vec::new();
+        shard_tree(
+            root,
+            root,
+            cfg,
+            filter,
+            &mut planned,
+            &mut set,
+            stats,
+            reporter,
+        )?;
+        return Ok(set);
+    }
+    let set = stamp_subtree(root, root, cfg, filter, &mut planned, stats, reporter)?;
+    Ok(set.unwrap_or_default())
+}
+
+/// `--shard`: stamp each folder in `top` as a tree of its own, one after
+/// another, then `top` itself. One that is too big for `--max-memory` is
+/// split the same way. Only the mtimes the shards' tops were given are kept
+/// from one shard to the next; `planned` gets the one `top` is given.
+#[allow(clippy::too_many_arguments)]
+fn shard_tree(
+    top: &Path,
+    root: &Path,
+    cfg: &Config,
+    filter: Option<&DirFilter>,
+    planned: &mut HashMap<PathBuf, SystemTime>,
+    set: &mut Vec<PathBuf>,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<()> {
+    if !DeviceFilter::new(cfg).wants_dir(top) {
+        reporter.skip(top, SkipReason::OtherDevice);
+        return Ok(());
+    }
+    let shards = {
+        let _discovery = timing::phase(Phase::Discovery);
+        walk::subfolders(top, |step| skip_walked(step, stats, reporter))
+    };
+    let mut below = HashMap::new();
+    for shard in shards {
+        if interrupt::requested() {
+            return Ok(());
+        }
+        let mut shard_planned = HashMap::new();
+        match stamp_subtree(
+            &shard,
+            root,
+            cfg,
+            filter,
+            &mut shard_planned,
+            stats,
+            reporter,
+        )? {
+            Some(shard_set) => set.extend(shard_set),
+            None => shard_tree(
+                &shard,
+                root,
+                cfg,
+                filter,
+                &mut shard_planned,
+                set,
+                stats,
+                reporter,
+            )?,
+        }
+        if let Some(mtime) = shard_planned.remove(&shard) {
+            below.insert(shard, mtime);
+        }
+        reporter.end_shard();
+    }
+    if interrupt::requested() {
+        return Ok(());
+    }
+    let mut steps = CliSteps::new(root, cfg, filter, stats);
+    let status = visit_dir(top, &mut steps, &mut below, reporter)?;
+    if status == Some(Status::Applied) {
+        set.push(top.to_path_buf());
+    }
+    if let Some(mtime) = below.remove(top) {
+        planned.insert(top.to_path_buf(), mtime);
+    }
+    Ok(())
+}
+
+/// `stamp_tree` for the folders under `top` (and `top` itself), with
+/// `root` as the top of the tree for the options that look at it. `None`
+/// if `--shard` wants the folders buffered and there are too many.
+fn stamp_subtree(
+    top: &Path,
+    root: &Path,
+    cfg: &Config,
+    filter: Option<&DirFilter>,
+    planned: &mut HashMap<PathBuf, SystemTime>,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<Option<Vec<PathBuf>>> {
+    let jobs = cfg.jobs.unwrap_or(1);
+    // --jobs works a depth at a time, --limit takes the deepest changes
+    // first, --atomic plans everything before it sets anything, and
+    // --git-changed has its list already.
+    let stream =
+        !cfg.buffered && !cfg.atomic && jobs <= 1 && cfg.limit.is_none() && stats.scope.is_none();
+    if stream {
+        return stream_tree(top, root, cfg, filter, planned, stats, reporter).map(Some);
+    }
+    // Collected a while ago, so each folder's mtime is read afresh.
+    let mut steps = CliSteps::new(root, cfg, filter, stats);
+    steps.budget = cfg.entry_budget().filter(|_| cfg.shard);
+    let Some(dirs) = collect_dirs(top, &mut steps, reporter) else {
+        return Ok(None);
+    };
+    let mut set = Vec::new();
+    let levels = dirs.chunk_by(|a, b| depth_of(a) == depth_of(b));
+
+    // --atomic plans the whole tree first, then applies it.
+    if cfg.atomic && cfg.confirm {
+        let events = ReportEvents::planning(reporter);
+        let plan = dirstamp::plan_dirs(&dirs, planned, &mut steps, &events);
+        events.finish()?;
+        if interrupt::requested() {
+            return Ok(Some(set));
+        }
+        return apply_atomically(plan.changes, &mut steps, reporter).map(Some);
+    }
+    // A depth at a time, so the report keeps up with the tree. Under --jobs
+    // a depth's folders don't depend on one another: their reads, and then
+    // their mtime sets, are made on that many threads.
+    for level in levels {
+        if interrupt::requested() {
+            break;
+        }
+        if jobs > 1 {
+            steps.prefetch(level, jobs);
+        }
+        let events = ReportEvents::planning(reporter);
+        let plan = dirstamp::plan_dirs(level, planned, &mut steps, &events);
+        events.finish()?;
+        let events = ReportEvents::applying(reporter);
+        let applied = dirstamp::apply_steps(&plan, planned, &mut steps, &events);
+        events.finish()?;
+        let applied = applied.changes.into_iter();
+        set.extend(applied.filter_map(|(c, s)| (s == Status::Applied).then_some(c.path)));
+    }
+    Ok(Some(set))
+}
+
+/// The binary's `dirstamp::Steps`: the walk with its device filters, the
+/// reads with their retries and timeout, the child rules and the options
+/// around the pick, and each stamp's prompt, journal and hooks. Errors are
+/// counted in `stats` instead, and what the walk skipped is held for the
+/// reporter, which the library's steps don't see.
+struct CliSteps<'a> {
+    root: &'a Path,
+    cfg: &'a Config,
+    filter: Option<&'a DirFilter>,
+    stats: &'a mut Stats,
+    /// `--shard`: how many folders (and other steps) a walk may find.
+    budget: Option<usize>,
+    /// Set once a walk found more.
+    over: bool,
+    /// The walk's other steps, for `skip_walked` once it is done.
+    walked: Vec<Walked>,
+    /// Reads made before a folder's turn: from the walk, or a `--jobs` level's.
+    ahead: HashMap<PathBuf, DirReads>,
+}
+
+impl<'a> CliSteps<'a> {
+    fn new(
+        root: &'a Path,
+        cfg: &'a Config,
+        filter: Option<&'a DirFilter>,
+        stats: &'a mut Stats,
+    ) -> Self {
+        CliSteps {
+            root,
+            cfg,
+            filter,
+            stats,
+            budget: None,
+            over: false,
+            walked: Vec::new(),
+            ahead: HashMap::new(),
+        }
+    }
+
+    /// Make `dir`'s reads now, but for what the walk has `seen` of it.
+    fn read_ahead(&mut self, dir: &Path, seen: Seen) {
+        let _planning = timing::phase(Phase::Planning);
+        let stats = &mut *self.stats;
+        let reads = read_dir_state(dir, seen, &mut stats.retry, &mut stats.watchdog);
+        self.ahead.insert(dir.to_path_buf(), reads);
+    }
+
+    /// `--jobs`: read a depth's folders on `jobs` threads before it is planned.
+    fn prefetch(&mut self, level: &[PathBuf], jobs: usize) {
+        let _planning = timing::phase(Phase::Planning);
+        let retry = self.stats.retry.fresh();
+        let reads = jobs::map(jobs, level, |path| {
+            let mut retry = retry.fresh();
+            let reads = read_dir_state(path, Seen::default(), &mut retry, &mut None);
+            (reads, retry)
+        });
+        for (path, (reads, counted)) in level.iter().zip(reads) {
+            self.stats.retry.absorb(counted);
+            self.ahead.insert(path.clone(), reads);
+        }
+    }
+}
+
+impl dirstamp::Steps for CliSteps<'_> {
+    fn policy(&self) -> &dyn scan::SelectionPolicy {
+        &self.stats.child_rules.strategy
+    }
+
+    fn walk(&mut self, root: &Path, dirs: &mut Vec<PathBuf>, _: &mut dirstamp::Plan) {
+        let devices = DeviceFilter::new(self.cfg);
+        // --git-changed's folders, held to the device filters as a walk is.
+        if let Some(scope) = self.stats.scope.take() {
+            for dir in scope {
+                if devices.wants_dir(&dir) {
+                    dirs.push(dir);
+                } else {
+                    self.walked.push(Walked::OtherDevice(dir));
+                }
+            }
+            return;
+        }
+        let budget = self.budget;
+        let (walked, over) = (&mut self.walked, &mut self.over);
+        let emit = |step| {
+            match step {
+                Walked::Dir(dir, ..) => dirs.push(dir),
+                other => walked.push(other),
+            }
+            if budget.is_some_and(|n| dirs.len() + walked.len() > n) {
+                *over = true;
+                return false;
             }
+            // Ctrl-C stops the walk too.
+            !interrupt::requested()
         };
+        match self.cfg.jobs {
+            Some(jobs) if jobs > 1 => walk::walk_parallel(root, &devices, jobs, emit),
+            _ => walk::walk(root, &devices, self.stats.watchdog.as_mut(), emit),
+        }
+    }
 
-        // Newest immediate child (file preferred, else subdir)
-        let latest = match find_latest_mtime(&path) {
-            Ok(Some(t)) => t,
-            Ok(None) => continue, // empty dir
-            Err(e) => {
-                eprintln!("skipped (child scan failed): {:?} ({e})", path);
-                continue;
+    fn read(
+        &mut self,
+        dir: &Path,
+        _: &mut Vec<DirstampError>,
+    ) -> Result<(SystemTime, Vec<Child>), SkipReason> {
+        let _planning = timing::phase(Phase::Planning);
+        let ahead = self.ahead.remove(dir);
+        // --no-update-top: the root is depth 0.
+        let depth = dir
+            .strip_prefix(self.root)
+            .map_or(usize::MAX, |rel| rel.components().count());
+        if depth < self.cfg.no_update_top {
+            return Err(SkipReason::TopLevel);
+        }
+        let stats = &mut *self.stats;
+        let reads = ahead.unwrap_or_else(|| {
+            read_dir_state(dir, Seen::default(), &mut stats.retry, &mut stats.watchdog)
+        });
+        let (mtime, mut children) = reads.map_err(|(e, read)| scan_failed(dir, &e, read, stats))?;
+        stats.child_rules.apply(&mut children);
+        Ok((mtime, children))
+    }
+
+    fn consider(&mut self, _: &Path, children: &[Child]) -> Result<(), SkipReason> {
+        // --newer-ref: nothing in here changed since the reference was
+        // touched. Subfolders count with the mtime they are getting.
+        if let Some(reference) = self.stats.newer_ref {
+            if !children.iter().any(|c| c.mtime > reference) {
+                return Err(SkipReason::NotNewer);
             }
-        };
+        }
+        if let Some(extensions) = &mut self.stats.extensions {
+            for child in children.iter().filter(|c| c.kind != scan::Kind::Dir) {
+                extensions.add(&child.name, child.mtime);
+            }
+        }
+        Ok(())
+    }
 
-        // Tolerance: only act if delta > 1s to avoid noisy rewrites on coarse FS
-        let needs_change = latest > dir_mtime + one_sec || latest + one_sec < dir_mtime;
-        if !needs_change {
-            continue;
+    // --clamp-to-parent: never newer than the parent. Parents are stamped
+    // after their children, so this is the parent's pre-run mtime. The
+    // root's parent is outside the tree and isn't consulted.
+    fn ceiling(&mut self, dir: &Path) -> Option<SystemTime> {
+        if !self.cfg.clamp_to_parent || dir == self.root {
+            return None;
         }
+        let parent = dir.parent()?;
+        fs::metadata(parent).and_then(|m| m.modified()).ok()
+    }
 
-        // Optional verbose strings
-        let maybe_dates = if let Some(f) = &fmt {
-            let from_s = OffsetDateTime::from(dir_mtime)
-                .format(f)
-                .unwrap_or_else(|_| "<bad time>".into());
-            let to_s = OffsetDateTime::from(latest)
-                .format(f)
-                .unwrap_or_else(|_| "<bad time>".into());
-            let days = match latest.duration_since(dir_mtime) {
-                Ok(d) => d.as_secs_f64() / 86_400.0,
-                Err(e) => -(e.duration().as_secs_f64() / 86_400.0),
-            };
-            Some((from_s, to_s, days))
-        } else {
-            None
-        };
+    fn wanted(&mut self, change: &Change) -> bool {
+        let _planning = timing::phase(Phase::Planning);
+        selected(self.filter, change, self.root)
+    }
 
-        if confirm {
-            if let Err(e) = set_folder_mtime(&path, latest) {
-                eprintln!("skipped (set mtime failed): {:?} ({e})", path);
-                continue;
+    fn prepare(
+        &mut self,
+        change: &mut Change,
+        _: &mut Vec<DirstampError>,
+    ) -> Result<bool, SkipReason> {
+        let _applying = timing::phase(Phase::Applying);
+        prepare_stamp(change, self.cfg.confirm, self.stats)
+    }
+
+    /// On `--jobs` threads, each set waiting its turn under `--rate-limit`.
+    fn set(&mut self, changes: &[&Change]) -> Vec<io::Result<()>> {
+        let _applying = timing::phase(Phase::Applying);
+        let retry = self.stats.retry.fresh();
+        let throttle = &self.stats.throttle;
+        let sets = jobs::map(self.cfg.jobs.unwrap_or(1), changes, |change| {
+            let mut retry = retry.fresh();
+            let set = retry.run(|| {
+                throttle.wait();
+                set_folder_mtime(&change.path, change.to)
+            });
+            (set, retry)
+        });
+        sets.into_iter()
+            .map(|(set, counted)| {
+                self.stats.retry.absorb(counted);
+                set
+            })
+            .collect()
+    }
+
+    fn stamped(&mut self, change: &Change, result: &io::Result<()>) {
+        stamped(change, result, self.stats);
+    }
+
+    // Ctrl-C stops between folders, never halfway through one.
+    fn stop(&self) -> bool {
+        interrupt::requested()
+    }
+}
+
+/// `--atomic`: make every change, deepest first, or if one fails (or the run
+/// is interrupted) set the folders already changed back to their old mtimes.
+/// Those are reported as skipped with an error, as is anything not reached.
+/// Returns the folders that keep a new mtime.
+fn apply_atomically(
+    changes: Vec<Change>,
+    steps: &mut CliSteps,
+    reporter: &mut Reporter,
+) -> io::Result<Vec<PathBuf>> {
+    let mut done = Vec::new();
+    let mut failed = None;
+    let mut changes = changes.into_iter();
+    for change in changes.by_ref() {
+        let path = change.path.clone();
+        match stamp_one(change, steps, &dirstamp::NoEvents) {
+            Some(Ok((change, Status::Applied))) => done.push(change),
+            Some(Ok((change, Status::Failed))) => {
+                failed = Some(change);
+                break;
             }
-            if let Some((from_s, to_s, days)) = &maybe_dates {
-                println!(
-                    "updated {:?} (from {} to {}, {:+.1} days)",
-                    path, from_s, to_s, days
-                );
-            } else {
-                println!("updated {:?}", path);
+            // Held back by --limit.
+            Some(Ok((change, status))) => reporter.record(change, status)?,
+            Some(Err(reason)) => reporter.skip(&path, reason),
+            // Interrupted.
+            None => {
+                reporter.skip(&path, SkipReason::Error);
+                break;
             }
-        } else {
-            if let Some((from_s, to_s, days)) = &maybe_dates {
-                println!(
-                    "would update {:?} (from {} to {}, {:+.1} days)",
-                    path, from_s, to_s, days
+        }
+    }
+    let stats = &mut *steps.stats;
+    if failed.is_none() && !interrupt::requested() {
+        let set = done.iter().map(|c| c.path.clone()).collect();
+        for change in done {
+            reporter.record(change, Status::Applied)?;
+        }
+        return Ok(set);
+    }
+
+    let mut restored = 0;
+    let mut kept = Vec::new();
+    for change in done.into_iter().rev() {
+        let restore = stats
+            .retry
+            .run(|| set_folder_mtime(&change.path, change.from));
+        match restore {
+            Ok(()) => {
+                if let Some(applied) = &mut stats.applied {
+                    applied.remove(&change.path);
+                }
+                restored += 1;
+                reporter.skip(&change.path, SkipReason::Error);
+            }
+            Err(e) => {
+                let error = DirstampError::SetMtime {
+                    path: change.path.clone(),
+                    source: copied(&e),
+                };
+                stats.note_error(ErrorCategory::SetMtime, error);
+                eprintln!(
+                    "rollback failed: {:?} keeps its new mtime ({e})",
+                    change.path
                 );
-            } else {
-                println!("would update {:?}", path);
+                kept.push(change.path.clone());
+                reporter.record(change, Status::Applied)?;
             }
         }
+    }
+    for change in changes {
+        reporter.skip(&change.path, SkipReason::Error);
+    }
+    let cause = if failed.is_some() {
+        "a folder couldn't be set"
+    } else {
+        interrupt::cause().unwrap_or("the run was interrupted")
+    };
+    eprintln!("--atomic: {cause}; set {restored} folder(s) back to their old mtimes");
+    if let Some(change) = failed {
+        reporter.record(change, Status::Failed)?;
+    }
+    Ok(kept)
+}
+
+/// `stamp_subtree` without collecting the folders first: each one is
+/// stamped as soon as the walk has left it, so memory grows with the depth
+/// of the tree rather than its size and the report starts at once.
+#[allow(clippy::too_many_arguments)]
+fn stream_tree(
+    top: &Path,
+    root: &Path,
+    cfg: &Config,
+    filter: Option<&DirFilter>,
+    planned: &mut HashMap<PathBuf, SystemTime>,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<Vec<PathBuf>> {
+    let devices = DeviceFilter::new(cfg);
+    // The walk gets a watchdog of its own; the one in `stats` is the scans'.
+    let mut walk_watchdog = cfg.dir_timeout.map(|t| Watchdog::new(t, show_status(cfg)));
+    let mut cache = stats.cache.take();
+    let mut steps = CliSteps::new(root, cfg, filter, stats);
+    let mut set = Vec::new();
+    let mut visit = |(path, seen, done): OpenDir, steps: &mut CliSteps, reporter: &mut Reporter| {
+        steps.read_ahead(&path, seen);
+        let status = visit_dir(&path, steps, planned, reporter)?;
+        // Only this folder needed the mtimes its subfolders were given.
+        for sub in done {
+            planned.remove(&sub);
+        }
+        if status == Some(Status::Applied) {
+            set.push(path.clone());
+        }
+        io::Result::Ok(path)
+    };
 
-        updated_count += 1;
+    // The folders the walk is inside, outermost first, with what it read of
+    // them and their subfolders visited so far. The walk lists a folder
+    // before its subfolders, in name order, so any that isn't an ancestor of
+    // the next one is finished with. Their children were listed before the
+    // subfolders among them were stamped; `planned` has the mtimes those
+    // were given.
+    let mut open: Vec<OpenDir> = Vec::new();
+    let mut failed = None;
+    let discovery = timing::phase(Phase::Discovery);
+    walk::walk_listing(
+        top,
+        &devices,
+        walk_watchdog.as_mut(),
+        cache.as_mut(),
+        |step| {
+            let Walked::Dir(dir, mtime, children) = step else {
+                skip_walked(step, steps.stats, reporter);
+                return !interrupt::requested();
+            };
+            while let Some(done) = open.pop_if(|(last, ..)| !dir.starts_with(last)) {
+                // Ctrl-C stops between folders, never halfway through one.
+                if interrupt::requested() {
+                    return false;
+                }
+                match visit(done, &mut steps, reporter) {
+                    Ok(path) => {
+                        if let Some((.., parent_done)) = open.last_mut() {
+                            parent_done.push(path);
+                        }
+                    }
+                    Err(e) => {
+                        failed = Some(e);
+                        return false;
+                    }
+                }
+            }
+            open.push((dir, Seen { mtime, children }, Vec::new()));
+            !interrupt::requested()
+        },
+    );
+    drop(discovery);
+    // A walk cut short listed only part of the tree; the last whole one stays.
+    if let Some(cache) = &mut cache {
+        if failed.is_none() && !interrupt::requested() {
+            cache.finish_run();
+        }
+    }
+    steps.stats.cache = cache;
+    if let Some(e) = failed {
+        return Err(e);
+    }
+    while let Some(done) = open.pop() {
+        if interrupt::requested() {
+            break;
+        }
+        let path = visit(done, &mut steps, reporter)?;
+        if let Some((.., parent_done)) = open.last_mut() {
+            parent_done.push(path);
+        }
     }
+    Ok(set)
+}
+
+/// A folder `stream_tree` is inside: what the walk read of it, and its
+/// subfolders already visited.
+type OpenDir = (PathBuf, Seen, Vec<PathBuf>);
+
+/// Plan one folder and stamp it through the library, or report why it was
+/// left alone. Returns what became of its change, if it had one.
+fn visit_dir(
+    path: &Path,
+    steps: &mut CliSteps,
+    planned: &mut HashMap<PathBuf, SystemTime>,
+    reporter: &mut Reporter,
+) -> io::Result<Option<Status>> {
+    let dirs = [path.to_path_buf()];
+    let events = ReportEvents::planning(reporter);
+    let plan = dirstamp::plan_dirs(&dirs, planned, steps, &events);
+    events.finish()?;
+    let events = ReportEvents::applying(reporter);
+    let applied = dirstamp::apply_steps(&plan, planned, steps, &events);
+    events.finish()?;
+    Ok(applied.changes.first().map(|&(_, status)| status))
+}
 
-    if updated_count == 0 {
-        println!("No folder timestamps needed updating.");
-    } else if !confirm {
-        println!("\nNote: this was a dry run. Use -C to confirm and apply changes.");
+/// A change the library didn't plan (a copy, a saved time, a plan read
+/// back) made through its `apply_steps`, as a plan of its own. `None` if
+/// the run stopped first.
+fn stamp_one(
+    change: Change,
+    steps: &mut CliSteps,
+    events: &dyn dirstamp::Events,
+) -> Option<Result<(Change, Status), SkipReason>> {
+    let plan = dirstamp::Plan {
+        changes: vec![change],
+        ..dirstamp::Plan::default()
+    };
+    let mut applied = dirstamp::apply_steps(&plan, &mut HashMap::new(), steps, events);
+    match applied.changes.pop() {
+        Some(made) => Some(Ok(made)),
+        None => applied.skipped.pop().map(|(_, reason)| Err(reason)),
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// `stamp_one`, reported. `None` if the change was left alone.
+fn apply_one(
+    change: Change,
+    steps: &mut CliSteps,
+    reporter: &mut Reporter,
+) -> io::Result<Option<(Change, Status)>> {
+    let events = ReportEvents::applying(reporter);
+    let made = stamp_one(change, steps, &events);
+    events.finish()?;
+    Ok(made.and_then(Result::ok))
+}
+
+/// `--watch`: stamp the tree once, then the folders that filesystem events
+/// point at, until Ctrl-C. A folder's parent is only looked at again when
+/// the folder itself changed or has events of its own.
+fn watch_tree(
+    root: &Path,
+    cfg: &Config,
+    filter: Option<&DirFilter>,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<()> {
+    // Watching before the first pass, so nothing changed during it is missed.
+    let debounce = cfg.debounce.unwrap_or(watch::DEFAULT_DEBOUNCE);
+    let mut watcher = match Watcher::new(root, debounce) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Cannot watch {} ({e})", root.display());
+            interrupt::fail("the tree couldn't be watched");
+            return Ok(());
+        }
+    };
+    let set = stamp_tree(root, cfg, filter, stats, reporter)?;
+    watcher.ignore(set);
+    let devices = DeviceFilter::new(cfg);
+
+    while let Some(batch) = watcher.next_batch() {
+        let mut touched = batch.dirs;
+        for dir in &batch.rescan {
+            walk::walk(dir, &devices, None, |step| {
+                if let Walked::Dir(dir, ..) = step {
+                    touched.insert(dir);
+                }
+                !interrupt::requested()
+            });
+        }
+        // Deepest first, as in a full run; parents join as their children change.
+        let mut queue: BTreeSet<(Reverse<usize>, PathBuf)> = touched
+            .into_iter()
+            .map(|dir| (Reverse(depth_of(&dir)), dir))
+            .collect();
+        let mut planned = HashMap::new();
+        let mut set = Vec::new();
+        let mut steps = CliSteps::new(root, cfg, filter, stats);
+        while let Some((_, path)) = queue.pop_first() {
+            if interrupt::requested() {
+                break;
+            }
+            if !path.is_dir() {
+                continue; // Gone again since the event.
+            }
+            if !devices.wants_dir(&path) {
+                reporter.skip(&path, SkipReason::OtherDevice);
+                continue;
+            }
+            let status = visit_dir(&path, &mut steps, &mut planned, reporter)?;
+            if status.is_none() {
+                continue;
+            }
+            if status == Some(Status::Applied) {
+                set.push(path.clone());
+            }
+            if let Some(parent) = path.parent().filter(|_| path != root) {
+                queue.insert((Reverse(depth_of(parent)), parent.to_path_buf()));
+            }
+        }
+        watcher.ignore(set);
+    }
+    Ok(())
+}
+
+/// `--interval`: stamp the tree every `every`, until Ctrl-C or SIGTERM,
+/// with one summary line per run. A stop during a run lets it finish.
+fn repeat_tree(
+    root: &Path,
+    cfg: &Config,
+    filter: Option<&DirFilter>,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+    every: Duration,
+) -> io::Result<()> {
+    let verb = if cfg.confirm { "updated" } else { "to update" };
+    for run in 1.. {
+        let started = Instant::now();
+        let (before, errors_before) = (reporter.counts(), stats.errors.len());
+        interrupt::hold(true);
+        let result = stamp_tree(root, cfg, filter, stats, reporter);
+        interrupt::hold(false);
+        result?;
+
+        let after = reporter.counts();
+        let line = format!(
+            "run {run}: {} folder(s) scanned, {} {verb}, {} failed, {} error(s) in {:.1}s",
+            after.scanned - before.scanned,
+            after.changed() - before.changed(),
+            after.failed - before.failed,
+            stats.errors.len() - errors_before,
+            started.elapsed().as_secs_f64()
+        );
+        outln!("{} {line}", log::timestamp(now(cfg)));
+        reporter.note(&line);
+
+        while started.elapsed() < every {
+            if interrupt::requested() {
+                return Ok(());
+            }
+            std::thread::sleep(
+                every
+                    .saturating_sub(started.elapsed())
+                    .min(Duration::from_millis(200)),
+            );
+        }
+        if interrupt::requested() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a folder that keeps `--dir-timeout`'s worker waiting is named on
+/// stderr's status line.
+fn show_status(cfg: &Config) -> bool {
+    !cfg.quiet && io::stderr().is_terminal()
+}
+
+/// `op(path)`, on the `--dir-timeout` worker if there is one.
+fn watched<T: Send + 'static>(
+    watchdog: &mut Option<Watchdog>,
+    path: &Path,
+    op: fn(&Path) -> io::Result<T>,
+) -> io::Result<T> {
+    match watchdog {
+        Some(watchdog) => watchdog.call(path, op),
+        None => op(path),
+    }
+}
+
+/// Count and report a failed look at `path`; returns why it is skipped.
+fn scan_failed(path: &Path, e: &io::Error, read: DirRead, stats: &mut Stats) -> SkipReason {
+    let (category, reason, what) = if watchdog::is_timeout(e) {
+        (ErrorCategory::TimedOut, SkipReason::TimedOut, "timed out")
+    } else {
+        let category = ErrorCategory::classify(Some(e.kind()));
+        (category, SkipReason::Error, read.failed())
+    };
+    if stats.note_error(category, read.error(path, e)) {
+        eprintln!("skipped ({what}): {:?} ({e})", path);
+    }
+    reason
+}
+
+/// Which look at a folder failed.
+#[derive(Clone, Copy)]
+enum DirRead {
+    Mtime,
+    Children,
+    /// The walk's, getting into it.
+    Walk,
+}
+
+impl DirRead {
+    /// Said in the "skipped" line.
+    fn failed(self) -> &'static str {
+        match self {
+            DirRead::Mtime => "mtime read failed",
+            DirRead::Children => "child scan failed",
+            DirRead::Walk => "walk error",
+        }
+    }
+
+    fn error(self, path: &Path, e: &io::Error) -> DirstampError {
+        let path = path.to_path_buf();
+        let source = copied(e);
+        match self {
+            DirRead::Mtime => DirstampError::ReadMtime { path, source },
+            DirRead::Children => DirstampError::ReadDir { path, source },
+            DirRead::Walk => DirstampError::Walk { path, source },
+        }
+    }
+}
+
+/// A folder's own reads: its mtime, then its children. The error comes with
+/// which failed.
+type DirReads = Result<(SystemTime, Vec<Child>), (io::Error, DirRead)>;
+
+/// What the walk already read of a folder.
+#[derive(Default)]
+struct Seen {
+    mtime: Option<SystemTime>,
+    children: Option<Vec<Child>>,
+}
+
+/// Current dir mtime, then its immediate children, each unless the walk
+/// has `seen` it: the folder's only reads, so they're what --dir-timeout bounds.
+fn read_dir_state(
+    path: &Path,
+    seen: Seen,
+    retry: &mut Retry,
+    watchdog: &mut Option<Watchdog>,
+) -> DirReads {
+    let mtime = match seen.mtime {
+        Some(mtime) => mtime,
+        None => retry
+            .run(|| watched(watchdog, path, own_mtime))
+            .map_err(|e| (e, DirRead::Mtime))?,
+    };
+    let children = match seen.children {
+        Some(children) => children,
+        None => retry
+            .run(|| watched(watchdog, path, read_children))
+            .map_err(|e| (e, DirRead::Children))?,
+    };
+    Ok((mtime, children))
+}
+
+/// A folder's mtime, counted and timed for `--timing`.
+fn own_mtime(path: &Path) -> io::Result<SystemTime> {
+    timing::count(Call::Stat, 1);
+    timing::timed(Call::Stat, path, || fs::metadata(path))?.modified()
+}
+
+/// `scan::read_children`, counted and timed for `--timing`: one listing,
+/// and a metadata read per entry.
+fn read_children(path: &Path) -> io::Result<Vec<Child>> {
+    timing::count(Call::Listing, 1);
+    let children = timing::timed(Call::Listing, path, || {
+        let _open = handles::open();
+        scan::read_children(path)
+    })?;
+    timing::count(Call::Stat, children.len());
+    Ok(children)
+}
+
+/// `--directory-filter`: whether `change` is one the user wants made.
+fn selected(filter: Option<&DirFilter>, change: &Change, root: &Path) -> bool {
+    let Some(filter) = filter else { return true };
+    let path = &change.path;
+    let children = if filter.uses_children() {
+        fs::read_dir(path).map_or(0, |entries| entries.count())
+    } else {
+        0
+    };
+    let name = path.file_name().map(Path::new).unwrap_or(Path::new(""));
+    filter.matches(&DirAttrs {
+        depth: depth_of(path).saturating_sub(depth_of(root)),
+        children,
+        delta_days: change.delta_secs().abs() / 86_400.0,
+        name: &paths::raw_bytes(name),
+    })
+}
+
+/// `--compare-to`: give each directory under `root` the mtime of the same
+/// relative directory under `reference`, instead of looking at its children.
+fn compare_tree(
+    root: &Path,
+    reference: &Path,
+    cfg: &Config,
+    filter: Option<&DirFilter>,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<()> {
+    let mut steps = CliSteps::new(root, cfg, filter, stats);
+    let dirs = collect_dirs(root, &mut steps, reporter);
+    for path in &dirs.unwrap_or_default() {
+        if interrupt::requested() {
+            break;
+        }
+        let rel = path.strip_prefix(root).unwrap_or(path);
+        let counterpart = reference.join(rel);
+        if !counterpart.is_dir() {
+            reporter.skip(path, SkipReason::NotInReference);
+            continue;
+        }
+        let stats = &mut *steps.stats;
+        let (Some(to), Some(from)) = (read_mtime(&counterpart, stats), read_mtime(path, stats))
+        else {
+            reporter.skip(path, SkipReason::Error);
+            continue;
+        };
+        if !differs(to, from) {
+            reporter.skip(path, SkipReason::InSync);
+            continue;
+        }
+        let change = Change {
+            path: path.to_path_buf(),
+            from,
+            to,
+            source_child: None,
+            copied_from: Some(counterpart),
+            reason: ChangeReason::Copied,
+        };
+        if !selected(filter, &change, root) {
+            reporter.skip(path, SkipReason::Filtered);
+            continue;
+        }
+        apply_one(change, &mut steps, reporter)?;
+    }
+    Ok(())
+}
+
+/// `--propagate`: after stamping individual folders, re-check their ancestors
+/// with the normal child rule, deepest first. An ancestor is only looked at if
+/// one of its children changed, and never above `ceiling`.
+fn propagate(
+    stamped: HashMap<PathBuf, SystemTime>,
+    ceiling: &Path,
+    cfg: &Config,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<()> {
+    let mut pending: BTreeSet<(Reverse<usize>, PathBuf)> = BTreeSet::new();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    let queue_parent = |path: &Path, pending: &mut BTreeSet<_>, visited: &mut HashSet<_>| {
+        let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) else {
+            return;
+        };
+        if !parent.starts_with(ceiling) {
+            return;
+        }
+        if visited.insert(parent.to_path_buf()) {
+            pending.insert((Reverse(depth_of(parent)), parent.to_path_buf()));
+        }
+    };
+
+    for path in stamped.keys() {
+        queue_parent(path, &mut pending, &mut visited);
+    }
+    // Dry runs see the mtimes the stamped folders would have after -C.
+    let mut planned = stamped;
+    let mut steps = CliSteps::new(ceiling, cfg, None, stats);
+    while let Some((_, dir)) = pending.pop_first() {
+        if interrupt::requested() {
+            break;
+        }
+        let status = visit_dir(&dir, &mut steps, &mut planned, reporter)?;
+        if status.is_some_and(|status| status != Status::Failed) {
+            queue_parent(&dir, &mut pending, &mut visited);
+        }
+    }
+    Ok(())
+}
+
+/// `--copy-from`: give each DST the mtime of its SRC, ignoring DST's children.
+fn copy_times(cfg: &Config, stats: &mut Stats, reporter: &mut Reporter) -> io::Result<()> {
+    // --propagate climbs no higher than PATH, so every DST must be in it.
+    // Compared as absolute paths, since either may be relative.
+    let top = cfg.propagate.then(|| {
+        let top = std::path::absolute(&cfg.root).unwrap_or_else(|_| cfg.root.clone());
+        if !top.is_dir() {
+            usage_error(format!("Not a directory: {}", cfg.root.display()));
+        }
+        top
+    });
+    let inside = |dst: &Path| {
+        let top = top.as_deref()?;
+        let dst = std::path::absolute(dst).ok()?;
+        dst.starts_with(top).then_some(dst)
+    };
+    if top.is_some() {
+        if let Some((_, dst)) = cfg.copy_from.iter().find(|(_, dst)| inside(dst).is_none()) {
+            usage_error(format!(
+                "--propagate: {} is not inside {}",
+                dst.display(),
+                cfg.root.display()
+            ));
+        }
+    }
+    let mut stamped = HashMap::new();
+    let mut steps = CliSteps::new(&cfg.root, cfg, None, stats);
+    for (src, dst) in &cfg.copy_from {
+        if interrupt::requested() {
+            break;
+        }
+        let stats = &mut *steps.stats;
+        let (Some(to), Some(from)) = (read_mtime(src, stats), read_mtime(dst, stats)) else {
+            reporter.skip(dst, SkipReason::Error);
+            continue;
+        };
+        if !differs(to, from) {
+            reporter.skip(dst, SkipReason::InSync);
+            continue;
+        }
+        let change = Change {
+            path: dst.clone(),
+            from,
+            to,
+            source_child: None,
+            copied_from: Some(src.clone()),
+            reason: ChangeReason::Copied,
+        };
+        let Some((_, status)) = apply_one(change, &mut steps, reporter)? else {
+            continue;
+        };
+        if let Some(dst) = inside(dst).filter(|_| status != Status::Failed) {
+            stamped.insert(dst, to);
+        }
+    }
+    if let Some(top) = &top {
+        propagate(stamped, top, cfg, stats, reporter)?;
+    }
+    Ok(())
+}
+
+/// Carry out a saved plan. Folders whose mtime no longer matches the plan are
+/// skipped unless `force` is set; returns how many were skipped that way.
+fn apply_plan(
+    plan: &Plan,
+    cfg: &Config,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<usize> {
+    let mut mismatched = 0usize;
+    let mut stamped = HashMap::new();
+    let mut steps = CliSteps::new(&plan.root, cfg, None, stats);
+    for record in &plan.changes {
+        if interrupt::requested() {
+            break;
+        }
+        let Some(current) = read_mtime(&record.path, steps.stats) else {
+            reporter.skip(&record.path, SkipReason::Error);
+            continue;
+        };
+        let mut change = Change::from(record);
+        if differs(current, change.from) {
+            if !(cfg.plan_force || cfg.undo_force) {
+                mismatched += 1;
+                let since = if cfg.undo.is_some() { "run" } else { "plan" };
+                eprintln!("skipped (changed since {since}): {:?}", record.path);
+                reporter.skip(&record.path, SkipReason::ChangedSincePlan);
+                continue;
+            }
+            change.from = current;
+        }
+        let Some((change, status)) = apply_one(change, &mut steps, reporter)? else {
+            continue;
+        };
+        if status != Status::Failed {
+            stamped.insert(change.path, change.to);
+        }
+    }
+    if cfg.propagate {
+        propagate(stamped, &plan.root, cfg, stats, reporter)?;
+    }
+    Ok(mismatched)
+}
+
+/// `--save-snapshot`: stream every directory's mtime to `file`, without
+/// holding the tree in memory. Returns how many were recorded.
+fn save_snapshot(root: &Path, file: &Path, cfg: &Config, stats: &mut Stats) -> io::Result<usize> {
+    let root = fs::canonicalize(root)?;
+    let mut out = SnapshotWriter::create(file, &root, now(cfg))?;
+    // Every folder, whatever device it is on.
+    let mut watchdog = stats.watchdog.take();
+    let mut written = Ok(());
+    walk::walk(&root, &DeviceFilter::default(), watchdog.as_mut(), |step| {
+        match step {
+            Walked::Dir(dir, seen, _) => {
+                if let Some(mtime) = seen.or_else(|| read_mtime(&dir, stats)) {
+                    written = out.push(&dir, mtime);
+                }
+            }
+            Walked::OtherDevice(_) => {}
+            Walked::Error(err) => note_walk_error(&err, stats),
+            Walked::TimedOut(dir, e) => {
+                scan_failed(&dir, &e, DirRead::Walk, stats);
+            }
+        }
+        written.is_ok() && !interrupt::requested()
+    });
+    stats.watchdog = watchdog;
+    written?;
+    out.finish()
+}
+
+/// `--sparse`: what a sample of `n` folders under `root`, picked uniformly
+/// at random in one walk, says about the whole tree. Returns the folders
+/// found, those judged, and how many of those need updating.
+fn sparse(root: &Path, n: usize, cfg: &Config, stats: &mut Stats) -> (usize, usize, usize) {
+    // Seeded from the run's time, so --pretend-time picks the same sample.
+    let seed = now(cfg)
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let mut rng = Rng::new(seed);
+    let mut sample: Vec<(PathBuf, Seen)> = Vec::with_capacity(n);
+    let mut found = 0;
+    let mut watchdog = stats.watchdog.take();
+    walk::walk(root, &DeviceFilter::new(cfg), watchdog.as_mut(), |step| {
+        match step {
+            Walked::Dir(dir, mtime, children) => {
+                // Reservoir sampling: each folder so far is in with chance n/found.
+                found += 1;
+                let seen = Seen { mtime, children };
+                if sample.len() < n {
+                    sample.push((dir, seen));
+                } else {
+                    let i = rng.below(found as u64) as usize;
+                    if i < n {
+                        sample[i] = (dir, seen);
+                    }
+                }
+            }
+            Walked::OtherDevice(_) => {}
+            Walked::Error(err) => note_walk_error(&err, stats),
+            Walked::TimedOut(dir, e) => {
+                scan_failed(&dir, &e, DirRead::Walk, stats);
+            }
+        }
+        !interrupt::requested()
+    });
+    stats.watchdog = watchdog;
+
+    // Each folder as it is on disk, not as it would be once its subfolders
+    // were stamped: a sample can't plan children first.
+    let (mut judged, mut stale) = (0, 0);
+    let mut steps = CliSteps::new(root, cfg, None, stats);
+    for (dir, seen) in sample {
+        if interrupt::requested() {
+            break;
+        }
+        steps.read_ahead(&dir, seen);
+        let dirs = [dir];
+        let plan = dirstamp::plan_dirs(&dirs, &mut HashMap::new(), &mut steps, &dirstamp::NoEvents);
+        if !plan.changes.is_empty() {
+            judged += 1;
+            stale += 1;
+        } else if plan
+            .skipped
+            .iter()
+            .any(|&(_, reason)| reason != SkipReason::Error)
+        {
+            judged += 1;
+        }
+    }
+    (found, judged, stale)
+}
+
+/// `--restore-snapshot`: give each recorded directory under `root` its saved
+/// mtime back. Entries are read one at a time.
+fn restore_snapshot(
+    snapshot: SnapshotReader,
+    root: &Path,
+    cfg: &Config,
+    stats: &mut Stats,
+    reporter: &mut Reporter,
+) -> io::Result<()> {
+    let mut steps = CliSteps::new(root, cfg, None, stats);
+    for entry in snapshot {
+        if interrupt::requested() {
+            break;
+        }
+        let (rel, saved) = entry?;
+        let path = if rel.as_os_str().is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(rel)
+        };
+        let Some(current) = read_mtime(&path, steps.stats) else {
+            reporter.skip(&path, SkipReason::Error);
+            continue;
+        };
+        // Exact: the snapshot has the full-resolution time.
+        if current == saved {
+            reporter.skip(&path, SkipReason::InSync);
+            continue;
+        }
+        let change = Change {
+            path,
+            from: current,
+            to: saved,
+            source_child: None,
+            copied_from: None,
+            reason: ChangeReason::Saved,
+        };
+        apply_one(change, &mut steps, reporter)?;
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let started = Instant::now();
+
+    // ---- parse CLI -------------------------------------------------------------
+    let cfg = match cli::parse_args(env::args_os().skip(1)) {
+        Ok(Command::Run(cfg)) => cfg,
+        Ok(Command::Help) => print_help_and_exit(),
+        Ok(Command::Version) => print_version_and_exit(),
+        Ok(Command::VersionCheck { quiet }) => version_check_and_exit(quiet),
+        Ok(Command::Doctor { path, json }) => doctor_and_exit(&path, json),
+        Ok(Command::GenTree(spec)) => gen_tree_and_exit(&spec),
+        Err(msg) => usage_error(msg),
+    };
+    if !cfg.quiet {
+        for note in &cfg.deprecations {
+            eprintln!("warning: {note}");
+        }
+    }
+    if let Some(dir) = &cfg.find_newest {
+        find_newest_and_exit(dir, &cfg);
+    }
+    interrupt::install();
+    if cfg.timing {
+        timing::enable();
+    }
+    let nofile = handles::raise_nofile_limit();
+    handles::set_limit(
+        cfg.max_open
+            .unwrap_or_else(|| handles::default_limit(nofile)),
+    );
+    // Held until the process exits, which releases it.
+    let _lock = cfg.lockfile.as_ref().map(|p| {
+        lock::acquire(p, cfg.wait_for_lock).unwrap_or_else(|e| {
+            match e {
+                LockError::Held => {
+                    eprintln!("Another dirstamp is running (lockfile: {})", p.display())
+                }
+                LockError::Io(e) => eprintln!("Cannot lock {:?} ({e})", p),
+            }
+            std::process::exit(EXIT_ERRORS);
+        })
+    });
+
+    let filter = cfg.directory_filter.as_deref().map(|expr| {
+        DirFilter::parse(expr)
+            .unwrap_or_else(|msg| usage_error(format!("Invalid --directory-filter: {msg}")))
+    });
+
+    let saved_plan = cfg.apply_plan.as_ref().map(|p| {
+        Plan::load(p).unwrap_or_else(|e| {
+            eprintln!("Cannot read plan file {:?} ({e})", p);
+            std::process::exit(EXIT_USAGE);
+        })
+    });
+    // --undo is the journal's changes turned around, applied like a plan.
+    let saved_plan = saved_plan.or_else(|| {
+        cfg.undo.as_ref().map(|p| {
+            Journal::load_undo_plan(p).unwrap_or_else(|e| {
+                eprintln!("Cannot read undo journal {:?} ({e})", p);
+                std::process::exit(EXIT_USAGE);
+            })
+        })
+    });
+    let root = match &saved_plan {
+        Some(plan) => plan.root.clone(),
+        None => cfg.root.clone(),
+    };
+    if saved_plan.is_none() && cfg.copy_from.is_empty() {
+        if !root.exists() {
+            error_exit(&DirstampError::RootNotFound(root.clone()));
+        } else if !root.is_dir() {
+            error_exit(&DirstampError::NotADirectory(root.clone()));
+        }
+    }
+    let copy_dirs = cfg.copy_from.iter().flat_map(|(src, dst)| [src, dst]);
+    for dir in copy_dirs.chain(&cfg.compare_to) {
+        if !dir.is_dir() {
+            error_exit(&DirstampError::NotADirectory(dir.clone()));
+        }
+    }
+
+    // --skip-if-stamped-file: the cheap check first, when it may save the whole run.
+    let stamped = cfg
+        .stamped_file
+        .as_ref()
+        .and_then(|f| fs::metadata(f).and_then(|m| m.modified()).ok());
+    if stamped.is_some_and(|t| walk::nothing_newer_than(&root, t)) {
+        if !cfg.quiet {
+            outln!("Already stamped (sentinel file is current)");
+        }
+        return Ok(());
+    }
+
+    let scope = cfg.git_changed.as_ref().map(|changed| {
+        git::changed_dirs(&root, changed).unwrap_or_else(|msg| {
+            eprintln!("--git-changed: {msg}");
+            std::process::exit(EXIT_USAGE);
+        })
+    });
+
+    let newer_ref = cfg.newer_ref.as_ref().map(|p| {
+        fs::metadata(p)
+            .and_then(|m| m.modified())
+            .unwrap_or_else(|e| {
+                eprintln!("Cannot read --newer-ref {:?} ({e})", p);
+                std::process::exit(EXIT_USAGE);
+            })
+    });
+
+    let snapshot = cfg.restore_snapshot.as_ref().map(|p| {
+        let snapshot = SnapshotReader::open(p).unwrap_or_else(|e| {
+            eprintln!("Cannot read snapshot {:?} ({e})", p);
+            std::process::exit(EXIT_USAGE);
+        });
+        // Paths are relative, so the wrong root would quietly restamp another tree.
+        if fs::canonicalize(&root).ok().as_ref() != Some(&snapshot.header.root) {
+            eprintln!(
+                "Snapshot {:?} was taken of {}, not {}",
+                p,
+                snapshot.header.root.display(),
+                root.display()
+            );
+            std::process::exit(EXIT_USAGE);
+        }
+        snapshot
+    });
+
+    // -C on a read-only mount would fail for every folder, after the whole walk.
+    if cfg.save_snapshot.is_none() && !cfg.check {
+        let mut targets: Vec<&Path> = cfg.copy_from.iter().map(|(_, dst)| dst.as_path()).collect();
+        if targets.is_empty() {
+            targets.push(&root);
+        }
+        let read_only = targets
+            .into_iter()
+            .find(|dir| readonly::is_read_only(dir).unwrap_or(false));
+        if let Some(dir) = read_only {
+            if cfg.confirm {
+                eprintln!(
+                    "{} is on a read-only file system; -C can't change any timestamps there.",
+                    dir.display()
+                );
+                std::process::exit(EXIT_READ_ONLY);
+            } else if !cfg.quiet {
+                eprintln!(
+                    "Note: {} is on a read-only file system, so applying these changes with -C would fail.",
+                    dir.display()
+                );
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    if cfg.confirm && !elevation::is_admin() {
+        eprintln!(
+            "Note: some directories may require administrator privileges; consider re-running as Administrator."
+        );
+    }
+
+    #[cfg(not(unix))]
+    if !cfg.include_devices.is_empty() || !cfg.exclude_devices.is_empty() {
+        eprintln!("warning: --include-device and --exclude-device are ignored on this platform");
+    }
+
+    let mut stats = Stats {
+        errors: Vec::new(),
+        error_log: ErrorLog::new(&root),
+        skipped_permission: 0,
+        ignore_permission: cfg.ignore_permission,
+        quiet_errors: cfg.quiet_errors,
+        journal: None,
+        prompter: None,
+        recheck: if cfg.recompute {
+            Some(Recheck::Recompute)
+        } else {
+            cfg.verify_before_apply.then_some(Recheck::Skip)
+        },
+        changed_since_scan: 0,
+        limit_left: cfg.limit,
+        applied: cfg.verify.then(BTreeMap::new),
+        extensions: cfg.extension_stats.then(ExtensionStats::default),
+        read_only_mounts: ReadOnlyMounts::default(),
+        retry: Retry::new(cfg.retries, cfg.retry_delay.unwrap_or(retry::DEFAULT_DELAY)),
+        watchdog: cfg.dir_timeout.map(|t| Watchdog::new(t, show_status(&cfg))),
+        scope,
+        child_rules: ChildRules::new(&cfg),
+        newer_ref,
+        throttle: Throttle::new(cfg.rate_limit),
+        cache: cfg.cache.as_ref().map(|p| DirCache::load(p, &root)),
+        touch_symlinks: cfg.touch_symlinks,
+        symlink_times_unsupported: false,
+        before_stamp: cfg.before_stamp.clone().map(|command| hook(command, &cfg)),
+        after_stamp: cfg.after_stamp.clone().map(|command| hook(command, &cfg)),
+        fail_fast: cfg.fail_fast,
+    };
+    if cfg.confirm && !cfg.no_journal {
+        match cfg
+            .journal
+            .clone()
+            .or_else(|| Journal::default_path(now(&cfg)))
+        {
+            Some(path) => stats.journal = Some(Journal::new(path, &root, cfg.pretend_time)),
+            None => eprintln!(
+                "warning: nowhere to keep the undo journal (set XDG_STATE_HOME or use --journal)"
+            ),
+        }
+    }
+
+    if cfg.interactive {
+        stats.prompter = Some(Prompter::new(&cfg, &root).unwrap_or_else(|msg| usage_error(msg)));
+    }
+
+    if let Some(file) = &cfg.save_snapshot {
+        let saved = save_snapshot(&root, file, &cfg, &mut stats).unwrap_or_else(|e| {
+            eprintln!("Cannot write snapshot {:?} ({e})", file);
+            std::process::exit(EXIT_ERRORS);
+        });
+        if interrupt::requested() {
+            eprintln!(
+                "Interrupted: {} is incomplete ({saved} folder(s) recorded).",
+                file.display()
+            );
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        if !stats.errors.is_empty() {
+            eprintln!(
+                "\nWarning: {} error(s); the affected folders are not in the snapshot:",
+                stats.errors.len()
+            );
+            stats.error_log.print_summary();
+        }
+        if !cfg.quiet {
+            outln!(
+                "Saved the mtimes of {saved} folder(s) to {}.",
+                file.display()
+            );
+        }
+        if cfg.strict && !stats.errors.is_empty() {
+            std::process::exit(EXIT_ERRORS);
+        }
+        return Ok(());
+    }
+
+    if let Some(n) = cfg.sparse {
+        let (found, judged, stale) = sparse(&root, n, &cfg, &mut stats);
+        if interrupt::requested() {
+            eprintln!("Interrupted.");
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        if !stats.errors.is_empty() {
+            eprintln!(
+                "\nWarning: {} error(s); the affected folders are not in the sample:",
+                stats.errors.len()
+            );
+            stats.error_log.print_summary();
+        }
+        let share = if judged == 0 {
+            0.0
+        } else {
+            stale as f64 / judged as f64
+        };
+        outln!(
+            "Sampled {judged} of {found} dirs: {:.0}% need updating (estimated {:.0} total)",
+            share * 100.0,
+            share * found as f64
+        );
+        if cfg.strict && !stats.errors.is_empty() {
+            std::process::exit(EXIT_ERRORS);
+        }
+        return Ok(());
+    }
+
+    let script = if cfg.emit_script {
+        let out: Box<dyn io::Write> = match &cfg.script_file {
+            Some(p) => Box::new(io::BufWriter::new(fs::File::create(p).unwrap_or_else(
+                |e| {
+                    eprintln!("Cannot create script file {:?} ({e})", p);
+                    std::process::exit(EXIT_USAGE);
+                },
+            ))),
+            None => Box::new(io::stdout().lock()),
+        };
+        Some(ScriptWriter::new(
+            out,
+            cfg.script_format,
+            VERSION,
+            &options_summary(),
+            now(&cfg),
+        )?)
+    } else {
+        None
+    };
+    let log = cfg.log_file.as_ref().map(|p| {
+        let keep = cfg.log_rotate_keep.unwrap_or(log::DEFAULT_KEEP);
+        let mut log = RunLog::open(p, cfg.log_rotate, keep, cfg.pretend_time).unwrap_or_else(|e| {
+            eprintln!("Cannot open log file {:?} ({e})", p);
+            std::process::exit(EXIT_USAGE);
+        });
+        log.line(&format!(
+            "dirstamp {VERSION} started: {}",
+            options_summary()
+        ));
+        log
+    });
+    let trace = if cfg.otel || otel::endpoint_configured() {
+        match RunTrace::start(&root, cfg.confirm) {
+            Ok(trace) => Some(trace),
+            Err(msg) if cfg.otel => usage_error(msg),
+            // Only the environment asked: other tools may share it, so don't nag.
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+    let baseline = cfg.baseline.as_ref().and_then(|path| {
+        Baseline::load(path).unwrap_or_else(|e| {
+            eprintln!("Cannot read baseline {:?} ({e})", path);
+            std::process::exit(EXIT_USAGE);
+        })
+    });
+    let mut reporter = Reporter::new(&cfg, &root, script, log, trace, baseline)
+        .unwrap_or_else(|msg| usage_error(msg));
+
+    let mismatched = match (&saved_plan, snapshot) {
+        (Some(plan), _) => apply_plan(plan, &cfg, &mut stats, &mut reporter)?,
+        (None, Some(snapshot)) => {
+            let restored = restore_snapshot(snapshot, &root, &cfg, &mut stats, &mut reporter);
+            if let Err(e) = restored {
+                eprintln!("Cannot read snapshot entry ({e})");
+                std::process::exit(EXIT_USAGE);
+            }
+            0
+        }
+        (None, None) if cfg.compare_to.is_some() => {
+            let reference = cfg.compare_to.as_deref().unwrap_or(&root);
+            compare_tree(
+                &root,
+                reference,
+                &cfg,
+                filter.as_ref(),
+                &mut stats,
+                &mut reporter,
+            )?;
+            0
+        }
+        (None, None) if !cfg.copy_from.is_empty() => {
+            copy_times(&cfg, &mut stats, &mut reporter)?;
+            0
+        }
+        (None, None) if cfg.watch => {
+            watch_tree(&root, &cfg, filter.as_ref(), &mut stats, &mut reporter)?;
+            0
+        }
+        (None, None) if cfg.interval.is_some() => {
+            let every = cfg.interval.unwrap_or_default();
+            repeat_tree(
+                &root,
+                &cfg,
+                filter.as_ref(),
+                &mut stats,
+                &mut reporter,
+                every,
+            )?;
+            0
+        }
+        (None, None) => {
+            stamp_tree(&root, &cfg, filter.as_ref(), &mut stats, &mut reporter)?;
+            0
+        }
+    };
+
+    let script_only = cfg.emit_script && cfg.script_file.is_none();
+    let machine_readable = reporter.is_machine_readable();
+    let mut outcome = reporter.finish()?;
+    let skipped = std::mem::take(&mut outcome.skipped);
+    let report = run_report(&outcome.counts, skipped, &mut stats, started.elapsed());
+    let updated_count = report.changes_planned - report.changes_failed;
+    // Under -C, anything still planned is what --limit held back.
+    let held = report.changes_planned - report.changes_applied - report.changes_failed;
+
+    // Make skipped folders impossible to miss, even when stdout is redirected.
+    if !report.errors.is_empty() {
+        eprintln!(
+            "\nWarning: {} error(s); the affected folders were skipped:",
+            report.errors.len()
+        );
+        stats.error_log.print_summary();
+    }
+    if mismatched > 0 && cfg.undo.is_some() {
+        eprintln!(
+            "{mismatched} folder(s) changed since that run and were skipped (use --undo-force to restore anyway)."
+        );
+    } else if mismatched > 0 {
+        eprintln!(
+            "{mismatched} folder(s) changed since the plan was made and were skipped (use --plan-force to apply anyway)."
+        );
+    }
+
+    let verify_failed = stats.applied.as_ref().map_or(0, verify_applied);
+
+    if let (Some(path), Some(changes)) = (&cfg.plan, outcome.plan) {
+        if let Err(e) = Plan::new(&root, changes, now(&cfg)).save(path) {
+            eprintln!("Cannot write plan file {:?} ({e})", path);
+            std::process::exit(EXIT_ERRORS);
+        }
+    }
+    if let (Some(path), Some(html)) = (&cfg.report_html, &outcome.html) {
+        let written = html.write(path, &root, &options_summary(), &stats.error_log, now(&cfg));
+        if let Err(e) = written {
+            eprintln!("Cannot write HTML report {:?} ({e})", path);
+            std::process::exit(EXIT_ERRORS);
+        }
+    }
+    // Ctrl-C (or SIGTERM) is how a watch or an --interval loop is meant to end.
+    // --fail-fast stopped the run: finished all the same, it exits 3.
+    let failed = interrupt::failed();
+    let interrupted = interrupt::requested() && !failed && !cfg.watch && cfg.interval.is_none();
+    // An interrupted run saw only part of the tree; keep the old baseline.
+    if let (Some(path), Some(drift)) = (&cfg.baseline, &outcome.new_baseline) {
+        if !interrupted && !failed {
+            if let Err(e) = baseline::save(path, &root, now(&cfg), drift) {
+                eprintln!("Cannot write baseline {:?} ({e})", path);
+                std::process::exit(EXIT_ERRORS);
+            }
+        }
+    }
+    if let (Some(path), Some(cache)) = (&cfg.cache, &stats.cache) {
+        if let Err(e) = cache.save(path) {
+            eprintln!("Cannot write cache {:?} ({e})", path);
+            std::process::exit(EXIT_ERRORS);
+        }
+    }
+    if let Some(path) = &cfg.metrics_file {
+        let metrics = metrics::RunMetrics {
+            root: &root,
+            counts: outcome.counts,
+            errors: &stats.error_log,
+            duration: started.elapsed(),
+        };
+        if let Err(e) = metrics.write(path) {
+            eprintln!("Cannot write metrics file {:?} ({e})", path);
+            std::process::exit(EXIT_ERRORS);
+        }
+    }
+    if cfg.output_json {
+        // Last on stdout, so a reader knows the stream is complete.
+        report::print_json_summary(
+            &root,
+            &report,
+            !cfg.confirm,
+            cfg.timing.then(timing::summary),
+        );
+    }
+    if interrupted && (script_only || cfg.check) {
+        interrupted_exit(&report);
+    }
+    // --dry-run-exit-code: a dry run that found work to do says so. Under
+    // --check, check's own exit codes win.
+    let dry_run_code = match cfg.dry_run_exit_code {
+        Some(code) if !cfg.confirm && !cfg.check && updated_count > 0 => i32::from(code),
+        _ => 0,
+    };
+    if script_only {
+        if failed {
+            std::process::exit(EXIT_ERRORS);
+        }
+        if dry_run_code != 0 {
+            std::process::exit(dry_run_code);
+        }
+        return Ok(());
+    }
+
+    if cfg.count_dirs {
+        // Every folder the walk turned up, left alone or not.
+        outln!("Scanned {} directories", report.dirs_scanned);
+    }
+
+    if cfg.check {
+        // Only the drifted folders (if any) were printed; the exit code says
+        // the rest. A folder that couldn't be read may be out of step too, so
+        // errors win over changes.
+        if !report.errors.is_empty() {
+            std::process::exit(EXIT_USAGE);
+        } else if updated_count > 0 {
+            std::process::exit(EXIT_CHANGES);
+        }
+        return Ok(());
+    }
+
+    // Machine-readable output carries no trailing prose; --quiet drops it too.
+    let summary = !machine_readable && !cfg.quiet;
+    let declined = stats
+        .prompter
+        .as_ref()
+        .map_or(0, |p| p.declined + p.unasked);
+    if !summary {
+    } else if updated_count == 0 && (declined > 0 || interrupted || failed) {
+        outln!("No folder timestamps were changed.");
+    } else if updated_count == 0 {
+        outln!("No folder timestamps needed updating.");
+    } else {
+        if cfg.summary_only {
+            outln!("{updated_count} folder timestamp(s) needed updating.");
+        }
+        outcome.drift.print();
+        if !cfg.confirm {
+            outln!("\nNote: this was a dry run. Use -C to confirm and apply changes.");
+        }
+    }
+    if summary && outcome.baseline_hidden > 0 {
+        outln!(
+            "{} change(s) unchanged since the baseline were not shown.",
+            outcome.baseline_hidden
+        );
+    }
+    let limit = cfg.limit.unwrap_or(usize::MAX);
+    if summary && !cfg.confirm && updated_count > limit {
+        outln!(
+            "With --limit {limit}, -C would make only the first {limit} of these {updated_count} changes."
+        );
+    }
+    let limit_hit = cfg.confirm && held > 0;
+    if limit_hit {
+        eprintln!(
+            "Stopped at --limit {limit}: {} change(s) made, {held} more not applied (shown as \"would update\").",
+            report.changes_applied + report.changes_failed,
+        );
+    }
+    if let Some(prompter) = stats.prompter.as_ref().filter(|p| p.quit()) {
+        // Always shown: it's the answer to the q.
+        outln!(
+            "Quit: {} change(s) applied, {} declined, {} not asked.",
+            report.changes_applied,
+            prompter.declined,
+            prompter.unasked
+        );
+    }
+    if summary && cfg.restore_snapshot.is_some() {
+        outln!(
+            "{updated_count} of {} folder(s) differ from the snapshot.",
+            report.dirs_scanned
+        );
+    }
+    if let Some(journal) = stats
+        .journal
+        .as_ref()
+        .filter(|j| summary && j.entries() > 0)
+    {
+        outln!(
+            "Undo journal: {} (dirstamp undo with this file reverts the run)",
+            journal.path().display()
+        );
+    }
+    if let Some(applied) = stats.applied.as_ref().filter(|_| summary) {
+        if verify_failed == 0 {
+            outln!(
+                "Verified {} folder(s): every new mtime took effect.",
+                applied.len()
+            );
+        } else {
+            outln!(
+                "Verify: {verify_failed} of {} folder(s) don't have the mtime that was set.",
+                applied.len()
+            );
+        }
+    }
+    if summary && stats.changed_since_scan > 0 {
+        if cfg.recompute {
+            outln!(
+                "{} folder(s) changed after they were scanned and were planned again.",
+                stats.changed_since_scan
+            );
+        } else {
+            outln!(
+                "{} folder(s) changed after they were scanned and were skipped (use --recompute to re-plan them).",
+                stats.changed_since_scan
+            );
+        }
+    }
+    if summary && stats.retry.retried > 0 {
+        outln!(
+            "{} operation(s) hit a transient error and were retried; {} recovered.",
+            stats.retry.retried,
+            stats.retry.recovered
+        );
+    }
+    if summary && stats.skipped_permission > 0 {
+        outln!(
+            "{} folder(s) skipped (permission denied).",
+            stats.skipped_permission
+        );
+    }
+    if let Some(extensions) = &stats.extensions {
+        // Already validated by the reporter.
+        let dates = DateFormat::from_config(&cfg).ok().flatten();
+        outln!();
+        extensions.print(&dates.unwrap_or_else(DateFormat::utc));
+    }
+    if cfg.timing {
+        eprint!("{}", timing::render(started.elapsed()));
+    }
+
+    if interrupted {
+        interrupted_exit(&report);
+    }
+    if failed {
+        std::process::exit(EXIT_ERRORS);
+    }
+
+    // Set failures during -C always count, as does an --undo that couldn't
+    // restore every folder; other skips only under --strict.
+    let counted = |e: &&DirstampError| {
+        cfg.strict || cfg.undo.is_some() || matches!(e, DirstampError::SetMtime { .. })
+    };
+    if let Some(code) = report.errors.iter().filter(counted).map(exit_code).max() {
+        std::process::exit(code);
+    }
+    if verify_failed > 0 {
+        std::process::exit(EXIT_CHANGES);
+    }
+    if limit_hit {
+        std::process::exit(EXIT_LIMIT);
+    }
+    if cfg.summary_only && updated_count > 0 {
+        std::process::exit(EXIT_CHANGES);
+    }
+    if dry_run_code != 0 {
+        std::process::exit(dry_run_code);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    use scan::{find_target_mtime, Strategy};
+    use walkdir::WalkDir;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn touch(path: &Path, secs: u64) {
+        fs::write(path, b"").unwrap();
+        set_folder_mtime(path, at(secs)).unwrap();
+    }
+
+    fn stats(root: &Path) -> Stats {
+        Stats {
+            errors: Vec::new(),
+            error_log: ErrorLog::new(root),
+            skipped_permission: 0,
+            ignore_permission: false,
+            quiet_errors: false,
+            journal: None,
+            prompter: None,
+            recheck: None,
+            changed_since_scan: 0,
+            limit_left: None,
+            applied: None,
+            extensions: None,
+            read_only_mounts: ReadOnlyMounts::default(),
+            retry: Retry::new(0, Duration::ZERO),
+            watchdog: None,
+            scope: None,
+            child_rules: ChildRules::default(),
+            newer_ref: None,
+            throttle: Throttle::default(),
+            cache: None,
+            touch_symlinks: false,
+            symlink_times_unsupported: false,
+            before_stamp: None,
+            after_stamp: None,
+            fail_fast: false,
+        }
+    }
+
+    /// After a confirmed run every non-empty directory matches its newest
+    /// immediate child, which only holds if children were stamped first.
+    #[test]
+    fn stamp_tree_leaves_parents_matching_children() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let root = tmp.path();
+        for dir in ["a/b/c", "a/d", "e", "f/g/h/i"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        touch(&root.join("a/b/c/deep.txt"), 5_000_000);
+        touch(&root.join("a/b/shallow.txt"), 1_000_000);
+        touch(&root.join("a/d/x"), 9_000_000);
+        touch(&root.join("f/g/h/i/only.txt"), 7_000_000);
+        touch(&root.join("top.txt"), 3_000_000);
+
+        let cfg = Config {
+            root: root.to_path_buf(),
+            confirm: true,
+            ..Config::default()
+        };
+        let mut stats = stats(root);
+        let mut reporter = Reporter::new(&cfg, root, None, None, None, None).unwrap();
+        stamp_tree(root, &cfg, None, &mut stats, &mut reporter).unwrap();
+        assert!(stats.errors.is_empty());
+
+        for entry in WalkDir::new(root).into_iter().map(Result::unwrap) {
+            if !entry.file_type().is_dir() {
+                continue;
+            }
+            let dir = entry.path();
+            let own = fs::metadata(dir).unwrap().modified().unwrap();
+            match find_target_mtime(dir, Strategy::Newest).unwrap() {
+                Some(newest) => assert!(
+                    !differs(own, newest.mtime),
+                    "{dir:?} is {own:?}, newest child {:?} is {:?}",
+                    newest.name,
+                    newest.mtime
+                ),
+                None => assert!(dir.ends_with("e")), // the only empty dir
+            }
+        }
+        // f/ has no files, so its time comes up from i/ through h/ and g/.
+        assert_eq!(
+            fs::metadata(root.join("f")).unwrap().modified().unwrap(),
+            at(7_000_000)
+        );
+    }
+
+    #[test]
+    fn verify_allows_two_seconds_of_rounding() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let dir = tmp.path().join("d");
+        fs::create_dir(&dir).unwrap();
+        set_folder_mtime(&dir, at(1_000_000)).unwrap();
+
+        let check = |target| verify_applied(&BTreeMap::from([(dir.clone(), target)]));
+        assert_eq!(check(at(1_000_002)), 0);
+        assert_eq!(check(at(999_998)), 0);
+        assert_eq!(check(at(1_000_003)), 1);
+        assert_eq!(
+            verify_applied(&BTreeMap::from([(tmp.path().join("gone"), at(0))])),
+            1
+        );
+    }
+
+    #[test]
+    fn recheck_catches_files_that_land_after_the_scan() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let dir = tmp.path().join("share");
+        fs::create_dir(&dir).unwrap();
+        touch(&dir.join("old.txt"), 1_000_000);
+        set_folder_mtime(&dir, at(5_000_000)).unwrap();
+        let scanned = Change {
+            path: dir.clone(),
+            from: at(5_000_000),
+            to: at(1_000_000),
+            source_child: Some("old.txt".into()),
+            copied_from: None,
+            reason: ChangeReason::Child,
+        };
+
+        let mut stats = stats(tmp.path());
+        let mut change = scanned.clone();
+        assert!(recheck_before_apply(&mut change, Recheck::Skip, &mut stats).is_ok());
+        assert_eq!(stats.changed_since_scan, 0);
+
+        touch(&dir.join("new.txt"), 2_000_000);
+        let mut change = scanned.clone();
+        assert_eq!(
+            recheck_before_apply(&mut change, Recheck::Skip, &mut stats).err(),
+            Some(SkipReason::ChangedSinceScan)
+        );
+        let mut change = scanned.clone();
+        assert!(recheck_before_apply(&mut change, Recheck::Recompute, &mut stats).is_ok());
+        assert_eq!(change.to, at(2_000_000));
+        assert_eq!(change.source_child, Some("new.txt".into()));
+        assert_eq!(stats.changed_since_scan, 2);
+    }
+
+    #[test]
+    fn atomic_sets_everything_back_when_one_fails() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let root = tmp.path();
+        let change = |name: &str| Change {
+            path: root.join(name),
+            from: at(5_000_000),
+            to: at(1_000_000),
+            source_child: None,
+            copied_from: None,
+            reason: ChangeReason::Child,
+        };
+        for name in ["a", "b"] {
+            fs::create_dir(root.join(name)).unwrap();
+            set_folder_mtime(&root.join(name), at(5_000_000)).unwrap();
+        }
+        let cfg = Config {
+            root: root.to_path_buf(),
+            confirm: true,
+            atomic: true,
+            ..Config::default()
+        };
+        let mut stats = stats(root);
+        let mut reporter = Reporter::new(&cfg, root, None, None, None, None).unwrap();
+        // The second can't be set, so the first is undone and the third never made.
+        let changes = vec![change("a"), change("gone"), change("b")];
+        let mut steps = CliSteps::new(root, &cfg, None, &mut stats);
+        apply_atomically(changes, &mut steps, &mut reporter).unwrap();
+        for name in ["a", "b"] {
+            let mtime = fs::metadata(root.join(name)).unwrap().modified().unwrap();
+            assert_eq!(mtime, at(5_000_000));
+        }
+        assert_eq!(steps.stats.error_log.count(ErrorCategory::SetMtime), 1);
+
+        apply_atomically(vec![change("a"), change("b")], &mut steps, &mut reporter).unwrap();
+        let mtime = fs::metadata(root.join("b")).unwrap().modified().unwrap();
+        assert_eq!(mtime, at(1_000_000));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn touch_symlinks_sets_the_link_not_its_target() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let dir = tmp.path().join("links");
+        fs::create_dir(&dir).unwrap();
+        let target = tmp.path().join("target.txt");
+        touch(&target, 1_000_000);
+        std::os::unix::fs::symlink(&target, dir.join("latest")).unwrap();
+        let lstat = |p: &Path| fs::symlink_metadata(p).unwrap().modified().unwrap();
+        let before = lstat(&dir.join("latest"));
+        // As under --touch-created, the folder's new time isn't the link's.
+        let change = Change {
+            path: dir.clone(),
+            from: at(5_000_000),
+            to: at(3_000_000),
+            source_child: Some("latest".into()),
+            copied_from: None,
+            reason: ChangeReason::Child,
+        };
+
+        let mut stats = stats(tmp.path());
+        assert_eq!(stamped(&change, &Ok(()), &mut stats), Status::Applied);
+        assert_eq!(lstat(&dir.join("latest")), before);
+        stats.touch_symlinks = true;
+        stamped(&change, &Ok(()), &mut stats);
+        assert_eq!(lstat(&dir.join("latest")), at(3_000_000));
+        assert_eq!(lstat(&target), at(1_000_000));
+    }
+}
@@ -0,0 +1,124 @@
+// src/metrics.rs
+// --metrics-file: run statistics in the node_exporter textfile-collector
+// format, written atomically so the collector never sees a partial file.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::errors::{ErrorCategory, ErrorLog};
+use crate::report::Counts;
+
+/// Everything a run reports to Prometheus.
+pub struct RunMetrics<'a> {
+    pub root: &'a Path,
+    pub counts: Counts,
+    pub errors: &'a ErrorLog,
+    pub duration: Duration,
+}
+
+impl RunMetrics<'_> {
+    /// Render the textfile contents.
+    pub fn render(&self) -> String {
+        let root = label_value(&self.root.display().to_string());
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name}{{root=\"{root}\"}} {value}");
+        };
+        gauge(
+            "dirstamp_dirs_scanned",
+            "Directories examined in the last run.",
+            self.counts.scanned.to_string(),
+        );
+        gauge(
+            "dirstamp_changes_planned",
+            "Directory mtime changes planned (dry run) in the last run.",
+            self.counts.planned.to_string(),
+        );
+        gauge(
+            "dirstamp_changes_applied",
+            "Directory mtime changes applied in the last run.",
+            self.counts.applied.to_string(),
+        );
+        gauge(
+            "dirstamp_max_drift_seconds",
+            "Largest absolute mtime change planned or applied in the last run.",
+            format!("{:.3}", self.counts.max_drift_secs),
+        );
+        gauge(
+            "dirstamp_run_duration_seconds",
+            "Wall-clock duration of the last run.",
+            format!("{:.6}", self.duration.as_secs_f64()),
+        );
+
+        // Errors are per run, so a gauge too despite the _total name.
+        let name = "dirstamp_errors_total";
+        let _ = writeln!(out, "# HELP {name} Errors in the last run, by category.");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for category in ErrorCategory::ALL {
+            let _ = writeln!(
+                out,
+                "{name}{{root=\"{root}\",category=\"{}\"}} {}",
+                category.key(),
+                self.errors.count(category)
+            );
+        }
+        out
+    }
+
+    /// Write to `path` via a temporary file in the same directory and a rename.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp = path.with_file_name(tmp_name);
+
+        let written = fs::File::create(&tmp).and_then(|mut f| {
+            f.write_all(self.render().as_bytes())?;
+            f.sync_all()
+        });
+        match written.and_then(|()| fs::rename(&tmp, path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Escape a label value: backslash, double quote and newline.
+fn label_value(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_help_type_and_escaped_labels() {
+        let errors = ErrorLog::new(Path::new("/x"));
+        let metrics = RunMetrics {
+            root: Path::new("/srv/\"archive\""),
+            counts: Counts {
+                scanned: 10,
+                planned: 3,
+                ..Counts::default()
+            },
+            errors: &errors,
+            duration: Duration::from_millis(1500),
+        };
+        let text = metrics.render();
+        assert!(text.contains("# TYPE dirstamp_dirs_scanned gauge\n"));
+        assert!(text.contains("dirstamp_dirs_scanned{root=\"/srv/\\\"archive\\\"\"} 10\n"));
+        assert!(text
+            .contains("dirstamp_run_duration_seconds{root=\"/srv/\\\"archive\\\"\"} 1.500000\n"));
+        assert!(text.contains("category=\"set_mtime\"} 0\n"));
+    }
+}
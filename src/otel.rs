@@ -0,0 +1,149 @@
+// src/otel.rs
+// --otel: OpenTelemetry traces over OTLP/HTTP. The run is one root span with
+// a child span for every directory stamped (or planned, in a dry run). The
+// exporter is configured by the standard OTEL_EXPORTER_OTLP_* variables.
+//
+// The exporter is only compiled in with the `otel` cargo feature; without it
+// `RunTrace::start` refuses and everything here is a no-op.
+
+use std::env;
+use std::path::Path;
+
+use crate::report::{Change, Counts, Status};
+
+/// Setting this turns tracing on without `--otel`.
+pub const ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Whether the environment asks for traces.
+pub fn endpoint_configured() -> bool {
+    env::var_os(ENDPOINT_VAR).is_some_and(|v| !v.is_empty())
+}
+
+#[cfg(feature = "otel")]
+pub use enabled::RunTrace;
+
+#[cfg(not(feature = "otel"))]
+pub use disabled::RunTrace;
+
+#[cfg(feature = "otel")]
+mod enabled {
+    use super::*;
+
+    use opentelemetry::trace::{
+        Span, Status as SpanStatus, TraceContextExt, Tracer, TracerProvider,
+    };
+    use opentelemetry::{Context, KeyValue};
+    use opentelemetry_otlp::SpanExporter;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use opentelemetry_sdk::Resource;
+
+    use crate::log::timestamp;
+    use crate::report::porcelain_escape;
+
+    pub struct RunTrace {
+        provider: SdkTracerProvider,
+        tracer: SdkTracer,
+        /// Holds the root span; directory spans are its children.
+        run: Context,
+    }
+
+    impl RunTrace {
+        /// Open the exporter and start the root span. `Err` is a message.
+        pub fn start(root: &Path, confirm: bool) -> Result<Self, String> {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .build()
+                .map_err(|e| format!("cannot set up the OTLP exporter ({e})"))?;
+            let provider = SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name("dirstamp")
+                        .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+                        .build(),
+                )
+                .build();
+            let tracer = provider.tracer("dirstamp");
+            let span = tracer
+                .span_builder("dirstamp")
+                .with_attributes([
+                    KeyValue::new("dirstamp.root", root.display().to_string()),
+                    KeyValue::new("dirstamp.confirm", confirm),
+                ])
+                .start(&tracer);
+            Ok(RunTrace {
+                provider,
+                tracer,
+                run: Context::new().with_span(span),
+            })
+        }
+
+        /// One span per directory, ended straight away: the change is
+        /// reported once it has been made.
+        pub fn change(&self, change: &Change, status: Status) {
+            let name = match status {
+                Status::Planned => "plan directory",
+                Status::Applied | Status::Failed => "stamp directory",
+            };
+            let mut span = self
+                .tracer
+                .span_builder(name)
+                .with_attributes([
+                    KeyValue::new("dir.path", porcelain_escape(&change.path)),
+                    KeyValue::new("dir.from_mtime", timestamp(change.from)),
+                    KeyValue::new("dir.to_mtime", timestamp(change.to)),
+                    KeyValue::new("dir.delta_seconds", change.delta_secs()),
+                ])
+                .start_with_context(&self.tracer, &self.run);
+            if status == Status::Failed {
+                span.set_status(SpanStatus::error("cannot set the folder's mtime"));
+            }
+            span.end();
+        }
+
+        /// End the root span with the run's totals and flush everything.
+        pub fn finish(self, counts: &Counts) {
+            let span = self.run.span();
+            span.set_attributes([
+                KeyValue::new("dirstamp.scanned", counts.scanned as i64),
+                KeyValue::new("dirstamp.planned", counts.planned as i64),
+                KeyValue::new("dirstamp.applied", counts.applied as i64),
+                KeyValue::new("dirstamp.failed", counts.failed as i64),
+            ]);
+            if counts.failed > 0 {
+                span.set_status(SpanStatus::error(format!(
+                    "{} folder(s) could not be stamped",
+                    counts.failed
+                )));
+            }
+            span.end();
+            if let Err(e) = self.provider.shutdown() {
+                eprintln!("warning: cannot export OpenTelemetry traces ({e})");
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod disabled {
+    use super::*;
+
+    /// Never constructed: this build has no exporter.
+    pub enum RunTrace {}
+
+    impl RunTrace {
+        pub fn start(_root: &Path, _confirm: bool) -> Result<Self, String> {
+            Err("this dirstamp was built without OpenTelemetry support \
+                 (rebuild with --features otel)"
+                .into())
+        }
+
+        pub fn change(&self, _change: &Change, _status: Status) {
+            match *self {}
+        }
+
+        pub fn finish(self, _counts: &Counts) {
+            match self {}
+        }
+    }
+}
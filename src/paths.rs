@@ -0,0 +1,228 @@
+// src/paths.rs
+// Lossless path output: the --escape styles for human-readable lines and the
+// JSON representation of paths that aren't valid UTF-8.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::cli::PathEscape;
+
+/// The path's bytes: raw on Unix, UTF-8 (lossy) elsewhere.
+pub fn raw_bytes(path: &Path) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(path.as_os_str().as_bytes())
+    }
+    #[cfg(not(unix))]
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+#[cfg(unix)]
+fn from_raw_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn from_raw_bytes(bytes: Vec<u8>) -> PathBuf {
+    String::from_utf8_lossy(&bytes).into_owned().into()
+}
+
+/// Render `path` for a human-readable line in the given `--escape` style.
+/// Every style but `never` keeps the line free of control characters.
+pub fn escape(path: &Path, style: PathEscape) -> Vec<u8> {
+    let raw = raw_bytes(path);
+    let mut out = Vec::with_capacity(raw.len() + 2);
+    match style {
+        PathEscape::Never => out.extend_from_slice(&raw),
+        PathEscape::Quoted => {
+            out.push(b'"');
+            for chunk in raw.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    match c {
+                        '"' => out.extend_from_slice(br#"\""#),
+                        '\\' => out.extend_from_slice(br"\\"),
+                        '\t' => out.extend_from_slice(br"\t"),
+                        '\n' => out.extend_from_slice(br"\n"),
+                        '\r' => out.extend_from_slice(br"\r"),
+                        c if c.is_control() => {
+                            out.extend_from_slice(format!("\\u{{{:x}}}", c as u32).as_bytes())
+                        }
+                        c => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+                for b in chunk.invalid() {
+                    out.extend_from_slice(format!("\\x{b:02X}").as_bytes());
+                }
+            }
+            out.push(b'"');
+        }
+        PathEscape::Octal => {
+            push_octal_escaped(&mut out, &raw, |c| c == '\\');
+        }
+        PathEscape::Shell => {
+            let safe = |b: &u8| b.is_ascii_alphanumeric() || b"_./-+,:@%=".contains(b);
+            if !raw.is_empty() && raw.iter().all(safe) {
+                out.extend_from_slice(&raw);
+            } else if std::str::from_utf8(&raw).is_ok_and(|s| !s.chars().any(char::is_control)) {
+                out.push(b'\'');
+                for &b in raw.iter() {
+                    if b == b'\'' {
+                        out.extend_from_slice(br"'\''");
+                    } else {
+                        out.push(b);
+                    }
+                }
+                out.push(b'\'');
+            } else {
+                out.extend_from_slice(b"$'");
+                push_octal_escaped(&mut out, &raw, |c| matches!(c, '\\' | '\''));
+                out.push(b'\'');
+            }
+        }
+    }
+    out
+}
+
+/// Copy `raw` to `out`, writing control characters and bytes that aren't
+/// valid UTF-8 as `\ooo`, and the characters `backslashed` picks as `\c`.
+fn push_octal_escaped(out: &mut Vec<u8>, raw: &[u8], backslashed: impl Fn(char) -> bool) {
+    let octal = |out: &mut Vec<u8>, b: u8| out.extend_from_slice(format!("\\{b:03o}").as_bytes());
+    for chunk in raw.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let mut buf = [0; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes();
+            if c.is_control() {
+                bytes.iter().for_each(|&b| octal(out, b));
+            } else {
+                if backslashed(c) {
+                    out.push(b'\\');
+                }
+                out.extend_from_slice(bytes);
+            }
+        }
+        chunk.invalid().iter().for_each(|&b| octal(out, b));
+    }
+}
+
+/// A path as stored in JSON: a plain string when it is valid UTF-8,
+/// otherwise the lossy string (for people) plus the exact bytes.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonPath {
+    Text(String),
+    Raw { lossy: String, bytes: Vec<u8> },
+}
+
+impl JsonPath {
+    fn new(path: &Path) -> Self {
+        match path.to_str() {
+            Some(s) => JsonPath::Text(s.to_string()),
+            None => JsonPath::Raw {
+                lossy: path.to_string_lossy().into_owned(),
+                bytes: raw_bytes(path).into_owned(),
+            },
+        }
+    }
+
+    fn into_path(self) -> PathBuf {
+        match self {
+            JsonPath::Text(s) => s.into(),
+            JsonPath::Raw { bytes, .. } => from_raw_bytes(bytes),
+        }
+    }
+}
+
+/// `#[serde(with = "paths::json")]` for `PathBuf` fields.
+pub mod json {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(path: &Path, s: S) -> Result<S::Ok, S::Error> {
+        JsonPath::new(path).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<PathBuf, D::Error> {
+        JsonPath::deserialize(d).map(JsonPath::into_path)
+    }
+}
+
+/// `#[serde(with = "paths::json_opt")]` for `Option<PathBuf>` fields.
+pub mod json_opt {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(path: &Option<PathBuf>, s: S) -> Result<S::Ok, S::Error> {
+        path.as_deref().map(JsonPath::new).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<PathBuf>, D::Error> {
+        Ok(Option::<JsonPath>::deserialize(d)?.map(JsonPath::into_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn esc(path: &Path, style: PathEscape) -> String {
+        String::from_utf8(escape(path, style)).unwrap()
+    }
+
+    #[test]
+    fn plain_paths() {
+        let p = Path::new("Photos/2014/Rome");
+        assert_eq!(esc(p, PathEscape::Quoted), r#""Photos/2014/Rome""#);
+        assert_eq!(esc(p, PathEscape::Octal), "Photos/2014/Rome");
+        assert_eq!(esc(p, PathEscape::Shell), "Photos/2014/Rome");
+        assert_eq!(
+            esc(Path::new("it's here"), PathEscape::Shell),
+            r"'it'\''s here'"
+        );
+        assert_eq!(esc(Path::new("a\\b"), PathEscape::Octal), r"a\\b");
+        assert_eq!(
+            esc(Path::new("tab\there"), PathEscape::Quoted),
+            r#""tab\there""#
+        );
+        assert_eq!(
+            esc(Path::new("bell\x07"), PathEscape::Shell),
+            r"$'bell\007'"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths() {
+        let latin1 = from_raw_bytes(b"caf\xe9".to_vec());
+        assert_eq!(esc(&latin1, PathEscape::Quoted), r#""caf\xE9""#);
+        assert_eq!(esc(&latin1, PathEscape::Octal), r"caf\351");
+        assert_eq!(esc(&latin1, PathEscape::Shell), r"$'caf\351'");
+        assert_eq!(escape(&latin1, PathEscape::Never), b"caf\xe9");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn json_round_trips_non_utf8() {
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper {
+            #[serde(with = "json")]
+            path: PathBuf,
+        }
+        let latin1 = from_raw_bytes(b"caf\xe9".to_vec());
+        let text = serde_json::to_string(&Wrapper {
+            path: latin1.clone(),
+        })
+        .unwrap();
+        assert_eq!(text, r#"{"path":{"lossy":"caf�","bytes":[99,97,102,233]}}"#);
+        let back: Wrapper = serde_json::from_str(&text).unwrap();
+        assert_eq!(back.path, latin1);
+
+        let plain: Wrapper = serde_json::from_str(r#"{"path":"a/b"}"#).unwrap();
+        assert_eq!(plain.path, Path::new("a/b"));
+    }
+}
@@ -0,0 +1,107 @@
+// src/plan.rs
+// --plan / --apply-plan: the computed changes as a reviewable JSON file that
+// can be applied later without re-walking the tree.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::paths;
+use crate::report::{Change, ChangeReason};
+
+/// Plan files this build writes and accepts.
+pub const PLAN_VERSION: u32 = 1;
+
+/// One directory's pending change. Also the per-change record of the JSON
+/// output, so a plan reads the same as a report.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    #[serde(with = "paths::json")]
+    pub path: PathBuf,
+    /// The mtime the directory had when the plan was made.
+    #[serde(with = "time::serde::rfc3339")]
+    pub current_mtime: OffsetDateTime,
+    /// The mtime it should be given.
+    #[serde(with = "time::serde::rfc3339")]
+    pub target_mtime: OffsetDateTime,
+    /// Name of the child the target mtime was taken from.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "paths::json_opt"
+    )]
+    pub source_child: Option<PathBuf>,
+}
+
+impl From<&Change> for ChangeRecord {
+    fn from(change: &Change) -> Self {
+        ChangeRecord {
+            path: change.path.clone(),
+            current_mtime: change.from.into(),
+            target_mtime: change.to.into(),
+            source_child: change.source_child.clone(),
+        }
+    }
+}
+
+impl From<&ChangeRecord> for Change {
+    fn from(record: &ChangeRecord) -> Self {
+        Change {
+            path: record.path.clone(),
+            from: record.current_mtime.into(),
+            to: record.target_mtime.into(),
+            source_child: record.source_child.clone(),
+            copied_from: None,
+            reason: ChangeReason::Saved,
+        }
+    }
+}
+
+/// A whole plan file. Changes are stored child-before-parent, in the order
+/// they must be applied.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub dirstamp_version: String,
+    #[serde(with = "paths::json")]
+    pub root: PathBuf,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+    pub changes: Vec<ChangeRecord>,
+}
+
+impl Plan {
+    pub fn new(root: &Path, changes: Vec<ChangeRecord>, created: SystemTime) -> Self {
+        Plan {
+            version: PLAN_VERSION,
+            dirstamp_version: env!("CARGO_PKG_VERSION").into(),
+            root: root.to_path_buf(),
+            created: created.into(),
+            changes,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Plan> {
+        let plan: Plan = serde_json::from_slice(&fs::read(path)?)?;
+        if plan.version != PLAN_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported plan version {} (this dirstamp reads version {PLAN_VERSION})",
+                    plan.version
+                ),
+            ));
+        }
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        fs::write(path, json)
+    }
+}
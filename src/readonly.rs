@@ -0,0 +1,100 @@
+// src/readonly.rs
+// Read-only filesystems: an up-front probe of the target, so -C on a mounted
+// snapshot fails at once instead of after the walk, and per-mount tracking so
+// a read-only mount met mid-run is reported once rather than per folder.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+/// Whether `path` is on a filesystem mounted read-only.
+#[cfg(unix)]
+pub fn is_read_only(path: &Path) -> io::Result<bool> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut vfs = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is NUL-terminated and `vfs` has room for the struct
+    // statvfs fills in; it is only read after statvfs reported success.
+    let vfs = unsafe {
+        if libc::statvfs(c_path.as_ptr(), vfs.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        vfs.assume_init()
+    };
+    Ok(vfs.f_flag & libc::ST_RDONLY != 0)
+}
+
+/// Whether `path` is on a read-only volume: open it for attribute writes,
+/// which changes nothing but is refused on write-protected media.
+#[cfg(windows)]
+pub fn is_read_only(path: &Path) -> io::Result<bool> {
+    use std::fs::OpenOptions;
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+    // Needed to open a directory at all.
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    const ERROR_WRITE_PROTECT: i32 = 19;
+
+    let opened = OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path);
+    match opened {
+        Ok(_) => Ok(false),
+        Err(e) if e.raw_os_error() == Some(ERROR_WRITE_PROTECT) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// No probe elsewhere; the set-mtime errors still say what happened.
+#[cfg(not(any(unix, windows)))]
+pub fn is_read_only(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Read-only mounts already reported in this run.
+#[derive(Default)]
+pub struct ReadOnlyMounts {
+    /// Device numbers on Unix; elsewhere the whole run counts as one mount.
+    seen: HashSet<u64>,
+}
+
+impl ReadOnlyMounts {
+    /// Note a read-only failure at `path`; true the first time for its mount.
+    pub fn first_failure(&mut self, path: &Path) -> bool {
+        self.seen.insert(mount_of(path))
+    }
+}
+
+#[cfg(unix)]
+fn mount_of(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(path).map_or(0, |m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn mount_of(_path: &Path) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_dirs_are_writable_and_one_mount() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_read_only(dir.path()).unwrap());
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+
+        let mut mounts = ReadOnlyMounts::default();
+        assert!(mounts.first_failure(dir.path()));
+        assert!(!mounts.first_failure(&dir.path().join("sub")));
+    }
+}
@@ -0,0 +1,1104 @@
+// src/report.rs
+// Per-change output: the human, porcelain and buffered (--sort/--top) reports,
+// plus the optional script and plan sinks that see the same changes.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Serialize;
+// For human-readable timestamps when -D/--show-dates is used.
+use time::format_description::{parse as parse_format, OwnedFormatItem};
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::baseline::{Baseline, Drift};
+use crate::cli::{strftime_description, Config, PathEscape, ShowSkipped, SortKey, TimeStyle};
+use crate::histogram::DriftHistogram;
+use crate::html::{self, HtmlReport};
+use crate::interrupt;
+use crate::log::{self, RunLog};
+use crate::otel::RunTrace;
+use crate::paths;
+use crate::plan::ChangeRecord;
+use crate::rollup::Rollup;
+use crate::script::ScriptWriter;
+use crate::timing;
+use crate::tree::{Mark, Tree};
+
+pub use dirstamp::{Change, ChangeReason, SkipReason, Status};
+
+/// Per-change line format on stdout.
+pub enum LineFormat {
+    /// Human-readable lines, with optional `-D` dates. `human_delta` spells
+    /// the drift out in words (`--human-delta`); paths follow `--escape`.
+    Human {
+        dates: Option<DateFormat>,
+        human_delta: bool,
+        escape: PathEscape,
+    },
+    /// `--porcelain=v1`: `STATUS<TAB>OLD<TAB>NEW<TAB>PATH`.
+    Porcelain,
+    /// `--itemize`: rsync-style `>t+9y  PATH` (legend in the README).
+    Itemize,
+    /// `--columns` on a terminal: an aligned table.
+    Columns(ColumnLayout),
+    /// `--output-json`: one `JsonLine` object per line.
+    Json,
+}
+
+/// Column widths for `--columns`.
+pub struct ColumnLayout {
+    dates: DateFormat,
+    delta_width: usize,
+    date_width: usize,
+    /// Terminal width; the path gets whatever is left.
+    width: usize,
+}
+
+impl ColumnLayout {
+    const GAP: &'static str = "  ";
+    /// Streaming width for the delta column, e.g. `-12345.6d`.
+    const DELTA_WIDTH: usize = 9;
+
+    fn new(dates: DateFormat, width: usize) -> Self {
+        // Every style but +FORMAT renders at a fixed width; measure a sample.
+        let date_width = dates.render(SystemTime::UNIX_EPOCH).chars().count();
+        ColumnLayout {
+            dates,
+            delta_width: Self::DELTA_WIDTH,
+            date_width,
+            width,
+        }
+    }
+
+    /// Size the columns to fit the (buffered) rows exactly.
+    fn fit(&mut self, rows: &[(Change, Status)]) {
+        let widest = |f: &dyn Fn(&Change) -> usize| rows.iter().map(|(c, _)| f(c)).max();
+        if let Some(w) = widest(&|c| Self::delta(c).len()) {
+            self.delta_width = w;
+        }
+        if let Some(w) = widest(&|c| {
+            let from = self.dates.render(c.from).chars().count();
+            from.max(self.dates.render(c.to).chars().count())
+        }) {
+            self.date_width = w;
+        }
+    }
+
+    fn delta(change: &Change) -> String {
+        format!("{:+.1}d", change.delta_secs() / 86_400.0)
+    }
+
+    fn print(&self, change: &Change) {
+        let fixed = self.delta_width + 2 * self.date_width + 3 * Self::GAP.len();
+        // Escaped so control characters can't break the table.
+        let path = porcelain_escape(&change.path);
+        let path = truncate_middle(&path, self.width.saturating_sub(fixed).max(12));
+        println!(
+            "{:>dw$}{gap}{:<tw$}{gap}{:<tw$}{gap}{}",
+            Self::delta(change),
+            self.dates.render(change.from),
+            self.dates.render(change.to),
+            path,
+            dw = self.delta_width,
+            tw = self.date_width,
+            gap = Self::GAP,
+        );
+    }
+}
+
+/// Shorten `s` to at most `max` characters by replacing its middle with `…`.
+pub fn truncate_middle(s: &str, max: usize) -> String {
+    let len = s.chars().count();
+    if len <= max {
+        return s.to_string();
+    }
+    let keep = max.saturating_sub(1);
+    let head = keep / 2;
+    let tail = keep - head;
+    let mut out: String = s.chars().take(head).collect();
+    out.push('…');
+    out.extend(s.chars().skip(len - tail));
+    out
+}
+
+/// Prepared `-D` timestamp rendering.
+pub enum DateFormat {
+    /// Calendar time in the given zone.
+    Calendar {
+        fmt: OwnedFormatItem,
+        offset: UtcOffset,
+    },
+    /// Integer seconds since the Unix epoch (`--epoch`).
+    Epoch,
+    /// Integer nanoseconds since the Unix epoch (`--epoch-ns`).
+    EpochNanos,
+    /// Seconds since the Unix epoch with this many fractional digits
+    /// (`--time-style epoch.N`).
+    EpochFrac(u8),
+}
+
+impl DateFormat {
+    /// Build the `-D` rendering requested on the command line, if any.
+    /// `Err` is a usage message for a bad `--time-style +FORMAT`.
+    pub fn from_config(cfg: &Config) -> Result<Option<DateFormat>, String> {
+        if cfg.epoch {
+            return Ok(Some(DateFormat::Epoch));
+        }
+        if cfg.epoch_ns {
+            return Ok(Some(DateFormat::EpochNanos));
+        }
+        if !cfg.show_dates && cfg.time_style.is_none() {
+            return Ok(None);
+        }
+
+        // Resolve the display timezone. Local offset lookup can fail (e.g. in
+        // containers without zoneinfo), in which case we fall back to UTC.
+        let offset = if cfg.local {
+            match UtcOffset::current_local_offset() {
+                Ok(o) => Some(o),
+                Err(e) => {
+                    eprintln!("warning: could not determine local timezone ({e}); using UTC");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let desc = match &cfg.time_style {
+            None => match offset {
+                None => return Ok(Some(DateFormat::utc())),
+                Some(_) => "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]".to_string(),
+            },
+            Some(TimeStyle::Epoch { digits: 0 }) => return Ok(Some(DateFormat::Epoch)),
+            Some(TimeStyle::Epoch { digits }) => return Ok(Some(DateFormat::EpochFrac(*digits))),
+            Some(TimeStyle::FullIso) => "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:9] [offset_hour sign:mandatory][offset_minute]".to_string(),
+            Some(TimeStyle::Iso) => "[year]-[month]-[day] [hour]:[minute]".to_string(),
+            Some(TimeStyle::Rfc3339) => "[year]-[month]-[day]T[hour]:[minute]:[second][offset_hour sign:mandatory]:[offset_minute]".to_string(),
+            Some(TimeStyle::Custom(f)) => strftime_description(f)?,
+        };
+        let fmt = parse_format(&desc).map_err(|e| format!("Invalid --time-style format ({e})"))?;
+        Ok(Some(DateFormat::Calendar {
+            fmt: fmt.into(),
+            offset: offset.unwrap_or(UtcOffset::UTC),
+        }))
+    }
+
+    /// The default `-D` rendering: calendar time in UTC.
+    pub fn utc() -> DateFormat {
+        DateFormat::Calendar {
+            fmt: parse_format("[year]-[month]-[day] [hour]:[minute]:[second] UTC")
+                .expect("valid time format")
+                .into(),
+            offset: UtcOffset::UTC,
+        }
+    }
+
+    pub fn render(&self, t: SystemTime) -> String {
+        match self {
+            DateFormat::Calendar { fmt, offset } => format_time(t, fmt, *offset),
+            DateFormat::Epoch => OffsetDateTime::from(t).unix_timestamp().to_string(),
+            DateFormat::EpochNanos => OffsetDateTime::from(t).unix_timestamp_nanos().to_string(),
+            DateFormat::EpochFrac(digits) => {
+                let nanos = OffsetDateTime::from(t).unix_timestamp_nanos();
+                let (secs, frac) = (
+                    nanos.div_euclid(1_000_000_000),
+                    nanos.rem_euclid(1_000_000_000),
+                );
+                let frac = format!("{frac:09}");
+                format!("{secs}.{}", &frac[..usize::from(*digits)])
+            }
+        }
+    }
+}
+
+/// Render `t` in the zone given by `offset` using the prepared format.
+fn format_time(t: SystemTime, fmt: &OwnedFormatItem, offset: UtcOffset) -> String {
+    OffsetDateTime::from(t)
+        .to_offset(offset)
+        .format(fmt)
+        .unwrap_or_else(|_| "<bad time>".into())
+}
+
+/// One line of `--output-json`; the summary is always the last.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonLine<'a> {
+    Change {
+        status: &'static str,
+        #[serde(flatten)]
+        record: ChangeRecord,
+    },
+    Skipped {
+        reason: &'static str,
+        #[serde(with = "paths::json")]
+        path: &'a Path,
+    },
+    Summary {
+        scanned: usize,
+        updated: usize,
+        errors: usize,
+        dry_run: bool,
+        #[serde(with = "paths::json")]
+        root: &'a Path,
+        duration_ms: u128,
+        /// `--timing`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timing: Option<timing::Summary>,
+    },
+}
+
+impl JsonLine<'_> {
+    fn print(&self) {
+        let mut line = serde_json::to_vec(self).expect("JSON lines always serialize");
+        line.push(b'\n');
+        let _ = io::stdout().lock().write_all(&line);
+    }
+}
+
+/// The closing `--output-json` line: totals for the whole run.
+pub fn print_json_summary(
+    root: &Path,
+    report: &dirstamp::Report,
+    dry_run: bool,
+    timing: Option<timing::Summary>,
+) {
+    JsonLine::Summary {
+        scanned: report.dirs_scanned,
+        updated: report.changes_planned - report.changes_failed,
+        errors: report.errors.len(),
+        dry_run,
+        root,
+        duration_ms: report.elapsed.as_millis(),
+        timing,
+    }
+    .print();
+}
+
+/// Version 1 of the per-change line, in each of the line formats.
+fn print_change(change: &Change, status: Status, format: &LineFormat) {
+    let dates = match format {
+        LineFormat::Porcelain => {
+            println!(
+                "{}\t{}\t{}\t{}",
+                status.code(),
+                DateFormat::Epoch.render(change.from),
+                DateFormat::Epoch.render(change.to),
+                porcelain_escape(&change.path)
+            );
+            return;
+        }
+        LineFormat::Columns(layout) => {
+            if status != Status::Failed {
+                layout.print(change);
+            }
+            return;
+        }
+        LineFormat::Itemize => {
+            println!(
+                "{} {}",
+                itemize_code(change, status),
+                porcelain_escape(&change.path)
+            );
+            return;
+        }
+        LineFormat::Json => {
+            JsonLine::Change {
+                status: status.word(),
+                record: ChangeRecord::from(change),
+            }
+            .print();
+            return;
+        }
+        // Failures have already been reported on stderr.
+        LineFormat::Human { .. } if status == Status::Failed => return,
+        LineFormat::Human {
+            dates,
+            human_delta,
+            escape,
+        } => (dates, *human_delta, *escape),
+    };
+    let (dates, human_delta, escape) = dates;
+    let mut line = Line::new(escape);
+    if let Some(src) = &change.copied_from {
+        // The target time is the whole point here, so it's shown even without -D.
+        let utc;
+        let d = match dates {
+            Some(d) => d,
+            None => {
+                utc = DateFormat::utc();
+                &utc
+            }
+        };
+        let verb = if status == Status::Applied {
+            "set"
+        } else {
+            "would set"
+        };
+        line.text(verb).text(" ").bare_path(&change.path);
+        line.text(&format!(" mtime to {} (copied from ", d.render(change.to)));
+        line.bare_path(src).text(")").print();
+        return;
+    }
+    let verb = if status == Status::Applied {
+        "updated"
+    } else {
+        "would update"
+    };
+    line.text(verb).text(" ").path(&change.path);
+    if let Some(d) = dates {
+        push_details(&mut line, change, d, human_delta);
+    }
+    line.print();
+}
+
+/// ` (from OLD to NEW, DELTA, driven by CHILD)`: the `-D` part of a change line.
+fn push_details(line: &mut Line, change: &Change, d: &DateFormat, human_delta: bool) {
+    let delta = if human_delta {
+        english_delta(change.delta_secs())
+    } else {
+        format!("{:+.1} days", change.delta_secs() / 86_400.0)
+    };
+    line.text(&format!(
+        " (from {} to {}, {}",
+        d.render(change.from),
+        d.render(change.to),
+        delta
+    ));
+    if let Some(name) = &change.source_child {
+        line.text(", driven by ").bare_path(name);
+    }
+    line.text(")");
+}
+
+/// A change with its `-D` details, for `--interactive` prompts: the change
+/// line without its verb.
+pub fn describe_change(
+    change: &Change,
+    dates: &DateFormat,
+    human_delta: bool,
+    escape: PathEscape,
+) -> Vec<u8> {
+    let mut line = Line::new(escape);
+    line.path(&change.path);
+    if let Some(src) = &change.copied_from {
+        line.text(&format!(" (to {}, copied from ", dates.render(change.to)));
+        line.bare_path(src).text(")");
+    } else {
+        push_details(&mut line, change, dates, human_delta);
+    }
+    line.buf
+}
+
+/// A human-readable stdout line, built as bytes so `--escape=never` can
+/// pass paths through exactly.
+struct Line {
+    buf: Vec<u8>,
+    escape: PathEscape,
+}
+
+impl Line {
+    fn new(escape: PathEscape) -> Self {
+        Line {
+            buf: Vec::new(),
+            escape,
+        }
+    }
+
+    fn text(&mut self, s: &str) -> &mut Self {
+        self.buf.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    fn path(&mut self, path: &Path) -> &mut Self {
+        self.buf.extend(paths::escape(path, self.escape));
+        self
+    }
+
+    /// Like `path`, but the default style leaves off the quotes (these spots
+    /// have always been unquoted).
+    fn bare_path(&mut self, path: &Path) -> &mut Self {
+        let escaped = paths::escape(path, self.escape);
+        match self.escape {
+            PathEscape::Quoted => self.buf.extend(&escaped[1..escaped.len() - 1]),
+            _ => self.buf.extend(escaped),
+        }
+        self
+    }
+
+    fn print(&mut self) {
+        self.buf.push(b'\n');
+        // Same as println!: a closed stdout isn't worth failing the run over.
+        let _ = io::stdout().lock().write_all(&self.buf);
+    }
+}
+
+/// `--human-delta`: "forward 3 hours", "back 2 weeks". Uses the largest unit
+/// that gives a count of at least two, so 14 months stays "14 months" rather
+/// than "1 year"; under two minutes it counts seconds.
+fn english_delta(secs: f64) -> String {
+    const UNITS: [(f64, &str); 6] = [
+        (365.25 * 86_400.0, "years"),
+        (30.44 * 86_400.0, "months"),
+        (7.0 * 86_400.0, "weeks"),
+        (86_400.0, "days"),
+        (3_600.0, "hours"),
+        (60.0, "minutes"),
+    ];
+    let direction = if secs < 0.0 { "back" } else { "forward" };
+    let abs = secs.abs();
+    let (count, unit) = UNITS
+        .iter()
+        .map(|(size, unit)| ((abs / size) as u64, *unit))
+        .find(|(count, _)| *count >= 2)
+        .unwrap_or_else(|| {
+            let n = abs as u64;
+            (n, if n == 1 { "second" } else { "seconds" })
+        });
+    format!("{direction} {count} {unit}")
+}
+
+/// `--itemize` code: direction (`>` forward, `<` back), status (`t` planned,
+/// `T` applied, `!` failed) and the signed drift in its largest whole unit,
+/// padded so paths line up.
+fn itemize_code(change: &Change, status: Status) -> String {
+    let direction = if change.to >= change.from { '>' } else { '<' };
+    let status = match status {
+        Status::Planned => 't',
+        Status::Applied => 'T',
+        Status::Failed => '!',
+    };
+    format!(
+        "{direction}{status}{:<4}",
+        compact_delta(change.delta_secs())
+    )
+}
+
+/// Signed drift as a whole number of its largest unit: `+9y`, `-3w`, `+45s`.
+fn compact_delta(secs: f64) -> String {
+    const UNITS: [(f64, char); 6] = [
+        (365.25 * 86_400.0, 'y'),
+        (7.0 * 86_400.0, 'w'),
+        (86_400.0, 'd'),
+        (3_600.0, 'h'),
+        (60.0, 'm'),
+        (1.0, 's'),
+    ];
+    let sign = if secs < 0.0 { '-' } else { '+' };
+    let abs = secs.abs();
+    let (size, unit) = UNITS
+        .iter()
+        .copied()
+        .find(|(size, _)| abs >= *size)
+        .unwrap_or((1.0, 's'));
+    format!("{sign}{}{unit}", (abs / size) as u64)
+}
+
+/// Escape a path for `--porcelain`: `\\`, `\t`, `\n` and `\r` are
+/// backslash-escaped, and other control characters (plus, on Unix, bytes
+/// that are not valid UTF-8) become `\xHH`.
+pub fn porcelain_escape(path: &Path) -> String {
+    fn push_char(out: &mut String, c: char) {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_ascii_control() => {
+                let _ = write!(out, "\\x{:02x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+
+    let mut out = String::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+            chunk.valid().chars().for_each(|c| push_char(&mut out, c));
+            for b in chunk.invalid() {
+                let _ = write!(out, "\\x{b:02x}");
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    path.to_string_lossy()
+        .chars()
+        .for_each(|c| push_char(&mut out, c));
+    out
+}
+
+/// Output schema versions this build can render, oldest first; the last is
+/// the default. Bump when a stdout format changes incompatibly and keep the
+/// old rendering reachable via `--format-version`.
+pub const FORMAT_VERSIONS: &[u8] = &[1];
+
+/// Settings that pick the rendering path for everything printed on stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputConfig {
+    pub format_version: u8,
+}
+
+impl OutputConfig {
+    /// `Err` lists the supported versions.
+    pub fn from_config(cfg: &Config) -> Result<Self, String> {
+        let newest = FORMAT_VERSIONS[FORMAT_VERSIONS.len() - 1];
+        let format_version = cfg.format_version.unwrap_or(newest);
+        if !FORMAT_VERSIONS.contains(&format_version) {
+            let available: Vec<String> = FORMAT_VERSIONS.iter().map(u8::to_string).collect();
+            return Err(format!(
+                "Unsupported --format-version: {format_version} (available: {})",
+                available.join(", ")
+            ));
+        }
+        Ok(OutputConfig { format_version })
+    }
+
+    fn print_change(&self, change: &Change, status: Status, format: &LineFormat) {
+        match self.format_version {
+            1 => print_change(change, status, format),
+            v => unreachable!("format version {v} passed validation"),
+        }
+    }
+}
+
+/// Fans each change out to the stdout report and any script / plan sinks.
+pub struct Reporter {
+    output: OutputConfig,
+    format: LineFormat,
+    sort: Option<SortKey>,
+    top: Option<usize>,
+    /// Sorting or truncating the report needs every change; otherwise stream.
+    buffer: Option<Vec<(Change, Status)>>,
+    /// `--max-memory`: buffered changes that make the buffer print (or,
+    /// with `--top`, drop all but the largest).
+    flush_at: Option<usize>,
+    /// Changes `--top` has already dropped.
+    hidden: usize,
+    script: Option<ScriptWriter>,
+    log: Option<RunLog>,
+    /// `--otel`: a span per change under the run's root span.
+    trace: Option<RunTrace>,
+    root: PathBuf,
+    /// `--baseline`: changes it already records aren't printed.
+    baseline: Option<Baseline>,
+    baseline_hidden: usize,
+    /// `--update-baseline`: this run's drift, to replace the file with.
+    new_baseline: Option<Vec<Drift>>,
+    /// `--emit-script` to stdout replaces the normal report entirely.
+    script_only: bool,
+    /// Planned changes collected for `--plan`.
+    plan: Option<Vec<ChangeRecord>>,
+    html: Option<HtmlReport>,
+    /// `--group-depth`: per-folder rollup printed at the end.
+    rollup: Option<Rollup>,
+    /// `--tree`: every folder, drawn at the end in place of the change lines.
+    tree: Option<Tree>,
+    /// Print individual changes (always, unless rolled up without `-v`).
+    per_change: bool,
+    /// `--interval`: the caller prints a line per run instead of change and skip lines.
+    per_run: bool,
+    show_skipped: Option<ShowSkipped>,
+    counts: Counts,
+    /// Skips by why, for the summary's `Report`.
+    skipped: HashMap<SkipReason, usize>,
+    drift: DriftHistogram,
+}
+
+/// Running totals over everything the report was told about.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Counts {
+    /// Directories looked at: every change or skip.
+    pub scanned: usize,
+    pub planned: usize,
+    pub applied: usize,
+    pub failed: usize,
+    /// Largest |delta| of a planned or applied change, in seconds.
+    pub max_drift_secs: f64,
+}
+
+impl Counts {
+    /// Changes made or planned (failures excluded).
+    pub fn changed(&self) -> usize {
+        self.planned + self.applied
+    }
+}
+
+/// What the report saw, for the end-of-run summary.
+pub struct Outcome {
+    pub counts: Counts,
+    pub skipped: HashMap<SkipReason, usize>,
+    /// The `--plan` records, if a plan was requested.
+    pub plan: Option<Vec<ChangeRecord>>,
+    pub drift: DriftHistogram,
+    /// The `--report-html` contents, if requested.
+    pub html: Option<HtmlReport>,
+    /// Changes not printed because the baseline already had them.
+    pub baseline_hidden: usize,
+    /// `--update-baseline`: every change of this run.
+    pub new_baseline: Option<Vec<Drift>>,
+}
+
+impl Reporter {
+    /// `Err` is a usage message (a bad `--time-style` or `--format-version`).
+    pub fn new(
+        cfg: &Config,
+        root: &Path,
+        script: Option<ScriptWriter>,
+        log: Option<RunLog>,
+        trace: Option<RunTrace>,
+        baseline: Option<Baseline>,
+    ) -> Result<Self, String> {
+        let output = OutputConfig::from_config(cfg)?;
+        let dates = DateFormat::from_config(cfg)?;
+        let format = if cfg.porcelain {
+            LineFormat::Porcelain
+        } else if cfg.output_json {
+            LineFormat::Json
+        } else if cfg.itemize {
+            LineFormat::Itemize
+        } else if cfg.columns && io::stdout().is_terminal() {
+            let width = terminal_size::terminal_size().map_or(80, |(w, _)| usize::from(w.0));
+            LineFormat::Columns(ColumnLayout::new(
+                dates.unwrap_or_else(DateFormat::utc),
+                width,
+            ))
+        } else {
+            // --columns implies -D, so without a terminal it degrades to that.
+            let dates = match dates {
+                None if cfg.columns => Some(DateFormat::utc()),
+                dates => dates,
+            };
+            LineFormat::Human {
+                dates,
+                human_delta: cfg.human_delta,
+                escape: cfg.escape,
+            }
+        };
+        let buffered = cfg.sort.is_some() || cfg.top.is_some();
+        Ok(Reporter {
+            output,
+            format,
+            sort: cfg.sort,
+            top: cfg.top,
+            buffer: buffered.then(Vec::new),
+            flush_at: cfg.entry_budget(),
+            hidden: 0,
+            script_only: script.is_some() && cfg.script_file.is_none(),
+            script,
+            log,
+            trace,
+            root: root.to_path_buf(),
+            baseline,
+            baseline_hidden: 0,
+            new_baseline: cfg.update_baseline.then(Vec::new),
+            plan: cfg.plan.is_some().then(Vec::new),
+            html: cfg
+                .report_html
+                .is_some()
+                .then(|| HtmlReport::new(cfg.report_rows.unwrap_or(html::DEFAULT_MAX_ROWS))),
+            rollup: cfg.group_depth.map(|depth| Rollup::new(root, depth)),
+            tree: cfg.tree.then(|| Tree::new(root)),
+            per_change: !cfg.tree
+                && !cfg.summary_only
+                && cfg.interval.is_none()
+                && (cfg.group_depth.is_none() || cfg.verbose),
+            per_run: cfg.interval.is_some(),
+            show_skipped: cfg.show_skipped,
+            counts: Counts::default(),
+            skipped: HashMap::new(),
+            drift: DriftHistogram::default(),
+        })
+    }
+
+    pub fn record(&mut self, change: Change, status: Status) -> io::Result<()> {
+        if status == Status::Planned {
+            if let Some(w) = &mut self.script {
+                w.push(&change.path, change.to)?;
+            }
+            if let Some(plan) = &mut self.plan {
+                plan.push(ChangeRecord::from(&change));
+            }
+        }
+        if let Some(html) = &mut self.html {
+            html.add(&change, status);
+        }
+        let drift = Drift::new(&change, &self.root);
+        let known =
+            status != Status::Failed && self.baseline.as_ref().is_some_and(|b| b.knows(&drift));
+        if known {
+            self.baseline_hidden += 1;
+        }
+        if let Some(new_baseline) = &mut self.new_baseline {
+            new_baseline.push(drift);
+        }
+        if let Some(log) = &mut self.log {
+            log.line(&format!(
+                "{} {} from {} to {}",
+                status.word(),
+                porcelain_escape(&change.path),
+                log::timestamp(change.from),
+                log::timestamp(change.to)
+            ));
+        }
+        if let Some(trace) = &self.trace {
+            trace.change(&change, status);
+        }
+        self.counts.scanned += 1;
+        match status {
+            Status::Planned => self.counts.planned += 1,
+            Status::Applied => self.counts.applied += 1,
+            Status::Failed => self.counts.failed += 1,
+        }
+        if let Some(tree) = &mut self.tree {
+            let mark = match status {
+                Status::Failed => Mark::Failed,
+                _ => Mark::Update(change.delta_secs()),
+            };
+            tree.add(&change.path, mark);
+        }
+        if status != Status::Failed {
+            let drift = change.delta_secs().abs();
+            self.counts.max_drift_secs = self.counts.max_drift_secs.max(drift);
+            self.drift.add(change.delta_secs());
+            if let Some(rollup) = &mut self.rollup {
+                rollup.add_change(&change.path, change.delta_secs());
+            }
+        } else {
+            if let Some(rollup) = &mut self.rollup {
+                rollup.add_error(&change.path);
+            }
+            if matches!(
+                self.format,
+                LineFormat::Human { .. } | LineFormat::Columns(_)
+            ) {
+                // Porcelain already has an F line for it.
+                self.print_skip(&change.path, SkipReason::Error);
+            }
+        }
+
+        if self.script_only || !self.per_change || known {
+            // The script (or the rollup) is the report; the baseline has seen it.
+        } else if let Some(buffer) = &mut self.buffer {
+            buffer.push((change, status));
+            if self.flush_at.is_some_and(|n| buffer.len() > n) {
+                self.flush();
+            }
+        } else {
+            self.output.print_change(&change, status, &self.format);
+        }
+        Ok(())
+    }
+
+    /// Note a directory that was left alone. Printed straight away (even when
+    /// the change report is buffered) if `--show-skipped` asks for it.
+    pub fn skip(&mut self, path: &Path, reason: SkipReason) {
+        self.counts.scanned += 1;
+        *self.skipped.entry(reason).or_default() += 1;
+        self.mark_skipped(path, reason);
+    }
+
+    /// Note a place the walk couldn't get into: shown as a skip, but not
+    /// counted as a folder looked at (its error is counted on its own).
+    pub fn unwalked(&mut self, path: &Path, reason: SkipReason) {
+        self.mark_skipped(path, reason);
+    }
+
+    fn mark_skipped(&mut self, path: &Path, reason: SkipReason) {
+        if let Some(tree) = &mut self.tree {
+            tree.add(path, Mark::Skipped(reason));
+        }
+        if reason == SkipReason::Error {
+            if let Some(rollup) = &mut self.rollup {
+                rollup.add_error(path);
+            }
+            if let Some(log) = &mut self.log {
+                log.line(&format!("error {}", porcelain_escape(path)));
+            }
+        }
+        self.print_skip(path, reason);
+    }
+
+    fn print_skip(&self, path: &Path, reason: SkipReason) {
+        let wanted = match self.show_skipped {
+            None => false,
+            Some(ShowSkipped::All) => true,
+            Some(ShowSkipped::Errors) => {
+                matches!(reason, SkipReason::Error | SkipReason::TimedOut)
+            }
+        };
+        if !wanted || self.script_only || self.tree.is_some() || self.per_run {
+            return;
+        }
+        match self.format {
+            LineFormat::Porcelain => {
+                println!("S\t{}\t-\t{}", reason.keyword(), porcelain_escape(path))
+            }
+            LineFormat::Itemize => println!("*{} {}", reason.keyword(), porcelain_escape(path)),
+            LineFormat::Json => JsonLine::Skipped {
+                reason: reason.keyword(),
+                path,
+            }
+            .print(),
+            LineFormat::Human { .. } | LineFormat::Columns(_) => {
+                let escape = match self.format {
+                    LineFormat::Human { escape, .. } => escape,
+                    _ => PathEscape::Quoted,
+                };
+                let mut line = Line::new(escape);
+                line.text("skipped ").text(reason.keyword()).text(" ");
+                line.path(path).print();
+            }
+        }
+    }
+
+    /// The totals so far.
+    pub fn counts(&self) -> Counts {
+        self.counts
+    }
+
+    /// Add `text` to the `--log-file` record, if there is one.
+    pub fn note(&mut self, text: &str) {
+        if let Some(log) = &mut self.log {
+            log.line(text);
+        }
+    }
+
+    /// Whether stdout carries only machine-readable output.
+    pub fn is_machine_readable(&self) -> bool {
+        self.script_only || matches!(self.format, LineFormat::Porcelain | LineFormat::Json)
+    }
+
+    /// `--shard`: one subtree is done. Its part of a sorted report is
+    /// printed now; `--top` keeps only the largest changes so far.
+    pub fn end_shard(&mut self) {
+        self.flush();
+    }
+
+    /// Empty the buffer, except for the changes `--top` may still show.
+    fn flush(&mut self) {
+        let Some(buffer) = &mut self.buffer else {
+            return;
+        };
+        if let Some(n) = self.top {
+            buffer.sort_by(by_delta);
+            if n < buffer.len() {
+                self.hidden += buffer.len() - n;
+                buffer.truncate(n);
+            }
+            return;
+        }
+        let report = std::mem::take(buffer);
+        self.print_buffered(report);
+    }
+
+    fn print_buffered(&mut self, mut report: Vec<(Change, Status)>) {
+        if self.sort == Some(SortKey::Path) {
+            report.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+        } else {
+            report.sort_by(by_delta);
+        }
+        if let LineFormat::Columns(layout) = &mut self.format {
+            layout.fit(&report);
+        }
+        for (change, status) in &report {
+            self.output.print_change(change, *status, &self.format);
+        }
+    }
+
+    /// Print the buffered report (if any) and close the sinks.
+    pub fn finish(mut self) -> io::Result<Outcome> {
+        if let Some(mut report) = self.buffer.take() {
+            // --top always keeps the largest drifts, whatever order they're shown in.
+            report.sort_by(by_delta);
+            if let Some(n) = self.top.filter(|n| *n < report.len()) {
+                self.hidden += report.len() - n;
+                report.truncate(n);
+            }
+            self.print_buffered(report);
+            let hidden = self.hidden;
+            if hidden > 0 && !matches!(self.format, LineFormat::Porcelain | LineFormat::Json) {
+                println!("... and {hidden} more change(s) not shown");
+            }
+        }
+        if let Some(rollup) = &self.rollup {
+            if !self.script_only {
+                rollup.print();
+            }
+        }
+        if let Some(tree) = &self.tree {
+            if !self.script_only {
+                tree.print();
+            }
+        }
+        if let Some(w) = self.script {
+            w.finish()?;
+        }
+        if let Some(mut log) = self.log {
+            let c = &self.counts;
+            let word = if interrupt::failed() {
+                "stopped"
+            } else if interrupt::requested() {
+                "interrupted"
+            } else {
+                "finished"
+            };
+            log.line(&format!(
+                "{word}: {} scanned, {} planned, {} updated, {} failed",
+                c.scanned, c.planned, c.applied, c.failed
+            ));
+        }
+        if let Some(trace) = self.trace {
+            trace.finish(&self.counts);
+        }
+        Ok(Outcome {
+            counts: self.counts,
+            skipped: self.skipped,
+            plan: self.plan,
+            drift: self.drift,
+            html: self.html,
+            baseline_hidden: self.baseline_hidden,
+            new_baseline: self.new_baseline,
+        })
+    }
+}
+
+/// The reporter as `dirstamp::Events`, for the library's `plan_dirs` and
+/// `apply_steps`, which the binary plans and stamps every folder through. A change is
+/// reported once, when it is stamped, so while planning only skips are.
+/// Events can't fail; the first write that does is kept for `finish`.
+pub struct ReportEvents<'a> {
+    reporter: RefCell<&'a mut Reporter>,
+    planning: bool,
+    failed: RefCell<Option<io::Error>>,
+}
+
+impl<'a> ReportEvents<'a> {
+    pub fn planning(reporter: &'a mut Reporter) -> Self {
+        Self::new(reporter, true)
+    }
+
+    pub fn applying(reporter: &'a mut Reporter) -> Self {
+        Self::new(reporter, false)
+    }
+
+    fn new(reporter: &'a mut Reporter, planning: bool) -> Self {
+        ReportEvents {
+            reporter: RefCell::new(reporter),
+            planning,
+            failed: RefCell::new(None),
+        }
+    }
+
+    /// The first report write that failed, if one did.
+    pub fn finish(self) -> io::Result<()> {
+        self.failed.into_inner().map_or(Ok(()), Err)
+    }
+}
+
+impl dirstamp::Events for ReportEvents<'_> {
+    fn on_change(&self, change: &Change, status: Status) {
+        let mut failed = self.failed.borrow_mut();
+        if self.planning || failed.is_some() {
+            return;
+        }
+        if let Err(e) = self.reporter.borrow_mut().record(change.clone(), status) {
+            *failed = Some(e);
+        }
+    }
+
+    fn on_skipped(&self, path: &Path, reason: SkipReason) {
+        self.reporter.borrow_mut().skip(path, reason);
+    }
+}
+
+/// Largest drift first, then by path.
+fn by_delta((a, _): &(Change, Status), (b, _): &(Change, Status)) -> std::cmp::Ordering {
+    b.delta_secs()
+        .abs()
+        .total_cmp(&a.delta_secs().abs())
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn change(from: u64, to: u64) -> Change {
+        Change {
+            path: PathBuf::from("Photos/2014/Rome"),
+            from: UNIX_EPOCH + Duration::from_secs(from),
+            to: UNIX_EPOCH + Duration::from_secs(to),
+            source_child: None,
+            copied_from: None,
+            reason: ChangeReason::Child,
+        }
+    }
+
+    #[test]
+    fn english_deltas() {
+        let day = 86_400.0;
+        assert_eq!(english_delta(3.0 * 3_600.0), "forward 3 hours");
+        assert_eq!(english_delta(-14.0 * day), "back 2 weeks");
+        assert_eq!(english_delta(14.0 * 30.5 * day), "forward 14 months");
+        assert_eq!(english_delta(45.0), "forward 45 seconds");
+        assert_eq!(english_delta(-1.5), "back 1 second");
+        assert_eq!(english_delta(3.0 * 365.25 * day), "forward 3 years");
+    }
+
+    #[test]
+    fn strftime_formats() {
+        let t = UNIX_EPOCH + Duration::new(1_577_934_245, 123_456_789);
+        let render = |f: &str| {
+            let desc = strftime_description(f).unwrap();
+            let fmt: OwnedFormatItem = parse_format(&desc).unwrap().into();
+            format_time(t, &fmt, UtcOffset::UTC)
+        };
+        assert_eq!(render("%F %T"), "2020-01-02 03:04:05");
+        assert_eq!(render("%d %b %Y [%s] %%"), "02 Jan 2020 [1577934245] %");
+        assert_eq!(render("%H:%M:%S.%N%:z"), "03:04:05.123456789+00:00");
+        assert_eq!(render("[%F] [x]"), "[2020-01-02] [x]");
+        assert!(strftime_description("%Q").is_err());
+        assert!(strftime_description("[").is_err());
+        assert!(strftime_description("%F]").is_err());
+        assert!(strftime_description("[year]-[month]").is_err());
+        assert_eq!(DateFormat::EpochFrac(3).render(t), "1577934245.123");
+    }
+
+    #[test]
+    fn truncates_long_paths_in_the_middle() {
+        assert_eq!(truncate_middle("short", 10), "short");
+        assert_eq!(
+            truncate_middle("/archive/photos/2014/rome", 12),
+            "/arch…4/rome"
+        );
+        assert_eq!(
+            truncate_middle("/archive/photos/2014/rome", 12)
+                .chars()
+                .count(),
+            12
+        );
+    }
+
+    #[test]
+    fn itemize_codes() {
+        let nine_years = 9 * 366 * 86_400;
+        assert_eq!(
+            itemize_code(&change(0, nine_years), Status::Planned),
+            ">t+9y "
+        );
+        assert_eq!(
+            itemize_code(&change(10 * 86_400, 0), Status::Applied),
+            "<T-1w "
+        );
+        assert_eq!(itemize_code(&change(100, 145), Status::Failed), ">!+45s");
+        assert_eq!(itemize_code(&change(7_200, 0), Status::Planned), "<t-2h ");
+    }
+}
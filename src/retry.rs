@@ -0,0 +1,134 @@
+// src/retry.rs
+// --retries / --retry-delay: another go, with exponential backoff, at
+// metadata reads, child scans and mtime writes that fail with an error a
+// flaky network share might not repeat.
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::interrupt;
+
+/// Wait before the first retry unless `--retry-delay` says otherwise.
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(200);
+
+/// Errors that may well go away if asked again.
+fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+pub struct Retry {
+    retries: u32,
+    delay: Duration,
+    /// Operations that failed transiently at least once.
+    pub retried: usize,
+    /// Of those, the ones a retry got through.
+    pub recovered: usize,
+}
+
+impl Retry {
+    pub fn new(retries: u32, delay: Duration) -> Self {
+        Retry {
+            retries,
+            delay,
+            retried: 0,
+            recovered: 0,
+        }
+    }
+
+    /// The same settings with nothing counted yet, for a `--jobs` worker.
+    pub fn fresh(&self) -> Self {
+        Retry::new(self.retries, self.delay)
+    }
+
+    /// Add up what a worker's `fresh` copy counted.
+    pub fn absorb(&mut self, other: Retry) {
+        self.retried += other.retried;
+        self.recovered += other.recovered;
+    }
+
+    /// Run `op`, retrying transient failures up to the configured count with
+    /// the delay doubling each time. An error that took several attempts says
+    /// so in its message; its kind is kept.
+    pub fn run<T>(&mut self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut delay = self.delay;
+        let mut attempts = 1;
+        loop {
+            let e = match op() {
+                Ok(value) => {
+                    if attempts > 1 {
+                        self.recovered += 1;
+                    }
+                    return Ok(value);
+                }
+                Err(e) => e,
+            };
+            let give_up =
+                !is_transient(e.kind()) || attempts > self.retries || interrupt::requested();
+            if give_up && attempts == 1 {
+                return Err(e);
+            }
+            if give_up {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{e}; gave up after {attempts} attempts"),
+                ));
+            }
+            if attempts == 1 {
+                self.retried += 1;
+            }
+            thread::sleep(delay);
+            delay = delay.saturating_mul(2);
+            attempts += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails with `kind` the first `failures` times, then succeeds.
+    fn flaky(kind: io::ErrorKind, failures: u32) -> impl FnMut() -> io::Result<u32> {
+        let mut calls = 0;
+        move || {
+            calls += 1;
+            if calls <= failures {
+                Err(io::Error::from(kind))
+            } else {
+                Ok(calls)
+            }
+        }
+    }
+
+    #[test]
+    fn retries_only_transient_errors() {
+        let mut retry = Retry::new(3, Duration::ZERO);
+        assert_eq!(retry.run(flaky(io::ErrorKind::TimedOut, 2)).unwrap(), 3);
+        assert_eq!((retry.retried, retry.recovered), (1, 1));
+
+        let e = retry
+            .run(flaky(io::ErrorKind::PermissionDenied, 1))
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!((retry.retried, retry.recovered), (1, 1));
+    }
+
+    #[test]
+    fn exhausted_retries_keep_the_kind_and_count_attempts() {
+        let mut retry = Retry::new(2, Duration::ZERO);
+        let e = retry.run(flaky(io::ErrorKind::WouldBlock, 5)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+        assert!(e.to_string().ends_with("gave up after 3 attempts"));
+        assert_eq!((retry.retried, retry.recovered), (1, 0));
+
+        // Without --retries, nothing changes.
+        let mut retry = Retry::new(0, Duration::ZERO);
+        let e = retry.run(flaky(io::ErrorKind::TimedOut, 1)).unwrap_err();
+        assert!(!e.to_string().contains("attempts"));
+        assert_eq!(retry.retried, 0);
+    }
+}
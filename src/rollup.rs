@@ -0,0 +1,128 @@
+// src/rollup.rs
+// --group-depth: one summary line per ancestor folder N levels below the
+// root, instead of one line per change.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const DAY: f64 = 86_400.0;
+const YEAR: f64 = 365.25 * DAY;
+
+#[derive(Debug, Default)]
+pub struct Group {
+    pub changes: usize,
+    pub errors: usize,
+    /// Smallest, largest and total signed delta in seconds.
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+}
+
+impl Group {
+    pub fn mean(&self) -> Option<f64> {
+        (self.changes > 0).then(|| self.sum / self.changes as f64)
+    }
+}
+
+pub struct Rollup {
+    root: PathBuf,
+    depth: usize,
+    groups: BTreeMap<PathBuf, Group>,
+}
+
+impl Rollup {
+    pub fn new(root: &Path, depth: usize) -> Self {
+        Rollup {
+            root: root.to_path_buf(),
+            depth,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// The ancestor of `path` at `depth` below the root (or `path` itself if
+    /// it is shallower).
+    fn key(&self, path: &Path) -> PathBuf {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return path.to_path_buf();
+        };
+        let mut key = self.root.clone();
+        key.extend(rel.components().take(self.depth));
+        key
+    }
+
+    pub fn add_change(&mut self, path: &Path, delta_secs: f64) {
+        let group = self.groups.entry(self.key(path)).or_default();
+        if group.changes == 0 {
+            group.min = delta_secs;
+            group.max = delta_secs;
+        } else {
+            group.min = group.min.min(delta_secs);
+            group.max = group.max.max(delta_secs);
+        }
+        group.sum += delta_secs;
+        group.changes += 1;
+    }
+
+    pub fn add_error(&mut self, path: &Path) {
+        self.groups.entry(self.key(path)).or_default().errors += 1;
+    }
+
+    /// `(group folder, stats)` in path order.
+    pub fn groups(&self) -> impl Iterator<Item = (&Path, &Group)> {
+        self.groups.iter().map(|(p, g)| (p.as_path(), g))
+    }
+
+    pub fn print(&self) {
+        for (path, g) in self.groups() {
+            let mut line = format!("{:?}: {} change(s)", path, g.changes);
+            if let Some(mean) = g.mean() {
+                line += &format!(
+                    ", drift min {} / max {} / mean {}",
+                    human_delta(g.min),
+                    human_delta(g.max),
+                    human_delta(mean)
+                );
+            }
+            if g.errors > 0 {
+                line += &format!(", {} error(s)", g.errors);
+            }
+            println!("{line}");
+        }
+    }
+}
+
+/// Signed delta in days, or years once it passes one.
+pub fn human_delta(secs: f64) -> String {
+    if secs.abs() >= YEAR {
+        format!("{:+.1}y", secs / YEAR)
+    } else {
+        format!("{:+.1}d", secs / DAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_by_ancestor_at_depth() {
+        let root = Path::new("/lib");
+        let mut r = Rollup::new(root, 1);
+        r.add_change(Path::new("/lib/Photos/2019/a"), -2.0 * DAY);
+        r.add_change(Path::new("/lib/Photos/2020"), 4.0 * DAY);
+        r.add_change(Path::new("/lib"), DAY);
+        r.add_error(Path::new("/lib/Music/x"));
+
+        let groups: Vec<(&Path, &Group)> = r.groups().collect();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].0, Path::new("/lib"));
+        assert_eq!(groups[1].0, Path::new("/lib/Music"));
+        assert_eq!((groups[1].1.changes, groups[1].1.errors), (0, 1));
+
+        let photos = groups[2].1;
+        assert_eq!(groups[2].0, Path::new("/lib/Photos"));
+        assert_eq!(photos.changes, 2);
+        assert_eq!((photos.min, photos.max), (-2.0 * DAY, 4.0 * DAY));
+        assert_eq!(photos.mean(), Some(DAY));
+    }
+}
@@ -0,0 +1,1101 @@
+// src/run.rs
+// The library's run: walk each root, deepest folders first, and stamp each
+// one from its children. The command line does the same through `Steps`,
+// which carry its many options into the walk, the reads, the pick and the
+// write; the order, the tolerance and the re-planning are kept here.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use filetime::{set_file_mtime, FileTime};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::change::{Change, ChangeReason, SkipReason, Status};
+use crate::error::{DirstampError, OptionsError};
+use crate::events::{Events, NoEvents};
+use crate::scan::{self, Child, Kind, SelectionPolicy, Strategy};
+
+/// A folder within this of its target is left alone, so coarse filesystems
+/// (FAT's 2-second times, SMB rounding) don't rewrite it on every run.
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// What `run` does. The defaults are the command line's: a dry run, with a
+/// 1-second tolerance, following symlinked folders, taking each folder's
+/// newest child. Made with `Options::builder()`, since fields will be added.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    /// The trees to stamp, one after another.
+    pub roots: Vec<PathBuf>,
+    /// Plan the changes without making them (the CLI without `-C`).
+    pub dry_run: bool,
+    /// Folders within this of their target are left alone.
+    pub tolerance: Duration,
+    /// Walk into symlinked folders and stamp them too, as the command line
+    /// always does. A link never counts as a child either way.
+    pub follow_links: bool,
+    /// How a folder's mtime is chosen from its children: a `Strategy`, or
+    /// one of the caller's own.
+    pub policy: Arc<dyn SelectionPolicy>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            roots: Vec::new(),
+            dry_run: true,
+            tolerance: DEFAULT_TOLERANCE,
+            follow_links: true,
+            policy: Arc::new(Strategy::Newest),
+        }
+    }
+}
+
+impl Options {
+    /// Options with the defaults, to change one setter at a time.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use dirstamp::scan::Strategy;
+    ///
+    /// // Stamp two shares for real, with FAT's 2-second times in mind.
+    /// let options = dirstamp::Options::builder()
+    ///     .root("/srv/photos")
+    ///     .root("/srv/music")
+    ///     .dry_run(false)
+    ///     .tolerance(Duration::from_secs(2))
+    ///     .build()?;
+    /// assert_eq!(options.roots.len(), 2);
+    ///
+    /// // A dry run giving each folder its oldest child's time.
+    /// let options = dirstamp::Options::builder()
+    ///     .root("/srv/archive")
+    ///     .policy(Strategy::Oldest)
+    ///     .build()?;
+    /// assert!(options.dry_run);
+    ///
+    /// // One tree inside another would be stamped twice.
+    /// let nested = dirstamp::Options::builder()
+    ///     .roots(["/srv", "/srv/photos"])
+    ///     .build();
+    /// assert!(nested.is_err());
+    /// # Ok::<(), dirstamp::OptionsError>(())
+    /// ```
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+}
+
+/// Sets up `Options`; see `Options::builder`. Anything not set keeps its
+/// default.
+#[derive(Clone, Debug, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// A tree to stamp, after any given before.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.options.roots.push(root.into());
+        self
+    }
+
+    /// Several trees to stamp, after any given before.
+    pub fn roots<P: Into<PathBuf>>(mut self, roots: impl IntoIterator<Item = P>) -> Self {
+        self.options.roots.extend(roots.into_iter().map(Into::into));
+        self
+    }
+
+    /// `false` makes the changes (the CLI's `-C`).
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.options.dry_run = dry_run;
+        self
+    }
+
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.options.tolerance = tolerance;
+        self
+    }
+
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.options.follow_links = follow_links;
+        self
+    }
+
+    /// A `Strategy`, or a policy of the caller's own.
+    pub fn policy(mut self, policy: impl SelectionPolicy + 'static) -> Self {
+        self.options.policy = Arc::new(policy);
+        self
+    }
+
+    /// The options, if they make sense together: at least one root, and no
+    /// root inside another (or given twice), which would stamp its folders
+    /// twice. Roots are compared as written, not resolved.
+    pub fn build(self) -> Result<Options, OptionsError> {
+        let roots = &self.options.roots;
+        if roots.is_empty() {
+            return Err(OptionsError::NoRoots);
+        }
+        for (i, outer) in roots.iter().enumerate() {
+            for (j, inner) in roots.iter().enumerate() {
+                if i != j && inner.starts_with(outer) {
+                    return Err(OptionsError::NestedRoots {
+                        outer: outer.clone(),
+                        inner: inner.clone(),
+                    });
+                }
+            }
+        }
+        Ok(self.options)
+    }
+}
+
+/// What `run` found and did: the figures of the CLI's summary (and of its
+/// `--metrics-file`), with the changes themselves. It serializes, to be kept
+/// and compared with the next run's.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Report {
+    /// Folders looked at: each is a change or a skip.
+    pub dirs_scanned: usize,
+    /// Folders that needed a change, dry run or not.
+    pub changes_planned: usize,
+    /// Of those, the ones given their new mtime (0 in a dry run).
+    pub changes_applied: usize,
+    /// And the ones that couldn't be.
+    #[serde(default)]
+    pub changes_failed: usize,
+    /// Places the walk couldn't get into. They weren't looked at, so they
+    /// aren't among `dirs_scanned` (their errors are in `errors`).
+    #[serde(default)]
+    pub walk_errors: usize,
+    /// Folders left alone, by why. A folder that moved between the plan and
+    /// the apply counts once as a change and once as `ChangedSincePlan`.
+    pub skipped: HashMap<SkipReason, usize>,
+    /// What went wrong reading a folder or setting its mtime. The folder is
+    /// left as it was (and is counted in `skipped` or among the changes as
+    /// well); the run goes on.
+    pub errors: Vec<DirstampError>,
+    /// How long the run took.
+    pub elapsed: Duration,
+    /// The largest change planned, either way in time.
+    pub max_drift: Duration,
+    /// Every folder that needed a change, children before parents, with
+    /// what became of it: `Planned` in a dry run, else `Applied` or `Failed`.
+    /// Changes skipped by the apply aren't here.
+    pub changes: Vec<(Change, Status)>,
+}
+
+/// The changes a run would make, for the caller to look over before
+/// `apply`. Each `Change` has the folder's mtime now (`from`), its target
+/// (`to`), the child that decided it (`source_child`) and why (`reason`).
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Children before parents. Dropping or reordering some is fine:
+    /// `apply` still sets the deepest first. A parent whose target came from
+    /// a subfolder keeps it even if that subfolder's change is dropped; one
+    /// whose subfolder failed or was skipped by the apply is planned again.
+    pub changes: Vec<Change>,
+    /// Folders that need no change, and why.
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    /// Folders that couldn't be read (also in `skipped`), and places the
+    /// walk couldn't get into (only here).
+    pub errors: Vec<DirstampError>,
+}
+
+/// How `apply` treats folders that moved on since they were planned.
+#[derive(Clone, Debug)]
+pub struct ApplyOptions {
+    /// Set a folder whose mtime is no longer the plan's `from`, instead of
+    /// skipping it as `ChangedSincePlan` (the CLI's `--plan-force`).
+    pub force: bool,
+    /// How far from `from` a folder may be and still count as unchanged.
+    pub tolerance: Duration,
+    /// How a folder planned again is given its mtime: the plan's policy.
+    pub policy: Arc<dyn SelectionPolicy>,
+}
+
+impl Default for ApplyOptions {
+    fn default() -> Self {
+        ApplyOptions {
+            force: false,
+            tolerance: DEFAULT_TOLERANCE,
+            policy: Arc::new(Strategy::Newest),
+        }
+    }
+}
+
+/// What `apply` did.
+#[derive(Debug, Default)]
+pub struct ApplyReport {
+    /// The changes it set, deepest first: `Applied` or `Failed`.
+    pub changes: Vec<(Change, Status)>,
+    /// Changes left out: `ChangedSincePlan`, or `Error` if the folder's
+    /// mtime couldn't be read. A folder planned again because a subfolder
+    /// failed or was left out may find it needs no change after all.
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    /// What went wrong reading or setting a folder.
+    pub errors: Vec<DirstampError>,
+}
+
+/// Stamp every folder under each of `options.roots`, children before
+/// parents: `plan`, then (unless it is a dry run) `apply`. Nothing is
+/// printed; it is all in the `Report`. The `Err` is for a root that is
+/// missing, isn't a folder or can't be read, before anything is touched.
+pub fn run(options: &Options) -> Result<Report, DirstampError> {
+    run_with(options, &NoEvents)
+}
+
+/// `run`, telling `events` about each step as it is taken.
+pub fn run_with(options: &Options, events: &dyn Events) -> Result<Report, DirstampError> {
+    let started = Instant::now();
+    let plan = plan_with(options, events)?;
+    let mut report = Report {
+        dirs_scanned: plan.changes.len() + plan.skipped.len(),
+        changes_planned: plan.changes.len(),
+        max_drift: plan.changes.iter().map(drift).max().unwrap_or_default(),
+        walk_errors: plan
+            .errors
+            .iter()
+            .filter(|e| matches!(e, DirstampError::Walk { .. }))
+            .count(),
+        ..Report::default()
+    };
+    let applied = (!options.dry_run).then(|| {
+        let apply_options = ApplyOptions {
+            force: false,
+            tolerance: options.tolerance,
+            policy: Arc::clone(&options.policy),
+        };
+        apply_with(&plan, &apply_options, events)
+    });
+    count_skips(&mut report.skipped, &plan.skipped);
+    report.errors = plan.errors;
+    match applied {
+        None => {
+            report.changes = plan
+                .changes
+                .into_iter()
+                .map(|c| (c, Status::Planned))
+                .collect();
+        }
+        Some(applied) => {
+            let count = |wanted| applied.changes.iter().filter(|(_, s)| *s == wanted).count();
+            report.changes_applied = count(Status::Applied);
+            report.changes_failed = count(Status::Failed);
+            count_skips(&mut report.skipped, &applied.skipped);
+            report.changes = applied.changes;
+            report.errors.extend(applied.errors);
+        }
+    }
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+fn count_skips(counts: &mut HashMap<SkipReason, usize>, skipped: &[(PathBuf, SkipReason)]) {
+    for (_, reason) in skipped {
+        *counts.entry(*reason).or_default() += 1;
+    }
+}
+
+/// How far `change` moves its folder, either way.
+fn drift(change: &Change) -> Duration {
+    match change.to.duration_since(change.from) {
+        Ok(d) => d,
+        Err(e) => e.duration(),
+    }
+}
+
+/// The changes `run` would make under each of `options.roots`, without
+/// making any (`options.dry_run` isn't looked at). Subfolders count with
+/// the mtime they are planned to get, so the plan is what a real run does.
+///
+/// ```
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// # let tmp = tempfile::tempdir()?;
+/// # let root = tmp.path();
+/// # for dir in ["keep", "skip"] {
+/// #     std::fs::create_dir(root.join(dir))?;
+/// #     std::fs::write(root.join(dir).join("f"), "")?;
+/// #     dirstamp::set_mtime(&root.join(dir).join("f"), UNIX_EPOCH + Duration::from_secs(86_400))?;
+/// # }
+/// let options = dirstamp::Options::builder().root(root).build()?;
+/// let mut plan = dirstamp::plan(&options)?;
+/// // Say the user unticked one in the UI.
+/// plan.changes.retain(|c| !c.path.ends_with("skip"));
+/// let report = dirstamp::apply(&plan, &dirstamp::ApplyOptions::default());
+/// assert_eq!(report.changes.len(), 2); // keep, then the root
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn plan(options: &Options) -> Result<Plan, DirstampError> {
+    plan_with(options, &NoEvents)
+}
+
+/// `plan`, telling `events` about each folder as it is planned.
+pub fn plan_with(options: &Options, events: &dyn Events) -> Result<Plan, DirstampError> {
+    let mut plan = Plan::default();
+    let mut steps = Library {
+        policy: &*options.policy,
+        tolerance: options.tolerance,
+        follow_links: options.follow_links,
+        force: false,
+    };
+    for root in &options.roots {
+        match fs::metadata(root) {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => return Err(DirstampError::NotADirectory(root.clone())),
+            Err(e) => return Err(DirstampError::root(root, e)),
+        }
+        let dirs = collect_dirs(root, &mut plan, &mut steps, events);
+        let planned = plan_dirs(&dirs, &mut HashMap::new(), &mut steps, events);
+        plan.changes.extend(planned.changes);
+        plan.skipped.extend(planned.skipped);
+        plan.errors.extend(planned.errors);
+    }
+    Ok(plan)
+}
+
+/// What `collect_dirs`, `plan_dirs` and `apply_steps` ask of the caller for
+/// each folder, for one with its own rules around the library's: the
+/// `dirstamp` binary walks and reads with its device filters, timeouts and
+/// cache, leaves some children out and stamps with its prompts, journal and
+/// hooks. The order, the child pick, the tolerance and the write stay the
+/// library's. Each method but `policy` does by default what `run` does;
+/// what goes wrong is pushed to `errors`, for the report and the events.
+pub trait Steps {
+    /// How a folder's mtime is chosen from its children.
+    fn policy(&self) -> &dyn SelectionPolicy;
+
+    /// Folders within this of their target are left alone.
+    fn tolerance(&self) -> Duration {
+        DEFAULT_TOLERANCE
+    }
+
+    /// Every folder under `root` (inclusive) into `dirs`, in any order. What
+    /// can't be walked goes to `plan.errors`, and folders left out on
+    /// purpose to `plan.skipped`. Symlinked folders are followed, as by
+    /// default in `Options`.
+    fn walk(&mut self, root: &Path, dirs: &mut Vec<PathBuf>, plan: &mut Plan) {
+        walk(root, true, dirs, plan);
+    }
+
+    /// `dir`'s mtime and its children, in listing order, as they are on
+    /// disk. Only files and folders count.
+    fn read(
+        &mut self,
+        dir: &Path,
+        errors: &mut Vec<DirstampError>,
+    ) -> Result<(SystemTime, Vec<Child>), SkipReason> {
+        read(dir, errors)
+    }
+
+    /// Whether `dir` is worth a change, from its children (subfolders with
+    /// the mtime they are getting), before the policy picks one.
+    fn consider(&mut self, _dir: &Path, _children: &[Child]) -> Result<(), SkipReason> {
+        Ok(())
+    }
+
+    /// The latest mtime `dir` may take. A later target is brought back to
+    /// it, as `Clamped`, with `..` for its `source_child`: the time is the
+    /// parent's, not a child's.
+    fn ceiling(&mut self, _dir: &Path) -> Option<SystemTime> {
+        None
+    }
+
+    /// Whether a folder's change is wanted; one that isn't is skipped as
+    /// `Filtered`.
+    fn wanted(&mut self, _change: &Change) -> bool {
+        true
+    }
+
+    /// Get ready to make `change`, which may be adjusted first: `Ok(false)`
+    /// leaves it `Planned`, and `Err` leaves the folder alone. A folder
+    /// whose mtime moved since the plan is skipped as `ChangedSincePlan`.
+    fn prepare(
+        &mut self,
+        change: &mut Change,
+        errors: &mut Vec<DirstampError>,
+    ) -> Result<bool, SkipReason> {
+        unmoved(change, self.tolerance(), errors)
+    }
+
+    /// Set the mtimes of `changes`, whose folders are all at one depth and
+    /// so don't depend on one another: a result for each, in order.
+    fn set(&mut self, changes: &[&Change]) -> Vec<io::Result<()>> {
+        changes.iter().map(|c| set_mtime(&c.path, c.to)).collect()
+    }
+
+    /// Told how setting `change` went, before the events are.
+    fn stamped(&mut self, _change: &Change, _result: &io::Result<()>) {}
+
+    /// Whether to stop before the next folder (the binary's Ctrl-C).
+    fn stop(&self) -> bool {
+        false
+    }
+}
+
+/// Every folder under `root` (inclusive) that `steps` walk to, deepest
+/// first and in path order within a depth, as `plan_dirs` wants them. What
+/// the walk couldn't get into or left out is added to `plan`, and `events`
+/// hear of it once the walk is done.
+pub fn collect_dirs(
+    root: &Path,
+    plan: &mut Plan,
+    steps: &mut dyn Steps,
+    events: &dyn Events,
+) -> Vec<PathBuf> {
+    let (errors, skipped) = (plan.errors.len(), plan.skipped.len());
+    let mut dirs = Vec::new();
+    steps.walk(root, &mut dirs, plan);
+    plan.errors[errors..]
+        .iter()
+        .for_each(|e| events.on_error(e));
+    for (path, reason) in &plan.skipped[skipped..] {
+        events.on_skipped(path, *reason);
+    }
+    let depth = |p: &Path| p.components().count();
+    dirs.sort_by(|a, b| depth(b).cmp(&depth(a)).then_with(|| a.cmp(b)));
+    dirs
+}
+
+/// Plan each of `dirs`, in order, with `steps`: deepest first, as
+/// `collect_dirs` lists them, so `planned` has each folder's subfolders by
+/// the time it is planned. The folders planned are added to it.
+pub fn plan_dirs(
+    dirs: &[PathBuf],
+    planned: &mut HashMap<PathBuf, SystemTime>,
+    steps: &mut dyn Steps,
+    events: &dyn Events,
+) -> Plan {
+    let mut plan = Plan::default();
+    for dir in dirs {
+        if steps.stop() {
+            break;
+        }
+        events.on_scanned(dir);
+        let seen = plan.errors.len();
+        let result = plan_dir(dir, planned, steps, &mut plan.errors);
+        plan.errors[seen..].iter().for_each(|e| events.on_error(e));
+        match result {
+            Ok(change) => {
+                events.on_change(&change, Status::Planned);
+                planned.insert(change.path.clone(), change.to);
+                plan.changes.push(change);
+            }
+            Err(reason) => {
+                events.on_skipped(dir, reason);
+                plan.skipped.push((dir.clone(), reason));
+            }
+        }
+    }
+    plan
+}
+
+/// Where one change of a depth stands once it is prepared.
+enum Prepared {
+    Skipped(SkipReason),
+    /// Left `Planned`.
+    Held(Change),
+    /// Waiting for `Steps::set`.
+    Ready(Change),
+}
+
+/// Make the changes in `plan` with `steps`, deepest folders first and
+/// within a depth in the plan's order. `planned` has the mtimes the plan
+/// gave its folders, and ends with the ones they were given. A folder whose
+/// subfolder failed, was left out or took another mtime is planned again
+/// first, so it never takes a time no child has. A depth's changes are
+/// prepared one by one, then set together; a stop comes between two, and
+/// what was prepared is still set.
+pub fn apply_steps(
+    plan: &Plan,
+    planned: &mut HashMap<PathBuf, SystemTime>,
+    steps: &mut dyn Steps,
+    events: &dyn Events,
+) -> ApplyReport {
+    let depth = |c: &&Change| c.path.components().count();
+    let mut order: Vec<&Change> = plan.changes.iter().collect();
+    order.sort_by_key(|c| Reverse(depth(c)));
+    let mut report = ApplyReport::default();
+    // Folders whose plan a subfolder's outcome has made out of date.
+    let mut stale = HashSet::new();
+    for level in order.chunk_by(|a, b| depth(a) == depth(b)) {
+        let mut prepared = Vec::new();
+        for &change in level {
+            if steps.stop() {
+                break;
+            }
+            let seen = report.errors.len();
+            let replanned = if stale.contains(&change.path) {
+                plan_dir(&change.path, planned, steps, &mut report.errors)
+            } else {
+                Ok(change.clone())
+            };
+            let step = match replanned.and_then(|mut changed| {
+                let ready = steps.prepare(&mut changed, &mut report.errors)?;
+                Ok((changed, ready))
+            }) {
+                Ok((changed, true)) => Prepared::Ready(changed),
+                Ok((changed, false)) => Prepared::Held(changed),
+                Err(reason) => Prepared::Skipped(reason),
+            };
+            prepared.push((change, step, seen..report.errors.len()));
+        }
+        let stopped = prepared.len() < level.len();
+        let ready: Vec<&Change> = prepared
+            .iter()
+            .filter_map(|(_, step, _)| match step {
+                Prepared::Ready(changed) => Some(changed),
+                _ => None,
+            })
+            .collect();
+        let mut results = steps.set(&ready).into_iter();
+        for (change, step, errors) in prepared {
+            report.errors[errors]
+                .iter()
+                .for_each(|e| events.on_error(e));
+            let path = &change.path;
+            let outcome = match step {
+                Prepared::Skipped(reason) => Err(reason),
+                Prepared::Held(changed) => Ok((changed, Status::Planned)),
+                Prepared::Ready(changed) => {
+                    let result = results
+                        .next()
+                        .expect("Steps::set gives a result per change");
+                    steps.stamped(&changed, &result);
+                    let status = match result {
+                        Ok(()) => Status::Applied,
+                        Err(source) => {
+                            let error = DirstampError::SetMtime {
+                                path: path.clone(),
+                                source,
+                            };
+                            failed(&mut report.errors, error, events);
+                            Status::Failed
+                        }
+                    };
+                    Ok((changed, status))
+                }
+            };
+            let kept = match outcome {
+                Ok((changed, status)) => {
+                    events.on_change(&changed, status);
+                    let kept = status != Status::Failed && changed.to == change.to;
+                    if status != Status::Failed {
+                        planned.insert(path.clone(), changed.to);
+                    } else {
+                        planned.remove(path);
+                    }
+                    report.changes.push((changed, status));
+                    kept
+                }
+                Err(reason) => {
+                    events.on_skipped(path, reason);
+                    planned.remove(path);
+                    report.skipped.push((path.clone(), reason));
+                    false
+                }
+            };
+            if !kept {
+                if let Some(parent) = path.parent() {
+                    stale.insert(parent.to_path_buf());
+                }
+            }
+        }
+        if stopped {
+            break;
+        }
+    }
+    report
+}
+
+/// Make the changes in `plan`, deepest folders first whatever order they
+/// are in, and within a depth in the plan's order. A failure is reported
+/// and the rest are still made.
+pub fn apply(plan: &Plan, options: &ApplyOptions) -> ApplyReport {
+    apply_with(plan, options, &NoEvents)
+}
+
+/// `apply`, telling `events` about each change as it is made.
+pub fn apply_with(plan: &Plan, options: &ApplyOptions, events: &dyn Events) -> ApplyReport {
+    let mut planned = plan
+        .changes
+        .iter()
+        .map(|c| (c.path.clone(), c.to))
+        .collect();
+    let mut steps = Library {
+        policy: &*options.policy,
+        tolerance: options.tolerance,
+        // Nothing is walked: a folder planned again is only read.
+        follow_links: false,
+        force: options.force,
+    };
+    apply_steps(plan, &mut planned, &mut steps, events)
+}
+
+/// The library's own steps: `run`'s options, with the trait's defaults.
+struct Library<'a> {
+    policy: &'a dyn SelectionPolicy,
+    tolerance: Duration,
+    follow_links: bool,
+    /// Set folders that moved since the plan, rather than skip them.
+    force: bool,
+}
+
+impl Steps for Library<'_> {
+    fn policy(&self) -> &dyn SelectionPolicy {
+        self.policy
+    }
+
+    fn tolerance(&self) -> Duration {
+        self.tolerance
+    }
+
+    fn walk(&mut self, root: &Path, dirs: &mut Vec<PathBuf>, plan: &mut Plan) {
+        walk(root, self.follow_links, dirs, plan);
+    }
+
+    fn prepare(
+        &mut self,
+        change: &mut Change,
+        errors: &mut Vec<DirstampError>,
+    ) -> Result<bool, SkipReason> {
+        if self.force {
+            return Ok(true);
+        }
+        unmoved(change, self.tolerance, errors)
+    }
+}
+
+/// Whether `a` and `b` are more than `tolerance` apart. Measured rather
+/// than added, so no tolerance is too large (`Duration::MAX` matches anything).
+pub fn differs(a: SystemTime, b: SystemTime, tolerance: Duration) -> bool {
+    a.duration_since(b).unwrap_or_else(|e| e.duration()) > tolerance
+}
+
+/// Set a folder's mtime, leaving its atime as it is. The error is the
+/// system's own; `apply` reports it as `DirstampError::SetMtime`.
+pub fn set_mtime(path: &Path, mtime: SystemTime) -> io::Result<()> {
+    set_file_mtime(path, FileTime::from_system_time(mtime))
+}
+
+/// Note what went wrong, for the report and for `events`.
+fn failed(errors: &mut Vec<DirstampError>, error: DirstampError, events: &dyn Events) {
+    events.on_error(&error);
+    errors.push(error);
+}
+
+/// `Steps::walk` as `run` does it: walkdir's, with what it can't get into
+/// in `plan.errors`.
+fn walk(root: &Path, follow_links: bool, dirs: &mut Vec<PathBuf>, plan: &mut Plan) {
+    for entry in WalkDir::new(root).follow_links(follow_links) {
+        match entry {
+            Ok(entry) if entry.file_type().is_dir() => dirs.push(entry.into_path()),
+            Ok(_) => {}
+            Err(e) => {
+                let path = e.path().unwrap_or(root).to_path_buf();
+                let source = e.into();
+                plan.errors.push(DirstampError::Walk { path, source });
+            }
+        }
+    }
+}
+
+/// `Steps::read` as `run` does it.
+fn read(
+    dir: &Path,
+    errors: &mut Vec<DirstampError>,
+) -> Result<(SystemTime, Vec<Child>), SkipReason> {
+    let path = || dir.to_path_buf();
+    let mut failed = |error| {
+        errors.push(error);
+        SkipReason::Error
+    };
+    let from = fs::metadata(dir)
+        .and_then(|m| m.modified())
+        .map_err(|source| {
+            failed(DirstampError::ReadMtime {
+                path: path(),
+                source,
+            })
+        })?;
+    let mut children = scan::read_children(dir).map_err(|source| {
+        failed(DirstampError::ReadDir {
+            path: path(),
+            source,
+        })
+    })?;
+    children.retain(|c| matches!(c.kind, Kind::File | Kind::Dir));
+    Ok((from, children))
+}
+
+/// `Steps::prepare` as `apply` does it: whether `change`'s folder still
+/// has the mtime it was planned from.
+fn unmoved(
+    change: &Change,
+    tolerance: Duration,
+    errors: &mut Vec<DirstampError>,
+) -> Result<bool, SkipReason> {
+    match fs::metadata(&change.path).and_then(|m| m.modified()) {
+        Ok(now) if !differs(now, change.from, tolerance) => Ok(true),
+        Ok(_) => Err(SkipReason::ChangedSincePlan),
+        Err(source) => {
+            let path = change.path.clone();
+            errors.push(DirstampError::ReadMtime { path, source });
+            Err(SkipReason::Error)
+        }
+    }
+}
+
+/// The change `dir` needs, or why it needs none: the children `steps` read,
+/// subfolders counted with the mtime they are getting, picked from by the
+/// policy, held to the ceiling and compared within the tolerance.
+fn plan_dir(
+    dir: &Path,
+    planned: &HashMap<PathBuf, SystemTime>,
+    steps: &mut dyn Steps,
+    errors: &mut Vec<DirstampError>,
+) -> Result<Change, SkipReason> {
+    let (from, mut children) = steps.read(dir, errors)?;
+    scan::with_planned(dir, &mut children, |p| planned.get(p).copied());
+    steps.consider(dir, &children)?;
+    let (mut to, mut source_child) = steps
+        .policy()
+        .select(dir, &children)
+        .ok_or(SkipReason::Empty)?;
+    let mut reason = ChangeReason::Child;
+    if let Some(cap) = steps.ceiling(dir).filter(|cap| to > *cap) {
+        to = cap;
+        source_child = Some("..".into());
+        reason = ChangeReason::Clamped;
+    }
+    if !differs(to, from, steps.tolerance()) {
+        return Err(SkipReason::InSync);
+    }
+    let change = Change {
+        path: dir.to_path_buf(),
+        from,
+        to,
+        source_child,
+        copied_from: None,
+        reason,
+    };
+    if !steps.wanted(&change) {
+        return Err(SkipReason::Filtered);
+    }
+    Ok(change)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::time::UNIX_EPOCH;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn touch(path: &Path, secs: u64) {
+        set_mtime(path, at(secs)).unwrap();
+    }
+
+    #[test]
+    fn a_dry_run_plans_what_a_real_one_makes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        let photos = root.join("photos");
+        fs::create_dir_all(&photos).unwrap();
+        fs::create_dir(root.join("empty")).unwrap();
+        fs::write(photos.join("a.jpg"), "").unwrap();
+        touch(&photos.join("a.jpg"), 1_000_000);
+        touch(&root.join("empty"), 500);
+        touch(&photos, 9_000_000);
+        touch(&root, 9_000_000);
+
+        let mut options = Options {
+            roots: vec![root.clone()],
+            ..Options::default()
+        };
+        let planned = run(&options).unwrap();
+        let changes: Vec<_> = planned
+            .changes
+            .iter()
+            .map(|(c, status)| (c.path.clone(), c.to, *status))
+            .collect();
+        // The root only has subfolders; photos is newest once it is stamped.
+        assert_eq!(
+            changes,
+            [
+                (photos.clone(), at(1_000_000), Status::Planned),
+                (root.clone(), at(1_000_000), Status::Planned),
+            ]
+        );
+        assert_eq!(planned.skipped, HashMap::from([(SkipReason::Empty, 1)]));
+        assert!(planned.errors.is_empty());
+        assert_eq!(
+            fs::metadata(&root).unwrap().modified().unwrap(),
+            at(9_000_000)
+        );
+
+        options.dry_run = false;
+        let applied = run(&options).unwrap();
+        assert!(applied.changes.iter().all(|(_, s)| *s == Status::Applied));
+        assert_eq!(
+            fs::metadata(&root).unwrap().modified().unwrap(),
+            at(1_000_000)
+        );
+        let again = run(&options).unwrap();
+        assert!(again.changes.is_empty());
+        assert_eq!(
+            again.skipped,
+            HashMap::from([(SkipReason::InSync, 2), (SkipReason::Empty, 1)])
+        );
+
+        options.roots = vec![tmp.path().join("missing")];
+        assert!(matches!(
+            run(&options),
+            Err(DirstampError::RootNotFound(p)) if p == tmp.path().join("missing")
+        ));
+        options.roots = vec![photos.join("a.jpg")];
+        assert!(matches!(
+            run(&options),
+            Err(DirstampError::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn the_builder_keeps_the_cli_defaults_and_turns_down_overlaps() {
+        let options = Options::builder().root("/srv/a").build().unwrap();
+        assert!(options.dry_run);
+        assert_eq!(options.tolerance, Duration::from_secs(1));
+        assert!(options.follow_links);
+
+        assert_eq!(
+            Options::builder().build().err(),
+            Some(OptionsError::NoRoots)
+        );
+        let twice = Options::builder().roots(["/srv/a", "/srv/a"]).build();
+        assert_eq!(twice.unwrap_err().to_string(), "/srv/a is given twice");
+        let nested = Options::builder().roots(["/srv/a/b", "/srv/a"]).build();
+        assert_eq!(
+            nested.err(),
+            Some(OptionsError::NestedRoots {
+                outer: "/srv/a".into(),
+                inner: "/srv/a/b".into(),
+            })
+        );
+        // Siblings sharing a prefix aren't nested.
+        assert!(Options::builder()
+            .roots(["/srv/a", "/srv/ab"])
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn options_can_go_to_another_thread() {
+        fn send_and_sync<T: Send + Sync>() {}
+        send_and_sync::<Options>();
+        send_and_sync::<ApplyOptions>();
+    }
+
+    #[test]
+    fn any_tolerance_compares_without_overflow() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let later = t + Duration::from_millis(1_500);
+        assert!(differs(t, later, Duration::from_secs(1)));
+        assert!(differs(later, t, Duration::from_secs(1)));
+        assert!(!differs(t, later, Duration::from_secs(2)));
+        assert!(!differs(t, later, Duration::MAX));
+        assert!(!differs(later, t, Duration::MAX));
+    }
+
+    #[test]
+    fn apply_sets_children_first_and_skips_what_moved() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for dir in ["a/b", "c"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("f"), "").unwrap();
+            touch(&root.join(dir).join("f"), 1_000_000);
+        }
+        let options = Options {
+            roots: vec![root.to_path_buf()],
+            ..Options::default()
+        };
+        let mut plan = plan(&options).unwrap();
+        let planned: Vec<_> = plan.changes.iter().map(|c| c.path.clone()).collect();
+        assert_eq!(
+            planned,
+            [
+                root.join("a/b"),
+                root.join("a"),
+                root.join("c"),
+                root.to_path_buf()
+            ]
+        );
+        // Parents first now, and one folder moves before the apply.
+        plan.changes.reverse();
+        touch(&root.join("c"), 5_000_000);
+
+        let report = apply(&plan, &ApplyOptions::default());
+        let applied: Vec<_> = report.changes.iter().map(|(c, _)| c.path.clone()).collect();
+        assert_eq!(
+            applied,
+            [root.join("a/b"), root.join("a"), root.to_path_buf()]
+        );
+        assert_eq!(
+            report.skipped,
+            [(root.join("c"), SkipReason::ChangedSincePlan)]
+        );
+        // c kept its own time, so the root was planned again and took it.
+        let (root_change, _) = &report.changes[2];
+        assert_eq!(root_change.to, at(5_000_000));
+        assert_eq!(
+            fs::metadata(root).unwrap().modified().unwrap(),
+            at(5_000_000)
+        );
+        let forced = ApplyOptions {
+            force: true,
+            ..ApplyOptions::default()
+        };
+        let report = apply(&plan, &forced);
+        assert_eq!(report.changes.len(), 4);
+        assert_eq!(
+            fs::metadata(root.join("c")).unwrap().modified().unwrap(),
+            at(1_000_000)
+        );
+
+        // A folder gone since the plan is an error in the report, not the
+        // end of the apply; the root is planned again without it.
+        fs::remove_dir_all(root.join("c")).unwrap();
+        let report = apply(&plan, &forced);
+        assert_eq!(report.changes.len(), 4);
+        assert!(matches!(
+            &report.errors[..],
+            [DirstampError::SetMtime { path, .. }] if *path == root.join("c")
+        ));
+        let (root_change, _) = &report.changes[3];
+        assert_eq!(root_change.source_child.as_deref(), Some(Path::new("a")));
+    }
+
+    #[test]
+    fn the_report_counts_what_the_run_did() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        for dir in ["a", "b", "empty"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for dir in ["a", "b"] {
+            fs::write(root.join(dir).join("f"), "").unwrap();
+            touch(&root.join(dir).join("f"), 1_000_000);
+        }
+        touch(&root.join("a"), 1_000_000);
+        touch(&root.join("b"), 1_000_000 + 86_400);
+        touch(&root.join("empty"), 500);
+        touch(&root, 1_000_000 + 86_400);
+
+        let mut options = Options {
+            roots: vec![root.clone()],
+            ..Options::default()
+        };
+        let planned = run(&options).unwrap();
+        assert_eq!(planned.dirs_scanned, 4);
+        assert_eq!(planned.changes_planned, 2); // b, then the root
+        assert_eq!(planned.changes_applied, 0);
+        assert_eq!(
+            planned.skipped,
+            HashMap::from([(SkipReason::InSync, 1), (SkipReason::Empty, 1)])
+        );
+        assert_eq!(planned.max_drift, Duration::from_secs(86_400));
+
+        // Kept and read back, the figures are the same.
+        let json = serde_json::to_string(&planned).unwrap();
+        assert!(json.contains(r#""in-sync":1"#));
+        assert!(json.contains(r#""planned""#));
+        let read: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.changes, planned.changes);
+        assert_eq!(read.skipped, planned.skipped);
+        assert_eq!(read.max_drift, planned.max_drift);
+
+        options.dry_run = false;
+        let applied = run(&options).unwrap();
+        assert_eq!((applied.changes_planned, applied.changes_applied), (2, 2));
+        assert_eq!((applied.changes_failed, applied.walk_errors), (0, 0));
+        assert!(applied.errors.is_empty());
+        // Kept by an older version, without the failure and walk counts.
+        let older = r#"{"dirs_scanned":4,"changes_planned":2,"changes_applied":2,
+            "skipped":{},"errors":[],"elapsed":{"secs":0,"nanos":0},
+            "max_drift":{"secs":0,"nanos":0},"changes":[]}"#;
+        let read: Report = serde_json::from_str(older).unwrap();
+        assert_eq!((read.changes_failed, read.walk_errors), (0, 0));
+        let error = DirstampError::SetMtime {
+            path: root.join("gone"),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        let json = serde_json::to_string(&error).unwrap();
+        let read: DirstampError = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.to_string(), error.to_string());
+    }
+
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<String>>);
+
+    impl Events for Recorder {
+        fn on_scanned(&self, path: &Path) {
+            self.0.borrow_mut().push(format!("scanned {}", name(path)));
+        }
+        fn on_change(&self, change: &Change, status: Status) {
+            let line = format!("{} {}", status.word(), name(&change.path));
+            self.0.borrow_mut().push(line);
+        }
+        fn on_skipped(&self, path: &Path, reason: SkipReason) {
+            let line = format!("{} {}", reason.keyword(), name(path));
+            self.0.borrow_mut().push(line);
+        }
+    }
+
+    fn name(path: &Path) -> String {
+        path.file_name().unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn events_follow_the_run_step_by_step() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir(root.join("empty")).unwrap();
+        fs::write(root.join("a/f"), "").unwrap();
+        touch(&root.join("a/f"), 1_000_000);
+        touch(&root.join("empty"), 500);
+
+        let options = Options {
+            roots: vec![root.clone()],
+            dry_run: false,
+            ..Options::default()
+        };
+        let events = Recorder::default();
+        let report = run_with(&options, &events).unwrap();
+        assert_eq!(report.changes.len(), 2);
+        assert_eq!(
+            events.0.into_inner(),
+            [
+                "scanned a",
+                "planned a",
+                "scanned empty",
+                "empty empty",
+                "scanned root",
+                "planned root",
+                "updated a",
+                "updated root",
+            ]
+        );
+    }
+}
@@ -0,0 +1,595 @@
+// src/scan.rs
+// Per-directory child scan. Self-contained so it can be fuzzed on its own
+// (see fuzz/fuzz_targets/find_latest_mtime.rs).
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The time a directory takes, and the child that decided it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Latest {
+    pub mtime: SystemTime,
+    /// File name of the winning entry (the first by name if several tie);
+    /// `None` if a `SelectionPolicy` made the time up from several.
+    pub name: Option<OsString>,
+}
+
+impl Latest {
+    fn beats(&self, other: &Latest) -> bool {
+        self.mtime > other.mtime || (self.mtime == other.mtime && self.name < other.name)
+    }
+}
+
+/// How a directory's time is chosen from its immediate children. `Strategy`
+/// is the built-in one; anything else, such as taking the largest file's
+/// time or that of a marker file, can be plugged in instead. It is `Send`
+/// and `Sync`, so `Options` holding one can be handed to a worker thread.
+///
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use std::time::SystemTime;
+///
+/// use dirstamp::scan::{Child, Kind, SelectionPolicy};
+///
+/// /// The time of the largest file.
+/// struct Largest;
+///
+/// impl SelectionPolicy for Largest {
+///     fn select(&self, _dir: &Path, children: &[Child]) -> Option<(SystemTime, Option<PathBuf>)> {
+///         let file = children
+///             .iter()
+///             .filter(|c| c.kind == Kind::File)
+///             .max_by_key(|c| c.size)?;
+///         Some((file.mtime, Some(file.name.clone().into())))
+///     }
+/// }
+/// let options = dirstamp::Options::builder().root("/srv").policy(Largest).build();
+/// # assert!(options.is_ok());
+/// ```
+pub trait SelectionPolicy: Send + Sync {
+    /// The time `dir` should have, from `children` (in listing order, each
+    /// subfolder with the time it is getting), and the child it came from,
+    /// if one did. `None` leaves the folder alone, as an empty one.
+    fn select(&self, dir: &Path, children: &[Child]) -> Option<(SystemTime, Option<PathBuf>)>;
+}
+
+impl fmt::Debug for dyn SelectionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SelectionPolicy")
+    }
+}
+
+/// Which child mtime a directory takes, among those of the deciding tier
+/// (files, or subdirectories when there are no files).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    #[default]
+    Newest,
+    Oldest,
+    /// The middle one by mtime, the lower of the two for an even count: a
+    /// single fresh temp file doesn't move the folder.
+    Median,
+    /// The one at this percentile (0-100) by mtime; 50 is `Median`, 0
+    /// `Oldest` and 100 `Newest`.
+    Percentile(u8),
+}
+
+impl Strategy {
+    /// Ties go to the first name, whichever end they are at.
+    fn pick(self, mut candidates: Vec<Latest>) -> Option<Latest> {
+        if matches!(self, Strategy::Newest | Strategy::Percentile(100..)) {
+            return candidates
+                .into_iter()
+                .reduce(|cur, c| if c.beats(&cur) { c } else { cur });
+        }
+        candidates.sort_by(|a, b| a.mtime.cmp(&b.mtime).then_with(|| a.name.cmp(&b.name)));
+        let last = candidates.len().checked_sub(1)?;
+        let at = match self {
+            Strategy::Median => last / 2,
+            Strategy::Percentile(p) => (last * usize::from(p) / 100).min(last),
+            _ => 0,
+        };
+        candidates.into_iter().nth(at)
+    }
+}
+
+/// Files first: whatever isn't a folder ranks with them. Folders only
+/// count when there are none.
+impl SelectionPolicy for Strategy {
+    fn select(&self, _dir: &Path, children: &[Child]) -> Option<(SystemTime, Option<PathBuf>)> {
+        let tier = |dirs: bool| -> Vec<Latest> {
+            children
+                .iter()
+                .filter(|c| (c.kind == Kind::Dir) == dirs)
+                .map(|c| Latest {
+                    mtime: c.mtime,
+                    name: Some(c.name.clone()),
+                })
+                .collect()
+        };
+        let files = tier(false);
+        let latest = self.pick(if files.is_empty() { tier(true) } else { files })?;
+        Some((latest.mtime, latest.name.map(PathBuf::from)))
+    }
+}
+
+/// Find the target mtime among *immediate* children of `path`: by `strategy`
+/// among the files; if none, among the immediate subdirs; None if no children.
+pub fn find_target_mtime(path: &Path, strategy: Strategy) -> io::Result<Option<Latest>> {
+    find_target_mtime_with(path, &strategy, |_| None, |_, _| {})
+}
+
+/// Like `find_target_mtime`, but `planned` may supply the mtime a subdirectory
+/// *will* have once its own pending change is applied (used by dry runs so
+/// they match what `-C` would do), and `on_file` sees the name and mtime of
+/// every file child considered.
+pub fn find_target_mtime_with(
+    path: &Path,
+    policy: &dyn SelectionPolicy,
+    planned: impl Fn(&Path) -> Option<SystemTime>,
+    on_file: impl FnMut(&OsStr, SystemTime),
+) -> io::Result<Option<Latest>> {
+    let mut children = read_children(path)?;
+    children.retain(|c| matches!(c.kind, Kind::File | Kind::Dir));
+    Ok(target_child(path, children, policy, planned, on_file))
+}
+
+/// What a child is, links not followed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+    Socket,
+    Fifo,
+    BlockDevice,
+    CharDevice,
+    Other,
+}
+
+impl Kind {
+    #[cfg(unix)]
+    fn of(t: fs::FileType) -> Kind {
+        use std::os::unix::fs::FileTypeExt;
+
+        if t.is_file() {
+            Kind::File
+        } else if t.is_dir() {
+            Kind::Dir
+        } else if t.is_symlink() {
+            Kind::Symlink
+        } else if t.is_socket() {
+            Kind::Socket
+        } else if t.is_fifo() {
+            Kind::Fifo
+        } else if t.is_block_device() {
+            Kind::BlockDevice
+        } else if t.is_char_device() {
+            Kind::CharDevice
+        } else {
+            Kind::Other
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(t: fs::FileType) -> Kind {
+        if t.is_file() {
+            Kind::File
+        } else if t.is_dir() {
+            Kind::Dir
+        } else if t.is_symlink() {
+            Kind::Symlink
+        } else {
+            Kind::Other
+        }
+    }
+}
+
+/// An immediate child of a directory, as the scan sees it.
+#[derive(Debug)]
+pub struct Child {
+    pub name: OsString,
+    pub mtime: SystemTime,
+    pub kind: Kind,
+    /// In bytes, as the metadata gives it (a folder's is the filesystem's).
+    pub size: u64,
+    /// Birth time, where the platform and filesystem record one.
+    pub created: Option<SystemTime>,
+    /// Inode of a file with more than one hard link (Unix only).
+    pub inode: Option<u64>,
+}
+
+/// Everything directly inside `path`, in listing order; which kinds count
+/// is up to the caller. This is all of the scan's I/O, so it can be done on
+/// another thread.
+pub fn read_children(path: &Path) -> io::Result<Vec<Child>> {
+    let mut children = Vec::new();
+    for item in fs::read_dir(path)? {
+        let entry = item?;
+        let meta = entry.metadata()?;
+        children.extend(Child::new(entry.file_name(), &meta));
+    }
+    Ok(children)
+}
+
+impl Child {
+    /// The child named `name`, from its own metadata (links not followed);
+    /// `None` if it has no mtime.
+    pub fn new(name: OsString, meta: &fs::Metadata) -> Option<Child> {
+        Some(Child {
+            name,
+            mtime: meta.modified().ok()?,
+            kind: Kind::of(meta.file_type()),
+            size: meta.len(),
+            created: meta.created().ok(),
+            inode: linked_inode(meta),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn linked_inode(meta: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    (meta.is_file() && meta.nlink() > 1).then(|| meta.ino())
+}
+
+#[cfg(not(unix))]
+fn linked_inode(_meta: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// `--hardlink-aware`: keep one name per hard-linked file, the first by name
+/// (the one that would win a tie anyway).
+pub fn drop_hardlink_duplicates(children: &mut Vec<Child>) {
+    let mut kept: HashMap<u64, usize> = HashMap::new();
+    let mut keep = vec![true; children.len()];
+    for (i, child) in children.iter().enumerate() {
+        let Some(inode) = child.inode else { continue };
+        let first = *kept.entry(inode).or_insert(i);
+        if first == i {
+            continue;
+        }
+        if child.name < children[first].name {
+            keep[first] = false;
+            kept.insert(inode, i);
+        } else {
+            keep[i] = false;
+        }
+    }
+    let mut keep = keep.into_iter();
+    children.retain(|_| keep.next().unwrap_or(true));
+}
+
+/// Give each subfolder among `path`'s `children` the mtime `planned` says
+/// it is getting, if it says.
+pub fn with_planned(
+    path: &Path,
+    children: &mut [Child],
+    planned: impl Fn(&Path) -> Option<SystemTime>,
+) {
+    for child in children.iter_mut().filter(|c| c.kind == Kind::Dir) {
+        child.mtime = planned(&path.join(&child.name)).unwrap_or(child.mtime);
+    }
+}
+
+/// What `policy` picks for `path` from `children`; see
+/// `find_target_mtime_with` for `planned` and `on_file`.
+pub fn target_child(
+    path: &Path,
+    mut children: Vec<Child>,
+    policy: &dyn SelectionPolicy,
+    planned: impl Fn(&Path) -> Option<SystemTime>,
+    mut on_file: impl FnMut(&OsStr, SystemTime),
+) -> Option<Latest> {
+    with_planned(path, &mut children, planned);
+    for child in children.iter().filter(|c| c.kind != Kind::Dir) {
+        on_file(&child.name, child.mtime);
+    }
+    let (mtime, name) = policy.select(path, &children)?;
+    Some(Latest {
+        mtime,
+        name: name.map(PathBuf::into_os_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use filetime::{set_file_mtime, FileTime};
+    use tempfile::TempDir;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn file(dir: &TempDir, name: &str, secs: u64) {
+        let p = dir.path().join(name);
+        fs::File::create(&p).unwrap();
+        set_file_mtime(&p, FileTime::from_system_time(at(secs))).unwrap();
+    }
+
+    fn subdir(dir: &TempDir, name: &str, secs: u64) {
+        let p = dir.path().join(name);
+        fs::create_dir(&p).unwrap();
+        set_file_mtime(&p, FileTime::from_system_time(at(secs))).unwrap();
+    }
+
+    fn latest(dir: &TempDir) -> Option<SystemTime> {
+        find_target_mtime(dir.path(), Strategy::Newest)
+            .unwrap()
+            .map(|l| l.mtime)
+    }
+
+    #[test]
+    fn empty_dir_has_no_latest() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(latest(&dir), None);
+    }
+
+    #[test]
+    fn only_subdirs_uses_newest_subdir() {
+        let dir = TempDir::new().unwrap();
+        subdir(&dir, "old", 1_000);
+        subdir(&dir, "new", 3_000);
+        subdir(&dir, "mid", 2_000);
+        assert_eq!(latest(&dir), Some(at(3_000)));
+    }
+
+    #[test]
+    fn file_wins_over_newer_subdir() {
+        let dir = TempDir::new().unwrap();
+        file(&dir, "a.txt", 1_000);
+        subdir(&dir, "newer", 9_000);
+        assert_eq!(latest(&dir), Some(at(1_000)));
+    }
+
+    #[test]
+    fn newest_of_several_files() {
+        let dir = TempDir::new().unwrap();
+        file(&dir, "b.txt", 2_000);
+        file(&dir, "c.txt", 3_000);
+        file(&dir, "a.txt", 1_000);
+        let newest = find_target_mtime(dir.path(), Strategy::Newest)
+            .unwrap()
+            .unwrap();
+        assert_eq!(newest.mtime, at(3_000));
+        assert_eq!(newest.name.unwrap(), "c.txt");
+    }
+
+    #[test]
+    fn ties_go_to_the_first_name() {
+        let dir = TempDir::new().unwrap();
+        file(&dir, "b.txt", 2_000);
+        file(&dir, "a.txt", 2_000);
+        assert_eq!(
+            find_target_mtime(dir.path(), Strategy::Newest)
+                .unwrap()
+                .unwrap()
+                .name
+                .unwrap(),
+            "a.txt"
+        );
+    }
+
+    #[test]
+    fn median_and_oldest_ignore_an_outlier() {
+        let dir = TempDir::new().unwrap();
+        file(&dir, "a.txt", 1_000);
+        file(&dir, "b.txt", 2_000);
+        file(&dir, "c.txt", 3_000);
+        file(&dir, "tmp", 900_000);
+        subdir(&dir, "sub", 500);
+        let pick = |strategy| {
+            find_target_mtime(dir.path(), strategy)
+                .unwrap()
+                .map(|l| l.mtime)
+        };
+        // Four files: the lower of the middle two.
+        assert_eq!(pick(Strategy::Median), Some(at(2_000)));
+        assert_eq!(pick(Strategy::Oldest), Some(at(1_000)));
+        file(&dir, "d.txt", 4_000);
+        assert_eq!(pick(Strategy::Median), Some(at(3_000)));
+        // Five files: 90% of the way up is the fourth.
+        assert_eq!(pick(Strategy::Percentile(90)), Some(at(4_000)));
+        assert_eq!(pick(Strategy::Percentile(50)), pick(Strategy::Median));
+        assert_eq!(pick(Strategy::Percentile(0)), pick(Strategy::Oldest));
+        assert_eq!(pick(Strategy::Percentile(100)), pick(Strategy::Newest));
+    }
+
+    #[test]
+    fn planned_subdir_mtime_overrides_disk() {
+        let dir = TempDir::new().unwrap();
+        subdir(&dir, "child", 1_000);
+        let child = dir.path().join("child");
+        let latest = find_target_mtime_with(
+            dir.path(),
+            &Strategy::Newest,
+            |p| (p == child).then(|| at(5_000)),
+            |_, _| {},
+        );
+        assert_eq!(latest.unwrap().map(|l| l.mtime), Some(at(5_000)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_count_once() {
+        let dir = TempDir::new().unwrap();
+        file(&dir, "b.txt", 1_000);
+        fs::hard_link(dir.path().join("b.txt"), dir.path().join("a.txt")).unwrap();
+        file(&dir, "c.txt", 500);
+        let mut children = read_children(dir.path()).unwrap();
+        assert_eq!(children.len(), 3);
+
+        drop_hardlink_duplicates(&mut children);
+        let mut names: Vec<_> = children.iter().map(|c| c.name.clone()).collect();
+        names.sort();
+        assert_eq!(names, ["a.txt", "c.txt"]);
+    }
+
+    /// Four files, one an outlier, and a newer subfolder: no filesystem.
+    fn fixture() -> Vec<Child> {
+        let child = |name: &str, kind, secs, size| Child {
+            name: name.into(),
+            mtime: at(secs),
+            kind,
+            size,
+            created: None,
+            inode: None,
+        };
+        vec![
+            child("c.txt", Kind::File, 3_000, 20),
+            child("sub", Kind::Dir, 9_000_000, 4096),
+            child("a.txt", Kind::File, 1_000, 10),
+            child("tmp", Kind::File, 900_000, 1),
+            child("b.txt", Kind::File, 2_000, 500),
+        ]
+    }
+
+    fn select<'a>(
+        policy: &dyn SelectionPolicy,
+        children: &'a [Child],
+    ) -> Option<(SystemTime, &'a str)> {
+        let (mtime, name) = policy.select(Path::new("dir"), children)?;
+        let name = name.unwrap();
+        let name = children.iter().find(|c| name == c.name)?.name.to_str()?;
+        Some((mtime, name))
+    }
+
+    #[test]
+    fn newest_takes_the_newest_file_over_any_folder() {
+        assert_eq!(
+            select(&Strategy::Newest, &fixture()),
+            Some((at(900_000), "tmp"))
+        );
+        let folders: Vec<_> = fixture()
+            .into_iter()
+            .filter(|c| c.kind == Kind::Dir)
+            .collect();
+        assert_eq!(
+            select(&Strategy::Newest, &folders),
+            Some((at(9_000_000), "sub"))
+        );
+        assert_eq!(Strategy::Newest.select(Path::new("dir"), &[]), None);
+    }
+
+    #[test]
+    fn oldest_takes_the_oldest_file() {
+        assert_eq!(
+            select(&Strategy::Oldest, &fixture()),
+            Some((at(1_000), "a.txt"))
+        );
+    }
+
+    #[test]
+    fn median_takes_the_lower_middle_file() {
+        assert_eq!(
+            select(&Strategy::Median, &fixture()),
+            Some((at(2_000), "b.txt"))
+        );
+    }
+
+    #[test]
+    fn percentile_counts_up_from_the_oldest_file() {
+        let children = fixture();
+        let pick = |p| select(&Strategy::Percentile(p), &children);
+        assert_eq!(pick(75), Some((at(3_000), "c.txt")));
+        assert_eq!(pick(0), select(&Strategy::Oldest, &children));
+        assert_eq!(pick(100), select(&Strategy::Newest, &children));
+    }
+
+    /// The time of the largest child, folders and all.
+    struct Largest;
+
+    impl SelectionPolicy for Largest {
+        fn select(&self, _dir: &Path, children: &[Child]) -> Option<(SystemTime, Option<PathBuf>)> {
+            let c = children.iter().max_by_key(|c| c.size)?;
+            Some((c.mtime, Some(c.name.clone().into())))
+        }
+    }
+
+    #[test]
+    fn a_policy_of_ones_own_sees_planned_folder_times() {
+        assert_eq!(select(&Largest, &fixture()), Some((at(9_000_000), "sub")));
+        let planned = |p: &Path| (p == Path::new("dir/sub")).then(|| at(7_000));
+        let latest = target_child(Path::new("dir"), fixture(), &Largest, planned, |_, _| {});
+        assert_eq!(
+            latest,
+            Some(Latest {
+                mtime: at(7_000),
+                name: Some("sub".into()),
+            })
+        );
+    }
+
+    /// Children are inspected without following symlinks, so links (to files
+    /// or folders) never count, whatever the walk's follow-links setting.
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_ignored() {
+        let target = TempDir::new().unwrap();
+        file(&target, "f.txt", 1_000);
+        let dir = TempDir::new().unwrap();
+        std::os::unix::fs::symlink(target.path().join("f.txt"), dir.path().join("to_file"))
+            .unwrap();
+        std::os::unix::fs::symlink(target.path(), dir.path().join("to_dir")).unwrap();
+        assert_eq!(latest(&dir), None);
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use filetime::{set_file_mtime, FileTime};
+    use proptest::prelude::*;
+    // proptest's Strategy, over the glob one; the scan's is `super::Strategy`.
+    use proptest::strategy::Strategy;
+
+    fn mtime() -> impl Strategy<Value = SystemTime> {
+        (0u64..4_000_000_000, 0u32..1_000_000_000)
+            .prop_map(|(secs, nanos)| UNIX_EPOCH + Duration::new(secs, nanos))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        /// Newest file if any file exists, else newest subdir, else None.
+        #[test]
+        fn child_priority_invariant(entries in prop::collection::vec((any::<bool>(), mtime()), 0..12)) {
+            let dir = tempfile::tempdir().unwrap();
+            let mut newest_file: Option<SystemTime> = None;
+            let mut newest_dir: Option<SystemTime> = None;
+
+            for (i, (is_file, t)) in entries.iter().enumerate() {
+                let p = dir.path().join(format!("entry{i}"));
+                if *is_file {
+                    fs::File::create(&p).unwrap();
+                } else {
+                    fs::create_dir(&p).unwrap();
+                }
+                set_file_mtime(&p, FileTime::from_system_time(*t)).unwrap();
+
+                // Compare against what the filesystem actually stored, which
+                // may be coarser than what we asked for.
+                let stored = fs::metadata(&p).unwrap().modified().unwrap();
+                let slot = if *is_file { &mut newest_file } else { &mut newest_dir };
+                *slot = Some(slot.map_or(stored, |cur| cur.max(stored)));
+            }
+
+            let got = find_target_mtime(dir.path(), super::Strategy::Newest)
+                .unwrap()
+                .map(|l| l.mtime);
+            prop_assert_eq!(got, newest_file.or(newest_dir));
+        }
+    }
+}
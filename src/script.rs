@@ -0,0 +1,116 @@
+// src/script.rs
+// --emit-script: write the planned changes as a shell or PowerShell script
+// for someone else to review and run.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::cli::ScriptFormat;
+
+pub struct ScriptWriter {
+    out: Box<dyn Write>,
+    format: ScriptFormat,
+}
+
+impl ScriptWriter {
+    /// Start a script, writing the header comment that records who made it and how.
+    pub fn new(
+        mut out: Box<dyn Write>,
+        format: ScriptFormat,
+        version: &str,
+        args: &str,
+        generated: SystemTime,
+    ) -> io::Result<Self> {
+        let generated = OffsetDateTime::from(generated)
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| "<bad time>".into());
+        if format == ScriptFormat::Sh {
+            writeln!(out, "#!/bin/sh")?;
+        }
+        writeln!(out, "# Generated by dirstamp {version} at {generated}")?;
+        writeln!(out, "# Options: {args}")?;
+        writeln!(
+            out,
+            "# Applies the same changes as -C, child folders before their parents."
+        )?;
+        Ok(ScriptWriter { out, format })
+    }
+
+    pub fn push(&mut self, path: &Path, mtime: SystemTime) -> io::Result<()> {
+        let t = OffsetDateTime::from(mtime);
+        match self.format {
+            ScriptFormat::Sh => {
+                let when = t
+                    .format(format_description!(
+                        "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:9]Z"
+                    ))
+                    .map_err(io::Error::other)?;
+                write!(self.out, "touch -m -d '{when}' -- ")?;
+                self.out.write_all(&sh_quote(path))?;
+                writeln!(self.out)
+            }
+            ScriptFormat::PowerShell => {
+                // .NET DateTime resolution is 100ns, the same as NTFS.
+                let when = t
+                    .format(format_description!(
+                        "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:7]Z"
+                    ))
+                    .map_err(io::Error::other)?;
+                writeln!(
+                    self.out,
+                    "(Get-Item -LiteralPath {}).LastWriteTimeUtc = \
+                     [DateTime]::Parse('{when}', [Globalization.CultureInfo]::InvariantCulture, 'RoundtripKind')",
+                    ps_quote(&path.to_string_lossy())
+                )
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// POSIX single-quoting: everything is literal except `'`, spelled `'\''`.
+/// On Unix the raw path bytes are kept, so non-UTF-8 names survive.
+pub fn sh_quote(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    let raw = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    };
+    #[cfg(not(unix))]
+    let raw = path.to_string_lossy().into_owned().into_bytes();
+
+    let mut out = Vec::with_capacity(raw.len() + 2);
+    out.push(b'\'');
+    for b in raw {
+        if b == b'\'' {
+            out.extend_from_slice(br"'\''");
+        } else {
+            out.push(b);
+        }
+    }
+    out.push(b'\'');
+    out
+}
+
+/// PowerShell single-quoting: quote characters (including the typographic
+/// ones PowerShell also accepts) are escaped by doubling.
+fn ps_quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('\'');
+    for c in s.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+            out.push(c);
+        }
+        out.push(c);
+    }
+    out.push('\'');
+    out
+}
@@ -0,0 +1,176 @@
+// src/snapshot.rs
+// --save-snapshot / --restore-snapshot: every directory's mtime under a root,
+// as JSON lines that are written and read one entry at a time so trees with
+// millions of folders never have to fit in memory.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::paths;
+
+/// Snapshot files this build writes and accepts.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// First line of a snapshot.
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+    pub snapshot_version: u32,
+    pub dirstamp_version: String,
+    /// Canonical root the paths are relative to.
+    #[serde(with = "paths::json")]
+    pub root: PathBuf,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: OffsetDateTime,
+}
+
+/// `["media/photos", SECS, NANOS]`: root-relative path and mtime. Seconds are
+/// signed so pre-1970 times survive.
+#[derive(Serialize, Deserialize)]
+struct Entry(#[serde(with = "paths::json")] PathBuf, i64, u32);
+
+pub struct SnapshotWriter {
+    out: BufWriter<File>,
+    root: PathBuf,
+    entries: usize,
+}
+
+impl SnapshotWriter {
+    /// `root` should be canonical; it is recorded so a restore can check it.
+    pub fn create(path: &Path, root: &Path, created: SystemTime) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(
+            &mut out,
+            &Header {
+                snapshot_version: SNAPSHOT_VERSION,
+                dirstamp_version: env!("CARGO_PKG_VERSION").into(),
+                root: root.to_path_buf(),
+                created: created.into(),
+            },
+        )?;
+        out.write_all(b"\n")?;
+        Ok(SnapshotWriter {
+            out,
+            root: root.to_path_buf(),
+            entries: 0,
+        })
+    }
+
+    pub fn push(&mut self, dir: &Path, mtime: SystemTime) -> io::Result<()> {
+        let rel = dir.strip_prefix(&self.root).unwrap_or(dir);
+        let (secs, nanos) = split_time(mtime);
+        serde_json::to_writer(&mut self.out, &Entry(rel.to_path_buf(), secs, nanos))?;
+        self.out.write_all(b"\n")?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Flush and return how many folders were recorded.
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.out.flush()?;
+        Ok(self.entries)
+    }
+}
+
+/// Streams the entries of a snapshot after its header.
+pub struct SnapshotReader {
+    pub header: Header,
+    lines: Lines<BufReader<File>>,
+}
+
+impl SnapshotReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let first = lines.next().transpose()?.unwrap_or_default();
+        let header: Header = serde_json::from_str(&first)?;
+        if header.snapshot_version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported snapshot version {} (this dirstamp reads version {SNAPSHOT_VERSION})",
+                    header.snapshot_version
+                ),
+            ));
+        }
+        Ok(SnapshotReader { header, lines })
+    }
+}
+
+impl Iterator for SnapshotReader {
+    /// Root-relative path and the mtime it had.
+    type Item = io::Result<(PathBuf, SystemTime)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        let Entry(rel, secs, nanos) = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e.into())),
+        };
+        Some(Ok((rel, join_time(secs, nanos))))
+    }
+}
+
+/// `t` as signed seconds and positive nanoseconds since the epoch; seconds
+/// before the epoch round down.
+pub fn split_time(t: SystemTime) -> (i64, u32) {
+    match t.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    }
+}
+
+/// The inverse of `split_time`.
+pub fn join_time(secs: i64, nanos: u32) -> SystemTime {
+    let whole = Duration::from_secs(secs.unsigned_abs());
+    let base = if secs >= 0 {
+        SystemTime::UNIX_EPOCH + whole
+    } else {
+        SystemTime::UNIX_EPOCH - whole
+    };
+    base + Duration::from_nanos(nanos.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_paths_and_nanoseconds() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("snap.jsonl");
+        let root = Path::new("/photos");
+        let times = [
+            SystemTime::UNIX_EPOCH + Duration::new(1_577_836_800, 123_456_789),
+            SystemTime::UNIX_EPOCH - Duration::new(86_400, 250_000_000),
+        ];
+
+        let mut w = SnapshotWriter::create(&file, root, SystemTime::UNIX_EPOCH).unwrap();
+        w.push(root, times[0]).unwrap();
+        w.push(&root.join("2014/Rome"), times[1]).unwrap();
+        assert_eq!(w.finish().unwrap(), 2);
+
+        let r = SnapshotReader::open(&file).unwrap();
+        assert_eq!(r.header.root, root);
+        let entries: Vec<_> = r.map(Result::unwrap).collect();
+        assert_eq!(
+            entries,
+            vec![
+                (PathBuf::new(), times[0]),
+                (PathBuf::from("2014/Rome"), times[1])
+            ]
+        );
+    }
+}
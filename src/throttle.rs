@@ -0,0 +1,70 @@
+// src/throttle.rs
+// --rate-limit: at most N mtime sets per second, so a run doesn't swamp a
+// busy share with writes. A set that would be one too many for the current
+// second waits for the next. Shared by the --jobs workers.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SECOND: Duration = Duration::from_secs(1);
+
+/// The default lets every set through straight away.
+#[derive(Default)]
+pub struct Throttle {
+    /// The limit, and the current second: when it began and the sets made in it.
+    window: Option<Mutex<(usize, Instant, usize)>>,
+}
+
+impl Throttle {
+    /// `per_second` sets a second; 0 is no limit.
+    pub fn new(per_second: usize) -> Self {
+        Throttle {
+            window: (per_second > 0).then(|| Mutex::new((per_second, Instant::now(), 0))),
+        }
+    }
+
+    /// Wait, if need be, until one more set fits in the limit.
+    pub fn wait(&self) {
+        let Some(window) = &self.window else {
+            return;
+        };
+        let mut window = window.lock().unwrap_or_else(|e| e.into_inner());
+        let (limit, started, made) = &mut *window;
+        let spent = started.elapsed();
+        if spent >= SECOND {
+            *started = Instant::now();
+            *made = 0;
+        } else if *made >= *limit {
+            // Held while sleeping, so the other workers queue up behind.
+            thread::sleep(SECOND - spent);
+            *started = Instant::now();
+            *made = 0;
+        }
+        *made += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_set_past_the_limit_waits_for_the_next_second() {
+        let throttle = Throttle::new(3);
+        let started = Instant::now();
+        for _ in 0..3 {
+            throttle.wait();
+        }
+        assert!(started.elapsed() < Duration::from_millis(500));
+        throttle.wait();
+        assert!(started.elapsed() >= Duration::from_millis(900));
+
+        let unlimited = Throttle::new(0);
+        let started = Instant::now();
+        for _ in 0..100 {
+            unlimited.wait();
+        }
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+}
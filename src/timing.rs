@@ -0,0 +1,251 @@
+// src/timing.rs
+// --timing: how long the run took, which phase the time went on, and how
+// many filesystem calls the walk and the folder scans made and how long they
+// took, which on a network share is what the time goes on. Counted with
+// atomics, so --jobs workers can count too. Everything is a no-op unless
+// `enable` was called, so the calls stay in for every run.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::paths;
+
+#[derive(Clone, Copy)]
+pub enum Call {
+    /// Reading a folder's entries.
+    Listing,
+    /// Reading one entry's metadata.
+    Stat,
+    /// Setting a folder's mtime.
+    Set,
+}
+
+/// Where the main thread is spending its time.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Walking the tree for the folders to visit.
+    Discovery,
+    /// Reading a folder's children and deciding its change.
+    Planning,
+    /// Setting mtimes, with the journal and the checks around it.
+    Applying,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static COUNTS: [AtomicUsize; 3] = [const { AtomicUsize::new(0) }; 3];
+/// Calls that were timed on their own, and their total time. A listing's
+/// time takes in its entries' metadata reads, which aren't timed apart.
+static TIMED: [AtomicUsize; 3] = [const { AtomicUsize::new(0) }; 3];
+static NANOS: [AtomicU64; 3] = [const { AtomicU64::new(0) }; 3];
+static PHASE_NANOS: [AtomicU64; 3] = [const { AtomicU64::new(0) }; 3];
+/// The phase under way, since when.
+static CURRENT: Mutex<Option<(Phase, Instant)>> = Mutex::new(None);
+static SLOWEST: Mutex<Option<(Duration, PathBuf)>> = Mutex::new(None);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn count(call: Call, n: usize) {
+    if enabled() {
+        COUNTS[call as usize].fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+fn counted(call: Call) -> usize {
+    COUNTS[call as usize].load(Ordering::Relaxed)
+}
+
+fn nanos(d: Duration) -> u64 {
+    u64::try_from(d.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Run `f`, one `call` on `path`, and add its time to the call's (counting
+/// it is up to the caller). The slowest listing is remembered.
+pub fn timed<R>(call: Call, path: &Path, f: impl FnOnce() -> R) -> R {
+    if !enabled() {
+        return f();
+    }
+    let started = Instant::now();
+    let out = f();
+    let took = started.elapsed();
+    TIMED[call as usize].fetch_add(1, Ordering::Relaxed);
+    NANOS[call as usize].fetch_add(nanos(took), Ordering::Relaxed);
+    if matches!(call, Call::Listing) {
+        let mut slowest = SLOWEST.lock().unwrap_or_else(|e| e.into_inner());
+        if slowest.as_ref().is_none_or(|(most, _)| took > *most) {
+            *slowest = Some((took, path.to_path_buf()));
+        }
+    }
+    out
+}
+
+/// Closes its phase when dropped, and goes back to the one it interrupted.
+pub struct InPhase {
+    outer: Option<Phase>,
+    active: bool,
+}
+
+/// Count the time from now on to `phase`, until the guard is dropped. A
+/// phase entered inside another pauses it. Main thread only.
+pub fn phase(phase: Phase) -> InPhase {
+    if !enabled() {
+        return InPhase {
+            outer: None,
+            active: false,
+        };
+    }
+    let outer = switch(Some(phase));
+    InPhase {
+        outer,
+        active: true,
+    }
+}
+
+impl Drop for InPhase {
+    fn drop(&mut self) {
+        if self.active {
+            switch(self.outer);
+        }
+    }
+}
+
+/// End the current phase and start `next`; returns the one that ended.
+fn switch(next: Option<Phase>) -> Option<Phase> {
+    let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    let ended = current.map(|(phase, since)| {
+        PHASE_NANOS[phase as usize].fetch_add(nanos(now - since), Ordering::Relaxed);
+        phase
+    });
+    *current = next.map(|phase| (phase, now));
+    ended
+}
+
+fn phase_time(phase: Phase) -> Duration {
+    Duration::from_nanos(PHASE_NANOS[phase as usize].load(Ordering::Relaxed))
+}
+
+/// One kind of call, for the JSON summary.
+#[derive(Serialize)]
+pub struct CallTimes {
+    count: usize,
+    /// Mean time of the calls timed on their own; `None` if there were none.
+    avg_ms: Option<f64>,
+}
+
+impl CallTimes {
+    fn of(call: Call) -> Self {
+        let timed = TIMED[call as usize].load(Ordering::Relaxed);
+        let total = NANOS[call as usize].load(Ordering::Relaxed);
+        CallTimes {
+            count: counted(call),
+            avg_ms: (timed > 0).then(|| total as f64 / timed as f64 / 1e6),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Slowest {
+    #[serde(with = "paths::json")]
+    path: PathBuf,
+    ms: f64,
+}
+
+/// Everything measured: the `timing` object of the `--output-json` summary.
+#[derive(Serialize)]
+pub struct Summary {
+    discovery_ms: f64,
+    planning_ms: f64,
+    applying_ms: f64,
+    listings: CallTimes,
+    stats: CallTimes,
+    sets: CallTimes,
+    /// The folder whose listing took longest.
+    slowest_listing: Option<Slowest>,
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e3
+}
+
+pub fn summary() -> Summary {
+    let slowest = SLOWEST.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    Summary {
+        discovery_ms: ms(phase_time(Phase::Discovery)),
+        planning_ms: ms(phase_time(Phase::Planning)),
+        applying_ms: ms(phase_time(Phase::Applying)),
+        listings: CallTimes::of(Call::Listing),
+        stats: CallTimes::of(Call::Stat),
+        sets: CallTimes::of(Call::Set),
+        slowest_listing: slowest.map(|(took, path)| Slowest { path, ms: ms(took) }),
+    }
+}
+
+pub fn render(elapsed: Duration) -> String {
+    let summary = summary();
+    let mut out = format!("Timing: {:.2}s\n", elapsed.as_secs_f64());
+    let phases = [
+        ("discovery", summary.discovery_ms),
+        ("planning", summary.planning_ms),
+        ("applying", summary.applying_ms),
+    ];
+    for (what, ms) in phases {
+        out.push_str(&format!("  {what:<16} {:>7.2}s\n", ms / 1e3));
+    }
+    let rows = [
+        ("folder listings", &summary.listings),
+        ("metadata reads", &summary.stats),
+        ("mtime sets", &summary.sets),
+    ];
+    for (what, calls) in rows {
+        out.push_str(&format!("  {what:<16} {:>8}", calls.count));
+        if let Some(avg) = calls.avg_ms {
+            out.push_str(&format!("  avg {avg:.3}ms"));
+        }
+        out.push('\n');
+    }
+    if let Some(slowest) = &summary.slowest_listing {
+        out.push_str(&format!(
+            "  slowest listing  {:>6.1}ms  {}\n",
+            slowest.ms,
+            slowest.path.display()
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_phases_show_up_in_the_table() {
+        enable();
+        count(Call::Set, 3);
+        timed(Call::Listing, Path::new("/slow"), || {
+            std::thread::sleep(Duration::from_millis(20))
+        });
+        {
+            let _planning = phase(Phase::Planning);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let table = render(Duration::from_millis(1_250));
+        assert!(table.starts_with("Timing: 1.25s\n"));
+        let sets = table.lines().find(|l| l.contains("mtime sets")).unwrap();
+        assert_eq!(
+            sets.split_whitespace().nth(2),
+            Some(counted(Call::Set).to_string().as_str())
+        );
+        assert!(table.contains("  slowest listing"));
+        assert!(phase_time(Phase::Planning) >= Duration::from_millis(20));
+    }
+}
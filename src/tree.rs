@@ -0,0 +1,126 @@
+// src/tree.rs
+// --tree: the folders under the root drawn like tree(1), each marked with
+// what the run found for it, printed once the walk is done.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::report::SkipReason;
+use crate::rollup::human_delta;
+
+/// What happened to one folder.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mark {
+    /// Planned or made, with the signed delta in seconds.
+    Update(f64),
+    /// Setting the mtime failed.
+    Failed,
+    Skipped(SkipReason),
+}
+
+impl Mark {
+    fn label(self) -> String {
+        match self {
+            Mark::Update(delta) => format!("[UPDATE {}]", human_delta(delta)),
+            Mark::Failed => "[FAILED]".into(),
+            Mark::Skipped(SkipReason::InSync) => "[OK]".into(),
+            Mark::Skipped(reason) => format!("[{}]", reason.keyword().to_uppercase()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Node {
+    /// `None` for a folder the walk didn't report on.
+    mark: Option<Mark>,
+    children: BTreeMap<OsString, Node>,
+}
+
+pub struct Tree {
+    root: PathBuf,
+    top: Node,
+}
+
+impl Tree {
+    pub fn new(root: &Path) -> Self {
+        Tree {
+            root: root.to_path_buf(),
+            top: Node::default(),
+        }
+    }
+
+    pub fn add(&mut self, path: &Path, mark: Mark) {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return;
+        };
+        let mut node = &mut self.top;
+        for part in rel {
+            node = node.children.entry(part.to_os_string()).or_default();
+        }
+        node.mark = Some(mark);
+    }
+
+    /// The diagram, one line per folder, children in name order.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![labelled(self.root.display().to_string(), &self.top)];
+        push_children(&mut lines, &self.top, "");
+        lines
+    }
+
+    /// Print the diagram, stopping quietly if stdout closes (`| head`).
+    pub fn print(&self) {
+        let mut out = io::stdout().lock();
+        for line in self.lines() {
+            if writeln!(out, "{line}").is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn labelled(name: String, node: &Node) -> String {
+    match node.mark {
+        Some(mark) => format!("{name} {}", mark.label()),
+        None => name,
+    }
+}
+
+fn push_children(lines: &mut Vec<String>, node: &Node, indent: &str) {
+    let last = node.children.len().saturating_sub(1);
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let (branch, more) = if i == last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        let name = name.to_string_lossy().into_owned();
+        lines.push(format!("{indent}{branch}{}", labelled(name, child)));
+        push_children(lines, child, &format!("{indent}{more}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_marked_folders_in_name_order() {
+        let mut tree = Tree::new(Path::new("."));
+        tree.add(Path::new("./tests"), Mark::Skipped(SkipReason::Empty));
+        tree.add(Path::new("./src/lib"), Mark::Skipped(SkipReason::InSync));
+        tree.add(Path::new("./src"), Mark::Update(3.2 * 86_400.0));
+        tree.add(Path::new("./src/bin"), Mark::Skipped(SkipReason::Error));
+        assert_eq!(
+            tree.lines(),
+            [
+                ".",
+                "├── src [UPDATE +3.2d]",
+                "│   ├── bin [ERROR]",
+                "│   └── lib [OK]",
+                "└── tests [EMPTY]",
+            ]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+// src/update.rs
+// --version-check: ask GitHub for the latest release and compare it to ours.
+
+use std::time::Duration;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/Crinklebine/dirstamp/releases/latest";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The newest published release.
+pub struct Release {
+    /// Version without the leading `v`, e.g. `0.1.5`.
+    pub version: String,
+    /// Release page to point the user at.
+    pub url: String,
+}
+
+/// Fetch the latest release with a single blocking GET.
+pub fn latest_release() -> Result<Release, String> {
+    let response = ureq::get(RELEASES_URL)
+        .timeout(TIMEOUT)
+        .set(
+            "User-Agent",
+            concat!("dirstamp/", env!("CARGO_PKG_VERSION")),
+        )
+        .set("Accept", "application/vnd.github+json")
+        .call()
+        .map_err(|e| e.to_string())?;
+    let body: serde_json::Value =
+        serde_json::from_reader(response.into_reader()).map_err(|e| e.to_string())?;
+    let tag = body["tag_name"]
+        .as_str()
+        .ok_or("response has no tag_name")?;
+    let url = body["html_url"]
+        .as_str()
+        .unwrap_or("https://github.com/Crinklebine/dirstamp/releases/latest");
+    Ok(Release {
+        version: tag.trim_start_matches('v').to_string(),
+        url: url.to_string(),
+    })
+}
+
+/// `X.Y.Z` as numbers; missing or non-numeric parts (e.g. `-beta`) count as 0.
+fn parse_version(v: &str) -> [u64; 3] {
+    let core = v
+        .trim_start_matches('v')
+        .split(['-', '+'])
+        .next()
+        .unwrap_or("");
+    let mut out = [0; 3];
+    for (slot, part) in out.iter_mut().zip(core.split('.')) {
+        *slot = part.parse().unwrap_or(0);
+    }
+    out
+}
+
+/// Whether `latest` is a newer version than `current`.
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    parse_version(latest) > parse_version(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_numerically() {
+        assert!(is_newer("0.1.10", "0.1.9"));
+        assert!(is_newer("v1.0.0", "0.9.9"));
+        assert!(!is_newer("0.1.4", "0.1.4"));
+        assert!(!is_newer("v0.1.3", "0.1.4"));
+        assert!(!is_newer("0.1.4-beta", "0.1.4"));
+    }
+}
@@ -0,0 +1,663 @@
+// src/walk.rs
+// The directory walk: every folder under the root, in file-name order, with
+// --include-device / --exclude-device applied. Under --dir-timeout each folder
+// is listed through the watchdog instead of by walkdir, which reads a folder
+// before yielding it, so a folder that never answers can be named and skipped.
+// Under --jobs the listings are made a level at a time on several threads
+// first, and the same walk then runs over them. A walk that lists folders
+// itself reads every child's metadata on the way, and hands the children
+// over so the folder's scan needn't list it a second time.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use dirstamp::scan::Child;
+use walkdir::WalkDir;
+
+use crate::cache::DirCache;
+use crate::cli::Config;
+use crate::timing::{self, Call};
+use crate::watchdog::{self, Watchdog};
+use crate::{handles, interrupt, jobs};
+
+/// One step of the walk. Files aren't reported.
+pub enum Walked {
+    /// With the folder's mtime as the walk read it, so the scan needn't,
+    /// and its children when the walk listed it and could read them all.
+    Dir(PathBuf, Option<SystemTime>, Option<Vec<Child>>),
+    /// Left out (with everything below) by the device filter.
+    OtherDevice(PathBuf),
+    Error(WalkError),
+    /// `--dir-timeout` expired listing it; nothing below it was visited.
+    TimedOut(PathBuf, io::Error),
+}
+
+pub struct WalkError {
+    pub path: Option<PathBuf>,
+    pub kind: Option<io::ErrorKind>,
+    message: String,
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<walkdir::Error> for WalkError {
+    fn from(err: walkdir::Error) -> Self {
+        WalkError {
+            path: err.path().map(Path::to_path_buf),
+            kind: err.io_error().map(io::Error::kind),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl WalkError {
+    /// Worded like walkdir's own errors.
+    fn io(path: &Path, e: &io::Error) -> Self {
+        WalkError {
+            path: Some(path.to_path_buf()),
+            kind: Some(e.kind()),
+            message: format!("IO error for operation on {}: {e}", path.display()),
+        }
+    }
+}
+
+/// `--include-device` / `--exclude-device`; the default lets everything through.
+#[derive(Default)]
+pub struct DeviceFilter {
+    include: Vec<u64>,
+    exclude: Vec<u64>,
+}
+
+impl DeviceFilter {
+    pub fn new(cfg: &Config) -> Self {
+        DeviceFilter {
+            include: cfg.include_devices.clone(),
+            exclude: cfg.exclude_devices.clone(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn wants(&self, meta: &Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        let dev = meta.dev();
+        (self.include.is_empty() || self.include.contains(&dev)) && !self.exclude.contains(&dev)
+    }
+
+    /// For a single folder outside a walk; one whose metadata can't be read
+    /// is let through, so the error surfaces when it's scanned.
+    pub fn wants_dir(&self, path: &Path) -> bool {
+        fs::metadata(path).map_or(true, |m| self.wants(&m))
+    }
+
+    /// Device numbers are a Unix notion; `main` warns that the filters are ignored.
+    #[cfg(not(unix))]
+    fn wants(&self, _meta: &Metadata) -> bool {
+        true
+    }
+}
+
+/// Walk `root` (following links), handing each step to `emit` until it
+/// returns false. With a watchdog, no single listing can hold the walk up
+/// for longer than its timeout.
+pub fn walk(
+    root: &Path,
+    devices: &DeviceFilter,
+    watchdog: Option<&mut Watchdog>,
+    emit: impl FnMut(Walked) -> bool,
+) {
+    match watchdog {
+        None => walk_plain(root, devices, emit),
+        Some(watchdog) => walk_listed(root, devices, watchdog, emit),
+    }
+}
+
+/// Like `walk`, but every folder is listed here rather than by walkdir, so
+/// each `Walked::Dir` comes with its children. With a cache, folders it
+/// knows to be unchanged aren't listed at all.
+pub fn walk_listing(
+    root: &Path,
+    devices: &DeviceFilter,
+    watchdog: Option<&mut Watchdog>,
+    cache: Option<&mut DirCache>,
+    emit: impl FnMut(Walked) -> bool,
+) {
+    match (watchdog, cache) {
+        (Some(watchdog), _) => walk_listed(root, devices, watchdog, emit),
+        (None, Some(cache)) => walk_listed(root, devices, &mut Cached(cache), emit),
+        (None, None) => walk_listed(root, devices, &mut Direct, emit),
+    }
+}
+
+/// Like `walk`, with the folders listed on up to `jobs` threads. The steps
+/// come in the same order.
+pub fn walk_parallel(
+    root: &Path,
+    devices: &DeviceFilter,
+    jobs: usize,
+    emit: impl FnMut(Walked) -> bool,
+) {
+    walk_listed(root, devices, &mut prefetch(root, devices, jobs), emit)
+}
+
+fn walk_plain(root: &Path, devices: &DeviceFilter, mut emit: impl FnMut(Walked) -> bool) {
+    // Sorted so the processing (and so the report) order never depends on
+    // the order the filesystem happens to list entries in.
+    let mut walk = WalkDir::new(root)
+        .follow_links(true)
+        .max_open(handles::limit())
+        .sort_by_file_name()
+        .into_iter();
+    while let Some(entry) = walk.next() {
+        let e = match entry {
+            Ok(e) if !e.file_type().is_dir() => continue,
+            Ok(e) => e,
+            Err(err) => {
+                if !emit(Walked::Error(err.into())) {
+                    return;
+                }
+                continue;
+            }
+        };
+        timing::count(Call::Stat, 1);
+        let step = match timing::timed(Call::Stat, e.path(), || e.metadata()) {
+            Ok(meta) if !devices.wants(&meta) => {
+                // Nothing below a filtered-out folder is visited either.
+                walk.skip_current_dir();
+                Walked::OtherDevice(e.into_path())
+            }
+            // Folders whose metadata can't be read are let through, so the
+            // error surfaces when they're scanned.
+            meta => {
+                timing::count(Call::Listing, 1);
+                let mtime = meta.ok().and_then(|m| m.modified().ok());
+                Walked::Dir(e.into_path(), mtime, None)
+            }
+        };
+        if !emit(step) {
+            return;
+        }
+    }
+}
+
+/// `--shard`: the folders directly in `dir`, in name order, for each to be
+/// walked on its own. One that can't be (`dir` unlistable, or a link back to
+/// it or above) goes to `emit` instead, as the walk of `dir` would report it.
+pub fn subfolders(dir: &Path, mut emit: impl FnMut(Walked)) -> Vec<PathBuf> {
+    let listing = match list(dir) {
+        Ok(listing) => listing,
+        Err(e) => {
+            emit(Walked::Error(WalkError::io(dir, &e)));
+            return Vec::new();
+        }
+    };
+    let canonical = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut shards = Vec::new();
+    for subdir in listing.subdirs {
+        let target = subdir
+            .is_link
+            .then(|| fs::canonicalize(&subdir.path).ok())
+            .flatten();
+        if let Some(ancestor) = target.filter(|t| canonical.starts_with(t)) {
+            let message = format!(
+                "File system loop found: {} points to an ancestor {}",
+                subdir.path.display(),
+                ancestor.display()
+            );
+            emit(Walked::Error(WalkError {
+                path: Some(subdir.path),
+                kind: None,
+                message,
+            }));
+            continue;
+        }
+        shards.push(subdir.path);
+    }
+    shards
+}
+
+/// Whether no file or folder below `root` has an mtime after `t`, which
+/// means no folder's newest child is newer either. Stops at the first that
+/// is, and counts anything it can't read as newer.
+pub fn nothing_newer_than(root: &Path, t: SystemTime) -> bool {
+    WalkDir::new(root)
+        .follow_links(true)
+        .max_open(handles::limit())
+        .min_depth(1)
+        .into_iter()
+        .all(|entry| {
+            entry
+                .ok()
+                .and_then(|e| e.metadata().ok())
+                .and_then(|m| m.modified().ok())
+                .is_some_and(|mtime| mtime <= t)
+        })
+}
+
+/// A subdirectory found by `list`.
+struct Subdir {
+    path: PathBuf,
+    meta: io::Result<Metadata>,
+    is_link: bool,
+}
+
+/// One folder's listing.
+struct Listing {
+    /// The subdirectories in file-name order, links followed; entries that
+    /// can't be examined are kept so their error gets reported.
+    subdirs: Vec<Subdir>,
+    /// Every child as `scan::read_children` would see it, in listing order;
+    /// `None` if one couldn't be examined, so the scan reads (and reports) it.
+    children: Option<Vec<Child>>,
+    /// Every entry's name, for `--cache`.
+    names: Vec<OsString>,
+}
+
+impl Listing {
+    fn new() -> Self {
+        Listing {
+            subdirs: Vec::new(),
+            children: Some(Vec::new()),
+            names: Vec::new(),
+        }
+    }
+
+    /// Add the entry at `path`, with its own (not followed) metadata.
+    fn add(&mut self, path: PathBuf, name: OsString, is_link: bool, own: io::Result<Metadata>) {
+        match &own {
+            Ok(meta) => {
+                if let Some(children) = &mut self.children {
+                    children.extend(Child::new(name.clone(), meta));
+                }
+            }
+            Err(_) => self.children = None,
+        }
+        self.names.push(name);
+        let meta = if is_link {
+            timing::count(Call::Stat, 1);
+            fs::metadata(&path)
+        } else {
+            own
+        };
+        if meta.as_ref().map_or(true, Metadata::is_dir) {
+            self.subdirs.push(Subdir {
+                path,
+                meta,
+                is_link,
+            });
+        }
+    }
+
+    fn sorted(mut self) -> Self {
+        self.subdirs
+            .sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name()));
+        self
+    }
+}
+
+fn list(dir: &Path) -> io::Result<Listing> {
+    timing::count(Call::Listing, 1);
+    timing::timed(Call::Listing, dir, || list_entries(dir))
+}
+
+fn list_entries(dir: &Path) -> io::Result<Listing> {
+    let _open = handles::open();
+    let mut listing = Listing::new();
+    for item in fs::read_dir(dir)? {
+        let entry = item?;
+        let is_link = entry.file_type().is_ok_and(|t| t.is_symlink());
+        timing::count(Call::Stat, 1);
+        listing.add(entry.path(), entry.file_name(), is_link, entry.metadata());
+    }
+    Ok(listing.sorted())
+}
+
+/// `list` for a folder known to hold just `names`: each is looked up
+/// directly. `Err` if one has gone after all.
+fn list_names(dir: &Path, names: &[OsString]) -> io::Result<Listing> {
+    let mut listing = Listing::new();
+    for name in names {
+        let path = dir.join(name);
+        timing::count(Call::Stat, 1);
+        let own = fs::symlink_metadata(&path)?;
+        let is_link = own.file_type().is_symlink();
+        listing.add(path, name.clone(), is_link, Ok(own));
+    }
+    Ok(listing.sorted())
+}
+
+/// The filesystem calls `walk_listed` makes.
+trait Lister {
+    fn metadata(&mut self, path: &Path) -> io::Result<Metadata>;
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf>;
+    /// `meta` is the folder's, as `metadata` just read it.
+    fn list(&mut self, dir: &Path, meta: &Metadata) -> io::Result<Listing>;
+}
+
+/// No watchdog and nothing listed ahead: every call is made as it comes.
+struct Direct;
+
+impl Lister for Direct {
+    fn metadata(&mut self, path: &Path) -> io::Result<Metadata> {
+        timing::count(Call::Stat, 1);
+        timing::timed(Call::Stat, path, || fs::metadata(path))
+    }
+
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn list(&mut self, dir: &Path, _meta: &Metadata) -> io::Result<Listing> {
+        list(dir)
+    }
+}
+
+/// `--cache`: folders unchanged since the last run aren't listed, and every
+/// listing is recorded for the next.
+struct Cached<'a>(&'a mut DirCache);
+
+impl Lister for Cached<'_> {
+    fn metadata(&mut self, path: &Path) -> io::Result<Metadata> {
+        Direct.metadata(path)
+    }
+
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        Direct.canonicalize(path)
+    }
+
+    fn list(&mut self, dir: &Path, meta: &Metadata) -> io::Result<Listing> {
+        let known = self.0.names(dir, meta).map(|names| list_names(dir, names));
+        let listing = match known {
+            Some(Ok(listing)) => listing,
+            // Gone since, or never seen: list it after all.
+            _ => list(dir)?,
+        };
+        self.0.record(dir, meta, listing.names.clone());
+        Ok(listing)
+    }
+}
+
+impl Lister for Watchdog {
+    fn metadata(&mut self, path: &Path) -> io::Result<Metadata> {
+        timing::count(Call::Stat, 1);
+        timing::timed(Call::Stat, path, || self.call(path, |p| fs::metadata(p)))
+    }
+
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        self.call(path, |p| fs::canonicalize(p))
+    }
+
+    fn list(&mut self, dir: &Path, _meta: &Metadata) -> io::Result<Listing> {
+        self.call(dir, list)
+    }
+}
+
+/// `--jobs`: listings and link targets made ahead of the walk. Anything
+/// missing is looked up when asked for.
+#[derive(Default)]
+struct Prefetched {
+    lists: HashMap<PathBuf, io::Result<Listing>>,
+    canonical: HashMap<PathBuf, io::Result<PathBuf>>,
+}
+
+impl Lister for Prefetched {
+    fn metadata(&mut self, path: &Path) -> io::Result<Metadata> {
+        timing::count(Call::Stat, 1);
+        timing::timed(Call::Stat, path, || fs::metadata(path))
+    }
+
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        self.canonical
+            .remove(path)
+            .unwrap_or_else(|| fs::canonicalize(path))
+    }
+
+    fn list(&mut self, dir: &Path, _meta: &Metadata) -> io::Result<Listing> {
+        self.lists.remove(dir).unwrap_or_else(|| list(dir))
+    }
+}
+
+/// List every folder `walk_listed` will descend into, a level at a time on
+/// `jobs` threads, following the same rules: device filter, links followed,
+/// no way back into an ancestor.
+fn prefetch(root: &Path, devices: &DeviceFilter, jobs: usize) -> Prefetched {
+    let mut done = Prefetched::default();
+    timing::count(Call::Stat, 1);
+    match timing::timed(Call::Stat, root, || fs::metadata(root)) {
+        Ok(meta) if meta.is_dir() && devices.wants(&meta) => {}
+        _ => return done,
+    }
+    let canonical = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    // Each folder with the real paths of its ancestors and itself.
+    let mut level = vec![(root.to_path_buf(), vec![canonical])];
+    while !level.is_empty() && !interrupt::requested() {
+        let listed = jobs::map(jobs, &level, |(dir, _)| {
+            let listing = list(dir);
+            let links: Vec<(PathBuf, io::Result<PathBuf>)> = listing
+                .iter()
+                .flat_map(|l| &l.subdirs)
+                .filter(|s| s.is_link && s.meta.is_ok())
+                .map(|s| (s.path.clone(), fs::canonicalize(&s.path)))
+                .collect();
+            (listing, links)
+        });
+        let mut next = Vec::new();
+        for ((dir, chain), (listing, links)) in level.into_iter().zip(listed) {
+            for subdir in listing.iter().flat_map(|l| &l.subdirs) {
+                if !subdir.meta.as_ref().is_ok_and(|m| devices.wants(m)) {
+                    continue;
+                }
+                let canonical = if subdir.is_link {
+                    match links.iter().find(|(link, _)| *link == subdir.path) {
+                        Some((_, Ok(target))) => target.clone(),
+                        _ => continue,
+                    }
+                } else {
+                    let name = subdir.path.file_name().unwrap_or_default();
+                    chain[chain.len() - 1].join(name)
+                };
+                if !chain.contains(&canonical) {
+                    let mut chain = chain.clone();
+                    chain.push(canonical);
+                    next.push((subdir.path.clone(), chain));
+                }
+            }
+            done.canonical.extend(links);
+            done.lists.insert(dir, listing);
+        }
+        level = next;
+    }
+    done
+}
+
+/// A folder being listed, with its position in the real tree for spotting
+/// links back to an ancestor.
+struct Frame {
+    path: PathBuf,
+    canonical: PathBuf,
+    rest: std::vec::IntoIter<Subdir>,
+}
+
+fn walk_listed(
+    root: &Path,
+    devices: &DeviceFilter,
+    lister: &mut impl Lister,
+    mut emit: impl FnMut(Walked) -> bool,
+) {
+    let meta = match lister.metadata(root) {
+        Ok(meta) if meta.is_dir() => meta,
+        Ok(_) => return,
+        Err(e) if watchdog::is_timeout(&e) => {
+            emit(Walked::TimedOut(root.to_path_buf(), e));
+            return;
+        }
+        Err(e) => {
+            emit(Walked::Error(WalkError::io(root, &e)));
+            return;
+        }
+    };
+    let canonical = match lister.canonicalize(root) {
+        Ok(canonical) => canonical,
+        Err(_) => root.to_path_buf(),
+    };
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut next = Some((root.to_path_buf(), meta, canonical));
+    loop {
+        if let Some((dir, meta, canonical)) = next.take() {
+            if !devices.wants(&meta) {
+                if !emit(Walked::OtherDevice(dir)) {
+                    return;
+                }
+            } else {
+                // Like walkdir: a folder that can't be listed is an error, not a folder.
+                let step = match lister.list(&dir, &meta) {
+                    Ok(listing) => {
+                        stack.push(Frame {
+                            path: dir.clone(),
+                            canonical,
+                            rest: listing.subdirs.into_iter(),
+                        });
+                        Walked::Dir(dir, meta.modified().ok(), listing.children)
+                    }
+                    Err(e) if watchdog::is_timeout(&e) => Walked::TimedOut(dir, e),
+                    Err(e) => Walked::Error(WalkError::io(&dir, &e)),
+                };
+                if !emit(step) {
+                    return;
+                }
+            }
+        }
+        let Some(frame) = stack.last_mut() else {
+            return;
+        };
+        let Some(subdir) = frame.rest.next() else {
+            stack.pop();
+            continue;
+        };
+        let meta = match subdir.meta {
+            Ok(meta) => meta,
+            Err(e) => {
+                if !emit(Walked::Error(WalkError::io(&subdir.path, &e))) {
+                    return;
+                }
+                continue;
+            }
+        };
+        let canonical = if subdir.is_link {
+            match lister.canonicalize(&subdir.path) {
+                Ok(canonical) => canonical,
+                Err(e) => {
+                    let step = if watchdog::is_timeout(&e) {
+                        Walked::TimedOut(subdir.path, e)
+                    } else {
+                        Walked::Error(WalkError::io(&subdir.path, &e))
+                    };
+                    if !emit(step) {
+                        return;
+                    }
+                    continue;
+                }
+            }
+        } else {
+            let name = subdir.path.file_name().unwrap_or_default();
+            frame.canonical.join(name)
+        };
+        if let Some(ancestor) = stack.iter().find(|f| f.canonical == canonical) {
+            let message = format!(
+                "File system loop found: {} points to an ancestor {}",
+                subdir.path.display(),
+                ancestor.path.display()
+            );
+            let looped = WalkError {
+                path: Some(subdir.path),
+                kind: None,
+                message,
+            };
+            if !emit(Walked::Error(looped)) {
+                return;
+            }
+            continue;
+        }
+        next = Some((subdir.path, meta, canonical));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn dirs(root: &Path, watchdog: Option<&mut Watchdog>) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+        walk(root, &DeviceFilter::default(), watchdog, |step| {
+            if let Walked::Dir(dir, ..) = step {
+                found.push(dir);
+            }
+            true
+        });
+        found
+    }
+
+    #[test]
+    fn watched_and_parallel_walks_visit_what_walkdir_does() {
+        let tmp = tempfile::tempdir().unwrap();
+        for dir in ["b/y", "a/x/deep", "a/w", "c"] {
+            fs::create_dir_all(tmp.path().join(dir)).unwrap();
+        }
+        fs::write(tmp.path().join("a/file"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(tmp.path().join("a"), tmp.path().join("b/y/up")).unwrap();
+
+        let mut watchdog = Watchdog::new(Duration::from_secs(10), false);
+        let watched = dirs(tmp.path(), Some(&mut watchdog));
+        assert_eq!(watched, dirs(tmp.path(), None));
+        let mut parallel = Vec::new();
+        walk_parallel(tmp.path(), &DeviceFilter::default(), 4, |step| {
+            if let Walked::Dir(dir, ..) = step {
+                parallel.push(dir);
+            }
+            true
+        });
+        assert_eq!(parallel, watched);
+        assert_eq!(
+            watched[..3],
+            [tmp.path(), &tmp.path().join("a"), &tmp.path().join("a/w")]
+        );
+    }
+
+    #[test]
+    fn a_listing_walk_hands_over_what_the_scan_would_read() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("sub/deeper")).unwrap();
+        fs::write(tmp.path().join("sub/file"), "").unwrap();
+
+        let mut listed = Vec::new();
+        walk_listing(tmp.path(), &DeviceFilter::default(), None, None, |step| {
+            if let Walked::Dir(dir, _, children) = step {
+                listed.push((dir, children.unwrap()));
+            }
+            true
+        });
+        assert_eq!(listed.len(), 3);
+        // Both in listing order.
+        for (dir, children) in listed {
+            let got: Vec<_> = children
+                .iter()
+                .map(|c| (&c.name, c.mtime, c.kind))
+                .collect();
+            let read = dirstamp::scan::read_children(&dir).unwrap();
+            let want: Vec<_> = read.iter().map(|c| (&c.name, c.mtime, c.kind)).collect();
+            assert_eq!(got, want, "{}", dir.display());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// src/watch.rs
+// --watch: filesystem notifications for the tree, gathered into batches once
+// they have been quiet for the debounce time, so a large copy ends up as one
+// restamp instead of thousands. Overflows and watcher errors become rescans.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use crate::interrupt;
+
+/// `--debounce` unless given.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often a wait for events looks for Ctrl-C.
+const POLL: Duration = Duration::from_millis(200);
+
+/// What a burst of events asks for.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Batch {
+    /// Folders whose children changed: the parents of the changed entries.
+    pub dirs: BTreeSet<PathBuf>,
+    /// Subtrees to walk again because events for them were lost.
+    pub rescan: BTreeSet<PathBuf>,
+}
+
+pub struct Watcher {
+    /// Kept alive for the notifications; dropping it stops them.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    root: PathBuf,
+    /// Some backends report canonical paths; they are turned back into `root`'s spelling.
+    canonical_root: PathBuf,
+    debounce: Duration,
+    /// Folders dirstamp just set, whose own mtime events are echoes.
+    ours: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    pub fn new(root: &Path, debounce: Duration) -> notify::Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        Ok(Watcher {
+            _watcher: watcher,
+            events,
+            root: root.to_path_buf(),
+            canonical_root: fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf()),
+            debounce,
+            ours: HashSet::new(),
+        })
+    }
+
+    /// Don't treat the coming mtime events for `dirs` as changes: they are
+    /// dirstamp's own writes.
+    pub fn ignore(&mut self, dirs: Vec<PathBuf>) {
+        self.ours = dirs.into_iter().collect();
+    }
+
+    /// Wait for events, then until none has arrived for the debounce time.
+    /// `None` once Ctrl-C is pressed or the watcher has stopped.
+    pub fn next_batch(&mut self) -> Option<Batch> {
+        let mut batch = Batch::default();
+        let mut last: Option<Instant> = None;
+        loop {
+            if interrupt::requested() {
+                return None;
+            }
+            let wait = match last {
+                None => POLL,
+                Some(t) if t.elapsed() >= self.debounce => {
+                    self.ours.clear();
+                    return Some(batch);
+                }
+                Some(t) => self.debounce.saturating_sub(t.elapsed()).min(POLL),
+            };
+            let added = match self.events.recv_timeout(wait) {
+                Ok(Ok(event)) => self.add_event(&mut batch, event),
+                Ok(Err(e)) => {
+                    eprintln!("warning: watch error ({e}); rescanning");
+                    self.add_rescan(&mut batch, &e.paths);
+                    true
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => {
+                    eprintln!("warning: the filesystem watcher stopped");
+                    return None;
+                }
+            };
+            if added {
+                last = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Whether `event` added anything to `batch`.
+    fn add_event(&self, batch: &mut Batch, event: Event) -> bool {
+        if event.need_rescan() {
+            self.add_rescan(batch, &event.paths);
+            return true;
+        }
+        let echo_kind = matches!(
+            event.kind,
+            EventKind::Modify(ModifyKind::Metadata(_) | ModifyKind::Any)
+        );
+        match event.kind {
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {}
+            _ => return false,
+        }
+        let mut added = false;
+        for path in &event.paths {
+            let Some(path) = self.spelled(path) else {
+                continue;
+            };
+            if echo_kind && self.ours.contains(&path) {
+                continue;
+            }
+            if let Some(parent) = path.parent().filter(|p| p.starts_with(&self.root)) {
+                added |= batch.dirs.insert(parent.to_path_buf());
+            }
+        }
+        added
+    }
+
+    /// With no paths to go on, the whole tree.
+    fn add_rescan(&self, batch: &mut Batch, paths: &[PathBuf]) {
+        let dirs: Vec<PathBuf> = paths.iter().filter_map(|p| self.spelled(p)).collect();
+        if dirs.is_empty() {
+            batch.rescan.insert(self.root.clone());
+        }
+        for dir in dirs {
+            // A file's folder, or a folder that's gone: rescan from its parent.
+            let dir = if dir.is_dir() {
+                dir
+            } else {
+                dir.parent().map_or(self.root.clone(), Path::to_path_buf)
+            };
+            batch.rescan.insert(dir);
+        }
+    }
+
+    /// `path` as the walk would spell it, if it is under the root.
+    fn spelled(&self, path: &Path) -> Option<PathBuf> {
+        if path.starts_with(&self.root) {
+            return Some(path.to_path_buf());
+        }
+        let rel = path.strip_prefix(&self.canonical_root).ok()?;
+        Some(if rel.as_os_str().is_empty() {
+            self.root.clone()
+        } else {
+            self.root.join(rel)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use notify::event::{CreateKind, MetadataKind};
+
+    #[test]
+    fn events_become_parent_folders_and_echoes_are_dropped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let mut watcher = Watcher::new(root, Duration::from_millis(10)).unwrap();
+        watcher.ignore(vec![root.join("a")]);
+        let mut batch = Batch::default();
+
+        let created = Event::new(EventKind::Create(CreateKind::File)).add_path(root.join("a/x"));
+        assert!(watcher.add_event(&mut batch, created));
+        let echo = Event::new(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)))
+            .add_path(root.join("a"));
+        assert!(!watcher.add_event(&mut batch, echo));
+        let outside =
+            Event::new(EventKind::Create(CreateKind::File)).add_path("/elsewhere/y".into());
+        assert!(!watcher.add_event(&mut batch, outside));
+        assert_eq!(batch.dirs, BTreeSet::from([root.join("a")]));
+
+        let lost = Event::new(EventKind::Other).set_flag(notify::event::Flag::Rescan);
+        assert!(watcher.add_event(&mut batch, lost));
+        assert_eq!(batch.rescan, BTreeSet::from([root.to_path_buf()]));
+    }
+
+    #[test]
+    fn a_burst_of_changes_is_one_batch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        let mut watcher = Watcher::new(root, Duration::from_millis(300)).unwrap();
+        for i in 0..20 {
+            fs::write(root.join(format!("sub/{i}")), "").unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+        let batch = watcher.next_batch().unwrap();
+        assert!(batch.dirs.contains(&root.join("sub")));
+        assert!(batch.rescan.is_empty());
+    }
+}
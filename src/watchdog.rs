@@ -0,0 +1,166 @@
+// src/watchdog.rs
+// --dir-timeout: filesystem calls made on a worker thread that is abandoned
+// when a folder doesn't answer in time (a dead DFS link, a hung NFS server).
+// std's blocking calls can't be cancelled, so an abandoned worker stays stuck
+// until its call returns, if ever; the run carries on with a fresh one.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, SendError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::report::truncate_middle;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// How long a folder may keep the run waiting before the status line names it.
+const STATUS_AFTER: Duration = Duration::from_secs(1);
+
+pub struct Watchdog {
+    timeout: Duration,
+    /// Feeds the current worker; `None` until the first call, or after the
+    /// worker was abandoned.
+    worker: Option<Sender<Job>>,
+    /// Name a slow folder on stderr's status line (stderr is a terminal).
+    status: bool,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, status: bool) -> Self {
+        Watchdog {
+            timeout,
+            worker: None,
+            status,
+        }
+    }
+
+    /// `op(path)` on the worker. A call that doesn't finish within the
+    /// timeout fails with an error `is_timeout` recognises.
+    pub fn call<T: Send + 'static>(
+        &mut self,
+        path: &Path,
+        op: fn(&Path) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let (tx, rx) = mpsc::channel();
+        let owned = path.to_path_buf();
+        let mut job: Job = Box::new(move || {
+            let _ = tx.send(op(&owned));
+        });
+        // A worker only goes away by panicking; start another then.
+        loop {
+            let worker = self.worker.get_or_insert_with(spawn_worker);
+            match worker.send(job) {
+                Ok(()) => break,
+                Err(SendError(unsent)) => {
+                    job = unsent;
+                    self.worker = None;
+                }
+            }
+        }
+        let started = Instant::now();
+        let mut shown = false;
+        let answer = loop {
+            let left = self.timeout.saturating_sub(started.elapsed());
+            let wait = if self.status {
+                left.min(STATUS_AFTER)
+            } else {
+                left
+            };
+            match rx.recv_timeout(wait) {
+                Ok(answer) => break Some(answer),
+                Err(mpsc::RecvTimeoutError::Timeout) if wait < left => {
+                    show_status(path, started.elapsed());
+                    shown = true;
+                }
+                Err(_) => break None,
+            }
+        };
+        if shown {
+            eprint!("\r\x1b[K");
+        }
+        answer.unwrap_or_else(|| {
+            self.worker = None;
+            Err(io::Error::other(TimedOut(self.timeout)))
+        })
+    }
+}
+
+/// `waiting 12s on /mnt/dfs/…/projects`, in place of the previous status.
+fn show_status(path: &Path, waited: Duration) {
+    let width = terminal_size::terminal_size().map_or(80, |(w, _)| usize::from(w.0));
+    let prefix = format!("waiting {}s on ", waited.as_secs());
+    let path = truncate_middle(
+        &path.display().to_string(),
+        width.saturating_sub(prefix.len() + 1).max(12),
+    );
+    eprint!("\r\x1b[K{prefix}{path}");
+}
+
+fn spawn_worker() -> Sender<Job> {
+    let (tx, rx) = mpsc::channel::<Job>();
+    thread::spawn(move || {
+        for job in rx {
+            job();
+        }
+    });
+    tx
+}
+
+/// Whether `e` is a `--dir-timeout` expiring.
+pub fn is_timeout(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<TimedOut>())
+}
+
+/// Of kind `Other`, so `--retries` doesn't wait on a dead folder all over again.
+#[derive(Debug)]
+struct TimedOut(Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no answer within {:?}, see --dir-timeout", self.0)
+    }
+}
+
+impl Error for TimedOut {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    /// Held by `stuck` so a call can be made to hang on demand.
+    static GATE: Mutex<()> = Mutex::new(());
+
+    fn stuck(_: &Path) -> io::Result<u32> {
+        drop(GATE.lock());
+        Ok(1)
+    }
+
+    #[test]
+    fn a_hung_call_is_abandoned_and_the_next_gets_a_new_worker() {
+        let mut watchdog = Watchdog::new(Duration::from_millis(50), false);
+        let gate = GATE.lock().unwrap();
+        let e = watchdog.call(Path::new("dead"), stuck).unwrap_err();
+        assert!(is_timeout(&e));
+        assert!(e.to_string().starts_with("no answer within 50ms"));
+
+        let answer = watchdog.call(Path::new("alive"), |p| Ok(p.to_path_buf()));
+        assert_eq!(answer.unwrap(), Path::new("alive"));
+        drop(gate);
+        assert!(!is_timeout(&io::Error::other("boom")));
+    }
+
+    #[test]
+    fn a_slow_call_shown_on_the_status_line_still_answers() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(30), true);
+        let slow = |_: &Path| {
+            thread::sleep(STATUS_AFTER + Duration::from_millis(200));
+            Ok(2)
+        };
+        assert_eq!(watchdog.call(Path::new("slow"), slow).unwrap(), 2);
+    }
+}
@@ -0,0 +1,387 @@
+use predicates::prelude::*;
+
+use crate::*;
+
+#[test]
+fn check_exits_1_when_stale() {
+    let tree = sample_tree();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--check")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("photos"));
+}
+
+#[test]
+fn check_exits_0_and_stays_quiet_when_current() {
+    let tree = sample_tree();
+    dirstamp().arg(tree.path()).arg("-C").assert().success();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--check")
+        .assert()
+        .code(0)
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn check_does_not_modify_the_tree() {
+    let tree = sample_tree();
+    let docs = tree.path().join("docs");
+    let before = mtime(&docs);
+
+    dirstamp().arg(tree.path()).arg("--check").assert().code(1);
+
+    assert_eq!(mtime(&docs), before);
+}
+
+/// 0 in sync, 1 with changes to make, 2 once a folder couldn't be read,
+/// even with changes elsewhere in the tree.
+#[cfg(unix)]
+#[test]
+fn check_exit_codes_follow_the_readme() {
+    let tree = sample_tree();
+    dirstamp().arg("check").arg(tree.path()).assert().code(1);
+    dirstamp().arg(tree.path()).arg("-C").assert().success();
+    dirstamp().arg("check").arg(tree.path()).assert().code(0);
+
+    // A link back to the root is a loop the walk can't go into.
+    std::os::unix::fs::symlink("..", tree.path().join("docs/loop")).unwrap();
+    touch(&tree.path().join("docs/new.txt"), T2020 + 20 * DAY);
+    dirstamp()
+        .arg("check")
+        .arg(tree.path())
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("docs"))
+        .stderr(predicate::str::contains("loop"));
+}
+
+#[test]
+fn command_words_do_what_the_old_flags_did() {
+    let tree = sample_tree();
+    let out = TempDir::new().unwrap();
+    let journal = out.path().join("undo.jsonl");
+
+    dirstamp()
+        .arg("check")
+        .arg(tree.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::is_empty());
+    dirstamp()
+        .arg(tree.path())
+        .arg("--check")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "--check is deprecated; use dirstamp check [PATH]",
+        ));
+    dirstamp()
+        .arg("stamp")
+        .arg(tree.path())
+        .arg("-C")
+        .arg("--journal")
+        .arg(&journal)
+        .assert()
+        .success();
+    dirstamp().arg("check").arg(tree.path()).assert().code(0);
+    dirstamp()
+        .arg("undo")
+        .arg(&journal)
+        .args(["-C", "--no-journal"])
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+    dirstamp().arg("check").arg(tree.path()).assert().code(1);
+
+    dirstamp().args(["stamp", "--check"]).assert().code(2);
+    dirstamp().arg("undo").assert().code(2);
+}
+
+#[test]
+fn summary_only_prints_the_count_and_exits_1_for_changes() {
+    let tree = sample_tree();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--summary-only")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::starts_with(
+            "4 folder timestamp(s) needed updating.\n",
+        ))
+        .stdout(predicate::str::contains("would update").not());
+    dirstamp()
+        .arg(tree.path())
+        .args(["--summary-only", "-C"])
+        .assert()
+        .code(1);
+    dirstamp()
+        .arg(tree.path())
+        .arg("--summary-only")
+        .assert()
+        .success()
+        .stdout("No folder timestamps needed updating.\n");
+    dirstamp()
+        .args(["--summary-only", "--quiet"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn usage_errors_exit_2() {
+    dirstamp().arg("--no-such-flag").assert().code(2);
+    dirstamp().args(["--check", "--confirm"]).assert().code(2);
+    dirstamp().args(["--no-act", "-C"]).assert().code(2);
+    dirstamp().args(["--retry-delay", "1s"]).assert().code(2);
+    dirstamp().arg("--json").assert().code(2);
+    dirstamp()
+        .args(["--time-style=+["])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("without a closing ]"));
+    dirstamp()
+        .args(["--time-style", "+[year]"])
+        .assert()
+        .code(2);
+    dirstamp()
+        .args(["--touch-symlinks", "-C"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("needs --include-type l"));
+    dirstamp()
+        .args(["--retries", "2", "--retry-delay", "1h"])
+        .assert()
+        .code(2);
+    dirstamp()
+        .arg("/definitely/not/a/real/path")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Path does not exist"));
+    let file = tempfile::NamedTempFile::new().unwrap();
+    dirstamp()
+        .arg(file.path())
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Not a directory"));
+}
+
+#[test]
+fn doctor_reports_and_leaves_nothing_behind() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dirstamp()
+        .args([
+            "--doctor".as_ref(),
+            dir.path().as_os_str(),
+            "--json".as_ref(),
+        ])
+        .assert()
+        .code(0)
+        .get_output()
+        .stdout
+        .clone();
+    let findings: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(findings["round_trip"], true);
+    assert_eq!(
+        findings["recommended_tolerance_ns"],
+        findings["granularity_ns"]
+    );
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    dirstamp()
+        .arg("--doctor")
+        .arg(dir.path())
+        .assert()
+        .code(0)
+        .stdout(predicate::str::contains("Recommended tolerance: "));
+    dirstamp()
+        .args(["--doctor", "/definitely/not/a/real/path"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("Cannot test timestamps"));
+}
+
+#[test]
+fn interactive_refuses_without_a_terminal() {
+    let tree = sample_tree();
+    dirstamp()
+        .arg(tree.path())
+        .arg("--interactive")
+        .write_stdin("y\n")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("needs a terminal"));
+    dirstamp()
+        .arg(tree.path())
+        .args(["--interactive", "--check"])
+        .assert()
+        .code(2);
+}
+
+#[cfg(not(feature = "otel"))]
+#[test]
+fn otel_needs_the_feature_but_the_endpoint_alone_is_ignored() {
+    let tree = sample_tree();
+    dirstamp()
+        .arg(tree.path())
+        .arg("--otel")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--features otel"));
+    dirstamp()
+        .arg(tree.path())
+        .env("OTEL_EXPORTER_OTLP_ENDPOINT", "http://127.0.0.1:4318")
+        .assert()
+        .code(0);
+}
+
+#[test]
+fn unknown_format_version_lists_the_available_ones() {
+    let tree = sample_tree();
+    dirstamp()
+        .arg(tree.path())
+        .args(["--format-version", "1"])
+        .assert()
+        .code(0);
+    dirstamp()
+        .arg(tree.path())
+        .args(["--format-version", "9"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("available: 1"));
+}
+
+#[test]
+fn dry_run_exit_code_signals_pending_changes() {
+    let tree = sample_tree();
+    dirstamp()
+        .arg(tree.path())
+        .args(["--dry-run-exit-code", "2"])
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("would update"));
+    dirstamp()
+        .arg(tree.path())
+        .args(["--dry-run-exit-code=0"])
+        .assert()
+        .code(0);
+    dirstamp()
+        .arg("check")
+        .arg(tree.path())
+        .args(["--dry-run-exit-code", "9"])
+        .assert()
+        .code(1);
+
+    dirstamp().arg(tree.path()).arg("-C").assert().success();
+    dirstamp()
+        .arg(tree.path())
+        .args(["--dry-run-exit-code", "9"])
+        .assert()
+        .code(0);
+}
+
+#[test]
+fn find_newest_prints_the_child_a_stamp_would_use() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let before = mtime(&photos);
+
+    dirstamp()
+        .arg("--find-newest")
+        .arg(&photos)
+        .arg("--epoch")
+        .assert()
+        .success()
+        .stdout(format!("{}\tb.jpg\n", T2020 + 4 * DAY));
+    dirstamp()
+        .args(["find-newest", "--oldest", "--epoch"])
+        .arg(&photos)
+        .assert()
+        .success()
+        .stdout(format!("{}\ta.jpg\n", T2020 + 2 * DAY));
+    assert_eq!(mtime(&photos), before);
+
+    dirstamp()
+        .arg("--find-newest")
+        .arg(tree.path().join("empty"))
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty());
+    dirstamp()
+        .arg("--find-newest")
+        .arg(tree.path().join("missing"))
+        .assert()
+        .code(2);
+
+    // A sidecar copied in later doesn't become the folder's newest child.
+    touch(&photos.join("._b.jpg"), T2020 + 9 * DAY);
+    dirstamp()
+        .args(["find-newest", "--epoch"])
+        .arg(&photos)
+        .assert()
+        .stdout(format!("{}\t._b.jpg\n", T2020 + 9 * DAY));
+    dirstamp()
+        .args(["find-newest", "--ignore-dot-underscore", "--epoch"])
+        .arg(&photos)
+        .assert()
+        .success()
+        .stdout(format!("{}\tb.jpg\n", T2020 + 4 * DAY));
+}
+
+#[test]
+fn sparse_judges_a_sample_and_stamps_nothing() {
+    let tree = sample_tree();
+    let before = mtime(tree.path());
+
+    // A sample as big as the tree is the whole tree, whatever the seed. Of
+    // the 4 a run would stamp, 2 only follow from their subfolders' stamps,
+    // which a sample doesn't make first.
+    dirstamp()
+        .arg(tree.path())
+        .args(["--sparse", "100"])
+        .assert()
+        .success()
+        .stdout("Sampled 5 of 5 dirs: 40% need updating (estimated 2 total)\n");
+    assert_eq!(mtime(tree.path()), before);
+    dirstamp()
+        .arg(tree.path())
+        .args(["--sparse", "2", "-C"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("can't be combined with -C"));
+    dirstamp()
+        .arg(tree.path())
+        .args(["--sparse", "0"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Invalid --sparse: 0"));
+}
+
+/// Run dirstamp with `args` on a stdout that is closed before it writes.
+fn with_closed_stdout(args: &[&str]) -> std::process::Output {
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin("dirstamp"))
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn a_closed_stdout_ends_the_summary_quietly() {
+    let tree = sample_tree();
+    let root = tree.path().to_str().unwrap();
+    for args in [&[root][..], &["--tree", root]] {
+        let out = with_closed_stdout(args);
+        assert!(out.status.success(), "{out:?}");
+        assert!(!String::from_utf8_lossy(&out.stderr).contains("panicked"));
+    }
+}
@@ -0,0 +1,80 @@
+// tests/integration/main.rs
+// End-to-end tests: build a small tree in a temp dir, run the real binary.
+
+mod check;
+mod plan;
+mod stamp;
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use assert_cmd::Command;
+use filetime::{set_file_mtime, FileTime};
+use tempfile::TempDir;
+
+/// 2020-01-01 00:00:00 UTC, a convenient "old" timestamp.
+pub const T2020: u64 = 1_577_836_800;
+pub const DAY: u64 = 86_400;
+
+pub fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+pub fn set_mtime(path: &Path, secs: u64) {
+    set_file_mtime(path, FileTime::from_system_time(at(secs))).unwrap();
+}
+
+pub fn mtime(path: &Path) -> SystemTime {
+    fs::metadata(path).unwrap().modified().unwrap()
+}
+
+pub fn touch(path: &Path, secs: u64) {
+    fs::write(path, b"").unwrap();
+    set_mtime(path, secs);
+}
+
+pub fn dirstamp() -> Command {
+    let mut cmd = Command::cargo_bin("dirstamp").unwrap();
+    // Keep -C runs' undo journals out of the real state directory.
+    cmd.env(
+        "XDG_STATE_HOME",
+        std::env::temp_dir().join("dirstamp-test-state"),
+    );
+    cmd
+}
+
+/// ```text
+/// root/
+///   docs/     report.txt (2020-01-01)
+///   empty/    (no children, mtime 2020-01-11)
+///   media/
+///     photos/ a.jpg (2020-01-03), b.jpg (2020-01-05)
+/// ```
+/// Directory mtimes are left at "now", so everything but `empty` is stale.
+pub fn sample_tree() -> TempDir {
+    let tmp = TempDir::new().unwrap();
+    let root = tmp.path();
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::create_dir_all(root.join("empty")).unwrap();
+    fs::create_dir_all(root.join("media/photos")).unwrap();
+    touch(&root.join("docs/report.txt"), T2020);
+    touch(&root.join("media/photos/a.jpg"), T2020 + 2 * DAY);
+    touch(&root.join("media/photos/b.jpg"), T2020 + 4 * DAY);
+    set_mtime(&root.join("empty"), T2020 + 10 * DAY);
+    tmp
+}
+
+/// A tree holding one stale folder whose name is Latin-1 `café`, which isn't
+/// valid UTF-8. Returns the tree and that folder.
+#[cfg(unix)]
+pub fn latin1_tree() -> (TempDir, std::path::PathBuf) {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    let tmp = TempDir::new().unwrap();
+    let cafe = tmp.path().join(OsString::from_vec(b"caf\xe9".to_vec()));
+    fs::create_dir(&cafe).unwrap();
+    touch(&cafe.join("menu.txt"), T2020);
+    (tmp, cafe)
+}
@@ -0,0 +1,205 @@
+use predicates::prelude::*;
+use tempfile::TempDir;
+
+use crate::*;
+
+/// Write a plan for `tree` into a separate temp dir (so it isn't a child).
+fn make_plan(tree: &TempDir) -> (TempDir, std::path::PathBuf) {
+    let out = TempDir::new().unwrap();
+    let plan = out.path().join("plan.json");
+    dirstamp()
+        .arg(tree.path())
+        .arg("--plan")
+        .arg(&plan)
+        .assert()
+        .success();
+    (out, plan)
+}
+
+#[test]
+fn plan_then_apply_matches_confirm() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let before = mtime(&photos);
+    let (_out, plan) = make_plan(&tree);
+
+    // Writing the plan is read-only, and so is applying it without -C.
+    assert_eq!(mtime(&photos), before);
+    dirstamp()
+        .arg("--apply-plan")
+        .arg(&plan)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would update"));
+    assert_eq!(mtime(&photos), before);
+
+    dirstamp()
+        .arg("--apply-plan")
+        .arg(&plan)
+        .arg("-C")
+        .assert()
+        .success();
+    assert_eq!(mtime(&photos), at(T2020 + 4 * DAY));
+    assert_eq!(mtime(&tree.path().join("media")), at(T2020 + 4 * DAY));
+    assert_eq!(mtime(&tree.path().join("docs")), at(T2020));
+}
+
+#[test]
+fn folders_changed_since_plan_are_skipped_unless_forced() {
+    let tree = sample_tree();
+    let docs = tree.path().join("docs");
+    let (_out, plan) = make_plan(&tree);
+    set_mtime(&docs, T2020 + 100 * DAY);
+
+    dirstamp()
+        .arg("--apply-plan")
+        .arg(&plan)
+        .arg("-C")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("skipped (changed since plan)"));
+    assert_eq!(mtime(&docs), at(T2020 + 100 * DAY));
+    assert_eq!(
+        mtime(&tree.path().join("media/photos")),
+        at(T2020 + 4 * DAY)
+    );
+
+    dirstamp()
+        .arg("--apply-plan")
+        .arg(&plan)
+        .args(["-C", "--plan-force"])
+        .assert()
+        .success();
+    assert_eq!(mtime(&docs), at(T2020));
+}
+
+#[cfg(unix)]
+#[test]
+fn plan_keeps_non_utf8_paths_exact() {
+    let (tree, cafe) = latin1_tree();
+    let (_out, plan) = make_plan(&tree);
+
+    let json = std::fs::read_to_string(&plan).unwrap();
+    assert!(json.contains(r#""bytes": ["#), "{json}");
+
+    dirstamp()
+        .arg("--apply-plan")
+        .arg(&plan)
+        .arg("-C")
+        .assert()
+        .success();
+    assert_eq!(mtime(&cafe), at(T2020));
+}
+
+#[test]
+fn pretend_time_makes_plans_reproducible() {
+    let tree = sample_tree();
+    let out = TempDir::new().unwrap();
+    let write_plan = |name: &str| {
+        let plan = out.path().join(name);
+        dirstamp()
+            .arg(tree.path())
+            .args(["--pretend-time", "2020-01-01T00:00:00Z", "--plan"])
+            .arg(&plan)
+            .assert()
+            .success();
+        std::fs::read(plan).unwrap()
+    };
+    let first = write_plan("a.json");
+    assert_eq!(first, write_plan("b.json"));
+    assert!(String::from_utf8(first)
+        .unwrap()
+        .contains(r#""created": "2020-01-01T00:00:00Z""#));
+
+    for bad in ["2020-02-30", "yesterday", "@-1"] {
+        dirstamp()
+            .args(["--pretend-time", bad])
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("Invalid --pretend-time"));
+    }
+}
+
+#[test]
+fn baseline_hides_drift_it_already_records() {
+    let tree = sample_tree();
+    let out = TempDir::new().unwrap();
+    let baseline = out.path().join("baseline.jsonl");
+    let run = || {
+        let mut cmd = dirstamp();
+        cmd.arg(tree.path()).arg("--baseline").arg(&baseline);
+        cmd
+    };
+
+    // No baseline yet: everything is new, and the run records it.
+    run()
+        .arg("--update-baseline")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("photos"));
+    run()
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("would update")
+                .not()
+                .and(predicate::str::contains(
+                    "4 change(s) unchanged since the baseline",
+                )),
+        );
+
+    // A new file moves the target of docs (and only docs).
+    touch(&tree.path().join("docs/new.txt"), T2020 + DAY);
+    run().assert().success().stdout(
+        predicate::str::contains("docs")
+            .and(predicate::str::contains("photos").not())
+            .and(predicate::str::contains("3 change(s) unchanged")),
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn the_library_defaults_plan_what_the_cli_does() {
+    let tree = sample_tree();
+    // A folder outside the tree, linked into it: both walk into it.
+    let outside = TempDir::new().unwrap();
+    let linked = outside.path().join("scans");
+    fs::create_dir(&linked).unwrap();
+    touch(&linked.join("page.png"), T2020 + 30 * DAY);
+    std::os::unix::fs::symlink(&linked, tree.path().join("docs/scans")).unwrap();
+
+    let out = dirstamp()
+        .arg(tree.path())
+        .arg("--porcelain")
+        .output()
+        .unwrap();
+    // Porcelain lines are status, from, to and the path, tab-separated.
+    let mut cli: Vec<(String, u64)> = String::from_utf8(out.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let fields: Vec<_> = line.split('\t').collect();
+            (fields[3].to_string(), fields[2].parse().unwrap())
+        })
+        .collect();
+    cli.sort();
+
+    let options = dirstamp::Options::builder()
+        .root(tree.path())
+        .build()
+        .unwrap();
+    let plan = dirstamp::plan(&options).unwrap();
+    let mut library: Vec<(String, u64)> = plan
+        .changes
+        .iter()
+        .map(|c| {
+            let to = c.to.duration_since(UNIX_EPOCH).unwrap().as_secs();
+            (c.path.display().to_string(), to)
+        })
+        .collect();
+    library.sort();
+
+    let scans = tree.path().join("docs/scans").display().to_string();
+    assert!(cli.contains(&(scans, T2020 + 30 * DAY)), "{cli:?}");
+    assert_eq!(library, cli);
+}
@@ -0,0 +1,1478 @@
+use predicates::prelude::*;
+use tempfile::TempDir;
+
+use crate::*;
+
+#[test]
+fn dry_run_reports_without_changing_anything() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let before = mtime(&photos);
+
+    dirstamp()
+        .arg(tree.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would update"))
+        .stdout(predicate::str::contains("photos"))
+        .stdout(predicate::str::contains("this was a dry run"));
+
+    assert_eq!(mtime(&photos), before);
+}
+
+#[test]
+fn confirm_sets_mtime_to_newest_file() {
+    let tree = sample_tree();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--confirm")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("updated"));
+
+    assert_eq!(mtime(&tree.path().join("docs")), at(T2020));
+    assert_eq!(
+        mtime(&tree.path().join("media/photos")),
+        at(T2020 + 4 * DAY)
+    );
+}
+
+#[test]
+fn parent_is_stamped_after_child() {
+    let tree = sample_tree();
+
+    dirstamp().arg(tree.path()).arg("-C").assert().success();
+
+    // media/ has no files, so it takes photos/'s *new* mtime.
+    assert_eq!(mtime(&tree.path().join("media")), at(T2020 + 4 * DAY));
+}
+
+#[test]
+fn empty_dirs_are_unchanged() {
+    let tree = sample_tree();
+
+    dirstamp().arg(tree.path()).arg("-C").assert().success();
+
+    assert_eq!(mtime(&tree.path().join("empty")), at(T2020 + 10 * DAY));
+}
+
+#[test]
+fn second_run_has_nothing_to_do() {
+    let tree = sample_tree();
+
+    dirstamp().arg(tree.path()).arg("-C").assert().success();
+    dirstamp()
+        .arg(tree.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "No folder timestamps needed updating.",
+        ));
+}
+
+#[test]
+fn show_skipped_gives_reasons() {
+    let tree = sample_tree();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--show-skipped")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("skipped empty").and(predicate::str::contains("empty\"")));
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--show-skipped=errors")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("skipped").not());
+}
+
+#[test]
+fn tree_marks_every_folder_without_changing_any() {
+    let tree = sample_tree();
+    let docs = tree.path().join("docs");
+    let before = mtime(&docs);
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--tree")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("├── docs [UPDATE -")
+                .and(predicate::str::contains("├── empty [EMPTY]\n"))
+                .and(predicate::str::contains("└── media [UPDATE -"))
+                .and(predicate::str::contains("    └── photos [UPDATE -"))
+                .and(predicate::str::contains("would update").not()),
+        );
+    assert_eq!(mtime(&docs), before);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--tree", "-C"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn git_changed_visits_only_touched_folders() {
+    let tree = sample_tree();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(tree.path())
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?}");
+    };
+    git(&["init", "-q"]);
+    git(&["add", "docs"]);
+    git(&["commit", "-q", "-m", "docs"]);
+    git(&["add", "media"]);
+    git(&["commit", "-q", "-m", "media"]);
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--git-changed")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("photos").and(predicate::str::contains("docs").not()));
+    touch(&tree.path().join("docs/draft.txt"), T2020 + 20 * DAY);
+    dirstamp()
+        .arg(tree.path())
+        .arg("--git-changed=worktree")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("docs").and(predicate::str::contains("photos").not()));
+    // The device filters hold for the changed folders too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let dev = std::fs::metadata(tree.path()).unwrap().dev().to_string();
+        dirstamp()
+            .arg(tree.path())
+            .args(["--exclude-device", &dev, "--git-changed=worktree"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("would update").not());
+    }
+
+    let outside = TempDir::new().unwrap();
+    dirstamp()
+        .arg(outside.path())
+        .arg("--git-changed")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("not in a git work tree"));
+}
+
+#[test]
+fn exclude_type_leaves_children_out() {
+    let tree = sample_tree();
+    let root = format!("skipped empty {:?}\n", tree.path());
+    dirstamp()
+        .arg(tree.path())
+        .args(["--exclude-type", "f", "--show-skipped"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("photos\"\n").and(predicate::str::contains(
+                "No folder timestamps needed updating.",
+            )),
+        );
+    dirstamp()
+        .arg(tree.path())
+        .args(["--exclude-type=d", "--show-skipped"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(root)
+                .and(predicate::str::contains("would update"))
+                .and(predicate::str::contains("docs\"")),
+        );
+    dirstamp()
+        .arg(tree.path())
+        .args(["--exclude-type", "x"])
+        .assert()
+        .code(2);
+}
+
+#[cfg(unix)]
+#[test]
+fn include_type_makes_symlinks_count() {
+    let stamped_docs = |args: &[&str]| {
+        let tree = sample_tree();
+        let docs = tree.path().join("docs");
+        let link = docs.join("link");
+        std::os::unix::fs::symlink("report.txt", &link).unwrap();
+        let t = filetime::FileTime::from_system_time(at(T2020 + 30 * DAY));
+        filetime::set_symlink_file_times(&link, t, t).unwrap();
+        dirstamp()
+            .arg(tree.path())
+            .args(["-C", "--no-journal"])
+            .args(args)
+            .assert()
+            .success();
+        mtime(&docs)
+    };
+    assert_eq!(stamped_docs(&[]), at(T2020));
+    assert_eq!(stamped_docs(&["--include-type", "l"]), at(T2020 + 30 * DAY));
+    assert_eq!(
+        stamped_docs(&["--include-type=f", "--include-type=l", "--exclude-type=l"]),
+        at(T2020)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn watch_restamps_changed_folders_until_interrupted() {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("dirstamp"))
+        .arg(tree.path())
+        .args(["-C", "--no-journal", "--watch", "--debounce", "200ms"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(800));
+    assert_eq!(mtime(&photos), at(T2020 + 4 * DAY));
+
+    touch(&photos.join("c.jpg"), T2020 + 8 * DAY);
+    sleep(Duration::from_millis(1_500));
+    let killed = std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(mtime(&photos), at(T2020 + 8 * DAY));
+    assert_eq!(mtime(&tree.path().join("media")), at(T2020 + 8 * DAY));
+    // The root still follows `empty`, so it isn't touched again.
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(stdout.matches("updated").count(), 4 + 2, "{stdout}");
+}
+
+#[cfg(unix)]
+#[test]
+fn interval_repeats_runs_until_sigterm() {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    let tree = sample_tree();
+    let elsewhere = tempfile::tempdir().unwrap();
+    let log = elsewhere.path().join("interval.log");
+    let photos = tree.path().join("media/photos");
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("dirstamp"))
+        .arg(tree.path())
+        .args(["-C", "--no-journal", "--interval", "500ms", "--log-file"])
+        .arg(&log)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(800));
+    touch(&photos.join("c.jpg"), T2020 + 8 * DAY);
+    sleep(Duration::from_millis(1_200));
+    let killed = std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(mtime(&tree.path().join("media")), at(T2020 + 8 * DAY));
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        stdout.contains("run 1: 5 folder(s) scanned, 4 updated, 0 failed"),
+        "{stdout}"
+    );
+    assert!(stdout.contains(", 2 updated, "), "{stdout}");
+    // Summary lines instead of one line per folder.
+    assert!(!stdout.contains("photos"), "{stdout}");
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("run 1: "), "{logged}");
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_finishes_the_folder_in_hand_and_exits_130() {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    let tree = sample_tree();
+    let elsewhere = tempfile::tempdir().unwrap();
+    let journal = elsewhere.path().join("undo.jsonl");
+    let log = elsewhere.path().join("run.log");
+    // One set a second, so the run is still going when the signal comes.
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("dirstamp"))
+        .arg(tree.path())
+        .args(["-C", "--rate-limit", "1", "--journal"])
+        .arg(&journal)
+        .arg("--log-file")
+        .arg(&log)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(1_500));
+    let killed = std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(out.status.code(), Some(130), "{out:?}");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("Interrupted: stopped after"), "{stderr}");
+
+    // Every folder that was set is in the journal, one line each after the
+    // header, and the rest of the tree wasn't touched.
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let updated = stdout.matches("updated").count();
+    assert!((1..4).contains(&updated), "{stdout}");
+    let journaled = std::fs::read_to_string(&journal).unwrap();
+    let lines: Vec<serde_json::Value> = journaled
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 1 + updated, "{journaled}");
+    assert_ne!(mtime(tree.path()), at(T2020 + 10 * DAY));
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        logged.lines().last().unwrap().contains("interrupted: "),
+        "{logged}"
+    );
+}
+
+#[test]
+fn a_journal_that_cannot_be_written_stops_the_run_cleanly() {
+    let tree = sample_tree();
+    let elsewhere = tempfile::tempdir().unwrap();
+    let log = elsewhere.path().join("run.log");
+    let docs = tree.path().join("docs");
+    let before = mtime(&docs);
+
+    // Under a file, so the journal can't be created.
+    dirstamp()
+        .arg(tree.path())
+        .arg("-C")
+        .arg("--journal")
+        .arg(docs.join("report.txt/undo.jsonl"))
+        .arg("--log-file")
+        .arg(&log)
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains(
+            "No folder timestamps were changed.",
+        ))
+        .stderr(predicate::str::contains("Cannot write undo journal"));
+    assert_eq!(mtime(&docs), before);
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        logged.lines().last().unwrap().contains("stopped: "),
+        "{logged}"
+    );
+}
+
+#[test]
+fn median_is_not_moved_by_one_new_file() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    touch(&photos.join("c.jpg"), T2020 + 9 * DAY);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--no-journal", "--median"])
+        .assert()
+        .success();
+    assert_eq!(mtime(&photos), at(T2020 + 4 * DAY));
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--median", "--oldest"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("mutually exclusive"));
+}
+
+#[test]
+fn percentile_picks_from_the_sorted_children() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    touch(&photos.join("c.jpg"), T2020 + 6 * DAY);
+    touch(&photos.join("d.jpg"), T2020 + 9 * DAY);
+
+    // Four files at +2d, +4d, +6d, +9d: index (4 - 1) * 70 / 100 = 2.
+    dirstamp()
+        .arg(tree.path())
+        .args([
+            "-C",
+            "--no-journal",
+            "--percentile",
+            "0",
+            "--percentile",
+            "70",
+        ])
+        .assert()
+        .success();
+    assert_eq!(mtime(&photos), at(T2020 + 6 * DAY));
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--percentile", "101"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Invalid --percentile: 101"));
+    dirstamp()
+        .arg(tree.path())
+        .args(["--median", "--percentile", "70"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("mutually exclusive"));
+}
+
+#[test]
+fn every_way_of_walking_plans_and_stamps_the_same_changes() {
+    let tree = sample_tree();
+    // Sorted, since a streamed run reports each folder as the walk leaves it.
+    let planned = |extra: &[&str]| {
+        let out = dirstamp()
+            .arg(tree.path())
+            .args(["--porcelain", "--clamp-to-parent"])
+            .args(extra)
+            .output()
+            .unwrap();
+        let mut lines: Vec<_> = String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.sort();
+        lines
+    };
+    let streamed = planned(&[]);
+    assert!(!streamed.is_empty());
+    for extra in [&["--buffered"][..], &["--jobs", "4"], &["--shard"]] {
+        assert_eq!(planned(extra), streamed, "{extra:?}");
+    }
+    // And -C makes what the dry run planned.
+    let applied = planned(&["-C", "--no-journal", "--jobs", "4"]);
+    assert_eq!(applied.len(), streamed.len());
+    assert!(planned(&[]).is_empty());
+}
+
+#[test]
+fn jobs_report_and_stamp_as_one_thread_does() {
+    let tree = sample_tree();
+    for i in 0..8u64 {
+        let dir = tree.path().join(format!("archive/{i}/inner"));
+        fs::create_dir_all(&dir).unwrap();
+        touch(&dir.join("f"), T2020 + i * DAY);
+    }
+    let report = |jobs: &str| {
+        let out = dirstamp()
+            .arg(tree.path())
+            .args(["--buffered", "--jobs", jobs])
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    };
+    // --jobs works a depth at a time, in the order --buffered visits.
+    let serial = report("1");
+    assert!(serial.contains("archive/7/inner"), "{serial}");
+    assert_eq!(report("4"), serial);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--no-journal", "--jobs", "4"])
+        .assert()
+        .success();
+    assert_eq!(mtime(&tree.path().join("archive")), at(T2020 + 7 * DAY));
+    assert_eq!(
+        mtime(&tree.path().join("media/photos")),
+        at(T2020 + 4 * DAY)
+    );
+    dirstamp()
+        .arg(tree.path())
+        .arg("--check")
+        .assert()
+        .success();
+}
+
+#[test]
+fn timing_shows_the_walk_reads_each_entry_once() {
+    let tree = sample_tree();
+    let calls = |extra: &[&str], what: &str| -> usize {
+        let out = dirstamp()
+            .arg(tree.path())
+            .arg("--timing")
+            .args(extra)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        let line = stderr
+            .lines()
+            .find(|l| l.trim_start().starts_with(what))
+            .unwrap_or_else(|| panic!("{stderr}"));
+        line.split_whitespace().nth(2).unwrap().parse().unwrap()
+    };
+    // The root, then the seven entries below it, each listed once.
+    assert_eq!(calls(&[], "metadata reads"), 8);
+    assert_eq!(calls(&[], "folder listings"), 5);
+    // --buffered walks first and scans each of the five folders again.
+    assert_eq!(calls(&["--buffered"], "folder listings"), 10);
+}
+
+#[cfg(unix)]
+#[test]
+fn cache_skips_listing_folders_unchanged_since_the_last_run() {
+    let tree = sample_tree();
+    let cache = tree.path().join("cache");
+    let tree_root = tree.path().join("media");
+    // Folders changed in the last two seconds aren't cached.
+    std::thread::sleep(Duration::from_millis(2_100));
+    let run = |cached: bool| {
+        let mut cmd = dirstamp();
+        cmd.arg(&tree_root).arg("--timing");
+        if cached {
+            cmd.arg("--cache").arg(&cache);
+        }
+        let out = cmd.output().unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        let count = |what: &str| -> usize {
+            let line = stderr
+                .lines()
+                .find(|l| l.trim_start().starts_with(what))
+                .unwrap_or_else(|| panic!("{stderr}"));
+            line.split_whitespace().nth(2).unwrap().parse().unwrap()
+        };
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        (count("folder listings"), count("metadata reads"), stdout)
+    };
+    let (listings, _, plain) = run(false);
+    assert_eq!(listings, 2);
+    assert_eq!(run(true).0, 2);
+    // Nothing changed: every entry is stat'd by name, and none is listed.
+    let (listings, reads, cached) = run(true);
+    assert_eq!((listings, reads), (0, 4));
+    assert_eq!(cached, plain);
+
+    // A new file changes its folder, which is listed again.
+    touch(&tree_root.join("photos/c.jpg"), T2020 + 20 * DAY);
+    let (_, _, plain) = run(false);
+    let (listings, _, cached) = run(true);
+    assert_eq!(listings, 1);
+    assert_eq!(cached, plain);
+    assert!(cached.contains("photos"), "{cached}");
+}
+
+#[test]
+fn copy_from_ignores_destination_children() {
+    let tree = sample_tree();
+    let empty = tree.path().join("empty");
+    let photos = tree.path().join("media/photos");
+
+    dirstamp()
+        .arg("--copy-from")
+        .arg(&empty)
+        .arg(&photos)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would set").and(predicate::str::contains("copied from")));
+    assert_ne!(mtime(&photos), at(T2020 + 10 * DAY));
+
+    dirstamp()
+        .arg("--copy-from")
+        .arg(&empty)
+        .arg(&photos)
+        .arg("-C")
+        .assert()
+        .success();
+    assert_eq!(mtime(&photos), at(T2020 + 10 * DAY));
+}
+
+#[test]
+fn show_dates_names_the_driving_child() {
+    let tree = sample_tree();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("-D")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("driven by b.jpg"))
+        .stdout(predicate::str::contains("driven by report.txt"));
+}
+
+#[test]
+fn report_html_is_written_in_dry_run() {
+    let tree = sample_tree();
+    let out = tempfile::TempDir::new().unwrap();
+    let report = out.path().join("report.html");
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--report-html")
+        .arg(&report)
+        .assert()
+        .success();
+
+    let html = std::fs::read_to_string(&report).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("dry run"));
+    assert!(html.contains("photos"));
+    assert!(!html.contains("src=") && !html.contains("href="));
+}
+
+#[test]
+fn compare_to_uses_reference_tree_mtimes() {
+    let tree = sample_tree();
+    let reference = sample_tree();
+    set_mtime(&reference.path().join("docs"), T2020 + 50 * DAY);
+    std::fs::remove_dir(reference.path().join("empty")).unwrap();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--compare-to")
+        .arg(reference.path())
+        .args(["-C", "--show-skipped"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("skipped not-in-reference"));
+
+    assert_eq!(mtime(&tree.path().join("docs")), at(T2020 + 50 * DAY));
+    assert_eq!(mtime(&tree.path().join("empty")), at(T2020 + 10 * DAY));
+}
+
+#[test]
+fn clamp_to_parent_caps_at_parent_mtime() {
+    let tree = sample_tree();
+    let docs = tree.path().join("docs");
+    set_mtime(tree.path(), T2020 - DAY);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--clamp-to-parent", "-C"])
+        .assert()
+        .success();
+
+    assert_eq!(mtime(&docs), at(T2020 - DAY));
+}
+
+#[test]
+fn propagate_restamps_ancestors_after_copy() {
+    // The tree sits in an outer folder, which --propagate must not reach.
+    let outer = TempDir::new().unwrap();
+    let root = outer.path().join("tree");
+    std::fs::create_dir(&root).unwrap();
+    let tree = sample_tree();
+    std::fs::rename(tree.path().join("empty"), root.join("empty")).unwrap();
+    std::fs::rename(tree.path().join("media"), root.join("media")).unwrap();
+    let empty = root.join("empty");
+    let photos = root.join("media/photos");
+    set_mtime(&root, T2020);
+    set_mtime(outer.path(), T2020);
+
+    dirstamp()
+        .arg("--copy-from")
+        .arg(&empty)
+        .arg(&photos)
+        .arg("--propagate")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("needs a PATH"));
+    dirstamp()
+        .arg(&root)
+        .arg("--copy-from")
+        .arg(&empty)
+        .arg(&photos)
+        .args(["--propagate", "-C"])
+        .assert()
+        .success();
+
+    // media/ has no files, so it follows photos/ up to its copied mtime,
+    // and so does the root; the folder above it is left alone.
+    assert_eq!(mtime(&photos), at(T2020 + 10 * DAY));
+    assert_eq!(mtime(&root.join("media")), at(T2020 + 10 * DAY));
+    assert_eq!(mtime(&root), at(T2020 + 10 * DAY));
+    assert_eq!(mtime(outer.path()), at(T2020));
+
+    dirstamp()
+        .arg(root.join("media"))
+        .arg("--copy-from")
+        .arg(&photos)
+        .arg(&empty)
+        .arg("--propagate")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("is not inside"));
+}
+
+#[cfg(unix)]
+#[test]
+fn device_filters_prune_the_walk() {
+    use std::os::unix::fs::MetadataExt;
+
+    let tree = sample_tree();
+    let dev = std::fs::metadata(tree.path()).unwrap().dev().to_string();
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--include-device", &dev])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would update"));
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--exclude-device", &dev, "--show-skipped"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("skipped other-device")
+                .and(predicate::str::contains("would update").not()),
+        );
+}
+
+#[cfg(unix)]
+#[test]
+fn escape_styles_print_non_utf8_names_losslessly() {
+    let (tree, _cafe) = latin1_tree();
+
+    dirstamp()
+        .arg(tree.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"caf\xE9""#));
+    dirstamp()
+        .arg(tree.path())
+        .arg("--escape=octal")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r"caf\351"));
+    dirstamp()
+        .arg(tree.path())
+        .args(["--escape", "never"])
+        .assert()
+        .success()
+        .stdout(predicate::function(|out: &[u8]| {
+            out.windows(4).any(|w| w == b"caf\xe9")
+        }));
+}
+
+#[test]
+fn dry_runs_are_byte_identical_and_sortable_by_path() {
+    let tree = sample_tree();
+    for name in ["zeta", "alpha", "mid"] {
+        let dir = tree.path().join(name);
+        std::fs::create_dir(&dir).unwrap();
+        touch(&dir.join("f.txt"), T2020);
+    }
+
+    let run = |extra: &[&str]| {
+        dirstamp()
+            .arg(tree.path())
+            .args(extra)
+            .output()
+            .unwrap()
+            .stdout
+    };
+    assert_eq!(run(&[]), run(&[]));
+
+    let sorted = String::from_utf8(run(&["--sort", "path"])).unwrap();
+    let paths: Vec<&str> = sorted
+        .lines()
+        .filter_map(|l| l.strip_prefix("would update "))
+        .collect();
+    let mut expected = paths.clone();
+    expected.sort();
+    assert_eq!(paths.len(), 7, "{sorted}");
+    assert_eq!(paths, expected);
+}
+
+#[test]
+fn log_file_records_each_change_and_rotates() {
+    let tree = sample_tree();
+    let out = tempfile::TempDir::new().unwrap();
+    let log = out.path().join("run.log");
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--log-file")
+        .arg(&log)
+        .assert()
+        .success();
+    let text = std::fs::read_to_string(&log).unwrap();
+    assert!(text.contains(" started: "), "{text}");
+    assert!(text.contains(" planned ") && text.contains("to 2020-01-05T00:00:00Z"));
+    assert!(text.contains(" finished: "), "{text}");
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--log-file")
+        .arg(&log)
+        .args(["--log-rotate", "1K", "--log-rotate-keep", "1"])
+        .assert()
+        .success();
+    assert!(out.path().join("run.log.1").exists());
+    assert!(!out.path().join("run.log.2").exists());
+
+    dirstamp().args(["--log-rotate", "1K"]).assert().code(2);
+}
+
+#[test]
+fn directory_filter_selects_changes() {
+    let tree = sample_tree();
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--directory-filter", "depth >= 2 AND name == 'photos'"])
+        .arg("--show-skipped")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("would update")
+                .count(1)
+                .and(predicate::str::contains("photos\""))
+                .and(predicate::str::contains("skipped filtered")),
+        );
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--directory-filter", "depth >"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Invalid --directory-filter"));
+}
+
+#[test]
+fn undo_restores_what_confirm_changed() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let docs = tree.path().join("docs");
+    let before = mtime(&photos);
+    let out = tempfile::TempDir::new().unwrap();
+    let journal = out.path().join("undo.jsonl");
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("-C")
+        .arg("--journal")
+        .arg(&journal)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Undo journal:"));
+    assert_eq!(mtime(&photos), at(T2020 + 4 * DAY));
+
+    // Someone touches docs afterwards; undo leaves it alone.
+    set_mtime(&docs, T2020 + 100 * DAY);
+
+    dirstamp()
+        .arg("--undo")
+        .arg(&journal)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would update"));
+    assert_eq!(mtime(&photos), at(T2020 + 4 * DAY));
+
+    dirstamp()
+        .arg("--undo")
+        .arg(&journal)
+        .args(["-C", "--no-journal"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("--undo-force"));
+    assert_eq!(mtime(&photos), before);
+    assert_eq!(mtime(&docs), at(T2020 + 100 * DAY));
+}
+
+#[test]
+fn undo_works_from_another_directory() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let before = mtime(&photos);
+    let out = tempfile::TempDir::new().unwrap();
+    let journal = out.path().join("undo.jsonl");
+
+    // A relative root, so the journal has to record where it really was.
+    dirstamp()
+        .current_dir(tree.path())
+        .args(["media", "-C", "--journal"])
+        .arg(&journal)
+        .assert()
+        .success();
+    assert_eq!(mtime(&photos), at(T2020 + 4 * DAY));
+
+    dirstamp()
+        .current_dir(out.path())
+        .arg("--undo")
+        .arg(&journal)
+        .args(["-C", "--no-journal"])
+        .assert()
+        .success();
+    assert_eq!(mtime(&photos), before);
+
+    // Entries that can't be restored fail the undo.
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--journal"])
+        .arg(out.path().join("again.jsonl"))
+        .assert()
+        .success();
+    std::fs::remove_dir_all(&photos).unwrap();
+    dirstamp()
+        .arg("--undo")
+        .arg(out.path().join("again.jsonl"))
+        .args(["-C", "--no-journal"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("mtime read failed"));
+}
+
+#[test]
+fn snapshot_restores_every_folder() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let before = mtime(&photos);
+    let out = tempfile::TempDir::new().unwrap();
+    let snap = out.path().join("snap.jsonl");
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--save-snapshot")
+        .arg(&snap)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Saved the mtimes of 5 folder(s)"));
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--no-journal"])
+        .assert()
+        .success();
+    assert_ne!(mtime(&photos), before);
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("--restore-snapshot")
+        .arg(&snap)
+        .arg("-C")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "4 of 5 folder(s) differ from the snapshot",
+        ));
+    assert_eq!(mtime(&photos), before);
+
+    // A snapshot only applies to the tree it was taken of.
+    dirstamp()
+        .arg(out.path())
+        .arg("--restore-snapshot")
+        .arg(&snap)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("was taken of"));
+}
+
+#[test]
+fn limit_stops_confirm_after_n_changes() {
+    let tree = sample_tree();
+    let docs = tree.path().join("docs");
+    let before = mtime(&docs);
+
+    // Deepest first, so media/photos is the one change made.
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--limit", "1"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains(
+            "1 change(s) made, 3 more not applied",
+        ));
+    assert_eq!(
+        mtime(&tree.path().join("media/photos")),
+        at(T2020 + 4 * DAY)
+    );
+    assert_eq!(mtime(&docs), before);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--limit", "0"])
+        .assert()
+        .code(4);
+    assert_eq!(mtime(&docs), before);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["--limit", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "only the first 1 of these 3 changes",
+        ));
+}
+
+#[test]
+fn verify_rereads_every_stamped_folder() {
+    let tree = sample_tree();
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--verify"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Verified 4 folder(s)"));
+    dirstamp().arg(tree.path()).arg("--verify").assert().code(2);
+}
+
+#[test]
+fn output_json_ends_with_a_summary() {
+    let tree = sample_tree();
+    let out = dirstamp()
+        .arg(tree.path())
+        .args(["--output-json", "-C"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let lines: Vec<serde_json::Value> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    assert_eq!(lines.len(), 5);
+    assert!(lines[..4]
+        .iter()
+        .all(|l| l["type"] == "change" && l["status"] == "updated"));
+    // Each folder as soon as the walk leaves it: docs is done first.
+    assert!(lines[0]["path"].as_str().unwrap().ends_with("docs"));
+    let summary = &lines[4];
+    assert_eq!(summary["type"], "summary");
+    assert_eq!(summary["scanned"], 5);
+    assert_eq!(summary["updated"], 4);
+    assert_eq!(summary["errors"], 0);
+    assert_eq!(summary["dry_run"], false);
+    assert!(summary["duration_ms"].is_u64());
+}
+
+#[test]
+fn timing_goes_into_the_json_summary() {
+    let tree = sample_tree();
+    let out = dirstamp()
+        .arg(tree.path())
+        .args(["--output-json", "--timing", "-C"])
+        .assert()
+        .success()
+        .get_output()
+        .clone();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    let timing = &summary["timing"];
+    assert_eq!(timing["listings"]["count"], 5);
+    assert_eq!(timing["sets"]["count"], 4);
+    assert!(timing["sets"]["avg_ms"].is_f64());
+    assert!(timing["planning_ms"].is_f64());
+    assert!(timing["slowest_listing"]["path"].is_string());
+    // The table still goes to stderr.
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("  planning "), "{stderr}");
+}
+
+#[test]
+fn current_sentinel_skips_the_run() {
+    let tree = sample_tree();
+    let out = TempDir::new().unwrap();
+    let sentinel = out.path().join("stamped");
+    touch(&sentinel, T2020 + 20 * DAY);
+    let run = || {
+        let mut cmd = dirstamp();
+        cmd.arg(tree.path())
+            .arg("--skip-if-stamped-file")
+            .arg(&sentinel);
+        cmd
+    };
+
+    // The folders themselves were created now, after the sentinel.
+    run()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would update"));
+
+    for dir in ["docs", "empty", "media/photos", "media", ""] {
+        set_mtime(&tree.path().join(dir), T2020);
+    }
+    run()
+        .assert()
+        .success()
+        .stdout("Already stamped (sentinel file is current)\n");
+
+    touch(&tree.path().join("docs/late.txt"), T2020 + 30 * DAY);
+    run()
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would update"));
+}
+
+#[test]
+fn newer_ref_leaves_folders_with_nothing_newer_alone() {
+    let tree = sample_tree();
+    let out = TempDir::new().unwrap();
+    let reference = out.path().join("last-run");
+    touch(&reference, T2020 + 3 * DAY);
+    let docs = tree.path().join("docs");
+    let before = mtime(&docs);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--show-skipped", "--newer-ref"])
+        .arg(&reference)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"not-newer\b.*docs").unwrap());
+
+    assert_eq!(mtime(&docs), before);
+    // b.jpg beats the reference, and media/ follows photos/ up.
+    assert_eq!(
+        mtime(&tree.path().join("media/photos")),
+        at(T2020 + 4 * DAY)
+    );
+    assert_eq!(mtime(&tree.path().join("media")), at(T2020 + 4 * DAY));
+}
+
+#[test]
+fn no_update_root_stamps_everything_but_the_top() {
+    let tree = sample_tree();
+    let root_before = mtime(tree.path());
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--show-skipped", "--no-update-root"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("top-level"));
+    assert_eq!(mtime(tree.path()), root_before);
+    assert_eq!(mtime(&tree.path().join("media")), at(T2020 + 4 * DAY));
+
+    // Two levels: media/ is left alone too, photos/ below it isn't.
+    let tree = sample_tree();
+    let media_before = mtime(&tree.path().join("media"));
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--no-update-top", "2"])
+        .assert()
+        .success();
+    assert_eq!(mtime(&tree.path().join("media")), media_before);
+    assert_eq!(
+        mtime(&tree.path().join("media/photos")),
+        at(T2020 + 4 * DAY)
+    );
+}
+
+#[test]
+fn shards_stamp_the_tree_as_one_run_would() {
+    let folders = ["", "docs", "empty", "media", "media/photos"];
+    let stamped = |extra: &[&str]| {
+        let tree = sample_tree();
+        dirstamp()
+            .arg(tree.path())
+            .arg("-C")
+            .args(extra)
+            .assert()
+            .success();
+        folders.map(|f| mtime(&tree.path().join(f)))
+    };
+    let whole = stamped(&[]);
+    assert_eq!(whole[0], at(T2020 + 10 * DAY));
+    assert_eq!(stamped(&["--shard"]), whole);
+    assert_eq!(stamped(&["--shard", "--jobs", "4"]), whole);
+    // One folder's worth: media/ is split into photos/ and itself.
+    assert_eq!(stamped(&["--max-memory", "256", "--buffered"]), whole);
+}
+
+#[test]
+fn shard_sorts_the_report_within_each_subtree() {
+    let tree = sample_tree();
+    touch(&tree.path().join("empty/new.txt"), T2020 + 20 * DAY);
+    let out = dirstamp()
+        .arg(tree.path())
+        .args(["--shard", "--porcelain", "--sort", "path"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let order: Vec<_> = stdout
+        .lines()
+        .map(|l| l.rsplit('\t').next().unwrap().to_string())
+        .collect();
+    let root = tree.path().display().to_string();
+    // A sort of the whole run would put the root first.
+    let expected: Vec<_> = ["docs", "empty", "media", "media/photos"]
+        .iter()
+        .map(|f| format!("{root}/{f}"))
+        .chain([root.clone()])
+        .collect();
+    assert_eq!(order, expected, "{stdout}");
+}
+
+#[test]
+fn a_generated_tree_is_stamped_in_one_run() {
+    let tmp = TempDir::new().unwrap();
+    let tree = tmp.path().join("tree");
+    dirstamp()
+        .arg("gen-tree")
+        .arg(&tree)
+        .args(["--dirs", "300", "--files-per-dir", "3", "--depth", "5"])
+        .args(["--seed", "42", "--time-spread", "2y"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Created 300 folder(s) and 903 file(s)",
+        ));
+    // Already there, and not to be replaced without --force.
+    dirstamp()
+        .arg("gen-tree")
+        .arg(&tree)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("--force"));
+
+    dirstamp().arg(&tree).arg("-C").assert().success();
+    dirstamp().arg(&tree).arg("--check").assert().success();
+}
+
+#[test]
+fn count_dirs_counts_folders_left_alone_too() {
+    let tree = sample_tree();
+
+    // Four folders need a change; empty/ doesn't.
+    dirstamp()
+        .arg(tree.path())
+        .args(["--quiet", "--count-dirs"])
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("\nScanned 5 directories\n"));
+}
+
+#[test]
+fn rate_limit_spreads_the_sets_over_seconds() {
+    let tree = sample_tree();
+    let run = |args: &[&str]| {
+        let started = std::time::Instant::now();
+        dirstamp()
+            .arg(tree.path())
+            .args(["--rate-limit", "2"])
+            .args(args)
+            .assert()
+            .success();
+        started.elapsed()
+    };
+
+    // Nothing is set in a dry run, so nothing waits.
+    assert!(run(&[]) < std::time::Duration::from_millis(900));
+    // Four sets at two a second: the third and fourth wait a second.
+    assert!(run(&["-C"]) >= std::time::Duration::from_millis(900));
+    assert_eq!(mtime(&tree.path().join("media")), at(T2020 + 4 * DAY));
+}
+
+#[test]
+fn a_held_lockfile_stops_the_run() {
+    let tree = sample_tree();
+    let out = TempDir::new().unwrap();
+    let lockfile = out.path().join("dirstamp.lock");
+    let held = fs::File::create(&lockfile).unwrap();
+    held.lock().unwrap();
+
+    dirstamp()
+        .arg(tree.path())
+        .arg("-C")
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "Another dirstamp is running (lockfile: ",
+        ));
+    assert_ne!(mtime(&tree.path().join("docs")), at(T2020));
+
+    drop(held);
+    dirstamp()
+        .arg(tree.path())
+        .arg("-C")
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .assert()
+        .success();
+    assert_eq!(mtime(&tree.path().join("docs")), at(T2020));
+}
+
+#[cfg(unix)]
+#[test]
+fn a_deep_tree_fits_in_a_low_open_file_limit() {
+    let tree = TempDir::new().unwrap();
+    let mut deepest = tree.path().to_path_buf();
+    for _ in 0..200 {
+        deepest.push("d");
+    }
+    fs::create_dir_all(&deepest).unwrap();
+    touch(&deepest.join("leaf.txt"), T2020);
+
+    for extra in [&[][..], &["--buffered"], &["--jobs", "8"]] {
+        // `ulimit -n` lowers the hard limit too, so it can't be raised again.
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(r#"ulimit -n 40 && exec "$0" "$@""#)
+            .arg(assert_cmd::cargo::cargo_bin("dirstamp"))
+            .arg(tree.path())
+            .arg("-C")
+            .args(extra)
+            .env(
+                "XDG_STATE_HOME",
+                std::env::temp_dir().join("dirstamp-test-state"),
+            )
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "{extra:?}");
+        assert_eq!(mtime(tree.path()), at(T2020), "{extra:?}");
+        assert_eq!(mtime(&deepest), at(T2020), "{extra:?}");
+        set_mtime(tree.path(), T2020 + DAY);
+    }
+}
+
+#[test]
+fn touch_created_ranks_children_by_birth_time() {
+    let tree = TempDir::new().unwrap();
+    let first = tree.path().join("first.jpg");
+    let second = tree.path().join("second.jpg");
+    touch(&first, T2020 + DAY);
+    std::thread::sleep(Duration::from_millis(20));
+    touch(&second, T2020);
+    let birth = fs::metadata(&second).unwrap().created();
+    // Far from either time, so there is a change to make.
+    set_mtime(tree.path(), T2020 - 10 * DAY);
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--touch-created"])
+        .assert()
+        .success();
+    match birth {
+        // Created last, so it wins although its mtime is the older one.
+        Ok(born) => assert_eq!(mtime(tree.path()), born),
+        // No birth times here: mtimes decide, as without the flag.
+        Err(_) => assert_eq!(mtime(tree.path()), at(T2020 + DAY)),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn after_stamp_runs_for_each_folder_set() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+
+    dirstamp()
+        .arg(tree.path())
+        .args([
+            "-C",
+            "--epoch",
+            "--after-stamp",
+            "echo done {path} {to_mtime}",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "done {} {}\n",
+            photos.display(),
+            T2020 + 4 * DAY
+        )));
+    // Nothing is set in a dry run, so nothing runs.
+    set_mtime(&photos, T2020);
+    dirstamp()
+        .arg(tree.path())
+        .args(["--after-stamp", "echo done"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("done").not());
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--after-stamp", "exit 1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("warning: --after-stamp failed"));
+    assert_eq!(mtime(&photos), at(T2020 + 4 * DAY));
+    set_mtime(&photos, T2020);
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--after-stamp", "exit 1", "--fail-fast"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("stopping (--fail-fast)"));
+
+    // The run stops, but still finishes: the JSON ends with its summary,
+    // and --atomic sets the folders back.
+    set_mtime(&photos, T2020);
+    let out = dirstamp()
+        .arg(tree.path())
+        .args([
+            "-C",
+            "--after-stamp",
+            "exit 1",
+            "--fail-fast",
+            "--output-json",
+        ])
+        .assert()
+        .code(3)
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.lines().last().unwrap().contains(r#""type":"summary""#));
+    set_mtime(&photos, T2020);
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--atomic", "--after-stamp", "exit 1", "--fail-fast"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "--atomic: an --after-stamp command failed; set 1 folder(s) back",
+        ));
+    assert_eq!(mtime(&photos), at(T2020));
+}
+
+#[cfg(unix)]
+#[test]
+fn before_stamp_can_veto_a_folder() {
+    let tree = sample_tree();
+    let photos = tree.path().join("media/photos");
+    let veto = "case {path} in *photos) exit 1;; esac; echo ok {path}";
+
+    // Asked in a dry run too, with nothing set.
+    dirstamp()
+        .arg(tree.path())
+        .args(["--before-stamp", veto])
+        .assert()
+        .stdout(predicate::str::contains(format!(
+            "ok {}\n",
+            tree.path().join("docs").display()
+        )));
+    assert_ne!(mtime(&tree.path().join("docs")), at(T2020));
+
+    dirstamp()
+        .arg(tree.path())
+        .args(["-C", "--before-stamp", veto])
+        .assert()
+        .success();
+    assert_ne!(mtime(&photos), at(T2020 + 4 * DAY));
+    assert_eq!(mtime(&tree.path().join("docs")), at(T2020));
+}